@@ -3,24 +3,76 @@
 //! Reads commands from the ring buffer and dispatches to D3D11 renderer.
 
 use crate::d3d11::{D3D11Renderer, MapResult, UpdateBox};
+use crate::error::ProcessError;
+use crate::logging;
+use crate::presentation::{validate_display_config, DisplayConfig};
 use crate::protocol::*;
+use crate::selftest;
 use anyhow::Result;
 use std::collections::HashMap;
-use tracing::{debug, info, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn, Level};
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Direct3D11::D3D11_VIEWPORT;
 use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
 
+/// Longest wait for the GPU to drain on WAIT_FENCE or a SYNC command
+const GPU_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Processes commands from the shared memory ring buffer.
 pub struct CommandProcessor {
     renderer: D3D11Renderer,
     current_fence: u64,
-    /// Last present command info (backbuffer_id, sync_interval)
-    pending_present: Option<(u32, u32)>,
+    /// Last present command info (backbuffer_id, sync_interval, flags)
+    pending_present: Option<(u32, u32, u32)>,
+    /// Dirty rects of the last present (None = whole frame)
+    pending_dirty_rects: Option<Vec<RECT>>,
+    /// Frame opened by BEGIN_FRAME and not yet presented (frame_id, fence_value)
+    open_frame: Option<(u64, u64)>,
+    /// Set once the guest brackets frames with BEGIN_FRAME
+    frames_bracketed: bool,
+    /// A bracketed frame was just presented; the batch should end here
+    frame_boundary: bool,
     /// Pending resize request (width, height)
     pending_resize: Option<(u32, u32)>,
-    /// Active map operations: (resource_id, subresource) -> MapResult
-    active_maps: HashMap<(u32, u32), MapResult>,
+    /// Pending display mode change from SET_DISPLAY_CONFIG
+    pending_display_config: Option<DisplayConfig>,
+    /// Pending fence query (heap offset to receive host_fence_completed)
+    pending_fence_query: Option<u32>,
+    /// Heap offset for the next device status snapshot
+    pending_status_query: Option<u32>,
+    /// Set when the guest reset the device; fence numbering restarts
+    device_reset: bool,
+    /// Guest asked for the recent-frame ring to be dumped
+    pending_frame_dump: bool,
+    /// Self-test result to write to the heap (heap offset, result code)
+    pending_selftest: Option<(u32, u32)>,
+    /// Log level requested by SET_LOG_LEVEL
+    pending_log_level: Option<Level>,
+    /// Adapter requested by SELECT_ADAPTER (adapter index, ack fence)
+    pending_adapter_switch: Option<(u32, u64)>,
+    /// Set by SHUTDOWN; the main loop exits once the batch is finished
+    pending_shutdown: bool,
+    /// Pitch of the last map to write to the heap (heap offset, pitch)
+    pending_map_pitch: Option<(u32, MapPitch)>,
+    /// Heap offset and staging contents of a read map
+    pending_map_readback: Option<(u32, Vec<u8>)>,
+    /// MSAA query result to write to the heap (heap offset, support)
+    pending_msaa_support: Option<(u32, MsaaSupport)>,
+    /// Format query result to publish in the control region (format, support)
+    pending_format_support: Option<(u32, u32)>,
+    /// Present skipped while its backbuffer was mapped, to report to the
+    /// guest (backbuffer ID, mapped subresource)
+    pending_torn_present: Option<(u32, u32)>,
+    /// The last command carried PVGPU_CMD_FLAG_NO_FENCE
+    fence_suppressed: bool,
+    /// Active map operations: (resource_id, subresource) -> (MapResult, MapType)
+    active_maps: HashMap<(u32, u32), (MapResult, MapType)>,
+    /// Resource or shader creations taking at least this long are logged
+    slow_create_threshold: Option<Duration>,
+    /// SET_LOG_LEVEL is honored; off by default since the level is shared
+    /// by every instance in the process
+    guest_log_level: bool,
     /// Statistics tracking
     stats: CommandProcessorStats,
 }
@@ -34,6 +86,19 @@ pub struct CommandProcessorStats {
     pub resources_created: u64,
     pub resources_destroyed: u64,
     pub errors: u64,
+    pub unknown_commands: u64,
+    pub leaked_maps: u64,
+    pub dropped_frames: u64,
+    pub dropped_presents: u64,
+    pub torn_presents: u64,
+    pub redundant_binds: u64,
+    /// Resource creations served by an identical existing upload
+    pub deduped_uploads: u64,
+    /// Shader creations served by the bytecode cache
+    pub shader_cache_hits: u64,
+    /// Time spent in CREATE_RESOURCE and CREATE_SHADER
+    pub creation_time: Duration,
+    pub slow_creations: u64,
 }
 
 impl CommandProcessor {
@@ -42,8 +107,29 @@ impl CommandProcessor {
             renderer,
             current_fence: 0,
             pending_present: None,
+            pending_dirty_rects: None,
+            open_frame: None,
+            frames_bracketed: false,
+            frame_boundary: false,
             pending_resize: None,
+            pending_display_config: None,
+            pending_fence_query: None,
+            pending_status_query: None,
+            device_reset: false,
+            pending_frame_dump: false,
+            pending_selftest: None,
+            pending_log_level: None,
+            pending_adapter_switch: None,
+            pending_shutdown: false,
+            pending_map_pitch: None,
+            pending_map_readback: None,
+            pending_msaa_support: None,
+            pending_format_support: None,
+            pending_torn_present: None,
+            fence_suppressed: false,
             active_maps: HashMap::new(),
+            slow_create_threshold: None,
+            guest_log_level: false,
             stats: CommandProcessorStats::default(),
         }
     }
@@ -51,9 +137,9 @@ impl CommandProcessor {
     /// Process a single command from the ring buffer.
     /// Returns the number of bytes consumed.
     /// `heap` is the shared memory heap for data transfer operations.
-    pub fn process_command(&mut self, data: &[u8], heap: &[u8]) -> Result<usize> {
+    pub fn process_command(&mut self, data: &[u8], heap: &[u8]) -> Result<usize, ProcessError> {
         if data.len() < PVGPU_CMD_HEADER_SIZE {
-            return Err(anyhow::anyhow!("Command too small"));
+            return Err(ProcessError::Internal(anyhow::anyhow!("Command too small")));
         }
 
         // Parse header
@@ -61,10 +147,14 @@ impl CommandProcessor {
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CommandHeader) };
 
         if header.command_size as usize > data.len() {
-            return Err(anyhow::anyhow!("Command size exceeds available data"));
+            return Err(ProcessError::Internal(anyhow::anyhow!(
+                "Command size exceeds available data"
+            )));
         }
 
         let cmd_data = &data[..header.command_size as usize];
+        self.fence_suppressed = header.flags & PVGPU_CMD_FLAG_NO_FENCE != 0;
+        let fence_before = self.current_fence;
 
         match header.command_type {
             // Resource commands
@@ -72,8 +162,10 @@ impl CommandProcessor {
             PVGPU_CMD_DESTROY_RESOURCE => self.handle_destroy_resource(&header)?,
             PVGPU_CMD_OPEN_RESOURCE => self.handle_open_resource(cmd_data, heap)?,
             PVGPU_CMD_COPY_RESOURCE => self.handle_copy_resource(cmd_data)?,
+            PVGPU_CMD_COPY_STRUCTURE_COUNT => self.handle_copy_structure_count(cmd_data)?,
             PVGPU_CMD_CREATE_SHADER => self.handle_create_shader(cmd_data, heap)?,
             PVGPU_CMD_DESTROY_SHADER => self.handle_destroy_shader(cmd_data)?,
+            PVGPU_CMD_REPLACE_SHADER => self.handle_replace_shader(cmd_data, heap)?,
             PVGPU_CMD_MAP_RESOURCE => self.handle_map_resource(cmd_data, heap)?,
             PVGPU_CMD_UNMAP_RESOURCE => self.handle_unmap_resource(cmd_data, heap)?,
             PVGPU_CMD_UPDATE_RESOURCE => self.handle_update_resource(cmd_data, heap)?,
@@ -87,6 +179,7 @@ impl CommandProcessor {
             PVGPU_CMD_SET_SHADER => self.handle_set_shader(cmd_data)?,
             PVGPU_CMD_SET_SAMPLER => self.handle_set_sampler(cmd_data)?,
             PVGPU_CMD_SET_CONSTANT_BUFFER => self.handle_set_constant_buffer(cmd_data)?,
+            PVGPU_CMD_SET_CONSTANT_BUFFERS => self.handle_set_constant_buffers(cmd_data)?,
             PVGPU_CMD_SET_VERTEX_BUFFER => self.handle_set_vertex_buffer(cmd_data)?,
             PVGPU_CMD_SET_INDEX_BUFFER => self.handle_set_index_buffer(cmd_data)?,
             PVGPU_CMD_SET_INPUT_LAYOUT => self.handle_set_input_layout(cmd_data)?,
@@ -97,16 +190,74 @@ impl CommandProcessor {
             PVGPU_CMD_DRAW_INDEXED => self.handle_draw_indexed(cmd_data)?,
             PVGPU_CMD_DRAW_INSTANCED => self.handle_draw_instanced(cmd_data)?,
             PVGPU_CMD_DRAW_INDEXED_INSTANCED => self.handle_draw_indexed_instanced(cmd_data)?,
+            PVGPU_CMD_DRAW_INSTANCED_INDIRECT => self.handle_draw_instanced_indirect(cmd_data)?,
+            PVGPU_CMD_DRAW_INDEXED_INSTANCED_INDIRECT => {
+                self.handle_draw_indexed_instanced_indirect(cmd_data)?
+            }
             PVGPU_CMD_DISPATCH => self.handle_dispatch(cmd_data)?,
             PVGPU_CMD_CLEAR_RENDER_TARGET => self.handle_clear_render_target(cmd_data)?,
+            PVGPU_CMD_CLEAR_RENDER_TARGET_UINT => self.handle_clear_render_target_uint(cmd_data)?,
             PVGPU_CMD_CLEAR_DEPTH_STENCIL => self.handle_clear_depth_stencil(cmd_data)?,
             // Sync commands
             PVGPU_CMD_FENCE => self.handle_fence(cmd_data)?,
-            PVGPU_CMD_PRESENT => self.handle_present(cmd_data)?,
+            PVGPU_CMD_PRESENT => self.handle_present(cmd_data, heap)?,
             PVGPU_CMD_FLUSH => self.handle_flush()?,
+            PVGPU_CMD_WAIT_FENCE => self.handle_wait_fence(cmd_data, heap)?,
+            PVGPU_CMD_QUERY_FENCE => self.handle_query_fence(cmd_data)?,
+            PVGPU_CMD_QUERY_DEVICE_STATUS => self.handle_query_device_status(cmd_data)?,
+            PVGPU_CMD_RESET_DEVICE => self.handle_reset_device(cmd_data)?,
+            PVGPU_CMD_SELECT_ADAPTER => self.handle_select_adapter(cmd_data)?,
+            PVGPU_CMD_SHUTDOWN => self.handle_shutdown(cmd_data)?,
+            PVGPU_CMD_SET_DISPLAY_CONFIG => self.handle_set_display_config(cmd_data)?,
+            PVGPU_CMD_SET_FRAME_LATENCY => self.handle_set_frame_latency(cmd_data)?,
+            PVGPU_CMD_BEGIN_FRAME => self.handle_begin_frame(cmd_data)?,
             PVGPU_CMD_RESIZE_BUFFERS => self.handle_resize_buffers(cmd_data)?,
+            // Debug commands
+            PVGPU_CMD_BEGIN_EVENT => self.handle_begin_event(cmd_data, heap)?,
+            PVGPU_CMD_END_EVENT => self.handle_end_event()?,
+            PVGPU_CMD_SET_MARKER => self.handle_set_marker(cmd_data, heap)?,
+            PVGPU_CMD_DUMP_FRAMES => {
+                debug!("DumpFrames requested");
+                self.pending_frame_dump = true;
+            }
+            PVGPU_CMD_SELFTEST => self.handle_selftest(cmd_data)?,
+            PVGPU_CMD_SET_LOG_LEVEL => self.handle_set_log_level(cmd_data)?,
+            PVGPU_CMD_SET_DEBUG_NAME => self.handle_set_debug_name(cmd_data, heap)?,
+            PVGPU_CMD_QUERY_MSAA_SUPPORT => self.handle_query_msaa_support(cmd_data)?,
+            PVGPU_CMD_CHECK_FORMAT_SUPPORT => self.handle_check_format_support(cmd_data)?,
+            PVGPU_CMD_CREATE_BLEND_STATE => self.handle_create_blend_state(cmd_data)?,
+            // View commands
+            PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW => {
+                self.handle_create_unordered_access_view(cmd_data)?
+            }
+            PVGPU_CMD_DESTROY_UNORDERED_ACCESS_VIEW => self.handle_destroy_resource(&header)?,
             _ => {
-                warn!("Unknown command type: 0x{:04X}", header.command_type);
+                // The main loop has already checked command_size is sane, so
+                // the command can be skipped without losing stream sync
+                warn!(
+                    "Unknown command type: 0x{:04X} ({} bytes), skipping",
+                    header.command_type, header.command_size
+                );
+                self.record_unknown_command();
+                return Err(ProcessError::UnknownCommand(header.command_type));
+            }
+        }
+
+        // The guest reads this command's result as soon as it is consumed.
+        // A command carrying a new fence signals it now rather than at the
+        // end of the batch, so the main loop publishes the fence and raises
+        // the IRQ right after it. The previous fence is already signaled
+        // before this command's work, so any other command drains the GPU.
+        if header.flags & PVGPU_CMD_FLAG_SYNC != 0 {
+            let result = if self.current_fence != fence_before {
+                self.renderer.signal_fence(self.current_fence);
+                self.renderer
+                    .wait_for_fence(self.current_fence, GPU_WAIT_TIMEOUT)
+            } else {
+                self.renderer.wait_for_gpu(GPU_WAIT_TIMEOUT)
+            };
+            if let Err(e) = result {
+                warn!("Sync command 0x{:04X}: {}", header.command_type, e);
             }
         }
 
@@ -119,6 +270,8 @@ impl CommandProcessor {
             | PVGPU_CMD_DRAW_INDEXED
             | PVGPU_CMD_DRAW_INSTANCED
             | PVGPU_CMD_DRAW_INDEXED_INSTANCED
+            | PVGPU_CMD_DRAW_INSTANCED_INDIRECT
+            | PVGPU_CMD_DRAW_INDEXED_INSTANCED_INDIRECT
             | PVGPU_CMD_DISPATCH => self.stats.draw_calls += 1,
             PVGPU_CMD_PRESENT => self.stats.presents += 1,
             _ => {}
@@ -128,8 +281,12 @@ impl CommandProcessor {
     }
 
     fn handle_create_resource(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        // Older guests send the command without `usage`; zero-fill it
+        let mut bytes = [0u8; std::mem::size_of::<CmdCreateResource>()];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
         let cmd: CmdCreateResource =
-            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdCreateResource) };
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const CmdCreateResource) };
 
         debug!(
             "CreateResource: id={}, type={}, {}x{}x{}, format={}, heap_offset={}, data_size={}",
@@ -143,10 +300,30 @@ impl CommandProcessor {
             cmd.data_size
         );
 
+        let started = Instant::now();
         let resource_id = cmd.header.resource_id;
+        let resource_type = ResourceType::try_from(cmd.resource_type).map_err(|value| {
+            warn!(
+                "CreateResource: unknown resource type {} for id={}",
+                value, resource_id
+            );
+            ProcessError::UnknownType(value)
+        })?;
+        let immutable = match cmd.usage {
+            PVGPU_USAGE_DEFAULT => false,
+            PVGPU_USAGE_IMMUTABLE => true,
+            usage => {
+                warn!(
+                    "CreateResource: unsupported usage {} for id={}",
+                    usage, resource_id
+                );
+                return Err(ProcessError::InvalidParameter(usage).into());
+            }
+        };
 
         // Get initial data from heap if provided
         let initial_data = if cmd.data_size > 0 && cmd.heap_offset > 0 {
+            check_heap_alignment("CreateResource", cmd.heap_offset)?;
             let offset = cmd.heap_offset as usize;
             let size = cmd.data_size as usize;
             if offset + size <= heap.len() {
@@ -159,119 +336,95 @@ impl CommandProcessor {
             None
         };
 
-        match cmd.resource_type {
-            // Texture2D
-            2 => {
+        match resource_type {
+            ResourceType::Texture2D => {
                 let format = DXGI_FORMAT(cmd.format as i32);
                 self.renderer.create_texture2d(
                     resource_id,
                     cmd.width,
                     cmd.height,
                     format,
+                    cmd.depth.max(1),
                     cmd.bind_flags,
+                    cmd.misc_flags & PVGPU_RESOURCE_MISC_SRGB_VIEW != 0,
+                    cmd.misc_flags & PVGPU_RESOURCE_MISC_TEXTURECUBE != 0,
+                    immutable,
                     initial_data,
                 )?;
             }
-            // Buffer
-            4 => {
+            ResourceType::Buffer => {
                 self.renderer.create_buffer(
                     resource_id,
                     cmd.width, // For buffers, width is the size
                     cmd.bind_flags,
+                    cmd.misc_flags,
+                    cmd.height, // and height the stride of structured ones
+                    immutable,
                     initial_data,
                 )?;
             }
-            // VertexShader
-            5 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_vertex_shader(resource_id, bytecode) {
-                        warn!("VertexShader creation failed for id={}: {}", resource_id, e);
-                        // Return shader compile error - the command is consumed but failed
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                    }
-                } else {
-                    warn!("VertexShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                }
-            }
-            // PixelShader
-            6 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_pixel_shader(resource_id, bytecode) {
-                        warn!("PixelShader creation failed for id={}: {}", resource_id, e);
-                        // Return shader compile error - the command is consumed but failed
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                    }
-                } else {
-                    warn!("PixelShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                }
-            }
-            // GeometryShader
-            7 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_geometry_shader(resource_id, bytecode) {
+            resource_type => match resource_type.shader_stage() {
+                Some(stage) => {
+                    let Some(bytecode) = initial_data else {
+                        warn!("{:?} creation requires bytecode in heap", resource_type);
+                        return Err(ProcessError::ShaderCompile(resource_id).into());
+                    };
+                    if let Err(e) = self.renderer.create_shader(resource_id, stage, bytecode) {
                         warn!(
-                            "GeometryShader creation failed for id={}: {}",
-                            resource_id, e
+                            "{:?} creation failed for id={}: {}",
+                            resource_type, resource_id, e
                         );
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
+                        // The command is consumed but failed
+                        return Err(ProcessError::ShaderCompile(resource_id).into());
                     }
-                } else {
-                    warn!("GeometryShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
                 }
-            }
-            // HullShader
-            8 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_hull_shader(resource_id, bytecode) {
-                        warn!("HullShader creation failed for id={}: {}", resource_id, e);
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                    }
-                } else {
-                    warn!("HullShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                }
-            }
-            // DomainShader
-            9 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_domain_shader(resource_id, bytecode) {
-                        warn!("DomainShader creation failed for id={}: {}", resource_id, e);
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                    }
-                } else {
-                    warn!("DomainShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                }
-            }
-            // ComputeShader
-            10 => {
-                if let Some(bytecode) = initial_data {
-                    if let Err(e) = self.renderer.create_compute_shader(resource_id, bytecode) {
-                        warn!(
-                            "ComputeShader creation failed for id={}: {}",
-                            resource_id, e
-                        );
-                        return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
-                    }
-                } else {
-                    warn!("ComputeShader creation requires bytecode in heap");
-                    return Err(anyhow::anyhow!("SHADER_COMPILE:{}", resource_id));
+                None => {
+                    warn!(
+                        "CreateResource: unsupported resource type {:?}",
+                        resource_type
+                    );
                 }
-            }
-            _ => {
-                warn!("Unknown resource type: {}", cmd.resource_type);
-            }
+            },
         }
 
+        self.record_creation(started, || {
+            format!(
+                "CreateResource: id={}, type={}, {}x{}x{}, format={}, data_size={}",
+                resource_id,
+                cmd.resource_type,
+                cmd.width,
+                cmd.height,
+                cmd.depth,
+                cmd.format,
+                cmd.data_size
+            )
+        });
         Ok(())
     }
 
     fn handle_destroy_resource(&mut self, header: &CommandHeader) -> Result<()> {
         debug!("DestroyResource: id={}", header.resource_id);
-        self.renderer.destroy_resource(header.resource_id);
+
+        // Maps still open on a destroyed resource will never see an Unmap
+        let leaked: Vec<(u32, u32)> = self
+            .active_maps
+            .keys()
+            .filter(|(id, _)| *id == header.resource_id)
+            .copied()
+            .collect();
+        for key in leaked {
+            if let Some((map_result, _)) = self.active_maps.remove(&key) {
+                warn!(
+                    "DestroyResource: dropping open map on resource {} subresource {}",
+                    key.0, key.1
+                );
+                self.renderer.unmap_resource(map_result, false);
+                self.stats.leaked_maps += 1;
+            }
+        }
+
+        self.renderer
+            .destroy_resource(header.resource_id, self.current_fence);
         Ok(())
     }
 
@@ -287,6 +440,28 @@ impl CommandProcessor {
         let new_id = cmd.header.resource_id;
         let original_id = cmd.shared_handle;
 
+        // A texture from another API or process, rather than an alias
+        if cmd.misc_flags & PVGPU_RESOURCE_MISC_SHARED_NTHANDLE != 0 {
+            if cmd.resource_type != 2 {
+                warn!(
+                    "OpenResource: NT handles only share textures, got type {}",
+                    cmd.resource_type
+                );
+                return Err(ProcessError::InvalidParameter(cmd.resource_type).into());
+            }
+            if let Err(e) = self
+                .renderer
+                .open_shared_texture_nthandle(new_id, cmd.shared_handle as u64)
+            {
+                warn!(
+                    "OpenResource: NT handle 0x{:X} could not be opened: {}",
+                    cmd.shared_handle, e
+                );
+                return Err(ProcessError::InvalidParameter(cmd.shared_handle).into());
+            }
+            return Ok(());
+        }
+
         // For shared resources, we create an alias to the original resource
         // The backend maintains resource ownership - the "open" creates a reference
         // that maps new_id -> same underlying D3D11 resource as original_id
@@ -339,6 +514,7 @@ impl CommandProcessor {
             }
         } else {
             warn!("OpenResource: original resource {} not found", original_id);
+            return Err(ProcessError::ResourceNotFound(original_id).into());
         }
 
         Ok(())
@@ -353,66 +529,122 @@ impl CommandProcessor {
             cmd.resource_id, cmd.subresource, cmd.map_type, cmd.heap_offset
         );
 
+        let map_type = MapType::try_from(cmd.map_type).map_err(|value| {
+            warn!("MapResource: unknown map type {}", value);
+            ProcessError::InvalidParameter(value)
+        })?;
+
         // Map the resource
+        check_heap_alignment("MapResource", cmd.heap_offset)?;
+
         let map_result =
             self.renderer
                 .map_resource(cmd.resource_id, cmd.subresource, cmd.map_type)?;
 
-        // For read maps, copy GPU data to shared memory heap
-        if cmd.map_type == 1 || cmd.map_type == 3 {
-            // Read or ReadWrite
+        // For read maps, the main loop copies the staging data into the heap
+        // at heap_offset, rows still padded to the row pitch
+        if map_type.reads() {
             let offset = cmd.heap_offset as usize;
-            let size = std::cmp::min(map_result.size, heap.len().saturating_sub(offset));
-            if size > 0 && !map_result.data_ptr.is_null() {
-                // Note: We need mutable heap access here. The caller must provide this.
-                // For now, we store the map result for later unmap which will handle the copy.
-                debug!(
-                    "MapResource: read map, data will be available at heap offset {}",
-                    offset
+            if offset.saturating_add(map_result.size) > heap.len() {
+                warn!(
+                    "MapResource: {} bytes at heap offset {} exceed the heap",
+                    map_result.size, offset
                 );
+                self.renderer.unmap_resource(map_result, false);
+                return Err(ProcessError::InvalidParameter(cmd.heap_offset).into());
+            }
+            if !map_result.data().is_empty() {
+                self.pending_map_readback = Some((cmd.heap_offset, map_result.data().to_vec()));
             }
         }
 
-        // Store the map result for later unmap
+        debug!(
+            "MapResource: row_pitch={}, depth_pitch={}",
+            map_result.row_pitch, map_result.depth_pitch
+        );
+        if cmd.pitch_offset != 0 {
+            self.pending_map_pitch = Some((
+                cmd.pitch_offset,
+                MapPitch {
+                    row_pitch: map_result.row_pitch,
+                    depth_pitch: map_result.depth_pitch,
+                },
+            ));
+        }
+
+        // Store the map result for later unmap. A second Map without an
+        // Unmap in between abandons the first one; its writes are dropped.
         let key = (cmd.resource_id, cmd.subresource);
-        self.active_maps.insert(key, map_result);
+        if let Some((previous, _)) = self.active_maps.insert(key, (map_result, map_type)) {
+            warn!(
+                "MapResource: resource {} subresource {} was already mapped, dropping old map",
+                cmd.resource_id, cmd.subresource
+            );
+            self.renderer.unmap_resource(previous, false);
+            self.stats.leaked_maps += 1;
+        }
 
         Ok(())
     }
 
     fn handle_unmap_resource(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        // Older guests send the command without `src_row_pitch`; zero-fill it
+        let mut bytes = [0u8; std::mem::size_of::<CmdUnmapResource>()];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
         let cmd: CmdUnmapResource =
-            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdUnmapResource) };
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const CmdUnmapResource) };
 
         debug!(
-            "UnmapResource: id={}, subresource={}, heap_offset={}, data_size={}",
-            cmd.resource_id, cmd.subresource, cmd.heap_offset, cmd.data_size
+            "UnmapResource: id={}, subresource={}, heap_offset={}, data_size={}, src_row_pitch={}, map_type={}",
+            cmd.resource_id, cmd.subresource, cmd.heap_offset, cmd.data_size, cmd.src_row_pitch, cmd.map_type
         );
 
+        if cmd.data_size > 0 {
+            check_heap_alignment("UnmapResource", cmd.heap_offset)?;
+        }
+
         let key = (cmd.resource_id, cmd.subresource);
 
-        if let Some(map_result) = self.active_maps.remove(&key) {
+        if let Some((mut map_result, map_type)) = self.active_maps.remove(&key) {
+            // A guest that states the type must agree with the map; its
+            // data is dropped rather than written back under the wrong one
+            if cmd.map_type != 0 && cmd.map_type != map_type as u32 {
+                warn!(
+                    "UnmapResource: resource {} subresource {} was mapped as {:?}, not {}",
+                    cmd.resource_id, cmd.subresource, map_type, cmd.map_type
+                );
+                self.renderer.unmap_resource(map_result, false);
+                return Err(ProcessError::InvalidParameter(cmd.map_type).into());
+            }
+
+            // The map type, not data_size, decides whether anything is
+            // written back; read maps leave the resource untouched
+            let was_write = map_type.writes() && cmd.data_size > 0;
+
             // For write operations, copy data from heap to the mapped buffer first
-            if cmd.data_size > 0 && !map_result.data_ptr.is_null() {
+            if was_write {
                 let offset = cmd.heap_offset as usize;
                 let size = cmd.data_size as usize;
                 if offset + size <= heap.len() {
-                    unsafe {
-                        std::ptr::copy_nonoverlapping(
-                            heap[offset..].as_ptr(),
-                            map_result.data_ptr,
-                            size,
+                    let src = &heap[offset..offset + size];
+                    if cmd.src_row_pitch != 0 && map_result.is_texture() {
+                        let rows = map_result.write_rows(src, cmd.src_row_pitch);
+                        debug!(
+                            "UnmapResource: copied {} rows of pitch {} from heap to staging pitch {}",
+                            rows, cmd.src_row_pitch, map_result.row_pitch
+                        );
+                    } else {
+                        let written = map_result.write(src);
+                        debug!(
+                            "UnmapResource: copied {} bytes from heap to staging",
+                            written
                         );
                     }
-                    debug!("UnmapResource: copied {} bytes from heap to staging", size);
                 }
             }
 
-            // Determine if this was a write operation
-            let was_write = cmd.data_size > 0;
-
-            self.renderer
-                .unmap_resource(&map_result, cmd.subresource, was_write);
+            self.renderer.unmap_resource(map_result, was_write);
         } else {
             warn!(
                 "UnmapResource: no active map for resource {} subresource {}",
@@ -434,6 +666,7 @@ impl CommandProcessor {
         );
 
         // Get data from heap
+        check_heap_alignment("UpdateResource", cmd.heap_offset)?;
         let offset = cmd.heap_offset as usize;
         let size = cmd.data_size as usize;
 
@@ -472,14 +705,31 @@ impl CommandProcessor {
     }
 
     fn handle_set_render_target(&mut self, data: &[u8]) -> Result<()> {
+        // Older guests send the command without DSV flags and UAVs; zero-fill them
+        let mut bytes = [0u8; std::mem::size_of::<CmdSetRenderTarget>()];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
         let cmd: CmdSetRenderTarget =
-            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetRenderTarget) };
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const CmdSetRenderTarget) };
 
         debug!(
-            "SetRenderTarget: num_rtvs={}, dsv_id={}",
-            cmd.num_rtvs, cmd.dsv_id
+            "SetRenderTarget: num_rtvs={}, dsv_id={}, dsv_flags={}, uavs={} from slot {}",
+            cmd.num_rtvs, cmd.dsv_id, cmd.dsv_flags, cmd.num_uavs, cmd.uav_start_slot
         );
 
+        if !om_slots_valid(cmd.num_rtvs, cmd.uav_start_slot, cmd.num_uavs) {
+            warn!(
+                "SetRenderTarget: {} RTVs and {} UAVs from slot {} do not fit",
+                cmd.num_rtvs, cmd.num_uavs, cmd.uav_start_slot
+            );
+            return Err(ProcessError::InvalidParameter(cmd.uav_start_slot).into());
+        }
+        let unknown = cmd.dsv_flags & !(PVGPU_DSV_READ_ONLY_DEPTH | PVGPU_DSV_READ_ONLY_STENCIL);
+        if unknown != 0 {
+            warn!("SetRenderTarget: unknown DSV flags 0x{:x}", unknown);
+            return Err(ProcessError::InvalidParameter(unknown).into());
+        }
+
         let rtv_ids: Vec<u32> = cmd.rtv_ids[..cmd.num_rtvs as usize].to_vec();
         let dsv_id = if cmd.dsv_id == 0 {
             None
@@ -487,7 +737,13 @@ impl CommandProcessor {
             Some(cmd.dsv_id)
         };
 
-        self.renderer.set_render_targets(&rtv_ids, dsv_id)?;
+        self.renderer.set_render_targets(
+            &rtv_ids,
+            dsv_id,
+            cmd.dsv_flags,
+            cmd.uav_start_slot,
+            &cmd.uav_ids[..cmd.num_uavs as usize],
+        )?;
         Ok(())
     }
 
@@ -497,7 +753,23 @@ impl CommandProcessor {
 
         debug!("SetViewport: {} viewports", cmd.num_viewports);
 
-        let viewports: Vec<D3D11_VIEWPORT> = cmd.viewports[..cmd.num_viewports as usize]
+        if cmd.num_viewports > PVGPU_MAX_VIEWPORTS {
+            warn!(
+                "SetViewport: {} viewports exceeds {}, clamping",
+                cmd.num_viewports, PVGPU_MAX_VIEWPORTS
+            );
+        }
+        let count = cmd.num_viewports.min(PVGPU_MAX_VIEWPORTS) as usize;
+
+        if let Some(index) = cmd.viewports[..count].iter().position(|v| !v.is_valid()) {
+            warn!(
+                "SetViewport: viewport {} is degenerate: {:?}",
+                index, cmd.viewports[index]
+            );
+            return Err(ProcessError::InvalidParameter(index as u32).into());
+        }
+
+        let viewports: Vec<D3D11_VIEWPORT> = cmd.viewports[..count]
             .iter()
             .map(|v| D3D11_VIEWPORT {
                 TopLeftX: v.x,
@@ -540,6 +812,19 @@ impl CommandProcessor {
         Ok(())
     }
 
+    fn handle_clear_render_target_uint(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdClearRenderTargetUint =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdClearRenderTargetUint) };
+
+        debug!(
+            "ClearRenderTargetUint: rtv={}, color={:?}",
+            cmd.rtv_id, cmd.color
+        );
+
+        self.renderer
+            .clear_render_target_uint(cmd.rtv_id, &cmd.color)
+    }
+
     fn handle_fence(&mut self, data: &[u8]) -> Result<()> {
         let cmd: CmdFence = unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdFence) };
         self.current_fence = cmd.fence_value;
@@ -554,23 +839,320 @@ impl CommandProcessor {
         Ok(())
     }
 
-    fn handle_present(&mut self, data: &[u8]) -> Result<()> {
+    fn handle_wait_fence(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        let cmd: CmdWaitFence =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdWaitFence) };
+
+        if cmd.fence_count == 0 || cmd.fence_count > PVGPU_MAX_WAIT_FENCES {
+            warn!("WaitFence: invalid fence count {}", cmd.fence_count);
+            return Err(ProcessError::InvalidParameter(cmd.fence_count).into());
+        }
+
+        check_heap_alignment("WaitFence", cmd.heap_offset)?;
+        let offset = cmd.heap_offset as usize;
+        let size = cmd.fence_count as usize * 8;
+        let values = match offset.checked_add(size) {
+            Some(end) if end <= heap.len() => &heap[offset..end],
+            _ => {
+                warn!(
+                    "WaitFence: fence array out of heap bounds: offset={}, count={}",
+                    offset, cmd.fence_count
+                );
+                return Err(ProcessError::InvalidParameter(cmd.heap_offset).into());
+            }
+        };
+
+        // Commands execute in order, so waiting for all fences is waiting
+        // for the highest one
+        let target = values
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap_or([0; 8])))
+            .max()
+            .unwrap_or(0);
+
+        debug!(
+            "WaitFence: {} fences, target={}, current={}",
+            cmd.fence_count, target, self.current_fence
+        );
+
+        // A fence later in the stream can never complete before this command
+        if target > self.current_fence {
+            warn!(
+                "WaitFence: fence {} has not been submitted (current={})",
+                target, self.current_fence
+            );
+            return Err(ProcessError::InvalidParameter(cmd.heap_offset).into());
+        }
+
+        self.renderer.signal_fence(self.current_fence);
+        if let Err(e) = self.renderer.wait_for_fence(target, GPU_WAIT_TIMEOUT) {
+            warn!("WaitFence: {}", e);
+        }
+        Ok(())
+    }
+
+    fn handle_query_fence(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdQueryFence =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdQueryFence) };
+
+        debug!("QueryFence: heap_offset={}", cmd.heap_offset);
+
+        // The main loop writes host_fence_completed once the preceding
+        // fences have been published
+        self.pending_fence_query = Some(cmd.heap_offset);
+        Ok(())
+    }
+
+    fn handle_query_device_status(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdQueryDeviceStatus =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdQueryDeviceStatus) };
+
+        debug!("QueryDeviceStatus: heap_offset={}", cmd.heap_offset);
+
+        // The control region lives with the main loop, which snapshots it
+        // after publishing this command's fence
+        self.pending_status_query = Some(cmd.heap_offset);
+        Ok(())
+    }
+
+    fn handle_query_msaa_support(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdQueryMsaaSupport =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdQueryMsaaSupport) };
+
+        let support = self.renderer.msaa_support(DXGI_FORMAT(cmd.format as i32));
+        debug!(
+            "QueryMsaaSupport: format={}, mask={:#x}, quality={:?}",
+            cmd.format, support.sample_count_mask, support.quality_levels
+        );
+        self.pending_msaa_support = Some((cmd.heap_offset, support));
+        Ok(())
+    }
+
+    fn handle_check_format_support(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdCheckFormatSupport =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdCheckFormatSupport) };
+
+        let support = self.renderer.format_support(DXGI_FORMAT(cmd.format as i32));
+        debug!(
+            "CheckFormatSupport: format={}, support={:#x}",
+            cmd.format, support
+        );
+        self.pending_format_support = Some((cmd.format, support));
+        Ok(())
+    }
+
+    fn handle_selftest(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSelftest =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSelftest) };
+
+        debug!("Selftest: heap_offset={}", cmd.heap_offset);
+
+        let result = selftest::run(self.renderer.device(), self.renderer.context());
+        self.pending_selftest = Some((cmd.heap_offset, result));
+        Ok(())
+    }
+
+    fn handle_set_log_level(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSetLogLevel =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetLogLevel) };
+
+        if !self.guest_log_level {
+            warn!("SetLogLevel: ignored, allow_guest_log_level is off");
+            return Err(ProcessError::UnknownCommand(PVGPU_CMD_SET_LOG_LEVEL).into());
+        }
+        let Some(level) = logging::level_from_protocol(cmd.level) else {
+            warn!("SetLogLevel: invalid level {}", cmd.level);
+            return Err(ProcessError::InvalidParameter(cmd.level).into());
+        };
+        // Applied by the main loop, since the filter is process-wide
+        self.pending_log_level = Some(level);
+        Ok(())
+    }
+
+    fn handle_reset_device(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdResetDevice =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdResetDevice) };
+
+        warn!(
+            "ResetDevice: destroying all resources, every resource ID is now invalid (ack fence={})",
+            cmd.fence_value
+        );
+
+        // Release outstanding maps before their resources go away
+        for (_, (map_result, _)) in self.active_maps.drain() {
+            self.renderer.unmap_resource(map_result, false);
+        }
+        self.renderer.clear_resources();
+        self.renderer.flush();
+
+        self.pending_present = None;
+        self.pending_dirty_rects = None;
+        self.open_frame = None;
+        self.frames_bracketed = false;
+        self.frame_boundary = false;
+        self.pending_fence_query = None;
+        self.pending_status_query = None;
+        // Numbering restarts below values the GPU fence has passed
+        self.current_fence = cmd.fence_value;
+        self.renderer.restart_gpu_fence(cmd.fence_value);
+        self.device_reset = true;
+        Ok(())
+    }
+
+    fn handle_shutdown(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdShutdown =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdShutdown) };
+
+        info!("Shutdown requested by guest (fence={})", cmd.fence_value);
+        if cmd.fence_value != 0 {
+            self.current_fence = cmd.fence_value;
+        }
+        // The main loop drains the GPU and exits once this batch ends
+        self.pending_shutdown = true;
+        Ok(())
+    }
+
+    fn handle_select_adapter(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSelectAdapter =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSelectAdapter) };
+
+        let by_luid = cmd.flags & PVGPU_SELECT_ADAPTER_BY_LUID != 0;
+        let adapters = D3D11Renderer::enumerate_adapters()?;
+        let adapter = adapters.iter().find(|adapter| {
+            if by_luid {
+                adapter.luid == cmd.adapter_luid
+            } else {
+                adapter.index == cmd.adapter_index
+            }
+        });
+        let Some(adapter) = adapter else {
+            warn!(
+                "SelectAdapter: no adapter with {} {:#x}",
+                if by_luid { "LUID" } else { "index" },
+                if by_luid {
+                    cmd.adapter_luid
+                } else {
+                    cmd.adapter_index as u64
+                }
+            );
+            return Err(ProcessError::InvalidParameter(cmd.adapter_index).into());
+        };
+
+        info!(
+            "SelectAdapter: switching to adapter {} ({}), ack fence={}",
+            adapter.index, adapter.description, cmd.fence_value
+        );
+        // The main loop recreates the device once this batch ends
+        self.pending_adapter_switch = Some((adapter.index, cmd.fence_value));
+        Ok(())
+    }
+
+    fn handle_present(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
         let cmd: CmdPresent =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdPresent) };
 
         debug!(
-            "Present: backbuffer={}, sync_interval={}",
-            cmd.backbuffer_id, cmd.sync_interval
+            "Present: backbuffer={}, sync_interval={}, flags={:#x}, dirty_rects_offset={}",
+            cmd.backbuffer_id, cmd.sync_interval, cmd.flags, cmd.dirty_rects_offset
         );
 
+        let dirty_rects = match cmd.dirty_rects_offset {
+            0 => None,
+            offset => {
+                check_heap_alignment("Present", offset)?;
+                let rects = read_dirty_rects(heap, offset as usize).ok_or_else(|| {
+                    warn!("Present: dirty rect list at {} exceeds heap bounds", offset);
+                    anyhow::Error::from(ProcessError::InvalidParameter(offset))
+                })?;
+                Some(
+                    rects
+                        .iter()
+                        .map(|r| RECT {
+                            left: r.left,
+                            top: r.top,
+                            right: r.right,
+                            bottom: r.bottom,
+                        })
+                        .collect(),
+                )
+            }
+        };
+
+        if self.frames_bracketed {
+            match self.open_frame.take() {
+                Some((frame_id, fence_value)) => {
+                    debug!("Present closes frame {}", frame_id);
+                    self.complete_frame_fence(fence_value);
+                    self.frame_boundary = true;
+                }
+                None => {
+                    // Without a BEGIN_FRAME the frame's commands may have
+                    // started in an earlier, already-presented frame
+                    warn!(
+                        "Present of backbuffer {} outside a frame, dropping",
+                        cmd.backbuffer_id
+                    );
+                    self.stats.dropped_frames += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        // A backbuffer still mapped would be shown half-written; the
+        // guest's Unmap comes too late for this frame
+        if let Some((_, subresource)) = self
+            .active_maps
+            .keys()
+            .find(|(id, _)| *id == cmd.backbuffer_id)
+        {
+            warn!(
+                "Present of backbuffer {} while subresource {} is still mapped, skipping",
+                cmd.backbuffer_id, subresource
+            );
+            self.stats.torn_presents += 1;
+            self.pending_torn_present = Some((cmd.backbuffer_id, *subresource));
+            return Ok(());
+        }
+
         // Store the present request - the main loop will handle actual presentation
-        self.pending_present = Some((cmd.backbuffer_id, cmd.sync_interval));
+        self.pending_present = Some((cmd.backbuffer_id, cmd.sync_interval, cmd.flags));
+        self.pending_dirty_rects = dirty_rects;
 
         // Flush to ensure all prior rendering is complete
         self.renderer.flush();
         Ok(())
     }
 
+    fn handle_begin_frame(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdBeginFrame =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdBeginFrame) };
+
+        debug!(
+            "BeginFrame: frame_id={}, fence={}",
+            cmd.frame_id, cmd.fence_value
+        );
+
+        // The previous frame never reached its present; its fence still
+        // completes so the guest does not wait on it forever
+        if let Some((frame_id, fence_value)) = self.open_frame.take() {
+            warn!(
+                "BeginFrame {}: frame {} was never presented, dropping",
+                cmd.frame_id, frame_id
+            );
+            self.complete_frame_fence(fence_value);
+            self.stats.dropped_frames += 1;
+        }
+
+        self.frames_bracketed = true;
+        self.open_frame = Some((cmd.frame_id, cmd.fence_value));
+        Ok(())
+    }
+
+    /// Complete a frame's fence, never moving the fence backwards
+    fn complete_frame_fence(&mut self, fence_value: u64) {
+        self.current_fence = self.current_fence.max(fence_value);
+    }
+
     fn handle_flush(&mut self) -> Result<()> {
         debug!("Flush");
         self.renderer.flush();
@@ -623,6 +1205,24 @@ impl CommandProcessor {
         Ok(())
     }
 
+    fn handle_set_constant_buffers(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSetConstantBuffers =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetConstantBuffers) };
+
+        if !constant_buffer_range_valid(cmd.start_slot, cmd.num_buffers) {
+            warn!(
+                "SetConstantBuffers: slots {}+{} exceed {} slots",
+                cmd.start_slot, cmd.num_buffers, PVGPU_MAX_CONSTANT_BUFFERS
+            );
+            return Err(ProcessError::InvalidParameter(cmd.num_buffers).into());
+        }
+
+        let buffer_ids = &cmd.buffer_ids[..cmd.num_buffers as usize];
+        self.renderer
+            .set_constant_buffers(cmd.stage, cmd.start_slot, buffer_ids);
+        Ok(())
+    }
+
     fn handle_set_input_layout(&mut self, data: &[u8]) -> Result<()> {
         let cmd: CmdSetInputLayout =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetInputLayout) };
@@ -635,6 +1235,11 @@ impl CommandProcessor {
         let cmd: CmdSetPrimitiveTopology =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetPrimitiveTopology) };
 
+        if !is_valid_topology(cmd.topology) {
+            warn!("SetPrimitiveTopology: unknown topology {}", cmd.topology);
+            return Err(ProcessError::InvalidParameter(cmd.topology).into());
+        }
+
         self.renderer.set_primitive_topology(cmd.topology);
         Ok(())
     }
@@ -655,15 +1260,36 @@ impl CommandProcessor {
         let cmd: CmdSetShaderResources =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetShaderResources) };
 
-        let count = (cmd.num_views as usize).min(128);
-        for i in 0..count {
-            self.renderer.set_shader_resource(
-                cmd.stage,
-                cmd.start_slot + i as u32,
-                cmd.view_ids[i],
+        // Views past the last of D3D11's 128 slots are dropped
+        let count = cmd
+            .num_views
+            .min(PVGPU_MAX_SHADER_RESOURCES.saturating_sub(cmd.start_slot));
+        let view_ids = &cmd.view_ids[..count as usize];
+        self.renderer
+            .set_shader_resources(cmd.stage, cmd.start_slot, view_ids);
+        Ok(())
+    }
+
+    fn handle_create_blend_state(&mut self, data: &[u8]) -> Result<()> {
+        // Older guests send the command without `num_targets` or the logic
+        // op; zero-fill them and treat all 8 targets as described
+        let mut bytes = [0u8; std::mem::size_of::<CmdCreateBlendState>()];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
+        let mut cmd: CmdCreateBlendState =
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const CmdCreateBlendState) };
+        if cmd.num_targets == 0 {
+            cmd.num_targets = PVGPU_MAX_BLEND_TARGETS;
+        }
+
+        if !blend_state_valid(&cmd) {
+            warn!(
+                "CreateBlendState: invalid description for id={} ({} targets, independent={}, logic_op={})",
+                cmd.state_id, cmd.num_targets, cmd.independent_blend, cmd.logic_op_enable
             );
+            return Err(ProcessError::InvalidParameter(cmd.state_id).into());
         }
-        Ok(())
+        self.renderer.create_blend_state(&cmd)
     }
 
     fn handle_set_blend_state(&mut self, data: &[u8]) -> Result<()> {
@@ -696,7 +1322,40 @@ impl CommandProcessor {
         let cmd: CmdSetScissor =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetScissor) };
 
-        let rects: Vec<RECT> = cmd.rects[..cmd.num_rects as usize]
+        if cmd.num_rects > PVGPU_MAX_VIEWPORTS {
+            warn!(
+                "SetScissor: {} rects exceeds {}, clamping",
+                cmd.num_rects, PVGPU_MAX_VIEWPORTS
+            );
+        }
+        let count = cmd.num_rects.min(PVGPU_MAX_VIEWPORTS);
+
+        if let Some(index) = cmd.rects[..count as usize]
+            .iter()
+            .position(|r| r.is_empty())
+        {
+            warn!(
+                "SetScissor: rect {} is empty: {:?}",
+                index, cmd.rects[index]
+            );
+            return Err(ProcessError::InvalidParameter(index as u32).into());
+        }
+
+        // Each scissor rect applies to the viewport with the same index
+        if self.renderer.scissor_enabled() {
+            let viewports = self.renderer.viewport_count();
+            if count > viewports {
+                warn!(
+                    "SetScissor: {} rects but only {} viewports bound",
+                    count, viewports
+                );
+                return Err(ProcessError::InvalidParameter(count).into());
+            }
+        } else if count > 0 {
+            debug!("SetScissor: rects set while scissor test is disabled");
+        }
+
+        let rects: Vec<RECT> = cmd.rects[..count as usize]
             .iter()
             .map(|r| RECT {
                 left: r.left,
@@ -737,16 +1396,38 @@ impl CommandProcessor {
         Ok(())
     }
 
+    fn handle_draw_instanced_indirect(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdDrawIndirect =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDrawIndirect) };
+
+        self.renderer
+            .draw_instanced_indirect(cmd.args_buffer_id, cmd.args_offset)
+    }
+
+    fn handle_draw_indexed_instanced_indirect(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdDrawIndirect =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDrawIndirect) };
+
+        self.renderer
+            .draw_indexed_instanced_indirect(cmd.args_buffer_id, cmd.args_offset)
+    }
+
     fn handle_dispatch(&mut self, data: &[u8]) -> Result<()> {
         let cmd: CmdDispatch =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDispatch) };
 
+        // The UMD encodes DispatchIndirect as (buffer id, offset, sentinel)
+        if cmd.thread_group_count_z == PVGPU_DISPATCH_INDIRECT {
+            return self
+                .renderer
+                .dispatch_indirect(cmd.thread_group_count_x, cmd.thread_group_count_y);
+        }
+
         self.renderer.dispatch(
             cmd.thread_group_count_x,
             cmd.thread_group_count_y,
             cmd.thread_group_count_z,
-        );
-        Ok(())
+        )
     }
 
     fn handle_clear_depth_stencil(&mut self, data: &[u8]) -> Result<()> {
@@ -763,8 +1444,27 @@ impl CommandProcessor {
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdCopyResource) };
 
         self.renderer
-            .copy_resource(cmd.dst_resource_id, cmd.src_resource_id);
-        Ok(())
+            .copy_resource(cmd.dst_resource_id, cmd.src_resource_id)
+    }
+
+    fn handle_copy_structure_count(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdCopyStructureCount =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdCopyStructureCount) };
+
+        self.renderer
+            .copy_structure_count(cmd.dst_buffer_id, cmd.dst_offset, cmd.src_uav_id)
+    }
+
+    fn handle_create_unordered_access_view(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdCreateUnorderedAccessView = unsafe {
+            std::ptr::read_unaligned(data.as_ptr() as *const CmdCreateUnorderedAccessView)
+        };
+
+        debug!(
+            "CreateUnorderedAccessView: id={}, resource={}, dimension={}, format={}",
+            cmd.view_id, cmd.resource_id, cmd.view_dimension, cmd.format
+        );
+        self.renderer.create_unordered_access_view(&cmd)
     }
 
     fn handle_create_shader(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
@@ -777,6 +1477,10 @@ impl CommandProcessor {
         );
 
         let shader_id = cmd.shader_id;
+        let stage = ShaderStage::try_from(cmd.shader_type).map_err(|value| {
+            warn!("CreateShader: unknown shader type {}", value);
+            ProcessError::UnknownType(value)
+        })?;
 
         let offset = cmd.bytecode_offset as usize;
         let size = cmd.bytecode_size as usize;
@@ -786,6 +1490,8 @@ impl CommandProcessor {
             return Ok(());
         }
 
+        check_heap_alignment("CreateShader", cmd.bytecode_offset)?;
+
         if offset + size > heap.len() {
             return Err(anyhow::anyhow!(
                 "CreateShader: bytecode_offset + bytecode_size exceeds heap bounds"
@@ -794,39 +1500,58 @@ impl CommandProcessor {
 
         let bytecode = &heap[offset..offset + size];
 
-        match cmd.shader_type {
-            0 => {
-                self.renderer.create_vertex_shader(shader_id, bytecode)?;
-            }
-            1 => {
-                self.renderer.create_pixel_shader(shader_id, bytecode)?;
-            }
-            2 => {
-                self.renderer.create_geometry_shader(shader_id, bytecode)?;
-            }
-            3 => {
-                self.renderer.create_hull_shader(shader_id, bytecode)?;
-            }
-            4 => {
-                self.renderer.create_domain_shader(shader_id, bytecode)?;
-            }
-            5 => {
-                self.renderer.create_compute_shader(shader_id, bytecode)?;
-            }
-            _ => {
-                warn!("CreateShader: unknown shader type {}", cmd.shader_type);
-            }
+        let started = Instant::now();
+        if let Err(e) = self.renderer.create_shader(shader_id, stage, bytecode) {
+            warn!(
+                "{:?} shader creation failed for id={}: {}",
+                stage, shader_id, e
+            );
+            return Err(ProcessError::ShaderCompile(shader_id).into());
         }
 
+        self.record_creation(started, || {
+            format!(
+                "CreateShader: id={}, type={}, bytecode_size={}",
+                shader_id, cmd.shader_type, cmd.bytecode_size
+            )
+        });
         Ok(())
     }
 
+    fn handle_replace_shader(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        let cmd: CmdReplaceShader =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdReplaceShader) };
+
+        debug!(
+            "ReplaceShader: id={}, type={}, bytecode_size={}, bytecode_offset={}",
+            cmd.shader_id, cmd.shader_type, cmd.bytecode_size, cmd.bytecode_offset
+        );
+
+        check_heap_alignment("ReplaceShader", cmd.bytecode_offset)?;
+
+        let offset = cmd.bytecode_offset as usize;
+        let bytecode = match offset.checked_add(cmd.bytecode_size as usize) {
+            Some(end) if cmd.bytecode_size > 0 && end <= heap.len() => &heap[offset..end],
+            _ => {
+                warn!(
+                    "ReplaceShader: invalid bytecode range: offset={}, size={}",
+                    cmd.bytecode_offset, cmd.bytecode_size
+                );
+                return Err(ProcessError::InvalidParameter(cmd.bytecode_offset).into());
+            }
+        };
+
+        self.renderer
+            .replace_shader(cmd.shader_id, cmd.shader_type, bytecode, self.current_fence)
+    }
+
     fn handle_destroy_shader(&mut self, data: &[u8]) -> Result<()> {
         let cmd: CmdDestroyShader =
             unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDestroyShader) };
 
         debug!("DestroyShader: id={}", cmd.shader_id);
-        self.renderer.destroy_resource(cmd.shader_id);
+        self.renderer
+            .destroy_resource(cmd.shader_id, self.current_fence);
         Ok(())
     }
 
@@ -835,6 +1560,28 @@ impl CommandProcessor {
         self.current_fence
     }
 
+    /// Highest fence safe to publish as completed. With a GPU fence this
+    /// trails `current_fence` until the GPU finishes the signaled work;
+    /// otherwise fences complete as their commands are consumed.
+    pub fn completed_fence(&self) -> u64 {
+        match self.renderer.completed_fence() {
+            Some(completed) => completed.min(self.current_fence),
+            None => self.current_fence,
+        }
+    }
+
+    /// Signal the GPU fence with the current fence value, after a batch
+    pub fn signal_fence(&mut self) {
+        self.renderer.signal_fence(self.current_fence);
+    }
+
+    /// Whether the last command asked not to publish the fence; it is
+    /// published after the next command without PVGPU_CMD_FLAG_NO_FENCE,
+    /// or at the end of the batch
+    pub fn fence_suppressed(&self) -> bool {
+        self.fence_suppressed
+    }
+
     /// Get a reference to the renderer
     pub fn renderer(&self) -> &D3D11Renderer {
         &self.renderer
@@ -850,12 +1597,117 @@ impl CommandProcessor {
         self.pending_present.is_some()
     }
 
-    /// Take the pending present info (backbuffer_id, sync_interval)
+    /// Take the pending present info (backbuffer_id, sync_interval, flags)
     /// Returns None if no present is pending
-    pub fn take_pending_present(&mut self) -> Option<(u32, u32)> {
+    pub fn take_pending_present(&mut self) -> Option<(u32, u32, u32)> {
         self.pending_present.take()
     }
 
+    /// Take the dirty rects of the last present.
+    /// Returns None if the whole frame should be presented
+    pub fn take_pending_dirty_rects(&mut self) -> Option<Vec<RECT>> {
+        self.pending_dirty_rects.take()
+    }
+
+    /// Take the flag set when a PRESENT closed a bracketed frame. The main
+    /// loop presents before consuming any of the next frame's commands.
+    pub fn take_frame_boundary(&mut self) -> bool {
+        std::mem::take(&mut self.frame_boundary)
+    }
+
+    /// Take the frame dump request set by DUMP_FRAMES
+    pub fn take_pending_frame_dump(&mut self) -> bool {
+        std::mem::take(&mut self.pending_frame_dump)
+    }
+
+    /// Take the pending self-test result (heap offset, result code)
+    /// Returns None if no self-test ran
+    pub fn take_pending_selftest(&mut self) -> Option<(u32, u32)> {
+        self.pending_selftest.take()
+    }
+
+    /// Take the log level requested by SET_LOG_LEVEL, if any
+    pub fn take_pending_log_level(&mut self) -> Option<Level> {
+        self.pending_log_level.take()
+    }
+
+    /// Take the MSAA query result to write to the heap, if any
+    pub fn take_pending_msaa_support(&mut self) -> Option<(u32, MsaaSupport)> {
+        self.pending_msaa_support.take()
+    }
+
+    /// Take the format query result to publish, if any
+    pub fn take_pending_format_support(&mut self) -> Option<(u32, u32)> {
+        self.pending_format_support.take()
+    }
+
+    /// Take the present skipped because its backbuffer was still mapped
+    /// (backbuffer ID, subresource), if any
+    pub fn take_pending_torn_present(&mut self) -> Option<(u32, u32)> {
+        self.pending_torn_present.take()
+    }
+
+    /// Take the pitch of the last map that asked for it (heap offset, pitch)
+    pub fn take_pending_map_pitch(&mut self) -> Option<(u32, MapPitch)> {
+        self.pending_map_pitch.take()
+    }
+
+    /// Take the heap offset and data of a pending read map
+    pub fn take_pending_map_readback(&mut self) -> Option<(u32, Vec<u8>)> {
+        self.pending_map_readback.take()
+    }
+
+    /// Whether SELECT_ADAPTER is waiting for the device to be recreated;
+    /// no later command may run on the old device
+    pub fn adapter_switch_pending(&self) -> bool {
+        self.pending_adapter_switch.is_some()
+    }
+
+    /// Take the adapter switch requested by SELECT_ADAPTER
+    /// (adapter index, ack fence)
+    pub fn take_pending_adapter_switch(&mut self) -> Option<(u32, u64)> {
+        self.pending_adapter_switch.take()
+    }
+
+    /// Whether SHUTDOWN was received; no later command may be consumed
+    pub fn shutdown_pending(&self) -> bool {
+        self.pending_shutdown
+    }
+
+    /// Signal the current fence and wait (up to GPU_WAIT_TIMEOUT) for the
+    /// GPU to pass it
+    pub fn wait_idle(&mut self) {
+        self.renderer.signal_fence(self.current_fence);
+        if let Err(e) = self
+            .renderer
+            .wait_for_fence(self.current_fence, GPU_WAIT_TIMEOUT)
+        {
+            warn!("Waiting for the GPU to go idle: {}", e);
+        }
+    }
+
+    /// Restart fence numbering at `fence` on a freshly created device
+    pub fn restart_fence(&mut self, fence: u64) {
+        self.current_fence = fence;
+        self.renderer.restart_gpu_fence(fence);
+    }
+
+    /// Take the device-reset flag set by RESET_DEVICE
+    pub fn take_device_reset(&mut self) -> bool {
+        std::mem::take(&mut self.device_reset)
+    }
+
+    /// Take the pending fence query heap offset
+    /// Returns None if no query is pending
+    pub fn take_pending_fence_query(&mut self) -> Option<u32> {
+        self.pending_fence_query.take()
+    }
+
+    /// Take the heap offset of a pending device status query
+    pub fn take_pending_status_query(&mut self) -> Option<u32> {
+        self.pending_status_query.take()
+    }
+
     /// Check if a resize is pending
     pub fn has_pending_resize(&self) -> bool {
         self.pending_resize.is_some()
@@ -887,6 +1739,139 @@ impl CommandProcessor {
         Ok(())
     }
 
+    /// Force-unmap maps the guest has held open longer than `max_age`.
+    ///
+    /// A guest that never sends Unmap would otherwise pin the staging
+    /// copies forever. Pending writes are discarded, not copied back.
+    /// Returns the number of maps reclaimed.
+    pub fn sweep_stale_maps(&mut self, max_age: std::time::Duration) -> usize {
+        let stale: Vec<(u32, u32)> = self
+            .active_maps
+            .iter()
+            .filter(|(_, (map_result, _))| map_result.age() > max_age)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in &stale {
+            if let Some((map_result, _)) = self.active_maps.remove(key) {
+                warn!(
+                    "Reclaiming leaked map: resource {} subresource {} open for {:?} ({} bytes)",
+                    key.0,
+                    key.1,
+                    map_result.age(),
+                    map_result.size
+                );
+                self.renderer.unmap_resource(map_result, false);
+            }
+        }
+        self.stats.leaked_maps += stale.len() as u64;
+        stale.len()
+    }
+
+    /// Take the pending display mode change
+    /// Returns None if no change is pending
+    pub fn take_pending_display_config(&mut self) -> Option<DisplayConfig> {
+        self.pending_display_config.take()
+    }
+
+    fn handle_set_display_config(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSetDisplayConfig =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetDisplayConfig) };
+
+        debug!(
+            "SetDisplayConfig: {}x{}@{}Hz, format={}",
+            cmd.width, cmd.height, cmd.refresh_rate, cmd.format
+        );
+
+        let mode = DisplayConfig {
+            width: cmd.width,
+            height: cmd.height,
+            refresh_rate: cmd.refresh_rate,
+            format: DXGI_FORMAT(cmd.format as i32),
+        };
+        if let Err(reason) = validate_display_config(&mode) {
+            warn!(
+                "SetDisplayConfig rejected ({}): {}x{}@{}Hz, format={}",
+                reason, cmd.width, cmd.height, cmd.refresh_rate, cmd.format
+            );
+            return Err(ProcessError::InvalidParameter(
+                (cmd.width & 0xFFFF) | ((cmd.height & 0xFFFF) << 16),
+            )
+            .into());
+        }
+
+        // Supersedes any plain resize queued earlier in the same batch
+        self.pending_resize = None;
+        self.pending_display_config = Some(mode);
+
+        // Flush so prior rendering completes against the old buffers
+        self.renderer.flush();
+        Ok(())
+    }
+
+    fn handle_set_frame_latency(&mut self, data: &[u8]) -> Result<()> {
+        let cmd: CmdSetFrameLatency =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdSetFrameLatency) };
+
+        debug!("SetFrameLatency: {}", cmd.max_frame_latency);
+
+        self.renderer.set_max_frame_latency(cmd.max_frame_latency)
+    }
+
+    /// Read a debug label from the heap. Labels are UTF-8 and may carry a
+    /// trailing NUL; out-of-bounds labels are replaced with a placeholder.
+    fn read_debug_label(cmd: &CmdDebugEvent, heap: &[u8]) -> String {
+        let offset = cmd.label_offset as usize;
+        let size = cmd.label_size as usize;
+        match offset.checked_add(size) {
+            Some(end) if end <= heap.len() => String::from_utf8_lossy(&heap[offset..end])
+                .trim_end_matches('\0')
+                .to_string(),
+            _ => {
+                warn!(
+                    "Debug label out of heap bounds: offset={}, size={}",
+                    offset, size
+                );
+                "<invalid label>".to_string()
+            }
+        }
+    }
+
+    fn handle_begin_event(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        let cmd: CmdDebugEvent =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDebugEvent) };
+
+        let label = Self::read_debug_label(&cmd, heap);
+        debug!("BeginEvent: {}", label);
+        self.renderer.begin_event(&label);
+        Ok(())
+    }
+
+    fn handle_end_event(&mut self) -> Result<()> {
+        debug!("EndEvent");
+        self.renderer.end_event();
+        Ok(())
+    }
+
+    fn handle_set_marker(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        let cmd: CmdDebugEvent =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDebugEvent) };
+
+        let label = Self::read_debug_label(&cmd, heap);
+        debug!("SetMarker: {}", label);
+        self.renderer.set_marker(&label);
+        Ok(())
+    }
+
+    fn handle_set_debug_name(&mut self, data: &[u8], heap: &[u8]) -> Result<()> {
+        let cmd: CmdDebugEvent =
+            unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CmdDebugEvent) };
+
+        let name = Self::read_debug_label(&cmd, heap);
+        debug!("SetDebugName: id={}, name={}", cmd.header.resource_id, name);
+        self.renderer.set_debug_name(cmd.header.resource_id, &name)
+    }
+
     /// Get a reference to the processing statistics
     pub fn stats(&self) -> &CommandProcessorStats {
         &self.stats
@@ -894,20 +1879,85 @@ impl CommandProcessor {
 
     /// Log and reset statistics
     pub fn log_and_reset_stats(&mut self) {
+        self.stats.redundant_binds += self.renderer.take_redundant_binds();
+        self.stats.deduped_uploads += self.renderer.take_deduped_uploads();
+        self.stats.shader_cache_hits += self.renderer.take_shader_cache_hits();
         info!(
-            "CommandProcessor stats: commands={}, draws={}, presents={}, resources_created={}, resources_destroyed={}, errors={}",
+            "CommandProcessor stats: commands={}, draws={}, presents={}, resources_created={}, resources_destroyed={}, errors={}, unknown_commands={}, leaked_maps={}, dropped_frames={}, dropped_presents={}, torn_presents={}, redundant_binds={}, deduped_uploads={}, shader_cache_hits={}, creation_ms={:.1}, slow_creations={}",
             self.stats.commands_processed,
             self.stats.draw_calls,
             self.stats.presents,
             self.stats.resources_created,
             self.stats.resources_destroyed,
-            self.stats.errors
+            self.stats.errors,
+            self.stats.unknown_commands,
+            self.stats.leaked_maps,
+            self.stats.dropped_frames,
+            self.stats.dropped_presents,
+            self.stats.torn_presents,
+            self.stats.redundant_binds,
+            self.stats.deduped_uploads,
+            self.stats.shader_cache_hits,
+            self.stats.creation_time.as_secs_f64() * 1000.0,
+            self.stats.slow_creations
         );
         self.stats = CommandProcessorStats::default();
     }
 
+    /// Log resource and shader creations taking at least `threshold`
+    /// (None = never)
+    pub fn set_slow_create_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_create_threshold = threshold;
+    }
+
+    /// Let SET_LOG_LEVEL change the process-wide log level
+    pub fn set_guest_log_level(&mut self, enabled: bool) {
+        self.guest_log_level = enabled;
+    }
+
+    /// Add a creation's duration to the stats, logging `describe()` when it
+    /// reached the slow-creation threshold
+    fn record_creation(&mut self, started: Instant, describe: impl FnOnce() -> String) {
+        let elapsed = started.elapsed();
+        self.stats.creation_time += elapsed;
+        if self
+            .slow_create_threshold
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            self.stats.slow_creations += 1;
+            warn!(
+                "Slow creation ({:.2}ms): {}",
+                elapsed.as_secs_f64() * 1000.0,
+                describe()
+            );
+        }
+    }
+
     /// Increment error counter
     fn record_error(&mut self) {
         self.stats.errors += 1;
     }
+
+    /// Count a present superseded by a later one in the same batch
+    pub fn record_dropped_present(&mut self) {
+        self.stats.dropped_presents += 1;
+    }
+
+    /// Increment unknown command counter
+    pub fn record_unknown_command(&mut self) {
+        self.stats.unknown_commands += 1;
+    }
+}
+
+/// Reject a bulk-data heap offset that breaks `PVGPU_HEAP_ALIGNMENT`
+fn check_heap_alignment(command: &str, offset: u32) -> Result<()> {
+    if is_heap_aligned(offset as usize) {
+        Ok(())
+    } else {
+        warn!(
+            "{}: heap offset {} is not {}-byte aligned",
+            command, offset, PVGPU_HEAP_ALIGNMENT
+        );
+        Err(ProcessError::InvalidParameter(offset).into())
+    }
 }
@@ -7,6 +7,8 @@ use std::path::Path;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::protocol::PVGPU_DEFAULT_SHMEM_SIZE;
+
 /// Backend configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -14,6 +16,11 @@ pub struct Config {
     #[serde(default = "default_pipe_path")]
     pub pipe_path: String,
 
+    /// Guests served by this process, each with its own pipe, device and
+    /// command thread
+    #[serde(default = "default_instances")]
+    pub instances: u32,
+
     /// Shared memory file path (Windows file mapping)
     #[serde(default)]
     pub shmem_path: Option<String>,
@@ -22,10 +29,49 @@ pub struct Config {
     #[serde(default)]
     pub adapter_index: u32,
 
+    /// Pick the adapter by power preference instead of adapter_index:
+    /// "high_performance", "minimum_power" or "unspecified"
+    #[serde(default)]
+    pub gpu_preference: Option<String>,
+
+    /// Adapter the D3D11 device renders on (defaults to adapter_index)
+    #[serde(default)]
+    pub render_adapter: Option<u32>,
+
+    /// Adapter the output is presented on (defaults to the render adapter)
+    #[serde(default)]
+    pub present_adapter: Option<u32>,
+
+    /// Fail to start instead of falling back to adapter 0 when a configured
+    /// adapter does not exist
+    #[serde(default)]
+    pub strict_adapter: bool,
+
+    /// Adapters the render adapter is picked from at startup, replacing
+    /// adapter_index and gpu_preference (empty = disabled)
+    #[serde(default)]
+    pub adapter_pool: Vec<u32>,
+
+    /// How `adapter_pool` is used: "least_loaded" or "round_robin"
+    #[serde(default = "default_adapter_policy")]
+    pub adapter_policy: String,
+
     /// Presentation mode: "headless", "windowed", "dual"
     #[serde(default = "default_presentation_mode")]
     pub presentation_mode: String,
 
+    /// Outputs presents write to in dual mode: "window", "shared" or "both"
+    #[serde(default = "default_present_targets")]
+    pub present_targets: String,
+
+    /// Window swapchain backend: "hwnd" or "dcomp" (DirectComposition)
+    #[serde(default = "default_present_backend")]
+    pub present_backend: String,
+
+    /// Let frame alpha show the desktop through the window ("dcomp" only)
+    #[serde(default)]
+    pub transparent_window: bool,
+
     /// Initial display width
     #[serde(default = "default_width")]
     pub width: u32,
@@ -41,16 +87,170 @@ pub struct Config {
     /// Number of frame buffers (2 or 3)
     #[serde(default = "default_buffer_count")]
     pub buffer_count: u32,
+
+    /// Switch between 2 and 3 buffers at runtime based on frame pacing
+    #[serde(default)]
+    pub adaptive_buffering: bool,
+
+    /// Milliseconds between visibility checks while the output window is
+    /// occluded; frames are skipped meanwhile
+    #[serde(default = "default_occlusion_poll_ms")]
+    pub occlusion_poll_ms: u64,
+
+    /// Recreate the swapchain when monitors are plugged, unplugged or
+    /// rearranged
+    #[serde(default = "default_display_change_recovery")]
+    pub display_change_recovery: bool,
+
+    /// Host owns the backbuffer; the guest renders into it directly
+    #[serde(default)]
+    pub host_backbuffer: bool,
+
+    /// Sample count of the host backbuffer (1 = no MSAA); needs `host_backbuffer`
+    #[serde(default = "default_swapchain_msaa")]
+    pub swapchain_msaa: u32,
+
+    /// Create the backbuffer render target view with an `_SRGB` format
+    #[serde(default)]
+    pub srgb_backbuffer: bool,
+
+    /// Copy each presented frame so the guest can sample it while rendering
+    /// the next one
+    #[serde(default)]
+    pub previous_frame: bool,
+
+    /// Zero destroyed resources and the heap on teardown (costs performance)
+    #[serde(default)]
+    pub scrub_on_destroy: bool,
+
+    /// Share one GPU resource between immutable textures and buffers
+    /// created from identical data
+    #[serde(default)]
+    pub dedup_uploads: bool,
+
+    /// Read back indirect draw/dispatch args and reject absurd counts
+    /// before issuing them (costs a GPU readback per indirect call)
+    #[serde(default)]
+    pub validate_indirect_args: bool,
+
+    /// Reject dispatches launching more than 4M thread groups in total,
+    /// on top of the D3D11 per-dimension limit
+    #[serde(default)]
+    pub strict_dispatch_limits: bool,
+
+    /// Frames the driver may queue ahead of the GPU (1-16, 0 = driver
+    /// default of 3); lower values reduce input latency
+    #[serde(default)]
+    pub max_frame_latency: u32,
+
+    /// Number of recent frames kept for crash dumps (0 = disabled)
+    #[serde(default)]
+    pub crash_frame_ring: u32,
+
+    /// Directory crash frame dumps are written to
+    #[serde(default = "default_crash_dump_dir")]
+    pub crash_dump_dir: String,
+
+    /// Seconds a guest map may stay open before it is force-unmapped
+    /// (0 = never)
+    #[serde(default = "default_map_timeout_secs")]
+    pub map_timeout_secs: u64,
+
+    /// Percent of the DXGI video memory budget at which
+    /// PVGPU_STATUS_MEMORY_PRESSURE is set (0 = never)
+    #[serde(default = "default_vram_warning_percent")]
+    pub vram_warning_percent: u32,
+
+    /// RGBA color (0.0-1.0) around frames smaller than the output
+    #[serde(default = "default_letterbox_color")]
+    pub letterbox_color: [f32; 4],
+
+    /// Log resource and shader creations taking at least this many
+    /// milliseconds (0 = never)
+    #[serde(default = "default_slow_create_ms")]
+    pub slow_create_ms: u64,
+
+    /// Milliseconds one command or present may run before the GPU is
+    /// reported lost and the device recreated (0 = no watchdog)
+    #[serde(default = "default_gpu_watchdog_ms")]
+    pub gpu_watchdog_ms: u64,
+
+    /// Microseconds of command processing per batch before window messages
+    /// and presents are handled (0 = process everything pending)
+    #[serde(default = "default_batch_budget_us")]
+    pub batch_budget_us: u64,
+
+    /// Seconds between stats reports in the log (0 = never)
+    #[serde(default)]
+    pub stats_interval_secs: u64,
+
+    /// Maximum level logged at startup ("error" .. "trace")
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+
+    /// Also log to this file, rolled over daily; unset = console only
+    #[serde(default)]
+    pub log_file: Option<String>,
+
+    /// Let the guest change the log level with CMD_SET_LOG_LEVEL; the
+    /// level is shared by every instance in the process
+    #[serde(default)]
+    pub allow_guest_log_level: bool,
+
+    /// Process priority class ("idle" .. "realtime"); unset = unchanged
+    #[serde(default)]
+    pub process_priority: Option<String>,
+
+    /// Command-processing thread priority ("idle" .. "time_critical")
+    #[serde(default)]
+    pub main_thread_priority: Option<String>,
+
+    /// Command-processing thread CPU affinity mask
+    #[serde(default)]
+    pub main_thread_affinity: Option<u64>,
+
+    /// Pipe-reader thread priority ("idle" .. "time_critical")
+    #[serde(default)]
+    pub pipe_thread_priority: Option<String>,
+
+    /// Pipe-reader thread CPU affinity mask
+    #[serde(default)]
+    pub pipe_thread_affinity: Option<u64>,
+
+    /// Largest shared memory region accepted at handshake, in bytes
+    #[serde(default = "default_max_shmem_size")]
+    pub max_shmem_size: u64,
+
+    /// Shared memory size suggested to QEMU in the handshake reply, in
+    /// bytes; capped at `max_shmem_size`
+    #[serde(default = "default_preferred_shmem_size")]
+    pub preferred_shmem_size: u64,
 }
 
 fn default_pipe_path() -> String {
     r"\\.\pipe\pvgpu".to_string()
 }
 
+fn default_instances() -> u32 {
+    1
+}
+
+fn default_adapter_policy() -> String {
+    "least_loaded".to_string()
+}
+
 fn default_presentation_mode() -> String {
     "headless".to_string()
 }
 
+fn default_present_targets() -> String {
+    "both".to_string()
+}
+
+fn default_present_backend() -> String {
+    "hwnd".to_string()
+}
+
 fn default_width() -> u32 {
     1920
 }
@@ -67,17 +267,110 @@ fn default_buffer_count() -> u32 {
     2
 }
 
+fn default_swapchain_msaa() -> u32 {
+    1
+}
+
+fn default_crash_dump_dir() -> String {
+    "pvgpu_crash_frames".to_string()
+}
+
+fn default_occlusion_poll_ms() -> u64 {
+    100
+}
+
+fn default_display_change_recovery() -> bool {
+    true
+}
+
+fn default_map_timeout_secs() -> u64 {
+    30
+}
+
+fn default_vram_warning_percent() -> u32 {
+    90
+}
+
+fn default_letterbox_color() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+fn default_slow_create_ms() -> u64 {
+    5
+}
+
+fn default_gpu_watchdog_ms() -> u64 {
+    5000
+}
+
+fn default_batch_budget_us() -> u64 {
+    2000
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_max_shmem_size() -> u64 {
+    1024 * 1024 * 1024 // 1GB
+}
+
+fn default_preferred_shmem_size() -> u64 {
+    PVGPU_DEFAULT_SHMEM_SIZE as u64
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             pipe_path: default_pipe_path(),
+            instances: default_instances(),
             shmem_path: None,
             adapter_index: 0,
+            gpu_preference: None,
+            render_adapter: None,
+            present_adapter: None,
+            strict_adapter: false,
+            adapter_pool: Vec::new(),
+            adapter_policy: default_adapter_policy(),
             presentation_mode: default_presentation_mode(),
+            present_targets: default_present_targets(),
+            present_backend: default_present_backend(),
+            transparent_window: false,
             width: default_width(),
             height: default_height(),
             vsync: default_vsync(),
             buffer_count: default_buffer_count(),
+            adaptive_buffering: false,
+            occlusion_poll_ms: default_occlusion_poll_ms(),
+            display_change_recovery: default_display_change_recovery(),
+            host_backbuffer: false,
+            swapchain_msaa: default_swapchain_msaa(),
+            srgb_backbuffer: false,
+            previous_frame: false,
+            scrub_on_destroy: false,
+            dedup_uploads: false,
+            validate_indirect_args: false,
+            strict_dispatch_limits: false,
+            max_frame_latency: 0,
+            crash_frame_ring: 0,
+            crash_dump_dir: default_crash_dump_dir(),
+            map_timeout_secs: default_map_timeout_secs(),
+            vram_warning_percent: default_vram_warning_percent(),
+            letterbox_color: default_letterbox_color(),
+            slow_create_ms: default_slow_create_ms(),
+            gpu_watchdog_ms: default_gpu_watchdog_ms(),
+            batch_budget_us: default_batch_budget_us(),
+            stats_interval_secs: 0,
+            log_level: default_log_level(),
+            log_file: None,
+            allow_guest_log_level: false,
+            process_priority: None,
+            main_thread_priority: None,
+            main_thread_affinity: None,
+            pipe_thread_priority: None,
+            pipe_thread_affinity: None,
+            max_shmem_size: default_max_shmem_size(),
+            preferred_shmem_size: default_preferred_shmem_size(),
         }
     }
 }
@@ -90,6 +383,38 @@ impl Config {
         Ok(config)
     }
 
+    /// Adapter index the renderer is created on.
+    pub fn render_adapter_index(&self) -> u32 {
+        self.render_adapter.unwrap_or(self.adapter_index)
+    }
+
+    /// Adapter index frames are presented on, given the adapter the
+    /// renderer was created on.
+    pub fn present_adapter_index(&self, render_adapter: u32) -> u32 {
+        self.present_adapter.unwrap_or(render_adapter)
+    }
+
+    /// Suffix that keeps instance `index`'s names apart: none for instance 0,
+    /// so a single instance keeps the configured names, `_<index>` otherwise
+    pub fn instance_suffix(index: u32) -> String {
+        match index {
+            0 => String::new(),
+            index => format!("_{}", index),
+        }
+    }
+
+    /// Configuration for instance `index`, with its pipe path and crash dump
+    /// directory made unique.
+    pub fn for_instance(&self, index: u32) -> Config {
+        let suffix = Self::instance_suffix(index);
+        Config {
+            pipe_path: format!("{}{}", self.pipe_path, suffix),
+            crash_dump_dir: format!("{}{}", self.crash_dump_dir, suffix),
+            instances: 1,
+            ..self.clone()
+        }
+    }
+
     /// Save configuration to a TOML file.
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let content = toml::to_string_pretty(self)?;
@@ -0,0 +1,233 @@
+//! Cross-Adapter Module
+//!
+//! Moves finished frames from the render device to a presentation device on
+//! a different GPU adapter (e.g. render on a dGPU, display wired to the iGPU).
+//!
+//! The fast path shares a keyed-mutex texture between the two devices via an
+//! NT handle. When the driver cannot open the resource on the other adapter,
+//! frames are read back through a staging texture and uploaded instead.
+
+use anyhow::{anyhow, Result};
+use tracing::{debug, info, warn};
+use windows::core::Interface;
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11Device1, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+    D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ,
+    D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX, D3D11_RESOURCE_MISC_SHARED_NTHANDLE,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::Common::DXGI_SAMPLE_DESC;
+use windows::Win32::Graphics::Dxgi::{IDXGIDevice, IDXGIKeyedMutex, IDXGIResource1};
+
+/// Keyed mutex key held while the render device writes the shared texture
+const KEY_RENDER: u64 = 0;
+/// Keyed mutex key held while the present device reads the shared texture
+const KEY_PRESENT: u64 = 1;
+/// Wait forever; both sides are driven from the same thread
+const INFINITE: u32 = 0xFFFF_FFFF;
+
+/// LUID of the adapter a device was created on
+pub fn adapter_luid(device: &ID3D11Device) -> Result<u64> {
+    let dxgi_device: IDXGIDevice = device.cast()?;
+    let adapter = unsafe { dxgi_device.GetAdapter()? };
+    let desc = unsafe { adapter.GetDesc()? };
+    Ok(((desc.AdapterLuid.HighPart as u64) << 32) | (desc.AdapterLuid.LowPart as u64))
+}
+
+/// How frames cross from the render adapter to the present adapter
+enum Transport {
+    /// Keyed-mutex texture opened on both devices
+    Shared {
+        render_side: ID3D11Texture2D,
+        present_side: ID3D11Texture2D,
+        render_mutex: IDXGIKeyedMutex,
+        present_mutex: IDXGIKeyedMutex,
+    },
+    /// CPU readback through a staging texture on the render device
+    Staging { staging: ID3D11Texture2D },
+}
+
+/// Copies frames from a render device to a presentation device on another
+/// adapter. Recreated whenever the frame size or format changes.
+pub struct CrossAdapterBridge {
+    render_context: ID3D11DeviceContext,
+    transport: Transport,
+    /// Present-device copy of the latest frame, handed to the swapchain path
+    output: ID3D11Texture2D,
+    desc: D3D11_TEXTURE2D_DESC,
+}
+
+impl CrossAdapterBridge {
+    /// Create a bridge sized for frames described by `frame_desc`
+    pub fn new(
+        render_device: &ID3D11Device,
+        render_context: &ID3D11DeviceContext,
+        present_device: &ID3D11Device,
+        frame_desc: &D3D11_TEXTURE2D_DESC,
+    ) -> Result<Self> {
+        let base = D3D11_TEXTURE2D_DESC {
+            Width: frame_desc.Width,
+            Height: frame_desc.Height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: frame_desc.Format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: (D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0) as u32,
+            CPUAccessFlags: Default::default(),
+            MiscFlags: Default::default(),
+        };
+
+        let mut output: Option<ID3D11Texture2D> = None;
+        unsafe {
+            present_device.CreateTexture2D(&base, None, Some(&mut output))?;
+        }
+        let output = output.ok_or_else(|| anyhow!("Failed to create cross-adapter output"))?;
+
+        let transport = match Self::create_shared(render_device, present_device, &base) {
+            Ok(transport) => {
+                info!(
+                    "Cross-adapter bridge: shared texture {}x{}",
+                    base.Width, base.Height
+                );
+                transport
+            }
+            Err(e) => {
+                warn!(
+                    "Cross-adapter sharing unavailable ({}), falling back to CPU readback",
+                    e
+                );
+                Self::create_staging(render_device, &base)?
+            }
+        };
+
+        Ok(Self {
+            render_context: render_context.clone(),
+            transport,
+            output,
+            desc: base,
+        })
+    }
+
+    /// Create a keyed-mutex texture on the render device and open it on the
+    /// present device
+    fn create_shared(
+        render_device: &ID3D11Device,
+        present_device: &ID3D11Device,
+        base: &D3D11_TEXTURE2D_DESC,
+    ) -> Result<Transport> {
+        let desc = D3D11_TEXTURE2D_DESC {
+            MiscFlags: (D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX.0
+                | D3D11_RESOURCE_MISC_SHARED_NTHANDLE.0) as u32,
+            ..*base
+        };
+
+        let mut render_side: Option<ID3D11Texture2D> = None;
+        unsafe {
+            render_device.CreateTexture2D(&desc, None, Some(&mut render_side))?;
+        }
+        let render_side =
+            render_side.ok_or_else(|| anyhow!("Failed to create cross-adapter texture"))?;
+
+        let dxgi_resource: IDXGIResource1 = render_side.cast()?;
+        let handle = unsafe {
+            dxgi_resource.CreateSharedHandle(
+                None,
+                windows::Win32::Storage::FileSystem::FILE_GENERIC_READ.0
+                    | windows::Win32::Storage::FileSystem::FILE_GENERIC_WRITE.0,
+                None,
+            )?
+        };
+
+        let present_device1: ID3D11Device1 = present_device.cast()?;
+        let opened: windows::core::Result<ID3D11Texture2D> =
+            unsafe { present_device1.OpenSharedResource1(handle) };
+        unsafe {
+            let _ = CloseHandle(handle);
+        }
+        let present_side = opened?;
+
+        Ok(Transport::Shared {
+            render_mutex: render_side.cast()?,
+            present_mutex: present_side.cast()?,
+            render_side,
+            present_side,
+        })
+    }
+
+    /// Create a CPU-readable staging texture on the render device
+    fn create_staging(
+        render_device: &ID3D11Device,
+        base: &D3D11_TEXTURE2D_DESC,
+    ) -> Result<Transport> {
+        let desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: 0,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            ..*base
+        };
+
+        let mut staging: Option<ID3D11Texture2D> = None;
+        unsafe {
+            render_device.CreateTexture2D(&desc, None, Some(&mut staging))?;
+        }
+        let staging = staging.ok_or_else(|| anyhow!("Failed to create staging texture"))?;
+        Ok(Transport::Staging { staging })
+    }
+
+    /// Whether this bridge can carry frames described by `desc`
+    pub fn matches(&self, desc: &D3D11_TEXTURE2D_DESC) -> bool {
+        self.desc.Width == desc.Width
+            && self.desc.Height == desc.Height
+            && self.desc.Format == desc.Format
+    }
+
+    /// Copy a render-device frame to the present device.
+    ///
+    /// Returns the present-device texture holding the frame.
+    pub fn transfer(
+        &self,
+        frame: &ID3D11Texture2D,
+        present_context: &ID3D11DeviceContext,
+    ) -> Result<&ID3D11Texture2D> {
+        match self.transport {
+            Transport::Shared {
+                ref render_side,
+                ref present_side,
+                ref render_mutex,
+                ref present_mutex,
+            } => unsafe {
+                render_mutex.AcquireSync(KEY_RENDER, INFINITE)?;
+                self.render_context.CopyResource(render_side, frame);
+                render_mutex.ReleaseSync(KEY_PRESENT)?;
+
+                present_mutex.AcquireSync(KEY_PRESENT, INFINITE)?;
+                present_context.CopyResource(&self.output, present_side);
+                present_mutex.ReleaseSync(KEY_RENDER)?;
+            },
+            Transport::Staging { ref staging } => unsafe {
+                self.render_context.CopyResource(staging, frame);
+
+                let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+                self.render_context
+                    .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+                present_context.UpdateSubresource(
+                    &self.output,
+                    0,
+                    None,
+                    mapped.pData,
+                    mapped.RowPitch,
+                    0,
+                );
+                self.render_context.Unmap(staging, 0);
+            },
+        }
+
+        debug!("Cross-adapter frame transferred");
+        Ok(&self.output)
+    }
+}
@@ -3,32 +3,117 @@
 //! Handles D3D11 device creation, resource management, and command execution.
 //! This module wraps Direct3D 11 APIs to execute graphics commands received
 //! from the guest via the command ring.
+//!
+//! Threading: the device is free-threaded, the immediate context is not.
+//! Every device is created with multithread protection on, so context calls
+//! from different threads (e.g. commands on one, presents on another) are
+//! serialized by the runtime instead of corrupting its state. The windows
+//! crate already marks the interfaces `Send + Sync`, and `D3D11Renderer` is
+//! asserted `Send` below. Protection only makes single calls atomic: a
+//! sequence such as Map/copy/Unmap or bind-then-draw still needs the
+//! threads to agree on who owns the context while it runs.
 
 use anyhow::{anyhow, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use tracing::{debug, info, warn};
-use windows::core::Interface;
+use windows::core::{Interface, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, WAIT_OBJECT_0};
 use windows::Win32::Graphics::Direct3D::{
-    D3D_DRIVER_TYPE_UNKNOWN, D3D_FEATURE_LEVEL, D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_11_1,
-    D3D_PRIMITIVE_TOPOLOGY,
+    WKPDID_D3DDebugObjectName, D3D11_SRV_DIMENSION_TEXTURE2DARRAY, D3D11_SRV_DIMENSION_TEXTURECUBE,
+    D3D11_SRV_DIMENSION_TEXTURECUBEARRAY, D3D_DRIVER_TYPE_UNKNOWN, D3D_FEATURE_LEVEL,
+    D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_11_1, D3D_PRIMITIVE_TOPOLOGY,
 };
 use windows::Win32::Graphics::Direct3D11::{
-    D3D11CreateDevice, ID3D11BlendState, ID3D11Buffer, ID3D11ComputeShader,
-    ID3D11DepthStencilState, ID3D11DepthStencilView, ID3D11Device, ID3D11DeviceContext,
-    ID3D11DomainShader, ID3D11GeometryShader, ID3D11HullShader, ID3D11InputLayout,
-    ID3D11PixelShader, ID3D11RasterizerState, ID3D11RenderTargetView, ID3D11Resource,
-    ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11VertexShader,
-    D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_BUFFER_DESC,
-    D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_SDK_VERSION, D3D11_SUBRESOURCE_DATA,
-    D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_VIEWPORT,
+    D3D11CreateDevice, ID3D11BlendState, ID3D11BlendState1, ID3D11Buffer, ID3D11ComputeShader,
+    ID3D11DepthStencilState, ID3D11DepthStencilView, ID3D11Device, ID3D11Device1, ID3D11Device5,
+    ID3D11DeviceChild, ID3D11DeviceContext, ID3D11DeviceContext4, ID3D11DomainShader, ID3D11Fence,
+    ID3D11GeometryShader, ID3D11HullShader, ID3D11InputLayout, ID3D11Multithread,
+    ID3D11PixelShader, ID3D11Query, ID3D11RasterizerState, ID3D11RenderTargetView, ID3D11Resource,
+    ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11UnorderedAccessView,
+    ID3D11VertexShader, ID3DUserDefinedAnnotation, D3D11_BIND_CONSTANT_BUFFER,
+    D3D11_BIND_DEPTH_STENCIL, D3D11_BIND_INDEX_BUFFER, D3D11_BIND_RENDER_TARGET,
+    D3D11_BIND_SHADER_RESOURCE, D3D11_BIND_STREAM_OUTPUT, D3D11_BIND_UNORDERED_ACCESS,
+    D3D11_BIND_VERTEX_BUFFER, D3D11_BLEND, D3D11_BLEND_DESC, D3D11_BLEND_DESC1, D3D11_BLEND_ONE,
+    D3D11_BLEND_OP, D3D11_BLEND_OP_ADD, D3D11_BLEND_ZERO, D3D11_BOX, D3D11_BUFFER_DESC,
+    D3D11_BUFFER_UAV, D3D11_CLEAR_DEPTH, D3D11_CLEAR_STENCIL, D3D11_COLOR_WRITE_ENABLE_ALL,
+    D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_DEPTH_STENCIL_VIEW_DESC,
+    D3D11_DEPTH_STENCIL_VIEW_DESC_0, D3D11_DSV_DIMENSION_TEXTURE2DARRAY,
+    D3D11_DSV_DIMENSION_TEXTURE2DMSARRAY, D3D11_FENCE_FLAG_NONE,
+    D3D11_FORMAT_SUPPORT_DEPTH_STENCIL, D3D11_FORMAT_SUPPORT_DISPLAY,
+    D3D11_FORMAT_SUPPORT_RENDER_TARGET, D3D11_FORMAT_SUPPORT_SHADER_SAMPLE,
+    D3D11_FORMAT_SUPPORT_TEXTURE2D, D3D11_FORMAT_SUPPORT_TYPED_UNORDERED_ACCESS_VIEW,
+    D3D11_LOGIC_OP, D3D11_LOGIC_OP_NOOP, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ,
+    D3D11_QUERY_DESC, D3D11_QUERY_EVENT, D3D11_RASTERIZER_DESC, D3D11_RENDER_TARGET_BLEND_DESC,
+    D3D11_RENDER_TARGET_BLEND_DESC1, D3D11_RENDER_TARGET_VIEW_DESC,
+    D3D11_RENDER_TARGET_VIEW_DESC_0, D3D11_RESOURCE_DIMENSION_BUFFER,
+    D3D11_RESOURCE_DIMENSION_TEXTURE2D, D3D11_RESOURCE_MISC_TEXTURECUBE,
+    D3D11_RTV_DIMENSION_TEXTURE2DARRAY, D3D11_RTV_DIMENSION_TEXTURE2DMSARRAY, D3D11_SDK_VERSION,
+    D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_SUBRESOURCE_DATA,
+    D3D11_TEX2DMS_ARRAY_DSV, D3D11_TEX2DMS_ARRAY_RTV, D3D11_TEX2D_ARRAY_DSV, D3D11_TEX2D_ARRAY_RTV,
+    D3D11_TEX2D_ARRAY_SRV, D3D11_TEX2D_ARRAY_UAV, D3D11_TEX2D_UAV, D3D11_TEXCUBE_ARRAY_SRV,
+    D3D11_TEXCUBE_SRV, D3D11_TEXTURE2D_DESC, D3D11_UAV_DIMENSION_BUFFER,
+    D3D11_UAV_DIMENSION_TEXTURE2D, D3D11_UAV_DIMENSION_TEXTURE2DARRAY,
+    D3D11_UNORDERED_ACCESS_VIEW_DESC, D3D11_UNORDERED_ACCESS_VIEW_DESC_0, D3D11_USAGE_DEFAULT,
+    D3D11_USAGE_IMMUTABLE, D3D11_USAGE_STAGING, D3D11_VIEWPORT,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_D16_UNORM, DXGI_FORMAT_D24_UNORM_S8_UINT, DXGI_FORMAT_D32_FLOAT,
+    DXGI_FORMAT_D32_FLOAT_S8X24_UINT, DXGI_FORMAT_R10G10B10A2_UINT, DXGI_FORMAT_R16G16B16A16_SINT,
+    DXGI_FORMAT_R16G16B16A16_UINT, DXGI_FORMAT_R16G16_SINT, DXGI_FORMAT_R16G16_UINT,
+    DXGI_FORMAT_R16_SINT, DXGI_FORMAT_R16_TYPELESS, DXGI_FORMAT_R16_UINT,
+    DXGI_FORMAT_R24G8_TYPELESS, DXGI_FORMAT_R32G32B32A32_SINT, DXGI_FORMAT_R32G32B32A32_UINT,
+    DXGI_FORMAT_R32G32B32_SINT, DXGI_FORMAT_R32G32B32_UINT, DXGI_FORMAT_R32G32_SINT,
+    DXGI_FORMAT_R32G32_UINT, DXGI_FORMAT_R32G8X24_TYPELESS, DXGI_FORMAT_R32_SINT,
+    DXGI_FORMAT_R32_TYPELESS, DXGI_FORMAT_R32_UINT, DXGI_FORMAT_R8G8B8A8_SINT,
+    DXGI_FORMAT_R8G8B8A8_UINT, DXGI_FORMAT_R8G8_SINT, DXGI_FORMAT_R8G8_UINT, DXGI_FORMAT_R8_SINT,
+    DXGI_FORMAT_R8_UINT, DXGI_SAMPLE_DESC,
+};
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, IDXGIAdapter1, IDXGIAdapter3, IDXGIDevice, IDXGIDevice1, IDXGIFactory1,
+    IDXGIFactory6, DXGI_GPU_PREFERENCE, DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE,
+    DXGI_GPU_PREFERENCE_MINIMUM_POWER, DXGI_GPU_PREFERENCE_UNSPECIFIED,
+    DXGI_MEMORY_SEGMENT_GROUP_LOCAL, DXGI_QUERY_VIDEO_MEMORY_INFO,
+};
+
+use windows::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+
+use crate::error::ProcessError;
+use crate::protocol::{
+    dispatch_groups_valid, texture_array_valid, CmdCreateBlendState, CmdCreateUnorderedAccessView,
+    MapType, MsaaSupport, ShaderStage, PVGPU_HOST_BACKBUFFER_ID, PVGPU_MAX_FRAME_LATENCY,
+    PVGPU_MAX_STRUCTURE_STRIDE, PVGPU_MAX_TEXTURE_DIMENSION, PVGPU_MSAA_SAMPLE_COUNTS,
+    PVGPU_PREVIOUS_FRAME_ID, PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS,
+    PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED, PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS,
+    PVGPU_RESOURCE_MISC_TEXTURECUBE, PVGPU_STRICT_DISPATCH_GROUPS, PVGPU_UAV_DIMENSION_BUFFER,
+    PVGPU_UAV_DIMENSION_TEXTURE2D, PVGPU_UAV_DIMENSION_TEXTURE2DARRAY, PVGPU_UAV_FLAG_APPEND,
+    PVGPU_UAV_FLAG_COUNTER, PVGPU_UAV_FLAG_RAW,
 };
-use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC};
-use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory1, IDXGIAdapter1, IDXGIFactory1};
+use crate::srgb;
+use crate::video;
 
 /// Resource ID type (matches guest resource IDs)
 pub type ResourceId = u32;
 
+/// Upper bound on vertices (or indices) times instances for a validated
+/// indirect draw
+const MAX_INDIRECT_DRAW_ELEMENTS: u64 = 1 << 26;
+
+/// Largest indirect args block (DrawIndexedInstancedIndirect: 5 u32s)
+const MAX_INDIRECT_ARGS_SIZE: u32 = 20;
+
+/// Destroyed resources held back at most; beyond this the oldest are
+/// released early so a guest that never fences cannot grow the queue forever
+const MAX_DEFERRED_DESTROYS: usize = 4096;
+
+/// Distinct shaders kept in the bytecode cache; later ones are created
+/// without being cached
+const MAX_SHADER_CACHE_ENTRIES: usize = 4096;
+
 /// D3D11 resource wrapper - holds the actual D3D11 objects
 #[allow(dead_code)]
+#[derive(Clone)]
 pub enum D3D11Resource {
     Texture2D {
         texture: ID3D11Texture2D,
@@ -42,6 +127,7 @@ pub enum D3D11Resource {
         buffer: ID3D11Buffer,
         size: u32,
         bind_flags: u32,
+        misc_flags: u32,
     },
     VertexShader {
         shader: ID3D11VertexShader,
@@ -86,6 +172,9 @@ pub enum D3D11Resource {
     ShaderResourceView {
         srv: ID3D11ShaderResourceView,
     },
+    UnorderedAccessView {
+        uav: ID3D11UnorderedAccessView,
+    },
 }
 
 /// Adapter information
@@ -99,6 +188,81 @@ pub struct AdapterInfo {
     pub luid: u64,
 }
 
+/// How D3D11Renderer::new picks its adapter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelection {
+    /// This adapter index
+    Index(u32),
+    /// The adapter of the pool with the most free local video memory
+    LeastLoaded(Vec<u32>),
+    /// Entry `slot` of the pool, wrapping around
+    RoundRobin { pool: Vec<u32>, slot: u32 },
+}
+
+// The renderer must stay movable to a submission or present thread; a
+// field that is not Send (e.g. a raw pointer) fails the build here
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<D3D11Renderer>();
+};
+
+/// Number of shader stages (ShaderStage::Vertex..=Compute)
+const SHADER_STAGE_COUNT: u32 = 6;
+
+/// State and bindings last set through the renderer, so redundant calls can
+/// be skipped. A missing entry means unknown, which lets the next call
+/// through.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct StateCache {
+    /// (state ID, blend factor bits, sample mask)
+    blend: Option<(ResourceId, [u32; 4], u32)>,
+    rasterizer: Option<ResourceId>,
+    /// (state ID, stencil reference)
+    depth_stencil: Option<(ResourceId, u32)>,
+    topology: Option<u32>,
+    input_layout: Option<ResourceId>,
+    /// Shader bound per stage (Some(0) = explicitly unbound)
+    shaders: [Option<ResourceId>; SHADER_STAGE_COUNT as usize],
+    /// Input slot -> (buffer ID, stride, offset)
+    vertex_buffers: HashMap<u32, (ResourceId, u32, u32)>,
+    /// (buffer ID, format, offset)
+    index_buffer: Option<(ResourceId, DXGI_FORMAT, u32)>,
+    /// (stage, slot) -> resource ID
+    constant_buffers: HashMap<(u32, u32), ResourceId>,
+    samplers: HashMap<(u32, u32), ResourceId>,
+    shader_resources: HashMap<(u32, u32), ResourceId>,
+}
+
+impl StateCache {
+    /// Forget bindings of `id`, whose slab entry is changing
+    fn forget(&mut self, id: ResourceId) {
+        for shader in self.shaders.iter_mut() {
+            if *shader == Some(id) {
+                *shader = None;
+            }
+        }
+        self.vertex_buffers.retain(|_, (buffer, ..)| *buffer != id);
+        if matches!(self.index_buffer, Some((buffer, ..)) if buffer == id) {
+            self.index_buffer = None;
+        }
+        self.constant_buffers.retain(|_, bound| *bound != id);
+        self.samplers.retain(|_, bound| *bound != id);
+        self.shader_resources.retain(|_, bound| *bound != id);
+        if matches!(self.blend, Some((bound, ..)) if bound == id) {
+            self.blend = None;
+        }
+        if self.rasterizer == Some(id) {
+            self.rasterizer = None;
+        }
+        if matches!(self.depth_stencil, Some((bound, _)) if bound == id) {
+            self.depth_stencil = None;
+        }
+        if self.input_layout == Some(id) {
+            self.input_layout = None;
+        }
+    }
+}
+
 /// Holds all D3D11 resources and state
 #[allow(dead_code)]
 pub struct D3D11Renderer {
@@ -120,6 +284,89 @@ pub struct D3D11Renderer {
     current_rtvs: Vec<Option<ID3D11RenderTargetView>>,
     /// Current depth stencil view
     current_dsv: Option<ID3D11DepthStencilView>,
+    /// Number of viewports currently bound
+    viewport_count: u32,
+    /// Whether the bound rasterizer state enables scissor testing
+    scissor_enabled: bool,
+    /// Annotation interface for PIX/RenderDoc event markers
+    annotation: Option<ID3DUserDefinedAnnotation>,
+    /// Zero GPU memory of resources as they are destroyed
+    scrub_on_destroy: bool,
+    /// Resources the guest created immutable that are backed by DEFAULT
+    /// ones, since D3D11 cannot write (and so scrub) IMMUTABLE resources
+    scrubbable_immutables: HashSet<ResourceId>,
+    /// Read back and bound-check indirect args before indirect calls
+    validate_indirect_args: bool,
+    /// Reject dispatches launching more than PVGPU_STRICT_DISPATCH_GROUPS
+    strict_dispatch_limits: bool,
+    /// Staging buffer for indirect args readback, created on first use
+    indirect_staging: Option<ID3D11Buffer>,
+    /// Host-owned backbuffer, addressed by PVGPU_HOST_BACKBUFFER_ID.
+    /// Kept outside the slab since the reserved ID is not a dense index.
+    host_backbuffer: Option<D3D11Resource>,
+    /// Copy of the last presented frame, addressed by PVGPU_PREVIOUS_FRAME_ID
+    previous_frame: Option<D3D11Resource>,
+    /// Destroyed resources awaiting release, with the fence current when
+    /// they were destroyed (oldest first)
+    deferred_destroys: VecDeque<(u64, D3D11Resource)>,
+    /// Last bound pipeline state, shaders and resource bindings
+    state_cache: StateCache,
+    /// Binds skipped because they matched `state_cache`, since last taken
+    redundant_binds: u64,
+    /// D3D11.4 fence signaled with guest fence values, when the device has one
+    gpu_fence: Option<GpuFence>,
+    /// Alias immutable resources created from identical data
+    content_dedup: bool,
+    /// Upload hash → live resource holding that content, with the content
+    /// to confirm a match
+    content_hashes: HashMap<u64, UploadContent>,
+    /// Upload key of every resource created with deduplication enabled
+    upload_keys: HashMap<ResourceId, u64>,
+    /// Creations served by an existing resource, since last taken
+    deduped_uploads: u64,
+    /// Bytecode hash → shader created from it. Outlives the guest's shader
+    /// IDs, so a guest relaunch reuses its shaders.
+    shader_cache: HashMap<u64, D3D11Resource>,
+    /// Shader creations served by `shader_cache`, since last taken
+    shader_cache_hits: u64,
+    /// Driver message of the last failed shader creation
+    shader_error: Option<String>,
+    /// Estimated bytes of each slab entry that owns its memory; an alias
+    /// of another entry has none
+    resource_bytes: HashMap<ResourceId, u64>,
+    /// Sum of `resource_bytes`
+    tracked_bytes: u64,
+    /// (DSV ID, PVGPU_DSV_READ_ONLY_* bits) → read-only copy of that view
+    read_only_dsvs: HashMap<(ResourceId, u32), ID3D11DepthStencilView>,
+}
+
+/// An ID3D11Fence and the context that signals it
+/// Identity of an immutable upload: a hash to look it up by, and the
+/// creation parameters and initial data to confirm a match
+struct UploadKey<'a> {
+    hash: u64,
+    params: &'a [u32],
+    data: &'a [u8],
+}
+
+/// Content of a deduplicated upload and the resource holding it
+struct UploadContent {
+    id: ResourceId,
+    params: Vec<u32>,
+    data: Box<[u8]>,
+}
+
+impl UploadContent {
+    fn matches(&self, key: &UploadKey) -> bool {
+        *self.params == *key.params && *self.data == *key.data
+    }
+}
+
+struct GpuFence {
+    fence: ID3D11Fence,
+    context: ID3D11DeviceContext4,
+    /// Last value signaled, to skip re-signaling an unchanged fence
+    signaled: u64,
 }
 
 impl D3D11Renderer {
@@ -162,15 +409,229 @@ impl D3D11Renderer {
         Ok(adapters)
     }
 
-    /// Create a new D3D11 renderer with the specified adapter
-    pub fn new(adapter_index: Option<u32>) -> Result<Self> {
+    /// Check that adapter `index` exists.
+    ///
+    /// A missing adapter logs the ones that do exist, then either falls back
+    /// to adapter 0 or, with `strict`, fails.
+    pub fn resolve_adapter_index(index: u32, strict: bool) -> Result<u32> {
+        let adapters = Self::enumerate_adapters()?;
+        if (index as usize) < adapters.len() {
+            return Ok(index);
+        }
+
+        warn!(
+            "Adapter {} does not exist, {} adapter(s) available:",
+            index,
+            adapters.len()
+        );
+        for adapter in &adapters {
+            warn!(
+                "  [{}] {} (VRAM: {} MB)",
+                adapter.index,
+                adapter.description,
+                adapter.dedicated_video_memory / (1024 * 1024)
+            );
+        }
+
+        if adapters.is_empty() {
+            return Err(anyhow!("No DXGI adapters found"));
+        }
+        if strict {
+            return Err(anyhow!(
+                "Adapter index {} is out of range (valid: 0-{})",
+                index,
+                adapters.len() - 1
+            ));
+        }
+        warn!("Falling back to adapter 0");
+        Ok(0)
+    }
+
+    /// Index of the adapter DXGI ranks first for `preference`.
+    ///
+    /// Returns None when IDXGIFactory6 is unavailable (before Windows 10
+    /// 1803), in which case the caller keeps index-based selection.
+    pub fn adapter_index_by_preference(preference: DXGI_GPU_PREFERENCE) -> Result<Option<u32>> {
+        let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+        let factory: IDXGIFactory6 = match factory.cast() {
+            Ok(factory) => factory,
+            Err(_) => return Ok(None),
+        };
+        let adapter: IDXGIAdapter1 = unsafe { factory.EnumAdapterByGpuPreference(0, preference)? };
+        let desc = unsafe { adapter.GetDesc1()? };
+        let luid = ((desc.AdapterLuid.HighPart as u64) << 32) | (desc.AdapterLuid.LowPart as u64);
+
+        Ok(Self::enumerate_adapters()?
+            .iter()
+            .find(|adapter| adapter.luid == luid)
+            .map(|adapter| adapter.index))
+    }
+
+    /// Index of the adapter `selection` picks. Pool entries that do not
+    /// exist are skipped; it is an error if none does.
+    pub fn select_adapter(selection: &AdapterSelection) -> Result<u32> {
+        let pool = match selection {
+            AdapterSelection::Index(index) => return Ok(*index),
+            AdapterSelection::LeastLoaded(pool) | AdapterSelection::RoundRobin { pool, .. } => pool,
+        };
+        let count = Self::enumerate_adapters()?.len() as u32;
+        let available: Vec<u32> = pool
+            .iter()
+            .copied()
+            .filter(|&index| {
+                if index >= count {
+                    warn!("adapter_pool: adapter {} does not exist, skipping", index);
+                }
+                index < count
+            })
+            .collect();
+        if available.is_empty() {
+            return Err(anyhow!("No adapter of adapter_pool {:?} exists", pool));
+        }
+
+        if let AdapterSelection::RoundRobin { slot, .. } = selection {
+            return Ok(available[*slot as usize % available.len()]);
+        }
+        let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+        let candidates: Vec<(u32, Option<u64>)> = available
+            .iter()
+            .map(|&index| (index, Self::adapter_headroom(&factory, index)))
+            .collect();
+        for (index, free) in &candidates {
+            match free {
+                Some(free) => info!("Adapter {}: {} MB free", index, free / (1024 * 1024)),
+                None => info!("Adapter {}: free memory unknown", index),
+            }
+        }
+        least_loaded_adapter(&candidates).ok_or_else(|| anyhow!("adapter_pool is empty"))
+    }
+
+    /// Local video memory this process could still use on adapter `index`:
+    /// its budget, which shrinks as other processes use the adapter, less
+    /// what it already uses. None when the adapter predates IDXGIAdapter3.
+    fn adapter_headroom(factory: &IDXGIFactory1, index: u32) -> Option<u64> {
+        let adapter: IDXGIAdapter3 = unsafe { factory.EnumAdapters1(index) }
+            .and_then(|adapter| adapter.cast())
+            .ok()?;
+        let mut info = DXGI_QUERY_VIDEO_MEMORY_INFO::default();
+        unsafe { adapter.QueryVideoMemoryInfo(0, DXGI_MEMORY_SEGMENT_GROUP_LOCAL, &mut info) }
+            .ok()?;
+        Some(info.Budget.saturating_sub(info.CurrentUsage))
+    }
+
+    /// Create a new D3D11 renderer on the adapter `selection` picks
+    pub fn new(selection: &AdapterSelection) -> Result<Self> {
         info!("Creating D3D11 device...");
 
+        let adapter_index = Self::select_adapter(selection)?;
+
         // Create DXGI factory
         let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
 
+        let (device, context, achieved_level, adapter_info) =
+            Self::create_device_on_adapter(&factory, adapter_index)?;
+
+        let annotation: Option<ID3DUserDefinedAnnotation> = context.cast().ok();
+        if annotation.is_none() {
+            debug!("ID3DUserDefinedAnnotation not available, debug events disabled");
+        }
+
+        let gpu_fence = Self::create_gpu_fence(&device, &context, 0);
+        if gpu_fence.is_none() {
+            info!("ID3D11Fence not available, using event queries for fence completion");
+        }
+
+        Ok(Self {
+            device,
+            context,
+            feature_level: achieved_level,
+            factory,
+            adapter_info,
+            resources: Vec::with_capacity(1024),
+            current_rtvs: vec![None; 8],
+            current_dsv: None,
+            viewport_count: 0,
+            scissor_enabled: false,
+            annotation,
+            scrub_on_destroy: false,
+            scrubbable_immutables: HashSet::new(),
+            validate_indirect_args: false,
+            strict_dispatch_limits: false,
+            indirect_staging: None,
+            host_backbuffer: None,
+            previous_frame: None,
+            deferred_destroys: VecDeque::new(),
+            state_cache: StateCache::default(),
+            redundant_binds: 0,
+            gpu_fence,
+            content_dedup: false,
+            content_hashes: HashMap::new(),
+            upload_keys: HashMap::new(),
+            deduped_uploads: 0,
+            shader_cache: HashMap::new(),
+            shader_cache_hits: 0,
+            shader_error: None,
+            resource_bytes: HashMap::new(),
+            tracked_bytes: 0,
+            read_only_dsvs: HashMap::new(),
+        })
+    }
+
+    /// Create a D3D11.4 fence already completed at `initial`, or None on
+    /// runtimes and drivers without one
+    fn create_gpu_fence(
+        device: &ID3D11Device,
+        context: &ID3D11DeviceContext,
+        initial: u64,
+    ) -> Option<GpuFence> {
+        let device5: ID3D11Device5 = device.cast().ok()?;
+        let context: ID3D11DeviceContext4 = context.cast().ok()?;
+        let mut fence: Option<ID3D11Fence> = None;
+        if let Err(e) = unsafe { device5.CreateFence(initial, D3D11_FENCE_FLAG_NONE, &mut fence) } {
+            debug!("CreateFence failed: {}", e);
+            return None;
+        }
+        Some(GpuFence {
+            fence: fence?,
+            context,
+            signaled: initial,
+        })
+    }
+
+    /// Replace the GPU fence with one already completed at `value`, for
+    /// when fence numbering restarts below values the old fence has passed
+    pub fn restart_gpu_fence(&mut self, value: u64) {
+        if self.gpu_fence.is_none() {
+            return;
+        }
+        self.gpu_fence = Self::create_gpu_fence(&self.device, &self.context, value);
+        if self.gpu_fence.is_none() {
+            warn!("Recreating the GPU fence failed; fences complete as consumed");
+        }
+    }
+
+    /// Create a bare device on another adapter, e.g. for presenting on the
+    /// GPU a display is attached to while rendering elsewhere.
+    pub fn create_device_for_adapter(
+        adapter_index: u32,
+    ) -> Result<(ID3D11Device, ID3D11DeviceContext, AdapterInfo)> {
+        let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+        let (device, context, _, adapter_info) =
+            Self::create_device_on_adapter(&factory, adapter_index)?;
+        Ok((device, context, adapter_info))
+    }
+
+    /// Create a D3D11 device and immediate context on the adapter at `index`
+    fn create_device_on_adapter(
+        factory: &IDXGIFactory1,
+        index: u32,
+    ) -> Result<(
+        ID3D11Device,
+        ID3D11DeviceContext,
+        D3D_FEATURE_LEVEL,
+        AdapterInfo,
+    )> {
         // Get adapter and info
-        let index = adapter_index.unwrap_or(0);
         let adapter: IDXGIAdapter1 = unsafe { factory.EnumAdapters1(index)? };
         let desc = unsafe { adapter.GetDesc1()? };
 
@@ -231,22 +692,30 @@ impl D3D11Renderer {
 
         let device = device.ok_or_else(|| anyhow!("Failed to create D3D11 device"))?;
         let context = context.ok_or_else(|| anyhow!("Failed to get device context"))?;
+        Self::enable_multithread_protection(&context);
 
         info!(
             "D3D11 device created with feature level: {:?}",
             achieved_level
         );
 
-        Ok(Self {
-            device,
-            context,
-            feature_level: achieved_level,
-            factory,
-            adapter_info,
-            resources: Vec::with_capacity(1024),
-            current_rtvs: vec![None; 8],
-            current_dsv: None,
-        })
+        Ok((device, context, achieved_level, adapter_info))
+    }
+
+    /// Have the runtime serialize immediate context calls made from
+    /// different threads. Costs an uncontended lock per call.
+    fn enable_multithread_protection(context: &ID3D11DeviceContext) {
+        match context.cast::<ID3D11Multithread>() {
+            Ok(multithread) => {
+                // Returns the previous setting, which is always off here
+                let _ = unsafe { multithread.SetMultithreadProtected(true) };
+                debug!("Device context multithread protection enabled");
+            }
+            Err(e) => warn!(
+                "ID3D11Multithread unavailable, context is not thread-safe: {}",
+                e
+            ),
+        }
     }
 
     // -- Resource slab helpers --
@@ -256,26 +725,49 @@ impl D3D11Renderer {
 
     /// Insert a resource into the slab at the given ID.
     fn slab_insert(&mut self, id: ResourceId, resource: D3D11Resource) {
+        if id == PVGPU_HOST_BACKBUFFER_ID || id == PVGPU_PREVIOUS_FRAME_ID {
+            warn!("Resource ID {} is reserved for the host", id);
+            return;
+        }
         let idx = id as usize;
         if idx >= self.resources.len() {
             self.resources.resize_with(idx + 1, || None);
         }
+        self.state_cache.forget(id);
+        self.forget_upload(id);
+        self.scrubbable_immutables.remove(&id);
+        self.read_only_dsvs.retain(|(dsv, _), _| *dsv != id);
+        if let Some(old) = self.resources[idx].take() {
+            self.forget_bytes(id, &old);
+        }
+        let bytes = resource_size(&resource);
+        if bytes > 0 && self.alias_of(&resource).is_none() {
+            self.resource_bytes.insert(id, bytes);
+            self.tracked_bytes += bytes;
+        }
         self.resources[idx] = Some(resource);
     }
 
     /// Get a reference to a resource by ID.
     fn slab_get(&self, id: ResourceId) -> Option<&D3D11Resource> {
+        if id == PVGPU_HOST_BACKBUFFER_ID {
+            return self.host_backbuffer.as_ref();
+        }
+        if id == PVGPU_PREVIOUS_FRAME_ID {
+            return self.previous_frame.as_ref();
+        }
         self.resources.get(id as usize).and_then(|r| r.as_ref())
     }
 
     /// Remove a resource by ID, returning it if present.
     fn slab_remove(&mut self, id: ResourceId) -> Option<D3D11Resource> {
-        let idx = id as usize;
-        if idx < self.resources.len() {
-            self.resources[idx].take()
-        } else {
-            None
-        }
+        self.state_cache.forget(id);
+        self.forget_upload(id);
+        self.scrubbable_immutables.remove(&id);
+        self.read_only_dsvs.retain(|(dsv, _), _| *dsv != id);
+        let resource = self.resources.get_mut(id as usize)?.take()?;
+        self.forget_bytes(id, &resource);
+        Some(resource)
     }
 
     /// Get the count of active (non-None) resources.
@@ -286,6 +778,115 @@ impl D3D11Renderer {
     /// Clear all resources from the slab.
     fn slab_clear(&mut self) {
         self.resources.clear();
+        self.state_cache = StateCache::default();
+        self.content_hashes.clear();
+        self.upload_keys.clear();
+        self.resource_bytes.clear();
+        self.tracked_bytes = 0;
+        self.scrubbable_immutables.clear();
+        self.read_only_dsvs.clear();
+    }
+
+    /// Stop counting the memory of `resource`, just removed from slot `id`,
+    /// handing it to a surviving alias if there is one
+    fn forget_bytes(&mut self, id: ResourceId, resource: &D3D11Resource) {
+        let Some(bytes) = self.resource_bytes.remove(&id) else {
+            return;
+        };
+        match self.alias_of(resource) {
+            Some(alias) => {
+                self.resource_bytes.insert(alias, bytes);
+            }
+            None => self.tracked_bytes -= bytes,
+        }
+    }
+
+    /// Upload key for an immutable creation, or None when it should not be
+    /// deduplicated
+    fn content_key<'a>(
+        &self,
+        id: ResourceId,
+        immutable: bool,
+        params: &'a [u32],
+        data: Option<&'a [u8]>,
+    ) -> Option<UploadKey<'a>> {
+        if !self.content_dedup
+            || !immutable
+            || id == PVGPU_HOST_BACKBUFFER_ID
+            || id == PVGPU_PREVIOUS_FRAME_ID
+        {
+            return None;
+        }
+        data.map(|data| UploadKey {
+            hash: upload_hash(params, data),
+            params,
+            data,
+        })
+    }
+
+    /// Register `id` as an alias of the live resource created with the
+    /// same content as `key`. Returns false when there is none; a hash
+    /// match with different content is not one.
+    fn alias_upload(&mut self, id: ResourceId, key: &UploadKey) -> bool {
+        let Some(existing) = self
+            .content_hashes
+            .get(&key.hash)
+            .filter(|content| content.matches(key))
+            .and_then(|content| self.slab_get(content.id))
+            .cloned()
+        else {
+            return false;
+        };
+        self.slab_insert(id, existing);
+        self.upload_keys.insert(id, key.hash);
+        // Only immutable creations are deduplicated
+        if self.scrub_on_destroy {
+            self.scrubbable_immutables.insert(id);
+        }
+        self.deduped_uploads += 1;
+        true
+    }
+
+    /// Remember `id` as holding the content of `key`, unless another
+    /// content already has its hash
+    fn record_upload(&mut self, id: ResourceId, key: &UploadKey) {
+        let content = self
+            .content_hashes
+            .entry(key.hash)
+            .or_insert_with(|| UploadContent {
+                id,
+                params: key.params.to_vec(),
+                data: key.data.into(),
+            });
+        if content.matches(key) {
+            self.upload_keys.insert(id, key.hash);
+        }
+    }
+
+    /// Drop `id` from the upload maps, handing its content entry to a
+    /// surviving alias if there is one
+    fn forget_upload(&mut self, id: ResourceId) {
+        let Some(key) = self.upload_keys.remove(&id) else {
+            return;
+        };
+        if self.content_hashes.get(&key).map(|content| content.id) != Some(id) {
+            return;
+        }
+        match self
+            .upload_keys
+            .iter()
+            .find(|(_, &k)| k == key)
+            .map(|(&alias, _)| alias)
+        {
+            Some(alias) => {
+                if let Some(content) = self.content_hashes.get_mut(&key) {
+                    content.id = alias;
+                }
+            }
+            None => {
+                self.content_hashes.remove(&key);
+            }
+        }
     }
 
     /// Get device reference
@@ -303,6 +904,17 @@ impl D3D11Renderer {
         &self.adapter_info
     }
 
+    /// Get the feature level the device was created with
+    pub fn feature_level(&self) -> D3D_FEATURE_LEVEL {
+        self.feature_level
+    }
+
+    /// Whether the adapter can scan out `format`
+    pub fn supports_display(&self, format: DXGI_FORMAT) -> bool {
+        let support = unsafe { self.device.CheckFormatSupport(format) }.unwrap_or(0);
+        support & D3D11_FORMAT_SUPPORT_DISPLAY.0 as u32 != 0
+    }
+
     /// Check if the device is in a lost/removed state.
     /// Returns true if the device is still valid, false if lost.
     pub fn check_device_status(&self) -> bool {
@@ -366,19 +978,32 @@ impl D3D11Renderer {
     /// Clear all resources (useful before device recreation)
     pub fn clear_resources(&mut self) {
         info!("Clearing {} resources", self.slab_count());
+        self.scrub_all_resources();
+        // Unbind everything so the context drops its references too
+        unsafe {
+            self.context.ClearState();
+        }
         self.slab_clear();
+        self.deferred_destroys.clear();
         self.current_rtvs = vec![None; 8];
         self.current_dsv = None;
+        self.viewport_count = 0;
+        self.scissor_enabled = false;
     }
 
     /// Create a 2D texture
+    #[allow(clippy::too_many_arguments)]
     pub fn create_texture2d(
         &mut self,
         id: ResourceId,
         width: u32,
         height: u32,
         format: DXGI_FORMAT,
+        array_size: u32,
         bind_flags: u32,
+        srgb_view: bool,
+        cube: bool,
+        immutable: bool,
         initial_data: Option<&[u8]>,
     ) -> Result<()> {
         // Validate dimensions
@@ -391,7 +1016,7 @@ impl D3D11Renderer {
         }
 
         // D3D11 max texture size is 16384x16384
-        if width > 16384 || height > 16384 {
+        if width > PVGPU_MAX_TEXTURE_DIMENSION || height > PVGPU_MAX_TEXTURE_DIMENSION {
             warn!(
                 "CreateTexture2D: dimensions {}x{} exceed max (16384) for id={}",
                 width, height, id
@@ -399,33 +1024,165 @@ impl D3D11Renderer {
             return Err(anyhow!("Texture dimensions exceed maximum"));
         }
 
+        if let Err((bad_flags, reason)) = texture_bind_flags_valid(bind_flags, format) {
+            warn!(
+                "CreateTexture2D: bind flags {} rejected for id={}: {}",
+                bind_flag_names(bad_flags),
+                id,
+                reason
+            );
+            return Err(ProcessError::InvalidParameter(bad_flags).into());
+        }
+        if immutable {
+            check_immutable("CreateTexture2D", id, bind_flags, initial_data.is_some())?;
+        }
+
+        if !texture_array_valid(width, height, array_size, cube)
+            || (array_size > 1 && video::is_planar_yuv(format))
+        {
+            warn!(
+                "CreateTexture2D: invalid array of {} {}x{} slices (cube={}) for id={}",
+                array_size, width, height, cube, id
+            );
+            return Err(ProcessError::InvalidParameter(array_size).into());
+        }
+
+        // Reject formats the adapter cannot create as a 2D texture
+        let support = self.format_support(format);
+        let mut required = D3D11_FORMAT_SUPPORT_TEXTURE2D.0 as u32;
+        if video::is_planar_yuv(format) {
+            // Presentation samples the planes for YUV -> RGB conversion
+            required |= D3D11_FORMAT_SUPPORT_SHADER_SAMPLE.0 as u32;
+        }
+        if support & required != required {
+            warn!(
+                "CreateTexture2D: format {:?} not supported (support=0x{:x}) for id={}",
+                format, support, id
+            );
+            return Err(ProcessError::UnsupportedFormat(id).into());
+        }
+
+        // 4:2:0 chroma subsampling needs even dimensions
+        if video::is_planar_yuv(format) && (!width.is_multiple_of(2) || !height.is_multiple_of(2)) {
+            warn!(
+                "CreateTexture2D: {}x{} must be even for format {:?}, id={}",
+                width, height, format, id
+            );
+            return Err(ProcessError::InvalidParameter(id).into());
+        }
+
+        // sRGB views over a UNORM texture need a TYPELESS resource
+        let (resource_format, view_format) = if srgb_view {
+            match (
+                srgb::typeless_format(format),
+                srgb::srgb_view_format(format),
+            ) {
+                (Some(typeless), Some(view)) => (typeless, Some(view)),
+                _ => {
+                    warn!(
+                        "CreateTexture2D: no sRGB view for {:?}, id={}; using default views",
+                        format, id
+                    );
+                    (format, None)
+                }
+            }
+        } else {
+            (format, None)
+        };
+
         let desc = D3D11_TEXTURE2D_DESC {
             Width: width,
             Height: height,
             MipLevels: 1,
-            ArraySize: 1,
-            Format: format,
+            ArraySize: array_size,
+            Format: resource_format,
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
                 Quality: 0,
             },
-            Usage: D3D11_USAGE_DEFAULT,
+            Usage: if immutable && !self.scrub_on_destroy {
+                D3D11_USAGE_IMMUTABLE
+            } else {
+                D3D11_USAGE_DEFAULT
+            },
             BindFlags: bind_flags,
             CPUAccessFlags: Default::default(),
-            MiscFlags: Default::default(),
+            MiscFlags: if cube {
+                D3D11_RESOURCE_MISC_TEXTURECUBE.0 as u32
+            } else {
+                0
+            },
         };
 
-        let init_data = initial_data.map(|data| D3D11_SUBRESOURCE_DATA {
-            pSysMem: data.as_ptr() as *const _,
-            SysMemPitch: width * 4, // Assuming 4 bytes per pixel
-            SysMemSlicePitch: 0,
+        // Planar YUV data is the luma plane followed by the chroma plane,
+        // both sharing the luma row pitch
+        let row_pitch = match video::planar_bytes_per_sample(format) {
+            Some(bytes) => width * bytes,
+            None => width * 4, // Assuming 4 bytes per pixel
+        };
+
+        if let (Some(data), Some(expected)) =
+            (initial_data, video::planar_data_size(format, width, height))
+        {
+            if data.len() < expected {
+                warn!(
+                    "CreateTexture2D: {} bytes of initial data, {:?} needs {} for id={}",
+                    data.len(),
+                    format,
+                    expected,
+                    id
+                );
+                return Err(ProcessError::InvalidParameter(id).into());
+            }
+        }
+
+        // Array initial data is the slices back to back, one subresource each
+        let slice_size = row_pitch as usize * height as usize;
+        if let Some(data) = initial_data {
+            if array_size > 1 && data.len() < slice_size * array_size as usize {
+                warn!(
+                    "CreateTexture2D: {} bytes of initial data, {} slices need {} for id={}",
+                    data.len(),
+                    array_size,
+                    slice_size * array_size as usize,
+                    id
+                );
+                return Err(ProcessError::InvalidParameter(id).into());
+            }
+        }
+        let params = [
+            D3D11_RESOURCE_DIMENSION_TEXTURE2D.0 as u32,
+            width,
+            height,
+            format.0 as u32,
+            array_size,
+            bind_flags,
+            srgb_view as u32,
+            cube as u32,
+        ];
+        let key = self.content_key(id, immutable, &params, initial_data);
+        if let Some(key) = &key {
+            if self.alias_upload(id, key) {
+                debug!("CreateTexture2D: id={} aliases identical upload", id);
+                return Ok(());
+            }
+        }
+
+        let init_data: Option<Vec<D3D11_SUBRESOURCE_DATA>> = initial_data.map(|data| {
+            (0..array_size as usize)
+                .map(|slice| D3D11_SUBRESOURCE_DATA {
+                    pSysMem: data[slice * slice_size..].as_ptr() as *const _,
+                    SysMemPitch: row_pitch,
+                    SysMemSlicePitch: 0,
+                })
+                .collect()
         });
 
         let mut texture: Option<ID3D11Texture2D> = None;
         let result = unsafe {
             self.device.CreateTexture2D(
                 &desc,
-                init_data.as_ref().map(|d| d as *const _),
+                init_data.as_ref().map(|d| d.as_ptr()),
                 Some(&mut texture),
             )
         };
@@ -440,7 +1197,19 @@ impl D3D11Renderer {
                         "CreateTexture2D OUT OF MEMORY: id={}, {}x{}, format={:?}",
                         id, width, height, format
                     );
-                    return Err(anyhow!("OutOfMemory: texture creation failed"));
+                    return Err(ProcessError::OutOfMemory("texture creation failed".into()).into());
+                }
+                // The format cannot be used the way the bind flags ask
+                let required = bind_format_support(bind_flags);
+                if support & required != required {
+                    warn!(
+                        "CreateTexture2D: format {:?} cannot be bound as {} (support=0x{:x}) for id={}",
+                        format,
+                        bind_flag_names(bind_flags),
+                        support,
+                        id
+                    );
+                    return Err(ProcessError::UnsupportedFormat(id).into());
                 }
                 warn!(
                     "CreateTexture2D FAILED: id={}, {}x{}, format={:?}, error={:?}",
@@ -452,12 +1221,27 @@ impl D3D11Renderer {
 
         let texture = texture.ok_or_else(|| anyhow!("Failed to create texture"))?;
 
-        // Create SRV if shader resource bind flag is set
-        let srv = if (bind_flags & D3D11_BIND_SHADER_RESOURCE.0 as u32) != 0 {
+        // Create SRV if shader resource bind flag is set. Planar YUV textures
+        // need per-plane views, which the presentation converter creates.
+        let srv = if (bind_flags & D3D11_BIND_SHADER_RESOURCE.0 as u32) != 0
+            && !video::is_planar_yuv(format)
+        {
+            let view_desc = if array_size > 1 {
+                Some(array_srv_desc(
+                    view_format.unwrap_or(format),
+                    array_size,
+                    cube,
+                ))
+            } else {
+                view_format.map(srgb::srv_desc)
+            };
             let mut srv: Option<ID3D11ShaderResourceView> = None;
             unsafe {
-                self.device
-                    .CreateShaderResourceView(&texture, None, Some(&mut srv))?;
+                self.device.CreateShaderResourceView(
+                    &texture,
+                    view_desc.as_ref().map(|d| d as *const _),
+                    Some(&mut srv),
+                )?;
             }
             srv
         } else {
@@ -465,11 +1249,21 @@ impl D3D11Renderer {
         };
 
         // Create RTV if render target bind flag is set
-        let rtv = if (bind_flags & D3D11_BIND_RENDER_TARGET.0 as u32) != 0 {
+        let rtv = if (bind_flags & D3D11_BIND_RENDER_TARGET.0 as u32) != 0
+            && !video::is_planar_yuv(format)
+        {
+            let view_desc = if array_size > 1 {
+                Some(array_rtv_desc(view_format.unwrap_or(format), array_size))
+            } else {
+                view_format.map(srgb::rtv_desc)
+            };
             let mut rtv: Option<ID3D11RenderTargetView> = None;
             unsafe {
-                self.device
-                    .CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+                self.device.CreateRenderTargetView(
+                    &texture,
+                    view_desc.as_ref().map(|d| d as *const _),
+                    Some(&mut rtv),
+                )?;
             }
             rtv
         } else {
@@ -477,8 +1271,14 @@ impl D3D11Renderer {
         };
 
         debug!(
-            "Created Texture2D: id={}, {}x{}, format={:?}",
-            id, width, height, format
+            "Created Texture2D: id={}, {}x{}x{}, format={:?}, srgb_view={}, cube={}",
+            id,
+            width,
+            height,
+            array_size,
+            format,
+            view_format.is_some(),
+            cube
         );
 
         self.slab_insert(
@@ -492,16 +1292,27 @@ impl D3D11Renderer {
                 rtv,
             },
         );
+        if immutable && self.scrub_on_destroy {
+            self.scrubbable_immutables.insert(id);
+        }
+        if let Some(key) = &key {
+            self.record_upload(id, key);
+        }
 
         Ok(())
     }
 
-    /// Create a buffer (vertex, index, or constant buffer)
+    /// Create a buffer (vertex, index, constant, structured or indirect
+    /// args). `structure_stride` is the element size of a structured buffer.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_buffer(
         &mut self,
         id: ResourceId,
         size: u32,
         bind_flags: u32,
+        misc_flags: u32,
+        structure_stride: u32,
+        immutable: bool,
         initial_data: Option<&[u8]>,
     ) -> Result<()> {
         // Validate size
@@ -520,15 +1331,61 @@ impl D3D11Renderer {
             return Err(anyhow!("Buffer size exceeds maximum"));
         }
 
+        if let Err((bad_flags, reason)) = buffer_bind_flags_valid(bind_flags) {
+            warn!(
+                "CreateBuffer: bind flags {} rejected for id={}: {}",
+                bind_flag_names(bad_flags),
+                id,
+                reason
+            );
+            return Err(ProcessError::InvalidParameter(bad_flags).into());
+        }
+        if let Err((bad_value, reason)) =
+            buffer_misc_flags_valid(misc_flags, size, structure_stride)
+        {
+            warn!(
+                "CreateBuffer: misc flags {:#x} (stride {}) rejected for id={}: {}",
+                misc_flags, structure_stride, id, reason
+            );
+            return Err(ProcessError::InvalidParameter(bad_value).into());
+        }
+        let structure_stride = if misc_flags & PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED != 0 {
+            structure_stride
+        } else {
+            0
+        };
+        if immutable {
+            check_immutable("CreateBuffer", id, bind_flags, initial_data.is_some())?;
+        }
+
         let desc = D3D11_BUFFER_DESC {
             ByteWidth: size,
-            Usage: D3D11_USAGE_DEFAULT,
+            Usage: if immutable && !self.scrub_on_destroy {
+                D3D11_USAGE_IMMUTABLE
+            } else {
+                D3D11_USAGE_DEFAULT
+            },
             BindFlags: bind_flags,
             CPUAccessFlags: Default::default(),
-            MiscFlags: Default::default(),
-            StructureByteStride: 0,
+            MiscFlags: misc_flags,
+            StructureByteStride: structure_stride,
         };
 
+        let params = [
+            D3D11_RESOURCE_DIMENSION_BUFFER.0 as u32,
+            size,
+            bind_flags,
+            misc_flags,
+            structure_stride,
+        ];
+        let key = self.content_key(id, immutable, &params, initial_data);
+        if let Some(key) = &key {
+            if self.alias_upload(id, key) {
+                debug!("CreateBuffer: id={} aliases identical upload", id);
+                return Ok(());
+            }
+        }
+
         let init_data = initial_data.map(|data| D3D11_SUBRESOURCE_DATA {
             pSysMem: data.as_ptr() as *const _,
             SysMemPitch: 0,
@@ -554,7 +1411,7 @@ impl D3D11Renderer {
                         "CreateBuffer OUT OF MEMORY: id={}, size={}, bind_flags={}",
                         id, size, bind_flags
                     );
-                    return Err(anyhow!("OutOfMemory: buffer creation failed"));
+                    return Err(ProcessError::OutOfMemory("buffer creation failed".into()).into());
                 }
                 warn!(
                     "CreateBuffer FAILED: id={}, size={}, bind_flags={}, error={:?}",
@@ -577,22 +1434,114 @@ impl D3D11Renderer {
                 buffer,
                 size,
                 bind_flags,
+                misc_flags,
             },
         );
+        if immutable && self.scrub_on_destroy {
+            self.scrubbable_immutables.insert(id);
+        }
+        if let Some(key) = &key {
+            self.record_upload(id, key);
+        }
 
         Ok(())
     }
 
-    /// Create a vertex shader from DXBC bytecode
-    pub fn create_vertex_shader(&mut self, id: ResourceId, bytecode: &[u8]) -> Result<()> {
-        if bytecode.is_empty() {
-            warn!("CreateVertexShader: empty bytecode for id={}", id);
-            return Err(anyhow!("Shader bytecode is empty"));
-        }
+    /// Log a failed shader creation, keeping the driver's message for the
+    /// guest's diagnostic buffer
+    fn shader_error(
+        &mut self,
+        command: &str,
+        id: ResourceId,
+        bytecode_size: usize,
+        error: windows::core::Error,
+    ) -> anyhow::Error {
+        warn!(
+            "{} FAILED: id={}, bytecode_size={}, error={:?}",
+            command, id, bytecode_size, error
+        );
+        let message = format!(
+            "{} failed: {} (HRESULT 0x{:08X})",
+            command,
+            error.message(),
+            error.code().0
+        );
+        self.shader_error = Some(message.clone());
+        anyhow!(message)
+    }
 
-        let mut shader: Option<ID3D11VertexShader> = None;
-        let result = unsafe {
-            self.device
+    /// Driver message of the last failed shader creation, if not yet taken
+    pub fn take_shader_error(&mut self) -> Option<String> {
+        self.shader_error.take()
+    }
+
+    /// Register `id` as an alias of a cached shader created from the same
+    /// bytecode. Returns false on a cache miss.
+    fn alias_cached_shader(&mut self, id: ResourceId, stage: u32, bytecode: &[u8]) -> bool {
+        let Some(shader) = self
+            .shader_cache
+            .get(&upload_hash(&[stage], bytecode))
+            .cloned()
+        else {
+            return false;
+        };
+        debug!(
+            "Shader cache hit: id={}, stage={}, bytecode_size={}",
+            id,
+            stage,
+            bytecode.len()
+        );
+        self.slab_insert(id, shader);
+        self.shader_cache_hits += 1;
+        true
+    }
+
+    /// Insert a newly created shader, remembering it for later uploads of
+    /// the same bytecode
+    fn insert_shader(
+        &mut self,
+        id: ResourceId,
+        stage: u32,
+        bytecode: &[u8],
+        shader: D3D11Resource,
+    ) {
+        if self.shader_cache.len() < MAX_SHADER_CACHE_ENTRIES {
+            self.shader_cache
+                .insert(upload_hash(&[stage], bytecode), shader.clone());
+        }
+        self.slab_insert(id, shader);
+    }
+
+    /// Create a shader of `stage` from DXBC bytecode
+    pub fn create_shader(
+        &mut self,
+        id: ResourceId,
+        stage: ShaderStage,
+        bytecode: &[u8],
+    ) -> Result<()> {
+        match stage {
+            ShaderStage::Vertex => self.create_vertex_shader(id, bytecode),
+            ShaderStage::Pixel => self.create_pixel_shader(id, bytecode),
+            ShaderStage::Geometry => self.create_geometry_shader(id, bytecode),
+            ShaderStage::Hull => self.create_hull_shader(id, bytecode),
+            ShaderStage::Domain => self.create_domain_shader(id, bytecode),
+            ShaderStage::Compute => self.create_compute_shader(id, bytecode),
+        }
+    }
+
+    /// Create a vertex shader from DXBC bytecode
+    pub fn create_vertex_shader(&mut self, id: ResourceId, bytecode: &[u8]) -> Result<()> {
+        if bytecode.is_empty() {
+            warn!("CreateVertexShader: empty bytecode for id={}", id);
+            return Err(anyhow!("Shader bytecode is empty"));
+        }
+        if self.alias_cached_shader(id, 0, bytecode) {
+            return Ok(());
+        }
+
+        let mut shader: Option<ID3D11VertexShader> = None;
+        let result = unsafe {
+            self.device
                 .CreateVertexShader(bytecode, None, Some(&mut shader))
         };
 
@@ -606,8 +1555,10 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(
+                self.insert_shader(
                     id,
+                    0,
+                    bytecode,
                     D3D11Resource::VertexShader {
                         shader,
                         bytecode: bytecode.to_vec(),
@@ -616,15 +1567,7 @@ impl D3D11Renderer {
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreateVertexShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Vertex shader compilation failed: {:?}", e))
-            }
+            Err(e) => Err(self.shader_error("CreateVertexShader", id, bytecode.len(), e)),
         }
     }
 
@@ -634,6 +1577,9 @@ impl D3D11Renderer {
             warn!("CreatePixelShader: empty bytecode for id={}", id);
             return Err(anyhow!("Shader bytecode is empty"));
         }
+        if self.alias_cached_shader(id, 1, bytecode) {
+            return Ok(());
+        }
 
         let mut shader: Option<ID3D11PixelShader> = None;
         let result = unsafe {
@@ -651,19 +1597,11 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(id, D3D11Resource::PixelShader { shader });
+                self.insert_shader(id, 1, bytecode, D3D11Resource::PixelShader { shader });
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreatePixelShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Pixel shader compilation failed: {:?}", e))
-            }
+            Err(e) => Err(self.shader_error("CreatePixelShader", id, bytecode.len(), e)),
         }
     }
 
@@ -673,6 +1611,9 @@ impl D3D11Renderer {
             warn!("CreateGeometryShader: empty bytecode for id={}", id);
             return Err(anyhow!("Shader bytecode is empty"));
         }
+        if self.alias_cached_shader(id, 2, bytecode) {
+            return Ok(());
+        }
 
         let mut shader: Option<ID3D11GeometryShader> = None;
         let result = unsafe {
@@ -690,19 +1631,11 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(id, D3D11Resource::GeometryShader { shader });
+                self.insert_shader(id, 2, bytecode, D3D11Resource::GeometryShader { shader });
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreateGeometryShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Geometry shader compilation failed: {:?}", e))
-            }
+            Err(e) => Err(self.shader_error("CreateGeometryShader", id, bytecode.len(), e)),
         }
     }
 
@@ -712,6 +1645,9 @@ impl D3D11Renderer {
             warn!("CreateHullShader: empty bytecode for id={}", id);
             return Err(anyhow!("Shader bytecode is empty"));
         }
+        if self.alias_cached_shader(id, 3, bytecode) {
+            return Ok(());
+        }
 
         let mut shader: Option<ID3D11HullShader> = None;
         let result = unsafe {
@@ -729,19 +1665,11 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(id, D3D11Resource::HullShader { shader });
+                self.insert_shader(id, 3, bytecode, D3D11Resource::HullShader { shader });
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreateHullShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Hull shader compilation failed: {:?}", e))
-            }
+            Err(e) => Err(self.shader_error("CreateHullShader", id, bytecode.len(), e)),
         }
     }
 
@@ -751,6 +1679,9 @@ impl D3D11Renderer {
             warn!("CreateDomainShader: empty bytecode for id={}", id);
             return Err(anyhow!("Shader bytecode is empty"));
         }
+        if self.alias_cached_shader(id, 4, bytecode) {
+            return Ok(());
+        }
 
         let mut shader: Option<ID3D11DomainShader> = None;
         let result = unsafe {
@@ -768,19 +1699,11 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(id, D3D11Resource::DomainShader { shader });
+                self.insert_shader(id, 4, bytecode, D3D11Resource::DomainShader { shader });
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreateDomainShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Domain shader compilation failed: {:?}", e))
-            }
+            Err(e) => Err(self.shader_error("CreateDomainShader", id, bytecode.len(), e)),
         }
     }
 
@@ -790,6 +1713,9 @@ impl D3D11Renderer {
             warn!("CreateComputeShader: empty bytecode for id={}", id);
             return Err(anyhow!("Shader bytecode is empty"));
         }
+        if self.alias_cached_shader(id, 5, bytecode) {
+            return Ok(());
+        }
 
         let mut shader: Option<ID3D11ComputeShader> = None;
         let result = unsafe {
@@ -807,26 +1733,350 @@ impl D3D11Renderer {
                     bytecode.len()
                 );
 
-                self.slab_insert(id, D3D11Resource::ComputeShader { shader });
+                self.insert_shader(id, 5, bytecode, D3D11Resource::ComputeShader { shader });
 
                 Ok(())
             }
-            Err(e) => {
-                warn!(
-                    "CreateComputeShader FAILED: id={}, bytecode_size={}, error={:?}",
-                    id,
-                    bytecode.len(),
-                    e
-                );
-                Err(anyhow!("Compute shader compilation failed: {:?}", e))
+            Err(e) => Err(self.shader_error("CreateComputeShader", id, bytecode.len(), e)),
+        }
+    }
+
+    /// Enable or disable zeroing of resource contents on destroy
+    pub fn set_scrub_on_destroy(&mut self, enabled: bool) {
+        if enabled {
+            info!("Resource scrubbing on destroy enabled");
+        }
+        self.scrub_on_destroy = enabled;
+    }
+
+    /// Enable or disable aliasing of immutable resources created from
+    /// identical data
+    pub fn set_content_dedup(&mut self, enabled: bool) {
+        if enabled {
+            info!("Upload deduplication enabled");
+        }
+        self.content_dedup = enabled;
+    }
+
+    /// Enable or disable readback validation of indirect draw/dispatch args
+    pub fn set_validate_indirect_args(&mut self, enabled: bool) {
+        if enabled {
+            info!("Indirect argument validation enabled");
+        }
+        self.validate_indirect_args = enabled;
+    }
+
+    /// Enable or disable the total thread group budget for dispatches
+    pub fn set_strict_dispatch_limits(&mut self, enabled: bool) {
+        if enabled {
+            info!(
+                "Strict dispatch limits enabled ({} thread groups)",
+                PVGPU_STRICT_DISPATCH_GROUPS
+            );
+        }
+        self.strict_dispatch_limits = enabled;
+    }
+
+    /// Limit how many frames the driver may queue ahead of the GPU.
+    ///
+    /// 0 restores the driver default of 3; values above
+    /// `PVGPU_MAX_FRAME_LATENCY` are rejected.
+    pub fn set_max_frame_latency(&mut self, frames: u32) -> Result<()> {
+        if frames > PVGPU_MAX_FRAME_LATENCY {
+            warn!(
+                "Frame latency {} exceeds {}",
+                frames, PVGPU_MAX_FRAME_LATENCY
+            );
+            return Err(ProcessError::InvalidParameter(frames).into());
+        }
+
+        let dxgi_device: IDXGIDevice1 = self.device.cast()?;
+        unsafe { dxgi_device.SetMaximumFrameLatency(frames)? };
+        info!("Maximum frame latency set to {}", frames);
+        Ok(())
+    }
+
+    /// Recreate shader `id` from new bytecode, keeping its ID.
+    ///
+    /// `stage` and the stage the bytecode was compiled for must both match
+    /// the existing shader. If the old shader is bound, the new one takes its
+    /// place; the old one is released like a destroyed resource once `fence`
+    /// has passed. On failure the old shader stays in place.
+    pub fn replace_shader(
+        &mut self,
+        id: ResourceId,
+        stage: u32,
+        bytecode: &[u8],
+        fence: u64,
+    ) -> Result<()> {
+        let (current_stage, old_raw) = match self.slab_get(id).and_then(shader_stage_and_raw) {
+            Some(info) => info,
+            None => {
+                warn!("ReplaceShader: {} is not a shader", id);
+                return Err(ProcessError::ResourceNotFound(id).into());
+            }
+        };
+        if stage != current_stage {
+            warn!(
+                "ReplaceShader: shader {} is stage {}, not {}",
+                id, current_stage, stage
+            );
+            return Err(ProcessError::InvalidParameter(stage).into());
+        }
+        let bytecode_stage = dxbc_shader_stage(bytecode);
+        if bytecode_stage != Some(stage) {
+            warn!(
+                "ReplaceShader: bytecode for shader {} is stage {:?}, expected {}",
+                id, bytecode_stage, stage
+            );
+            return Err(ProcessError::InvalidParameter(stage).into());
+        }
+
+        // Shaders from identical bytecode share one object, so the stage
+        // binding alone cannot tell which ID is bound
+        let bound_id = self.state_cache.shaders[stage as usize];
+        let was_bound = self.bound_shader_raw(stage) == Some(old_raw)
+            && bound_id.is_none_or(|bound| bound == id);
+        let Some(old) = self.slab_remove(id) else {
+            return Err(ProcessError::ResourceNotFound(id).into());
+        };
+
+        let result = match ShaderStage::try_from(stage) {
+            Ok(stage) => self.create_shader(id, stage, bytecode),
+            Err(stage) => Err(ProcessError::UnknownType(stage).into()),
+        };
+        if let Err(e) = result {
+            self.slab_insert(id, old);
+            warn!("ReplaceShader: keeping old shader {}: {}", id, e);
+            return Err(ProcessError::ShaderCompile(id).into());
+        }
+
+        if was_bound {
+            self.set_shader(stage, id);
+        }
+        self.deferred_destroys.push_back((fence, old));
+        info!(
+            "Replaced shader {} (stage {}, {} bytes, rebound={})",
+            id,
+            stage,
+            bytecode.len(),
+            was_bound
+        );
+        Ok(())
+    }
+
+    /// Raw pointer of the shader currently bound to `stage`, if any
+    fn bound_shader_raw(&self, stage: u32) -> Option<*mut std::ffi::c_void> {
+        unsafe {
+            match stage {
+                0 => {
+                    let mut shader = None;
+                    self.context.VSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                1 => {
+                    let mut shader = None;
+                    self.context.PSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                2 => {
+                    let mut shader = None;
+                    self.context.GSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                3 => {
+                    let mut shader = None;
+                    self.context.HSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                4 => {
+                    let mut shader = None;
+                    self.context.DSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                5 => {
+                    let mut shader = None;
+                    self.context.CSGetShader(&mut shader, None, None);
+                    shader.map(|s| s.as_raw())
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Check whether another slab entry shares the same underlying D3D11
+    /// object (created through OpenResource aliasing).
+    fn is_aliased(&self, resource: &D3D11Resource) -> bool {
+        self.alias_of(resource).is_some()
+    }
+
+    /// ID of a slab entry sharing the texture or buffer of `resource`
+    fn alias_of(&self, resource: &D3D11Resource) -> Option<ResourceId> {
+        let raw = match resource {
+            D3D11Resource::Texture2D { texture, .. } => texture.as_raw(),
+            D3D11Resource::Buffer { buffer, .. } => buffer.as_raw(),
+            _ => return None,
+        };
+        self.resources
+            .iter()
+            .position(|r| match r {
+                Some(D3D11Resource::Texture2D { texture, .. }) => texture.as_raw() == raw,
+                Some(D3D11Resource::Buffer { buffer, .. }) => buffer.as_raw() == raw,
+                _ => false,
+            })
+            .map(|idx| idx as ResourceId)
+    }
+
+    /// Overwrite a resource's GPU memory with zeros so its contents cannot
+    /// leak into a later allocation.
+    fn scrub_resource(&self, resource: &D3D11Resource) {
+        match resource {
+            D3D11Resource::Texture2D {
+                texture, format, ..
+            } => {
+                if let Err(e) = self.scrub_texture(texture, *format) {
+                    warn!("Failed to scrub texture: {}", e);
+                }
+            }
+            D3D11Resource::Buffer { buffer, size, .. } => {
+                let mut desc = D3D11_BUFFER_DESC::default();
+                unsafe {
+                    buffer.GetDesc(&mut desc);
+                }
+                if desc.Usage != D3D11_USAGE_DEFAULT {
+                    debug!("Not scrubbing a buffer of usage {:?}", desc.Usage);
+                    return;
+                }
+                let zeros = vec![0u8; *size as usize];
+                unsafe {
+                    self.context.UpdateSubresource(
+                        buffer,
+                        0,
+                        None,
+                        zeros.as_ptr() as *const _,
+                        0,
+                        0,
+                    );
+                }
+            }
+            // Shaders and state objects carry no guest data
+            _ => {}
+        }
+    }
+
+    /// Zero every mip and array slice of `texture`. Depth-stencil and
+    /// render target textures, the only ones that can be multisampled, are
+    /// cleared through a view per mip; the rest are overwritten with zeros.
+    /// `format` is the typed format views of the texture use.
+    fn scrub_texture(&self, texture: &ID3D11Texture2D, format: DXGI_FORMAT) -> Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            texture.GetDesc(&mut desc);
+        }
+        if desc.Usage != D3D11_USAGE_DEFAULT {
+            // Guest-immutable textures are created DEFAULT while scrubbing
+            debug!("Not scrubbing a texture of usage {:?}", desc.Usage);
+            return Ok(());
+        }
+        let multisampled = desc.SampleDesc.Count > 1;
+
+        if desc.BindFlags & D3D11_BIND_DEPTH_STENCIL.0 as u32 != 0 {
+            let view_format = depth_view_format(format);
+            let mut clear_flags = D3D11_CLEAR_DEPTH.0 as u32;
+            if matches!(
+                view_format,
+                DXGI_FORMAT_D24_UNORM_S8_UINT | DXGI_FORMAT_D32_FLOAT_S8X24_UINT
+            ) {
+                clear_flags |= D3D11_CLEAR_STENCIL.0 as u32;
+            }
+            for mip in 0..desc.MipLevels {
+                let view_desc = scrub_dsv_desc(view_format, mip, desc.ArraySize, multisampled);
+                let mut view: Option<ID3D11DepthStencilView> = None;
+                unsafe {
+                    self.device.CreateDepthStencilView(
+                        texture,
+                        Some(&view_desc),
+                        Some(&mut view),
+                    )?;
+                }
+                let view = view.ok_or_else(|| anyhow!("Failed to create scrub DSV"))?;
+                unsafe {
+                    self.context
+                        .ClearDepthStencilView(&view, clear_flags, 0.0, 0);
+                }
+            }
+        } else if desc.BindFlags & D3D11_BIND_RENDER_TARGET.0 as u32 != 0
+            && !video::is_planar_yuv(desc.Format)
+        {
+            for mip in 0..desc.MipLevels {
+                let view_desc = scrub_rtv_desc(format, mip, desc.ArraySize, multisampled);
+                let mut view: Option<ID3D11RenderTargetView> = None;
+                unsafe {
+                    self.device.CreateRenderTargetView(
+                        texture,
+                        Some(&view_desc),
+                        Some(&mut view),
+                    )?;
+                }
+                let view = view.ok_or_else(|| anyhow!("Failed to create scrub RTV"))?;
+                unsafe {
+                    self.context.ClearRenderTargetView(&view, &[0.0; 4]);
+                }
+            }
+        } else {
+            // Mip 0 is the largest subresource, so its zeros cover the rest
+            let size = upload_row_pitch(desc.Format, desc.Width) as usize
+                * mapped_rows(desc.Format, desc.Height, 1, 0) as usize;
+            let zeros = vec![0u8; size];
+            for slice in 0..desc.ArraySize {
+                for mip in 0..desc.MipLevels {
+                    let width = (desc.Width >> mip).max(1);
+                    unsafe {
+                        self.context.UpdateSubresource(
+                            texture,
+                            mip + slice * desc.MipLevels,
+                            None,
+                            zeros.as_ptr() as *const _,
+                            upload_row_pitch(desc.Format, width),
+                            0,
+                        );
+                    }
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Zero every live resource, used on session teardown when scrubbing
+    /// is enabled.
+    pub fn scrub_all_resources(&mut self) {
+        if !self.scrub_on_destroy {
+            return;
+        }
+        info!("Scrubbing {} resources", self.slab_count());
+        for resource in self.resources.iter().flatten() {
+            self.scrub_resource(resource);
+        }
+        self.flush();
     }
 
-    /// Destroy a resource by ID
-    pub fn destroy_resource(&mut self, id: ResourceId) -> bool {
-        if self.slab_remove(id).is_some() {
-            debug!("Destroyed resource {}", id);
+    /// Destroy a resource by ID.
+    ///
+    /// The ID is free for reuse immediately, but the D3D11 objects are only
+    /// released by `release_destroyed` once the fence current at destruction
+    /// (`fence`) has been passed, so work already queued against them is
+    /// never raced.
+    pub fn destroy_resource(&mut self, id: ResourceId, fence: u64) -> bool {
+        if let Some(resource) = self.slab_remove(id) {
+            if self.scrub_on_destroy && !self.is_aliased(&resource) {
+                self.scrub_resource(&resource);
+            }
+            self.deferred_destroys.push_back((fence, resource));
+            if self.deferred_destroys.len() > MAX_DEFERRED_DESTROYS {
+                self.deferred_destroys.pop_front();
+                debug!("Deferred destruction queue full, released oldest resource early");
+            }
+            debug!("Destroyed resource {} (release after fence {})", id, fence);
             true
         } else {
             warn!("Attempted to destroy non-existent resource {}", id);
@@ -834,6 +2084,26 @@ impl D3D11Renderer {
         }
     }
 
+    /// Release destroyed resources whose fence `completed_fence` has passed.
+    /// Returns the number released.
+    pub fn release_destroyed(&mut self, completed_fence: u64) -> usize {
+        let mut released = 0;
+        while let Some((fence, _)) = self.deferred_destroys.front() {
+            if *fence >= completed_fence {
+                break;
+            }
+            self.deferred_destroys.pop_front();
+            released += 1;
+        }
+        if released > 0 {
+            debug!(
+                "Released {} destroyed resources (fence {})",
+                released, completed_fence
+            );
+        }
+        released
+    }
+
     /// Get a resource by ID
     pub fn get_resource(&self, id: ResourceId) -> Option<&D3D11Resource> {
         self.slab_get(id)
@@ -847,6 +2117,14 @@ impl D3D11Renderer {
         }
     }
 
+    /// Get a texture's format by ID
+    pub fn get_texture_format(&self, id: ResourceId) -> Option<DXGI_FORMAT> {
+        match self.slab_get(id) {
+            Some(D3D11Resource::Texture2D { format, .. }) => Some(*format),
+            _ => None,
+        }
+    }
+
     /// Get a buffer by ID
     pub fn get_buffer(&self, id: ResourceId) -> Option<&ID3D11Buffer> {
         match self.slab_get(id) {
@@ -887,6 +2165,7 @@ impl D3D11Renderer {
                 buffer,
                 size: desc.ByteWidth,
                 bind_flags: desc.BindFlags,
+                misc_flags: desc.MiscFlags,
             },
         );
     }
@@ -903,65 +2182,371 @@ impl D3D11Renderer {
         }
     }
 
-    /// Get the DXGI factory
-    pub fn factory(&self) -> &IDXGIFactory1 {
-        &self.factory
+    /// Open a texture shared with D3D11_RESOURCE_MISC_SHARED_NTHANDLE, as D3D12
+    /// and Vulkan producers create them, and register it. The NT handle must
+    /// be valid in this process; it is left open for its owner to close.
+    pub fn open_shared_texture_nthandle(&mut self, id: ResourceId, handle: u64) -> Result<()> {
+        let device1: ID3D11Device1 = self.device.cast()?;
+        let handle = windows::Win32::Foundation::HANDLE(handle as *mut std::ffi::c_void);
+        let texture: ID3D11Texture2D = unsafe { device1.OpenSharedResource1(handle)? };
+        self.register_texture(id, texture);
+        Ok(())
     }
 
-    /// Set render targets
-    pub fn set_render_targets(
+    /// Install (or release, with `None`) the host-owned backbuffer.
+    ///
+    /// Releasing also unbinds it from the output merger, since the swapchain
+    /// cannot be resized while any reference to its buffers is outstanding.
+    ///
+    /// With `srgb_view`, the render target view uses the `_SRGB` variant of the
+    /// backbuffer's format so the guest's linear output is gamma-encoded.
+    pub fn set_host_backbuffer(
         &mut self,
-        rtv_ids: &[ResourceId],
-        dsv_id: Option<ResourceId>,
+        texture: Option<ID3D11Texture2D>,
+        srgb_view: bool,
     ) -> Result<()> {
-        // Collect RTVs
-        let mut rtvs: Vec<Option<ID3D11RenderTargetView>> = Vec::new();
-        for &id in rtv_ids {
-            if id == 0 {
-                rtvs.push(None);
-            } else if let Some(D3D11Resource::Texture2D { rtv, .. }) = self.slab_get(id) {
-                rtvs.push(rtv.clone());
-            } else if let Some(D3D11Resource::RenderTargetView { rtv }) = self.slab_get(id) {
-                rtvs.push(Some(rtv.clone()));
-            } else {
-                return Err(anyhow!("Invalid RTV resource ID: {}", id));
+        if self.host_backbuffer.take().is_some() {
+            unsafe {
+                self.context.OMSetRenderTargets(None, None);
             }
+            self.current_rtvs = vec![None; 8];
+            self.current_dsv = None;
         }
 
-        // Get DSV
-        let dsv = if let Some(id) = dsv_id {
-            if id == 0 {
-                None
-            } else if let Some(D3D11Resource::DepthStencilView { dsv }) = self.slab_get(id) {
-                Some(dsv.clone())
-            } else {
-                return Err(anyhow!("Invalid DSV resource ID: {}", id));
-            }
-        } else {
-            None
+        let texture = match texture {
+            Some(t) => t,
+            None => return Ok(()),
         };
 
-        // Set on context
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
         unsafe {
-            self.context.OMSetRenderTargets(Some(&rtvs), dsv.as_ref());
+            texture.GetDesc(&mut desc);
         }
 
-        self.current_rtvs = rtvs;
-        self.current_dsv = dsv;
+        let mut view_desc = if srgb_view {
+            srgb::srgb_view_format(desc.Format).map(srgb::rtv_desc)
+        } else {
+            None
+        };
+        let mut rtv: Option<ID3D11RenderTargetView> = None;
+        let result = unsafe {
+            self.device.CreateRenderTargetView(
+                &texture,
+                view_desc.as_ref().map(|d| d as *const _),
+                Some(&mut rtv),
+            )
+        };
+        if let Err(e) = result {
+            // Only swapchain buffers accept an _SRGB view over a typed UNORM
+            // resource; the headless shared texture does not
+            if view_desc.is_none() {
+                return Err(e.into());
+            }
+            warn!(
+                "Host backbuffer: sRGB view unavailable ({:?}), using UNORM",
+                e
+            );
+            view_desc = None;
+            unsafe {
+                self.device
+                    .CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+            }
+        }
+
+        debug!(
+            "Host backbuffer installed: {}x{}, format={:?}, srgb={}",
+            desc.Width,
+            desc.Height,
+            desc.Format,
+            view_desc.is_some()
+        );
+
+        self.host_backbuffer = Some(D3D11Resource::Texture2D {
+            texture,
+            width: desc.Width,
+            height: desc.Height,
+            format: desc.Format,
+            srv: None,
+            rtv,
+        });
 
         Ok(())
     }
 
-    /// Set viewports
-    pub fn set_viewports(&mut self, viewports: &[D3D11_VIEWPORT]) {
-        unsafe {
-            self.context.RSSetViewports(Some(viewports));
-        }
+    /// Whether a host-owned backbuffer is installed
+    pub fn has_host_backbuffer(&self) -> bool {
+        self.host_backbuffer.is_some()
     }
 
-    /// Execute a draw call
-    pub fn draw(&mut self, vertex_count: u32, start_vertex: u32) {
-        debug!("Draw: {} vertices from {}", vertex_count, start_vertex);
+    /// Copy the frame about to be presented into the previous-frame texture.
+    ///
+    /// FLIP_DISCARD swapchains do not preserve backbuffer contents across
+    /// presents, so guests that sample the prior frame (TAA, motion blur)
+    /// read this copy instead. Only the first subresource is kept; the
+    /// texture is recreated when the frame's size or format changes.
+    pub fn capture_previous_frame(&mut self, backbuffer_id: ResourceId) -> Result<()> {
+        let source = match self.slab_get(backbuffer_id) {
+            Some(D3D11Resource::Texture2D { texture, .. }) => texture.clone(),
+            _ => return Err(ProcessError::ResourceNotFound(backbuffer_id).into()),
+        };
+
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            source.GetDesc(&mut desc);
+        }
+        if desc.SampleDesc.Count > 1 || video::is_planar_yuv(desc.Format) {
+            debug!(
+                "Previous frame: backbuffer {} ({:?}, {}x MSAA) cannot be copied",
+                backbuffer_id, desc.Format, desc.SampleDesc.Count
+            );
+            return Ok(());
+        }
+
+        let reusable = matches!(
+            self.previous_frame,
+            Some(D3D11Resource::Texture2D { width, height, format, .. })
+                if width == desc.Width && height == desc.Height && format == desc.Format
+        );
+        if !reusable {
+            let copy_desc = D3D11_TEXTURE2D_DESC {
+                MipLevels: 1,
+                ArraySize: 1,
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE.0 as u32,
+                CPUAccessFlags: 0,
+                MiscFlags: 0,
+                ..desc
+            };
+            let mut texture: Option<ID3D11Texture2D> = None;
+            let mut srv: Option<ID3D11ShaderResourceView> = None;
+            unsafe {
+                self.device
+                    .CreateTexture2D(&copy_desc, None, Some(&mut texture))?;
+            }
+            let texture = texture.ok_or_else(|| anyhow!("Previous frame texture is null"))?;
+            unsafe {
+                self.device
+                    .CreateShaderResourceView(&texture, None, Some(&mut srv))?;
+            }
+            debug!(
+                "Previous frame texture created: {}x{}, format={:?}",
+                desc.Width, desc.Height, desc.Format
+            );
+            self.previous_frame = Some(D3D11Resource::Texture2D {
+                texture,
+                width: desc.Width,
+                height: desc.Height,
+                format: desc.Format,
+                srv,
+                rtv: None,
+            });
+        }
+
+        if let Some(D3D11Resource::Texture2D { texture, .. }) = &self.previous_frame {
+            unsafe {
+                self.context
+                    .CopySubresourceRegion(texture, 0, 0, 0, 0, &source, 0, None);
+            }
+        }
+        Ok(())
+    }
+
+    /// D3D11_FORMAT_SUPPORT bits of `format` on this adapter; 0 for formats
+    /// it doesn't know
+    pub fn format_support(&self, format: DXGI_FORMAT) -> u32 {
+        unsafe { self.device.CheckFormatSupport(format) }.unwrap_or(0)
+    }
+
+    /// Sample counts (of PVGPU_MSAA_SAMPLE_COUNTS) `format` supports as a
+    /// multisampled render target, with their quality levels. Unknown
+    /// formats report no support rather than failing.
+    pub fn msaa_support(&self, format: DXGI_FORMAT) -> MsaaSupport {
+        let quality_levels = PVGPU_MSAA_SAMPLE_COUNTS.map(|count| {
+            unsafe { self.device.CheckMultisampleQualityLevels(format, count) }.unwrap_or(0)
+        });
+        MsaaSupport::from_quality_levels(quality_levels)
+    }
+
+    /// Number of binds skipped as redundant since the last call
+    pub fn take_redundant_binds(&mut self) -> u64 {
+        std::mem::take(&mut self.redundant_binds)
+    }
+
+    /// Number of creations served by an existing identical resource since
+    /// the last call
+    pub fn take_deduped_uploads(&mut self) -> u64 {
+        std::mem::take(&mut self.deduped_uploads)
+    }
+
+    /// Number of shader creations served by the bytecode cache since the
+    /// last call
+    pub fn take_shader_cache_hits(&mut self) -> u64 {
+        std::mem::take(&mut self.shader_cache_hits)
+    }
+
+    /// Estimated bytes of video memory held by the guest's live resources.
+    /// Resources aliased through upload deduplication count once.
+    pub fn tracked_memory(&self) -> u64 {
+        self.tracked_bytes
+    }
+
+    /// (budget, current usage) of the adapter's local memory for this
+    /// process, or None when the adapter predates IDXGIAdapter3
+    pub fn query_video_memory(&self) -> Option<(u64, u64)> {
+        let adapter: IDXGIAdapter3 = self
+            .device
+            .cast::<IDXGIDevice>()
+            .and_then(|device| unsafe { device.GetAdapter() })
+            .and_then(|adapter| adapter.cast())
+            .ok()?;
+        let mut info = DXGI_QUERY_VIDEO_MEMORY_INFO::default();
+        match unsafe { adapter.QueryVideoMemoryInfo(0, DXGI_MEMORY_SEGMENT_GROUP_LOCAL, &mut info) }
+        {
+            Ok(()) => Some((info.Budget, info.CurrentUsage)),
+            Err(e) => {
+                debug!("QueryVideoMemoryInfo failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Get the DXGI factory
+    pub fn factory(&self) -> &IDXGIFactory1 {
+        &self.factory
+    }
+
+    /// Set render targets
+    ///
+    /// `dsv_flags` (PVGPU_DSV_READ_ONLY_*) binds a read-only copy of the
+    /// depth-stencil view. With `uav_ids` non-empty, the UAVs (created by
+    /// `create_unordered_access_view`) are bound from `uav_start_slot` in
+    /// the same call; 0 unbinds a slot.
+    pub fn set_render_targets(
+        &mut self,
+        rtv_ids: &[ResourceId],
+        dsv_id: Option<ResourceId>,
+        dsv_flags: u32,
+        uav_start_slot: u32,
+        uav_ids: &[ResourceId],
+    ) -> Result<()> {
+        // Collect RTVs
+        let mut rtvs: Vec<Option<ID3D11RenderTargetView>> = Vec::new();
+        for &id in rtv_ids {
+            if id == 0 {
+                rtvs.push(None);
+            } else if let Some(D3D11Resource::Texture2D { rtv, .. }) = self.slab_get(id) {
+                rtvs.push(rtv.clone());
+            } else if let Some(D3D11Resource::RenderTargetView { rtv }) = self.slab_get(id) {
+                rtvs.push(Some(rtv.clone()));
+            } else {
+                return Err(ProcessError::ResourceNotFound(id).into());
+            }
+        }
+
+        // Get DSV
+        let dsv = if let Some(id) = dsv_id {
+            if id == 0 {
+                None
+            } else if let Some(D3D11Resource::DepthStencilView { dsv }) = self.slab_get(id) {
+                let dsv = dsv.clone();
+                if dsv_flags == 0 {
+                    Some(dsv)
+                } else {
+                    Some(self.read_only_dsv(id, &dsv, dsv_flags)?)
+                }
+            } else {
+                return Err(ProcessError::ResourceNotFound(id).into());
+            }
+        } else {
+            None
+        };
+
+        let mut uavs: Vec<Option<ID3D11UnorderedAccessView>> = Vec::new();
+        for &id in uav_ids {
+            if id == 0 {
+                uavs.push(None);
+            } else if let Some(D3D11Resource::UnorderedAccessView { uav }) = self.slab_get(id) {
+                uavs.push(Some(uav.clone()));
+            } else {
+                return Err(ProcessError::ResourceNotFound(id).into());
+            }
+        }
+
+        // Set on context
+        unsafe {
+            if uavs.is_empty() {
+                self.context.OMSetRenderTargets(Some(&rtvs), dsv.as_ref());
+            } else {
+                // -1 keeps the current append/consume counters
+                let initial_counts = vec![u32::MAX; uavs.len()];
+                self.context.OMSetRenderTargetsAndUnorderedAccessViews(
+                    Some(&rtvs),
+                    dsv.as_ref(),
+                    uav_start_slot,
+                    uavs.len() as u32,
+                    Some(uavs.as_ptr()),
+                    Some(initial_counts.as_ptr()),
+                );
+            }
+        }
+        // The runtime unbinds SRVs of anything now bound for output (and
+        // refuses SRVs of bound targets), so cached SRVs are no longer known
+        self.state_cache.shader_resources.clear();
+
+        self.current_rtvs = rtvs;
+        self.current_dsv = dsv;
+
+        Ok(())
+    }
+
+    /// Read-only copy of depth-stencil view `id`, created on first use
+    fn read_only_dsv(
+        &mut self,
+        id: ResourceId,
+        dsv: &ID3D11DepthStencilView,
+        flags: u32,
+    ) -> Result<ID3D11DepthStencilView> {
+        if let Some(view) = self.read_only_dsvs.get(&(id, flags)) {
+            return Ok(view.clone());
+        }
+        let mut desc = D3D11_DEPTH_STENCIL_VIEW_DESC::default();
+        let resource = unsafe {
+            dsv.GetDesc(&mut desc);
+            dsv.GetResource()?
+        };
+        desc.Flags = flags;
+        let mut view: Option<ID3D11DepthStencilView> = None;
+        unsafe {
+            self.device
+                .CreateDepthStencilView(&resource, Some(&desc), Some(&mut view))?;
+        }
+        let view = view.ok_or_else(|| anyhow!("Failed to create read-only DSV"))?;
+        debug!("Created read-only DSV for {} (flags={})", id, flags);
+        self.read_only_dsvs.insert((id, flags), view.clone());
+        Ok(view)
+    }
+
+    /// Set viewports
+    pub fn set_viewports(&mut self, viewports: &[D3D11_VIEWPORT]) {
+        unsafe {
+            self.context.RSSetViewports(Some(viewports));
+        }
+        self.viewport_count = viewports.len() as u32;
+    }
+
+    /// Number of viewports currently bound
+    pub fn viewport_count(&self) -> u32 {
+        self.viewport_count
+    }
+
+    /// Whether the bound rasterizer state has scissor testing enabled
+    pub fn scissor_enabled(&self) -> bool {
+        self.scissor_enabled
+    }
+
+    /// Execute a draw call
+    pub fn draw(&mut self, vertex_count: u32, start_vertex: u32) {
+        debug!("Draw: {} vertices from {}", vertex_count, start_vertex);
         unsafe {
             self.context.Draw(vertex_count, start_vertex);
         }
@@ -994,6 +2579,46 @@ impl D3D11Renderer {
         }
     }
 
+    /// Clear an integer render target to exact integer values.
+    ///
+    /// ClearRenderTargetView converts float colors to the target's integer
+    /// format, which is exact for magnitudes up to 2^24; larger values and
+    /// non-integer formats are rejected.
+    pub fn clear_render_target_uint(&mut self, rtv_id: ResourceId, color: &[u32; 4]) -> Result<()> {
+        let rtv = match self.slab_get(rtv_id) {
+            Some(D3D11Resource::Texture2D { rtv: Some(rtv), .. })
+            | Some(D3D11Resource::RenderTargetView { rtv }) => rtv.clone(),
+            _ => return Err(ProcessError::ResourceNotFound(rtv_id).into()),
+        };
+
+        let mut desc = D3D11_RENDER_TARGET_VIEW_DESC::default();
+        unsafe {
+            rtv.GetDesc(&mut desc);
+        }
+        let signed = match integer_format_signed(desc.Format) {
+            Some(signed) => signed,
+            None => {
+                warn!(
+                    "ClearRenderTargetUint: RTV {} has non-integer format {:?}",
+                    rtv_id, desc.Format
+                );
+                return Err(ProcessError::InvalidParameter(desc.Format.0 as u32).into());
+            }
+        };
+        let float_color = exact_integer_clear(color, signed).map_err(|value| {
+            warn!(
+                "ClearRenderTargetUint: value 0x{:x} cannot be cleared exactly",
+                value
+            );
+            anyhow::Error::from(ProcessError::InvalidParameter(value))
+        })?;
+
+        unsafe {
+            self.context.ClearRenderTargetView(&rtv, &float_color);
+        }
+        Ok(())
+    }
+
     /// Flush pending commands
     pub fn flush(&mut self) {
         unsafe {
@@ -1001,6 +2626,97 @@ impl D3D11Renderer {
         }
     }
 
+    /// Signal the GPU fence with `value` once preceding work completes.
+    /// No-op without a GPU fence or if `value` was already signaled.
+    pub fn signal_fence(&mut self, value: u64) {
+        let Some(gpu) = self.gpu_fence.as_mut() else {
+            return;
+        };
+        if value == gpu.signaled {
+            return;
+        }
+        if let Err(e) = unsafe { gpu.context.Signal(&gpu.fence, value) } {
+            warn!("Fence signal {} failed: {}", value, e);
+            return;
+        }
+        gpu.signaled = value;
+        unsafe { self.context.Flush() };
+    }
+
+    /// Last fence value the GPU has completed, or None without a GPU fence
+    pub fn completed_fence(&self) -> Option<u64> {
+        self.gpu_fence
+            .as_ref()
+            .map(|gpu| unsafe { gpu.fence.GetCompletedValue() })
+    }
+
+    /// Block until the GPU fence reaches `value`, falling back to draining
+    /// the GPU with `wait_for_gpu` when there is no GPU fence.
+    pub fn wait_for_fence(&mut self, value: u64, timeout: std::time::Duration) -> Result<()> {
+        let Some(gpu) = self.gpu_fence.as_ref() else {
+            return self.wait_for_gpu(timeout);
+        };
+        if unsafe { gpu.fence.GetCompletedValue() } >= value {
+            return Ok(());
+        }
+        let event = unsafe { CreateEventW(None, false, false, None)? };
+        let result = unsafe { gpu.fence.SetEventOnCompletion(value, event) }.map(|()| unsafe {
+            WaitForSingleObject(event, timeout.as_millis().min(u32::MAX as u128) as u32)
+        });
+        unsafe {
+            let _ = CloseHandle(event);
+        }
+        if result? != WAIT_OBJECT_0 {
+            return Err(anyhow!(
+                "Fence {} wait timed out after {:?}",
+                value,
+                timeout
+            ));
+        }
+        Ok(())
+    }
+
+    /// Flush and block until the GPU has finished all submitted work.
+    ///
+    /// Uses an event query; returns an error if the GPU does not drain
+    /// within `timeout`.
+    pub fn wait_for_gpu(&mut self, timeout: std::time::Duration) -> Result<()> {
+        let desc = D3D11_QUERY_DESC {
+            Query: D3D11_QUERY_EVENT,
+            MiscFlags: 0,
+        };
+        let mut query: Option<ID3D11Query> = None;
+        unsafe {
+            self.device.CreateQuery(&desc, Some(&mut query))?;
+        }
+        let query = query.ok_or_else(|| anyhow!("Failed to create event query"))?;
+
+        unsafe {
+            self.context.End(&query);
+            self.context.Flush();
+        }
+
+        let start = std::time::Instant::now();
+        loop {
+            let mut done = windows::Win32::Foundation::BOOL(0);
+            let _ = unsafe {
+                self.context.GetData(
+                    &query,
+                    Some(&mut done as *mut _ as *mut std::ffi::c_void),
+                    std::mem::size_of::<windows::Win32::Foundation::BOOL>() as u32,
+                    0,
+                )
+            };
+            if done.as_bool() {
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                return Err(anyhow!("GPU wait timed out after {:?}", timeout));
+            }
+            std::thread::yield_now();
+        }
+    }
+
     /// Flush and signal that a frame is ready for presentation.
     ///
     /// The actual presentation is handled by the PresentationPipeline in the
@@ -1015,6 +2731,66 @@ impl D3D11Renderer {
         self.flush();
     }
 
+    // =========================================================================
+    // Debug Annotations
+    // =========================================================================
+
+    /// Open a named event region visible in GPU captures
+    pub fn begin_event(&mut self, label: &str) {
+        if let Some(ref annotation) = self.annotation {
+            let wide: Vec<u16> = label.encode_utf16().chain(std::iter::once(0)).collect();
+            unsafe {
+                annotation.BeginEvent(PCWSTR(wide.as_ptr()));
+            }
+        }
+    }
+
+    /// Close the innermost event region
+    pub fn end_event(&mut self) {
+        if let Some(ref annotation) = self.annotation {
+            unsafe {
+                annotation.EndEvent();
+            }
+        }
+    }
+
+    /// Insert a single named marker into the command stream
+    pub fn set_marker(&mut self, label: &str) {
+        if let Some(ref annotation) = self.annotation {
+            let wide: Vec<u16> = label.encode_utf16().chain(std::iter::once(0)).collect();
+            unsafe {
+                annotation.SetMarker(PCWSTR(wide.as_ptr()));
+            }
+        }
+    }
+
+    /// Name a texture, buffer or shader as it appears in GPU captures and
+    /// debug layer messages
+    pub fn set_debug_name(&mut self, id: ResourceId, name: &str) -> Result<()> {
+        let child: ID3D11DeviceChild = match self.slab_get(id) {
+            Some(D3D11Resource::Texture2D { texture, .. }) => texture.cast()?,
+            Some(D3D11Resource::Buffer { buffer, .. }) => buffer.cast()?,
+            Some(D3D11Resource::VertexShader { shader, .. }) => shader.cast()?,
+            Some(D3D11Resource::PixelShader { shader }) => shader.cast()?,
+            Some(D3D11Resource::GeometryShader { shader }) => shader.cast()?,
+            Some(D3D11Resource::HullShader { shader }) => shader.cast()?,
+            Some(D3D11Resource::DomainShader { shader }) => shader.cast()?,
+            Some(D3D11Resource::ComputeShader { shader }) => shader.cast()?,
+            Some(_) => return Err(ProcessError::InvalidParameter(id).into()),
+            None => return Err(ProcessError::ResourceNotFound(id).into()),
+        };
+        unsafe {
+            // Clear first; the debug layer warns when a name is overwritten
+            child.SetPrivateData(&WKPDID_D3DDebugObjectName, 0, None)?;
+            child.SetPrivateData(
+                &WKPDID_D3DDebugObjectName,
+                name.len() as u32,
+                Some(name.as_ptr() as *const std::ffi::c_void),
+            )?;
+        }
+        Ok(())
+    }
+
     // =========================================================================
     // State Commands
     // =========================================================================
@@ -1027,6 +2803,12 @@ impl D3D11Renderer {
         stride: u32,
         offset: u32,
     ) {
+        let key = (buffer_id, stride, offset);
+        if self.state_cache.vertex_buffers.get(&slot) == Some(&key) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if buffer_id == 0 {
             // Unbind
             let buffers: [Option<ID3D11Buffer>; 1] = [None];
@@ -1041,10 +2823,7 @@ impl D3D11Renderer {
                     Some(offsets.as_ptr()),
                 );
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
+        } else if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
             debug!(
                 "SetVertexBuffer: slot={}, buffer={}, stride={}, offset={}",
                 slot, buffer_id, stride, offset
@@ -1063,20 +2842,25 @@ impl D3D11Renderer {
             }
         } else {
             warn!("SetVertexBuffer: Invalid buffer ID {}", buffer_id);
+            return;
         }
+        self.state_cache.vertex_buffers.insert(slot, key);
     }
 
     /// Set the index buffer
     pub fn set_index_buffer(&mut self, buffer_id: ResourceId, format: DXGI_FORMAT, offset: u32) {
+        let key = (buffer_id, format, offset);
+        if self.state_cache.index_buffer == Some(key) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if buffer_id == 0 {
             // Unbind
             unsafe {
                 self.context.IASetIndexBuffer(None, format, offset);
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
+        } else if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
             debug!(
                 "SetIndexBuffer: buffer={}, format={:?}, offset={}",
                 buffer_id, format, offset
@@ -1086,69 +2870,120 @@ impl D3D11Renderer {
             }
         } else {
             warn!("SetIndexBuffer: Invalid buffer ID {}", buffer_id);
+            return;
         }
+        self.state_cache.index_buffer = Some(key);
     }
 
     /// Set a constant buffer for a shader stage
     pub fn set_constant_buffer(&mut self, stage: u32, slot: u32, buffer_id: ResourceId) {
-        let buffer = if buffer_id == 0 {
-            None
-        } else if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
-            Some(buffer.clone())
-        } else {
-            warn!("SetConstantBuffer: Invalid buffer ID {}", buffer_id);
+        self.set_constant_buffers(stage, slot, &[buffer_id]);
+    }
+
+    /// Set consecutive constant buffers for a shader stage in one call.
+    /// Nothing is bound if any ID is invalid.
+    pub fn set_constant_buffers(&mut self, stage: u32, start_slot: u32, buffer_ids: &[ResourceId]) {
+        let cached = (start_slot..)
+            .zip(buffer_ids)
+            .all(|(slot, id)| self.state_cache.constant_buffers.get(&(stage, slot)) == Some(id));
+        if cached {
+            self.redundant_binds += 1;
             return;
-        };
+        }
+
+        let mut buffers = Vec::with_capacity(buffer_ids.len());
+        for &buffer_id in buffer_ids {
+            if buffer_id == 0 {
+                buffers.push(None);
+            } else if let Some(D3D11Resource::Buffer { buffer, .. }) = self.slab_get(buffer_id) {
+                buffers.push(Some(buffer.clone()));
+            } else {
+                warn!("SetConstantBuffers: Invalid buffer ID {}", buffer_id);
+                return;
+            }
+        }
 
         debug!(
-            "SetConstantBuffer: stage={}, slot={}, buffer={}",
-            stage, slot, buffer_id
+            "SetConstantBuffers: stage={}, start_slot={}, buffers={:?}",
+            stage, start_slot, buffer_ids
         );
 
-        let buffers = [buffer];
         unsafe {
             match stage {
-                0 => self.context.VSSetConstantBuffers(slot, Some(&buffers)),
-                1 => self.context.PSSetConstantBuffers(slot, Some(&buffers)),
-                2 => self.context.GSSetConstantBuffers(slot, Some(&buffers)),
-                3 => self.context.HSSetConstantBuffers(slot, Some(&buffers)),
-                4 => self.context.DSSetConstantBuffers(slot, Some(&buffers)),
-                5 => self.context.CSSetConstantBuffers(slot, Some(&buffers)),
-                _ => warn!("SetConstantBuffer: Unknown stage {}", stage),
+                0 => self
+                    .context
+                    .VSSetConstantBuffers(start_slot, Some(&buffers)),
+                1 => self
+                    .context
+                    .PSSetConstantBuffers(start_slot, Some(&buffers)),
+                2 => self
+                    .context
+                    .GSSetConstantBuffers(start_slot, Some(&buffers)),
+                3 => self
+                    .context
+                    .HSSetConstantBuffers(start_slot, Some(&buffers)),
+                4 => self
+                    .context
+                    .DSSetConstantBuffers(start_slot, Some(&buffers)),
+                5 => self
+                    .context
+                    .CSSetConstantBuffers(start_slot, Some(&buffers)),
+                _ => warn!("SetConstantBuffers: Unknown stage {}", stage),
+            }
+        }
+        if stage < SHADER_STAGE_COUNT {
+            for (slot, &buffer_id) in (start_slot..).zip(buffer_ids) {
+                self.state_cache
+                    .constant_buffers
+                    .insert((stage, slot), buffer_id);
             }
         }
     }
 
     /// Set the input layout
     pub fn set_input_layout(&mut self, layout_id: ResourceId) {
+        if self.state_cache.input_layout == Some(layout_id) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if layout_id == 0 {
             unsafe {
                 self.context.IASetInputLayout(None);
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::InputLayout { layout }) = self.slab_get(layout_id) {
+        } else if let Some(D3D11Resource::InputLayout { layout }) = self.slab_get(layout_id) {
             debug!("SetInputLayout: layout={}", layout_id);
             unsafe {
                 self.context.IASetInputLayout(layout);
             }
         } else {
             warn!("SetInputLayout: Invalid layout ID {}", layout_id);
+            return;
         }
+        self.state_cache.input_layout = Some(layout_id);
     }
 
     /// Set the primitive topology
     pub fn set_primitive_topology(&mut self, topology: u32) {
+        if self.state_cache.topology == Some(topology) {
+            self.redundant_binds += 1;
+            return;
+        }
         debug!("SetPrimitiveTopology: topology={}", topology);
         unsafe {
             self.context
                 .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY(topology as i32));
         }
+        self.state_cache.topology = Some(topology);
     }
 
     /// Set a sampler for a shader stage
     pub fn set_sampler(&mut self, stage: u32, slot: u32, sampler_id: ResourceId) {
+        if self.state_cache.samplers.get(&(stage, slot)) == Some(&sampler_id) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         let sampler = if sampler_id == 0 {
             None
         } else if let Some(D3D11Resource::SamplerState { state }) = self.slab_get(sampler_id) {
@@ -1175,20 +3010,26 @@ impl D3D11Renderer {
                 _ => warn!("SetSampler: Unknown stage {}", stage),
             }
         }
+        if stage < SHADER_STAGE_COUNT {
+            self.state_cache.samplers.insert((stage, slot), sampler_id);
+        }
     }
 
     /// Set a shader resource view for a shader stage
     pub fn set_shader_resource(&mut self, stage: u32, slot: u32, srv_id: ResourceId) {
+        if self.state_cache.shader_resources.get(&(stage, slot)) == Some(&srv_id) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         let srv = if srv_id == 0 {
             None
-        } else if let Some(D3D11Resource::Texture2D { srv: Some(srv), .. }) = self.slab_get(srv_id)
-        {
-            Some(srv.clone())
-        } else if let Some(D3D11Resource::ShaderResourceView { srv }) = self.slab_get(srv_id) {
-            Some(srv.clone())
         } else {
-            warn!("SetShaderResource: Invalid SRV ID {}", srv_id);
-            return;
+            let Some(srv) = self.shader_resource_view(srv_id) else {
+                warn!("SetShaderResource: Invalid SRV ID {}", srv_id);
+                return;
+            };
+            Some(srv)
         };
 
         debug!(
@@ -1208,24 +3049,346 @@ impl D3D11Renderer {
                 _ => warn!("SetShaderResource: Unknown stage {}", stage),
             }
         }
+        if stage < SHADER_STAGE_COUNT {
+            self.state_cache
+                .shader_resources
+                .insert((stage, slot), srv_id);
+        }
     }
 
-    /// Set the blend state
+    /// Set consecutive shader resource views for a shader stage in one
+    /// call; ID 0 unbinds its slot. If any ID is invalid, the views are
+    /// bound one slot at a time so only the invalid slots are skipped.
+    pub fn set_shader_resources(&mut self, stage: u32, start_slot: u32, srv_ids: &[ResourceId]) {
+        let cached = (start_slot..)
+            .zip(srv_ids)
+            .all(|(slot, id)| self.state_cache.shader_resources.get(&(stage, slot)) == Some(id));
+        if cached {
+            self.redundant_binds += 1;
+            return;
+        }
+
+        let mut srvs = Vec::with_capacity(srv_ids.len());
+        for &srv_id in srv_ids {
+            if srv_id == 0 {
+                srvs.push(None);
+            } else if let Some(srv) = self.shader_resource_view(srv_id) {
+                srvs.push(Some(srv));
+            } else {
+                for (slot, &srv_id) in (start_slot..).zip(srv_ids) {
+                    self.set_shader_resource(stage, slot, srv_id);
+                }
+                return;
+            }
+        }
+
+        debug!(
+            "SetShaderResources: stage={}, start_slot={}, srvs={:?}",
+            stage, start_slot, srv_ids
+        );
+
+        unsafe {
+            match stage {
+                0 => self.context.VSSetShaderResources(start_slot, Some(&srvs)),
+                1 => self.context.PSSetShaderResources(start_slot, Some(&srvs)),
+                2 => self.context.GSSetShaderResources(start_slot, Some(&srvs)),
+                3 => self.context.HSSetShaderResources(start_slot, Some(&srvs)),
+                4 => self.context.DSSetShaderResources(start_slot, Some(&srvs)),
+                5 => self.context.CSSetShaderResources(start_slot, Some(&srvs)),
+                _ => warn!("SetShaderResources: Unknown stage {}", stage),
+            }
+        }
+        if stage < SHADER_STAGE_COUNT {
+            for (slot, &srv_id) in (start_slot..).zip(srv_ids) {
+                self.state_cache
+                    .shader_resources
+                    .insert((stage, slot), srv_id);
+            }
+        }
+    }
+
+    /// The view a shader samples for `id`: its own SRV, or the default SRV
+    /// of a texture
+    fn shader_resource_view(&self, id: ResourceId) -> Option<ID3D11ShaderResourceView> {
+        match self.slab_get(id) {
+            Some(D3D11Resource::Texture2D { srv: Some(srv), .. }) => Some(srv.clone()),
+            Some(D3D11Resource::ShaderResourceView { srv }) => Some(srv.clone()),
+            _ => None,
+        }
+    }
+
+    /// Create unordered access view `cmd.view_id` of a buffer or 2D texture
+    /// bound for unordered access
+    pub fn create_unordered_access_view(
+        &mut self,
+        cmd: &CmdCreateUnorderedAccessView,
+    ) -> Result<()> {
+        let view_id = cmd.view_id;
+        let (resource, bind_flags, dimension, anonymous): (ID3D11Resource, _, _, _) =
+            match (self.slab_get(cmd.resource_id), cmd.view_dimension) {
+                (
+                    Some(D3D11Resource::Buffer {
+                        buffer,
+                        bind_flags,
+                        misc_flags,
+                        ..
+                    }),
+                    PVGPU_UAV_DIMENSION_BUFFER,
+                ) => {
+                    if let Err(reason) = buffer_uav_flags_valid(cmd.flags, *misc_flags) {
+                        warn!(
+                            "CreateUnorderedAccessView: view {} of buffer {}: {}",
+                            view_id, cmd.resource_id, reason
+                        );
+                        return Err(ProcessError::InvalidParameter(view_id).into());
+                    }
+                    (
+                        buffer.cast()?,
+                        *bind_flags,
+                        D3D11_UAV_DIMENSION_BUFFER,
+                        D3D11_UNORDERED_ACCESS_VIEW_DESC_0 {
+                            Buffer: D3D11_BUFFER_UAV {
+                                FirstElement: cmd.first_element,
+                                NumElements: cmd.num_elements,
+                                Flags: cmd.flags,
+                            },
+                        },
+                    )
+                }
+                (
+                    Some(D3D11Resource::Texture2D { texture, .. }),
+                    PVGPU_UAV_DIMENSION_TEXTURE2D | PVGPU_UAV_DIMENSION_TEXTURE2DARRAY,
+                ) => {
+                    let mut desc = D3D11_TEXTURE2D_DESC::default();
+                    unsafe {
+                        texture.GetDesc(&mut desc);
+                    }
+                    let (dimension, anonymous) =
+                        if cmd.view_dimension == PVGPU_UAV_DIMENSION_TEXTURE2D {
+                            (
+                                D3D11_UAV_DIMENSION_TEXTURE2D,
+                                D3D11_UNORDERED_ACCESS_VIEW_DESC_0 {
+                                    Texture2D: D3D11_TEX2D_UAV {
+                                        MipSlice: cmd.first_element,
+                                    },
+                                },
+                            )
+                        } else {
+                            (
+                                D3D11_UAV_DIMENSION_TEXTURE2DARRAY,
+                                D3D11_UNORDERED_ACCESS_VIEW_DESC_0 {
+                                    Texture2DArray: D3D11_TEX2D_ARRAY_UAV {
+                                        MipSlice: cmd.first_element,
+                                        FirstArraySlice: cmd.num_elements,
+                                        ArraySize: cmd.flags,
+                                    },
+                                },
+                            )
+                        };
+                    (texture.cast()?, desc.BindFlags, dimension, anonymous)
+                }
+                (Some(_), dimension) => {
+                    warn!(
+                        "CreateUnorderedAccessView: dimension {} does not fit resource {}",
+                        dimension, cmd.resource_id
+                    );
+                    return Err(ProcessError::InvalidParameter(view_id).into());
+                }
+                (None, _) => return Err(ProcessError::ResourceNotFound(cmd.resource_id).into()),
+            };
+        if bind_flags & D3D11_BIND_UNORDERED_ACCESS.0 as u32 == 0 {
+            warn!(
+                "CreateUnorderedAccessView: resource {} lacks BIND_UNORDERED_ACCESS",
+                cmd.resource_id
+            );
+            return Err(ProcessError::InvalidParameter(view_id).into());
+        }
+
+        let desc = D3D11_UNORDERED_ACCESS_VIEW_DESC {
+            Format: DXGI_FORMAT(cmd.format as i32),
+            ViewDimension: dimension,
+            Anonymous: anonymous,
+        };
+        let mut uav: Option<ID3D11UnorderedAccessView> = None;
+        let result = unsafe {
+            self.device
+                .CreateUnorderedAccessView(&resource, Some(&desc), Some(&mut uav))
+        };
+        // The runtime checks the format and the element and slice ranges
+        let Some(uav) = result.ok().and(uav) else {
+            warn!(
+                "CreateUnorderedAccessView: view {} of resource {} rejected (format {})",
+                view_id, cmd.resource_id, cmd.format
+            );
+            return Err(ProcessError::InvalidParameter(view_id).into());
+        };
+
+        debug!(
+            "Created UAV {} of resource {} (dimension={}, flags={:#x})",
+            view_id, cmd.resource_id, cmd.view_dimension, cmd.flags
+        );
+        self.slab_insert(view_id, D3D11Resource::UnorderedAccessView { uav });
+        Ok(())
+    }
+
+    /// Copy the hidden counter of append or counter UAV `src_uav_id` into
+    /// indirect-args buffer `dst_id` at byte `dst_offset`
+    pub fn copy_structure_count(
+        &mut self,
+        dst_id: ResourceId,
+        dst_offset: u32,
+        src_uav_id: ResourceId,
+    ) -> Result<()> {
+        let uav = match self.slab_get(src_uav_id) {
+            Some(D3D11Resource::UnorderedAccessView { uav }) => uav.clone(),
+            _ => {
+                warn!("CopyStructureCount: invalid UAV ID {}", src_uav_id);
+                return Err(ProcessError::ResourceNotFound(src_uav_id).into());
+            }
+        };
+        let mut desc = D3D11_UNORDERED_ACCESS_VIEW_DESC::default();
+        unsafe {
+            uav.GetDesc(&mut desc);
+        }
+        let counters = PVGPU_UAV_FLAG_APPEND | PVGPU_UAV_FLAG_COUNTER;
+        if desc.ViewDimension != D3D11_UAV_DIMENSION_BUFFER
+            || unsafe { desc.Anonymous.Buffer.Flags } & counters == 0
+        {
+            warn!(
+                "CopyStructureCount: UAV {} is not an append or counter buffer view",
+                src_uav_id
+            );
+            return Err(ProcessError::InvalidParameter(src_uav_id).into());
+        }
+
+        let buffer = match self.slab_get(dst_id) {
+            Some(D3D11Resource::Buffer {
+                buffer,
+                size,
+                misc_flags,
+                ..
+            }) if misc_flags & PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS != 0
+                && dst_offset.is_multiple_of(4)
+                && dst_offset as u64 + 4 <= *size as u64 =>
+            {
+                buffer.clone()
+            }
+            _ => {
+                warn!(
+                    "CopyStructureCount: buffer {} cannot take a count at offset {}",
+                    dst_id, dst_offset
+                );
+                return Err(ProcessError::InvalidParameter(dst_id).into());
+            }
+        };
+
+        debug!(
+            "CopyStructureCount: uav={} -> buffer={} at {}",
+            src_uav_id, dst_id, dst_offset
+        );
+        unsafe {
+            self.context.CopyStructureCount(&buffer, dst_offset, &uav);
+        }
+        Ok(())
+    }
+
+    /// Create a blend state from a validated CREATE_BLEND_STATE command.
+    /// Targets after `num_targets` keep D3D11's defaults; a logic op needs
+    /// ID3D11Device1::CreateBlendState1.
+    pub fn create_blend_state(&mut self, cmd: &CmdCreateBlendState) -> Result<()> {
+        let mut targets = [D3D11_RENDER_TARGET_BLEND_DESC1 {
+            BlendEnable: false.into(),
+            LogicOpEnable: false.into(),
+            SrcBlend: D3D11_BLEND_ONE,
+            DestBlend: D3D11_BLEND_ZERO,
+            BlendOp: D3D11_BLEND_OP_ADD,
+            SrcBlendAlpha: D3D11_BLEND_ONE,
+            DestBlendAlpha: D3D11_BLEND_ZERO,
+            BlendOpAlpha: D3D11_BLEND_OP_ADD,
+            LogicOp: D3D11_LOGIC_OP_NOOP,
+            RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+        }; 8];
+        let described = &cmd.render_targets[..cmd.num_targets as usize];
+        for (target, src) in targets.iter_mut().zip(described) {
+            target.RenderTargetWriteMask = src.write_mask as u8;
+            if src.blend_enable != 0 {
+                target.BlendEnable = true.into();
+                target.SrcBlend = D3D11_BLEND(src.src_blend as i32);
+                target.DestBlend = D3D11_BLEND(src.dest_blend as i32);
+                target.BlendOp = D3D11_BLEND_OP(src.blend_op as i32);
+                target.SrcBlendAlpha = D3D11_BLEND(src.src_blend_alpha as i32);
+                target.DestBlendAlpha = D3D11_BLEND(src.dest_blend_alpha as i32);
+                target.BlendOpAlpha = D3D11_BLEND_OP(src.blend_op_alpha as i32);
+            }
+        }
+        let alpha_to_coverage = (cmd.alpha_to_coverage != 0).into();
+        let independent_blend = (cmd.independent_blend != 0).into();
+
+        let state: ID3D11BlendState = if cmd.logic_op_enable != 0 {
+            // Without independent blend, D3D11 applies target 0 everywhere
+            targets[0].LogicOpEnable = true.into();
+            targets[0].LogicOp = D3D11_LOGIC_OP(cmd.logic_op as i32);
+            let desc = D3D11_BLEND_DESC1 {
+                AlphaToCoverageEnable: alpha_to_coverage,
+                IndependentBlendEnable: independent_blend,
+                RenderTarget: targets,
+            };
+            let device1: ID3D11Device1 = self.device.cast()?;
+            let mut state: Option<ID3D11BlendState1> = None;
+            unsafe { device1.CreateBlendState1(&desc, Some(&mut state))? };
+            state
+                .ok_or_else(|| anyhow!("CreateBlendState1 returned null"))?
+                .cast()?
+        } else {
+            let desc = D3D11_BLEND_DESC {
+                AlphaToCoverageEnable: alpha_to_coverage,
+                IndependentBlendEnable: independent_blend,
+                RenderTarget: targets.map(|target| D3D11_RENDER_TARGET_BLEND_DESC {
+                    BlendEnable: target.BlendEnable,
+                    SrcBlend: target.SrcBlend,
+                    DestBlend: target.DestBlend,
+                    BlendOp: target.BlendOp,
+                    SrcBlendAlpha: target.SrcBlendAlpha,
+                    DestBlendAlpha: target.DestBlendAlpha,
+                    BlendOpAlpha: target.BlendOpAlpha,
+                    RenderTargetWriteMask: target.RenderTargetWriteMask,
+                }),
+            };
+            let mut state: Option<ID3D11BlendState> = None;
+            unsafe { self.device.CreateBlendState(&desc, Some(&mut state))? };
+            state.ok_or_else(|| anyhow!("CreateBlendState returned null"))?
+        };
+
+        debug!(
+            "Created BlendState: id={}, targets={}, independent={}, logic_op={}",
+            cmd.state_id,
+            cmd.num_targets,
+            cmd.independent_blend != 0,
+            cmd.logic_op_enable != 0
+        );
+        self.slab_insert(cmd.state_id, D3D11Resource::BlendState { state });
+        Ok(())
+    }
+
+    /// Set the blend state
     pub fn set_blend_state(
         &mut self,
         state_id: ResourceId,
         blend_factor: &[f32; 4],
         sample_mask: u32,
     ) {
+        let key = (state_id, blend_factor.map(f32::to_bits), sample_mask);
+        if self.state_cache.blend == Some(key) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if state_id == 0 {
             unsafe {
                 self.context
                     .OMSetBlendState(None, Some(blend_factor), sample_mask);
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::BlendState { state }) = self.slab_get(state_id) {
+        } else if let Some(D3D11Resource::BlendState { state }) = self.slab_get(state_id) {
             debug!("SetBlendState: state={}", state_id);
             unsafe {
                 self.context
@@ -1233,38 +3396,51 @@ impl D3D11Renderer {
             }
         } else {
             warn!("SetBlendState: Invalid state ID {}", state_id);
+            return;
         }
+        self.state_cache.blend = Some(key);
     }
 
     /// Set the rasterizer state
     pub fn set_rasterizer_state(&mut self, state_id: ResourceId) {
+        if self.state_cache.rasterizer == Some(state_id) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if state_id == 0 {
             unsafe {
                 self.context.RSSetState(None);
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::RasterizerState { state }) = self.slab_get(state_id) {
+            // The default rasterizer state has scissoring off
+            self.scissor_enabled = false;
+        } else if let Some(D3D11Resource::RasterizerState { state }) = self.slab_get(state_id) {
             debug!("SetRasterizerState: state={}", state_id);
+            let mut desc = D3D11_RASTERIZER_DESC::default();
             unsafe {
                 self.context.RSSetState(state);
+                state.GetDesc(&mut desc);
             }
+            self.scissor_enabled = desc.ScissorEnable.as_bool();
         } else {
             warn!("SetRasterizerState: Invalid state ID {}", state_id);
+            return;
         }
+        self.state_cache.rasterizer = Some(state_id);
     }
 
     /// Set the depth-stencil state
     pub fn set_depth_stencil_state(&mut self, state_id: ResourceId, stencil_ref: u32) {
+        if self.state_cache.depth_stencil == Some((state_id, stencil_ref)) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if state_id == 0 {
             unsafe {
                 self.context.OMSetDepthStencilState(None, stencil_ref);
             }
-            return;
-        }
-
-        if let Some(D3D11Resource::DepthStencilState { state }) = self.slab_get(state_id) {
+        } else if let Some(D3D11Resource::DepthStencilState { state }) = self.slab_get(state_id) {
             debug!(
                 "SetDepthStencilState: state={}, ref={}",
                 state_id, stencil_ref
@@ -1274,7 +3450,9 @@ impl D3D11Renderer {
             }
         } else {
             warn!("SetDepthStencilState: Invalid state ID {}", state_id);
+            return;
         }
+        self.state_cache.depth_stencil = Some((state_id, stencil_ref));
     }
 
     /// Set scissor rectangles
@@ -1287,6 +3465,12 @@ impl D3D11Renderer {
 
     /// Set a shader
     pub fn set_shader(&mut self, stage: u32, shader_id: ResourceId) {
+        let cached = self.state_cache.shaders.get(stage as usize).copied();
+        if cached == Some(Some(shader_id)) {
+            self.redundant_binds += 1;
+            return;
+        }
+
         if shader_id == 0 {
             // Unbind shader
             debug!("SetShader: stage={}, unbinding", stage);
@@ -1301,6 +3485,9 @@ impl D3D11Renderer {
                     _ => warn!("SetShader: Unknown stage {}", stage),
                 }
             }
+            if let Some(bound) = self.state_cache.shaders.get_mut(stage as usize) {
+                *bound = Some(0);
+            }
             return;
         }
 
@@ -1314,6 +3501,7 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid vertex shader ID {}", shader_id);
+                    return;
                 }
             }
             1 => {
@@ -1323,6 +3511,7 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid pixel shader ID {}", shader_id);
+                    return;
                 }
             }
             2 => {
@@ -1332,6 +3521,7 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid geometry shader ID {}", shader_id);
+                    return;
                 }
             }
             3 => {
@@ -1341,6 +3531,7 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid hull shader ID {}", shader_id);
+                    return;
                 }
             }
             4 => {
@@ -1350,6 +3541,7 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid domain shader ID {}", shader_id);
+                    return;
                 }
             }
             5 => {
@@ -1359,12 +3551,15 @@ impl D3D11Renderer {
                     }
                 } else {
                     warn!("SetShader: Invalid compute shader ID {}", shader_id);
+                    return;
                 }
             }
             _ => {
                 warn!("SetShader: Unknown stage {}", stage);
+                return;
             }
         }
+        self.state_cache.shaders[stage as usize] = Some(shader_id);
     }
 
     // =========================================================================
@@ -1413,12 +3608,200 @@ impl D3D11Renderer {
         }
     }
 
-    /// Dispatch a compute shader
-    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) {
+    // =========================================================================
+    // Indirect Draws
+    // =========================================================================
+
+    /// Look up an indirect args buffer and check `size` bytes at `offset` fit
+    fn indirect_args_buffer(
+        &self,
+        args_id: ResourceId,
+        offset: u32,
+        size: u32,
+    ) -> Result<ID3D11Buffer> {
+        let (buffer, buffer_size) = match self.slab_get(args_id) {
+            Some(D3D11Resource::Buffer {
+                buffer,
+                size,
+                misc_flags,
+                ..
+            }) if misc_flags & PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS != 0 => {
+                (buffer.clone(), *size)
+            }
+            Some(D3D11Resource::Buffer { .. }) => {
+                // The runtime drops indirect calls on any other buffer
+                warn!(
+                    "Indirect call: buffer {} was not created with DRAWINDIRECT_ARGS",
+                    args_id
+                );
+                return Err(ProcessError::InvalidParameter(args_id).into());
+            }
+            _ => {
+                warn!("Indirect call: invalid args buffer ID {}", args_id);
+                return Err(ProcessError::InvalidParameter(args_id).into());
+            }
+        };
+
+        let in_bounds = offset
+            .checked_add(size)
+            .map(|end| end <= buffer_size)
+            .unwrap_or(false);
+        if !offset.is_multiple_of(4) || !in_bounds {
+            warn!(
+                "Indirect call: args at offset {} (+{}) invalid for buffer {} of {} bytes",
+                offset, size, args_id, buffer_size
+            );
+            return Err(ProcessError::InvalidParameter(args_id).into());
+        }
+        Ok(buffer)
+    }
+
+    /// Copy `N` u32 args out of a GPU buffer through a staging buffer.
+    ///
+    /// This stalls until the GPU has produced the args.
+    fn read_indirect_args<const N: usize>(
+        &mut self,
+        buffer: &ID3D11Buffer,
+        offset: u32,
+    ) -> Result<[u32; N]> {
+        if self.indirect_staging.is_none() {
+            let desc = D3D11_BUFFER_DESC {
+                ByteWidth: MAX_INDIRECT_ARGS_SIZE,
+                Usage: D3D11_USAGE_STAGING,
+                BindFlags: 0,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                MiscFlags: 0,
+                StructureByteStride: 0,
+            };
+            let mut staging: Option<ID3D11Buffer> = None;
+            unsafe {
+                self.device.CreateBuffer(&desc, None, Some(&mut staging))?;
+            }
+            self.indirect_staging = staging;
+        }
+        let staging = self
+            .indirect_staging
+            .as_ref()
+            .ok_or_else(|| anyhow!("Failed to create indirect args staging buffer"))?;
+
+        let size = (N * 4) as u32;
+        let src_box = D3D11_BOX {
+            left: offset,
+            top: 0,
+            front: 0,
+            right: offset + size,
+            bottom: 1,
+            back: 1,
+        };
+
+        let mut args = [0u32; N];
+        unsafe {
+            self.context
+                .CopySubresourceRegion(staging, 0, 0, 0, 0, buffer, 0, Some(&src_box));
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.context
+                .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            std::ptr::copy_nonoverlapping(mapped.pData as *const u32, args.as_mut_ptr(), N);
+            self.context.Unmap(staging, 0);
+        }
+        Ok(args)
+    }
+
+    /// Reject draws whose element count times instance count is absurd
+    fn check_indirect_draw(args_id: ResourceId, elements: u32, instances: u32) -> Result<()> {
+        let total = elements as u64 * instances as u64;
+        if total > MAX_INDIRECT_DRAW_ELEMENTS {
+            warn!(
+                "Indirect draw rejected: {} elements x {} instances exceeds limit {}",
+                elements, instances, MAX_INDIRECT_DRAW_ELEMENTS
+            );
+            return Err(ProcessError::InvalidParameter(args_id).into());
+        }
+        Ok(())
+    }
+
+    /// Draw instanced primitives with args from a GPU buffer
+    pub fn draw_instanced_indirect(&mut self, args_id: ResourceId, offset: u32) -> Result<()> {
+        let buffer = self.indirect_args_buffer(args_id, offset, 16)?;
+
+        if self.validate_indirect_args {
+            // VertexCountPerInstance, InstanceCount, StartVertex, StartInstance
+            let args = self.read_indirect_args::<4>(&buffer, offset)?;
+            Self::check_indirect_draw(args_id, args[0], args[1])?;
+        }
+
+        debug!(
+            "DrawInstancedIndirect: buffer {}, offset {}",
+            args_id, offset
+        );
+        unsafe {
+            self.context.DrawInstancedIndirect(&buffer, offset);
+        }
+        Ok(())
+    }
+
+    /// Draw indexed, instanced primitives with args from a GPU buffer
+    pub fn draw_indexed_instanced_indirect(
+        &mut self,
+        args_id: ResourceId,
+        offset: u32,
+    ) -> Result<()> {
+        let buffer = self.indirect_args_buffer(args_id, offset, 20)?;
+
+        if self.validate_indirect_args {
+            // IndexCountPerInstance, InstanceCount, StartIndex, BaseVertex, StartInstance
+            let args = self.read_indirect_args::<5>(&buffer, offset)?;
+            Self::check_indirect_draw(args_id, args[0], args[1])?;
+        }
+
+        debug!(
+            "DrawIndexedInstancedIndirect: buffer {}, offset {}",
+            args_id, offset
+        );
+        unsafe {
+            self.context.DrawIndexedInstancedIndirect(&buffer, offset);
+        }
+        Ok(())
+    }
+
+    /// Dispatch a compute shader with thread group counts from a GPU buffer
+    pub fn dispatch_indirect(&mut self, args_id: ResourceId, offset: u32) -> Result<()> {
+        let buffer = self.indirect_args_buffer(args_id, offset, 12)?;
+
+        if self.validate_indirect_args {
+            let groups = self.read_indirect_args::<3>(&buffer, offset)?;
+            if !dispatch_groups_valid(groups, self.strict_dispatch_limits) {
+                warn!(
+                    "Indirect dispatch rejected: {}x{}x{} thread groups",
+                    groups[0], groups[1], groups[2]
+                );
+                return Err(ProcessError::InvalidParameter(args_id).into());
+            }
+        }
+
+        debug!("DispatchIndirect: buffer {}, offset {}", args_id, offset);
+        unsafe {
+            self.context.DispatchIndirect(&buffer, offset);
+        }
+        Ok(())
+    }
+
+    /// Dispatch a compute shader.
+    ///
+    /// Counts past the D3D11 per-dimension limit, or the strict budget when
+    /// enabled, fail with the largest count as the invalid parameter.
+    pub fn dispatch(&mut self, x: u32, y: u32, z: u32) -> Result<()> {
+        if !dispatch_groups_valid([x, y, z], self.strict_dispatch_limits) {
+            warn!("Dispatch rejected: {}x{}x{} thread groups", x, y, z);
+            return Err(ProcessError::InvalidParameter(x.max(y).max(z)).into());
+        }
+
         debug!("Dispatch: {}x{}x{}", x, y, z);
         unsafe {
             self.context.Dispatch(x, y, z);
         }
+        Ok(())
     }
 
     /// Clear a depth-stencil view
@@ -1445,30 +3828,115 @@ impl D3D11Renderer {
     }
 
     /// Copy entire resource
-    pub fn copy_resource(&mut self, dst_id: ResourceId, src_id: ResourceId) {
-        let src_resource: Option<ID3D11Resource> = match self.slab_get(src_id) {
-            Some(D3D11Resource::Texture2D { texture, .. }) => texture.cast().ok(),
-            Some(D3D11Resource::Buffer { buffer, .. }) => buffer.cast().ok(),
-            _ => None,
+    ///
+    /// Both resources must be the same kind with matching dimensions and
+    /// formats in the same family, or D3D11 drops the copy.
+    pub fn copy_resource(&mut self, dst_id: ResourceId, src_id: ResourceId) -> Result<()> {
+        let (Some(dst_shape), Some(src_shape)) = (self.copy_shape(dst_id), self.copy_shape(src_id))
+        else {
+            warn!(
+                "CopyResource: Invalid resource IDs dst={} src={}",
+                dst_id, src_id
+            );
+            return Ok(());
         };
 
-        let dst_resource: Option<ID3D11Resource> = match self.slab_get(dst_id) {
-            Some(D3D11Resource::Texture2D { texture, .. }) => texture.cast().ok(),
-            Some(D3D11Resource::Buffer { buffer, .. }) => buffer.cast().ok(),
-            _ => None,
-        };
+        if self.is_immutable(dst_id) {
+            warn!("CopyResource: dst={} is immutable", dst_id);
+            return Err(ProcessError::InvalidParameter(dst_id).into());
+        }
+
+        if let Some(mismatch) = copy_shape_mismatch(&dst_shape, &src_shape) {
+            warn!(
+                "CopyResource: dst={} src={} differ in {} ({:?} vs {:?})",
+                dst_id, src_id, mismatch, dst_shape, src_shape
+            );
+            return Err(ProcessError::InvalidParameter(dst_id).into());
+        }
 
-        if let (Some(dst), Some(src)) = (dst_resource, src_resource) {
+        let resource = |id| -> Option<ID3D11Resource> {
+            match self.slab_get(id) {
+                Some(D3D11Resource::Texture2D { texture, .. }) => texture.cast().ok(),
+                Some(D3D11Resource::Buffer { buffer, .. }) => buffer.cast().ok(),
+                _ => None,
+            }
+        };
+        if let (Some(dst), Some(src)) = (resource(dst_id), resource(src_id)) {
             debug!("CopyResource: dst={}, src={}", dst_id, src_id);
             unsafe {
                 self.context.CopyResource(&dst, &src);
             }
-        } else {
+        }
+        Ok(())
+    }
+
+    /// Whether a texture or buffer was created PVGPU_USAGE_IMMUTABLE
+    fn is_immutable(&self, id: ResourceId) -> bool {
+        if self.scrubbable_immutables.contains(&id) {
+            return true;
+        }
+        let usage = match self.slab_get(id) {
+            Some(D3D11Resource::Texture2D { texture, .. }) => {
+                let mut desc = D3D11_TEXTURE2D_DESC::default();
+                unsafe {
+                    texture.GetDesc(&mut desc);
+                }
+                desc.Usage
+            }
+            Some(D3D11Resource::Buffer { buffer, .. }) => {
+                let mut desc = D3D11_BUFFER_DESC::default();
+                unsafe {
+                    buffer.GetDesc(&mut desc);
+                }
+                desc.Usage
+            }
+            _ => return false,
+        };
+        usage == D3D11_USAGE_IMMUTABLE
+    }
+
+    /// What CopyResource requires to match for a texture or buffer
+    fn copy_shape(&self, id: ResourceId) -> Option<CopyShape> {
+        match self.slab_get(id)? {
+            D3D11Resource::Texture2D { texture, .. } => {
+                let mut desc = D3D11_TEXTURE2D_DESC::default();
+                unsafe {
+                    texture.GetDesc(&mut desc);
+                }
+                Some(CopyShape::Texture2D {
+                    width: desc.Width,
+                    height: desc.Height,
+                    mip_levels: desc.MipLevels,
+                    array_size: desc.ArraySize,
+                    sample_count: desc.SampleDesc.Count,
+                    format: desc.Format,
+                })
+            }
+            D3D11Resource::Buffer { size, .. } => Some(CopyShape::Buffer { size: *size }),
+            _ => None,
+        }
+    }
+
+    /// Reject a subresource index past a texture's mips * array slices, or
+    /// anything but 0 for a buffer. Unknown IDs are left to the caller.
+    fn check_subresource(&self, command: &str, id: ResourceId, subresource: u32) -> Result<()> {
+        let count = match self.copy_shape(id) {
+            Some(CopyShape::Texture2D {
+                mip_levels,
+                array_size,
+                ..
+            }) => mip_levels * array_size,
+            Some(CopyShape::Buffer { .. }) => 1,
+            None => return Ok(()),
+        };
+        if subresource >= count {
             warn!(
-                "CopyResource: Invalid resource IDs dst={} src={}",
-                dst_id, src_id
+                "{}: subresource {} out of range for id={} ({} subresources)",
+                command, subresource, id, count
             );
+            return Err(ProcessError::InvalidParameter(subresource).into());
         }
+        Ok(())
     }
 
     // =========================================================================
@@ -1489,6 +3957,17 @@ impl D3D11Renderer {
             D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
         };
 
+        // Reading back goes through a staging copy, but nothing may be
+        // written back to an immutable resource
+        if map_type != MapType::Read as u32 && self.is_immutable(id) {
+            warn!(
+                "MapResource: id={} is immutable, map type {} rejected",
+                id, map_type
+            );
+            return Err(ProcessError::InvalidParameter(id).into());
+        }
+        self.check_subresource("MapResource", id, subresource)?;
+
         let resource = self.slab_get(id);
 
         match resource {
@@ -1532,15 +4011,14 @@ impl D3D11Renderer {
                     id, subresource, map_type, *size
                 );
 
-                Ok(MapResult {
-                    data_ptr: mapped.pData as *mut u8,
-                    row_pitch: mapped.RowPitch,
-                    depth_pitch: mapped.DepthPitch,
-                    size: *size as usize,
-                    staging_resource: Some(StagingResource::Buffer(staging)),
-                    original_buffer: Some(buffer.clone()),
-                    original_texture: None,
-                })
+                Ok(MapResult::new(
+                    &self.context,
+                    &mapped,
+                    *size as usize,
+                    subresource,
+                    StagingResource::Buffer(staging),
+                    buffer.cast()?,
+                ))
             }
             Some(D3D11Resource::Texture2D {
                 texture,
@@ -1596,66 +4074,48 @@ impl D3D11Renderer {
                         .Map(&staging, subresource, d3d_map_type, 0, Some(&mut mapped))?;
                 }
 
-                // Calculate approximate size (row pitch * height for 2D textures)
-                let size = (mapped.RowPitch * *height) as usize;
+                let rows = mapped_rows(*format, *height, desc.MipLevels, subresource);
+                let size = mapped.RowPitch as usize * rows as usize;
 
                 debug!(
                     "MapResource: id={}, subresource={}, type={}, {}x{}, pitch={}",
                     id, subresource, map_type, width, height, mapped.RowPitch
                 );
 
-                Ok(MapResult {
-                    data_ptr: mapped.pData as *mut u8,
-                    row_pitch: mapped.RowPitch,
-                    depth_pitch: mapped.DepthPitch,
+                Ok(MapResult::new(
+                    &self.context,
+                    &mapped,
                     size,
-                    staging_resource: Some(StagingResource::Texture2D(staging)),
-                    original_buffer: None,
-                    original_texture: Some(texture.clone()),
-                })
+                    subresource,
+                    StagingResource::Texture2D(staging),
+                    texture.cast()?,
+                ))
+            }
+            _ => {
+                warn!("MapResource: invalid or unsupported resource ID {}", id);
+                Err(ProcessError::ResourceNotFound(id).into())
             }
-            _ => Err(anyhow!(
-                "MapResource: Invalid or unsupported resource ID {}",
-                id
-            )),
         }
     }
 
     /// Unmap a previously mapped resource.
     /// If the resource was mapped for writing, copies data back to the GPU resource.
-    pub fn unmap_resource(&mut self, map_result: &MapResult, subresource: u32, was_write: bool) {
-        // Unmap the staging resource
-        if let Some(ref staging) = map_result.staging_resource {
-            match staging {
-                StagingResource::Buffer(staging_buffer) => {
-                    unsafe {
-                        self.context.Unmap(staging_buffer, 0);
-                    }
-                    // Copy back if it was a write operation
-                    if was_write {
-                        if let Some(ref original) = map_result.original_buffer {
-                            unsafe {
-                                self.context.CopyResource(original, staging_buffer);
-                            }
-                            debug!("UnmapResource: copied buffer data back to GPU");
-                        }
-                    }
-                }
-                StagingResource::Texture2D(staging_texture) => {
-                    unsafe {
-                        self.context.Unmap(staging_texture, subresource);
+    pub fn unmap_resource(&mut self, mut map_result: MapResult, was_write: bool) {
+        map_result.unmap();
+
+        // Copy back if it was a write operation
+        if was_write {
+            unsafe {
+                match map_result.staging_resource {
+                    StagingResource::Buffer(ref staging) => {
+                        self.context.CopyResource(&map_result.original, staging)
                     }
-                    // Copy back if it was a write operation
-                    if was_write {
-                        if let Some(ref original) = map_result.original_texture {
-                            unsafe {
-                                self.context.CopyResource(original, staging_texture);
-                            }
-                            debug!("UnmapResource: copied texture data back to GPU");
-                        }
+                    StagingResource::Texture2D(ref staging) => {
+                        self.context.CopyResource(&map_result.original, staging)
                     }
                 }
             }
+            debug!("UnmapResource: copied data back to GPU");
         }
     }
 
@@ -1672,6 +4132,12 @@ impl D3D11Renderer {
     ) -> Result<()> {
         use windows::Win32::Graphics::Direct3D11::D3D11_BOX;
 
+        if self.is_immutable(id) {
+            warn!("UpdateSubresource: id={} is immutable", id);
+            return Err(ProcessError::InvalidParameter(id).into());
+        }
+        self.check_subresource("UpdateSubresource", id, subresource)?;
+
         let resource = self.slab_get(id);
 
         let d3d_resource: Option<ID3D11Resource> = match resource {
@@ -1680,8 +4146,13 @@ impl D3D11Renderer {
             _ => None,
         };
 
-        let d3d_resource =
-            d3d_resource.ok_or_else(|| anyhow!("UpdateSubresource: Invalid resource ID {}", id))?;
+        let d3d_resource = match d3d_resource {
+            Some(resource) => resource,
+            None => {
+                warn!("UpdateSubresource: invalid resource ID {}", id);
+                return Err(ProcessError::ResourceNotFound(id).into());
+            }
+        };
 
         let d3d_box = dst_box.map(|b| D3D11_BOX {
             left: b.left,
@@ -1716,15 +4187,130 @@ impl D3D11Renderer {
     }
 }
 
-/// Result of mapping a resource
+/// A mapped staging copy of a guest resource.
+///
+/// The mapped pointer is only reachable through this type, which owns the
+/// staging resource and unmaps it when dropped, so the pointer can never
+/// outlive the mapping. The raw pointer keeps the type `!Send`, and access
+/// additionally asserts it happens on the thread that created the map.
 pub struct MapResult {
-    pub data_ptr: *mut u8,
+    data_ptr: *mut u8,
     pub row_pitch: u32,
     pub depth_pitch: u32,
     pub size: usize,
-    pub staging_resource: Option<StagingResource>,
-    pub original_buffer: Option<ID3D11Buffer>,
-    pub original_texture: Option<ID3D11Texture2D>,
+    subresource: u32,
+    staging_resource: StagingResource,
+    /// Resource the staging copy is written back to
+    original: ID3D11Resource,
+    context: ID3D11DeviceContext,
+    owner: std::thread::ThreadId,
+    mapped_at: std::time::Instant,
+}
+
+impl MapResult {
+    fn new(
+        context: &ID3D11DeviceContext,
+        mapped: &D3D11_MAPPED_SUBRESOURCE,
+        size: usize,
+        subresource: u32,
+        staging_resource: StagingResource,
+        original: ID3D11Resource,
+    ) -> Self {
+        Self {
+            data_ptr: mapped.pData as *mut u8,
+            row_pitch: mapped.RowPitch,
+            depth_pitch: mapped.DepthPitch,
+            size,
+            subresource,
+            staging_resource,
+            original,
+            context: context.clone(),
+            owner: std::thread::current().id(),
+            mapped_at: std::time::Instant::now(),
+        }
+    }
+
+    fn assert_owner(&self) {
+        assert_eq!(
+            std::thread::current().id(),
+            self.owner,
+            "mapped resource accessed from another thread"
+        );
+    }
+
+    /// Mapped staging memory, `size` bytes long
+    pub fn data(&self) -> &[u8] {
+        self.assert_owner();
+        if self.data_ptr.is_null() {
+            return &[];
+        }
+        // SAFETY: the staging resource stays mapped for as long as `self`
+        // exists, and the pointer covers `size` bytes
+        unsafe { std::slice::from_raw_parts(self.data_ptr, self.size) }
+    }
+
+    /// Copy `src` into the mapped memory, truncated to the mapping size.
+    ///
+    /// Returns the number of bytes written.
+    pub fn write(&mut self, src: &[u8]) -> usize {
+        self.assert_owner();
+        if self.data_ptr.is_null() {
+            return 0;
+        }
+        let len = src.len().min(self.size);
+        // SAFETY: as in `data`; `&mut self` rules out overlapping borrows
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), self.data_ptr, len);
+        }
+        len
+    }
+
+    /// Copy rows `src_pitch` bytes apart in `src` into the mapped memory,
+    /// whose rows are `row_pitch` bytes apart.
+    ///
+    /// Returns the number of rows written.
+    pub fn write_rows(&mut self, src: &[u8], src_pitch: u32) -> usize {
+        self.assert_owner();
+        if self.data_ptr.is_null() || src_pitch == 0 || self.row_pitch == 0 {
+            return 0;
+        }
+        // SAFETY: as in `write`
+        let dst = unsafe { std::slice::from_raw_parts_mut(self.data_ptr, self.size) };
+        copy_rows(dst, self.row_pitch as usize, src, src_pitch as usize)
+    }
+
+    /// Whether the staging resource is a texture, whose rows are padded to
+    /// `row_pitch`
+    pub fn is_texture(&self) -> bool {
+        matches!(self.staging_resource, StagingResource::Texture2D(_))
+    }
+
+    /// Time since the resource was mapped
+    pub fn age(&self) -> std::time::Duration {
+        self.mapped_at.elapsed()
+    }
+
+    /// Unmap the staging resource; the pointer is unusable afterwards
+    fn unmap(&mut self) {
+        if self.data_ptr.is_null() {
+            return;
+        }
+        unsafe {
+            match self.staging_resource {
+                StagingResource::Buffer(ref buffer) => self.context.Unmap(buffer, 0),
+                StagingResource::Texture2D(ref texture) => {
+                    self.context.Unmap(texture, self.subresource)
+                }
+            }
+        }
+        self.data_ptr = std::ptr::null_mut();
+    }
+}
+
+impl Drop for MapResult {
+    fn drop(&mut self) {
+        self.unmap();
+    }
 }
 
 /// Staging resource used for Map/Unmap operations
@@ -1733,6 +4319,22 @@ pub enum StagingResource {
     Texture2D(ID3D11Texture2D),
 }
 
+/// Properties that must match between CopyResource's source and destination
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyShape {
+    Buffer {
+        size: u32,
+    },
+    Texture2D {
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        array_size: u32,
+        sample_count: u32,
+        format: DXGI_FORMAT,
+    },
+}
+
 /// Box for partial updates
 #[derive(Debug, Clone, Copy)]
 pub struct UpdateBox {
@@ -1743,3 +4345,1101 @@ pub struct UpdateBox {
     pub bottom: u32,
     pub back: u32,
 }
+
+/// Shader resource view over every slice of a 2D texture array, or every
+/// face of a cube map (array)
+fn array_srv_desc(
+    format: DXGI_FORMAT,
+    array_size: u32,
+    cube: bool,
+) -> D3D11_SHADER_RESOURCE_VIEW_DESC {
+    let (dimension, view) = match (cube, array_size) {
+        (true, 6) => (
+            D3D11_SRV_DIMENSION_TEXTURECUBE,
+            D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                TextureCube: D3D11_TEXCUBE_SRV {
+                    MostDetailedMip: 0,
+                    MipLevels: 1,
+                },
+            },
+        ),
+        (true, _) => (
+            D3D11_SRV_DIMENSION_TEXTURECUBEARRAY,
+            D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                TextureCubeArray: D3D11_TEXCUBE_ARRAY_SRV {
+                    MostDetailedMip: 0,
+                    MipLevels: 1,
+                    First2DArrayFace: 0,
+                    NumCubes: array_size / 6,
+                },
+            },
+        ),
+        (false, _) => (
+            D3D11_SRV_DIMENSION_TEXTURE2DARRAY,
+            D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2DArray: D3D11_TEX2D_ARRAY_SRV {
+                    MostDetailedMip: 0,
+                    MipLevels: 1,
+                    FirstArraySlice: 0,
+                    ArraySize: array_size,
+                },
+            },
+        ),
+    };
+    D3D11_SHADER_RESOURCE_VIEW_DESC {
+        Format: format,
+        ViewDimension: dimension,
+        Anonymous: view,
+    }
+}
+
+/// Render target view over every slice of a 2D texture array; shaders pick
+/// the slice with SV_RenderTargetArrayIndex
+fn array_rtv_desc(format: DXGI_FORMAT, array_size: u32) -> D3D11_RENDER_TARGET_VIEW_DESC {
+    D3D11_RENDER_TARGET_VIEW_DESC {
+        Format: format,
+        ViewDimension: D3D11_RTV_DIMENSION_TEXTURE2DARRAY,
+        Anonymous: D3D11_RENDER_TARGET_VIEW_DESC_0 {
+            Texture2DArray: D3D11_TEX2D_ARRAY_RTV {
+                MipSlice: 0,
+                FirstArraySlice: 0,
+                ArraySize: array_size,
+            },
+        },
+    }
+}
+
+/// Depth-stencil view over every slice of one mip of a 2D texture (array),
+/// multisampled textures having only mip 0
+fn scrub_dsv_desc(
+    format: DXGI_FORMAT,
+    mip: u32,
+    array_size: u32,
+    multisampled: bool,
+) -> D3D11_DEPTH_STENCIL_VIEW_DESC {
+    let (dimension, view) = if multisampled {
+        (
+            D3D11_DSV_DIMENSION_TEXTURE2DMSARRAY,
+            D3D11_DEPTH_STENCIL_VIEW_DESC_0 {
+                Texture2DMSArray: D3D11_TEX2DMS_ARRAY_DSV {
+                    FirstArraySlice: 0,
+                    ArraySize: array_size,
+                },
+            },
+        )
+    } else {
+        (
+            D3D11_DSV_DIMENSION_TEXTURE2DARRAY,
+            D3D11_DEPTH_STENCIL_VIEW_DESC_0 {
+                Texture2DArray: D3D11_TEX2D_ARRAY_DSV {
+                    MipSlice: mip,
+                    FirstArraySlice: 0,
+                    ArraySize: array_size,
+                },
+            },
+        )
+    };
+    D3D11_DEPTH_STENCIL_VIEW_DESC {
+        Format: format,
+        ViewDimension: dimension,
+        Flags: 0,
+        Anonymous: view,
+    }
+}
+
+/// Render target view over every slice of one mip of a 2D texture (array),
+/// multisampled textures having only mip 0
+fn scrub_rtv_desc(
+    format: DXGI_FORMAT,
+    mip: u32,
+    array_size: u32,
+    multisampled: bool,
+) -> D3D11_RENDER_TARGET_VIEW_DESC {
+    if multisampled {
+        return D3D11_RENDER_TARGET_VIEW_DESC {
+            Format: format,
+            ViewDimension: D3D11_RTV_DIMENSION_TEXTURE2DMSARRAY,
+            Anonymous: D3D11_RENDER_TARGET_VIEW_DESC_0 {
+                Texture2DMSArray: D3D11_TEX2DMS_ARRAY_RTV {
+                    FirstArraySlice: 0,
+                    ArraySize: array_size,
+                },
+            },
+        };
+    }
+    D3D11_RENDER_TARGET_VIEW_DESC {
+        Format: format,
+        ViewDimension: D3D11_RTV_DIMENSION_TEXTURE2DARRAY,
+        Anonymous: D3D11_RENDER_TARGET_VIEW_DESC_0 {
+            Texture2DArray: D3D11_TEX2D_ARRAY_RTV {
+                MipSlice: mip,
+                FirstArraySlice: 0,
+                ArraySize: array_size,
+            },
+        },
+    }
+}
+
+/// Stage (ShaderStage numbering) and raw pointer of a shader resource
+fn shader_stage_and_raw(resource: &D3D11Resource) -> Option<(u32, *mut std::ffi::c_void)> {
+    match resource {
+        D3D11Resource::VertexShader { shader, .. } => Some((0, shader.as_raw())),
+        D3D11Resource::PixelShader { shader } => Some((1, shader.as_raw())),
+        D3D11Resource::GeometryShader { shader } => Some((2, shader.as_raw())),
+        D3D11Resource::HullShader { shader } => Some((3, shader.as_raw())),
+        D3D11Resource::DomainShader { shader } => Some((4, shader.as_raw())),
+        D3D11Resource::ComputeShader { shader } => Some((5, shader.as_raw())),
+        _ => None,
+    }
+}
+
+/// Stage (ShaderStage numbering) a DXBC blob was compiled for, read from
+/// the program version token of its SHDR/SHEX chunk
+fn dxbc_shader_stage(bytecode: &[u8]) -> Option<u32> {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = bytecode.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    if bytecode.get(0..4)? != b"DXBC" {
+        return None;
+    }
+    let chunk_count = read_u32(28)? as usize;
+    for i in 0..chunk_count {
+        let chunk = read_u32(32 + i * 4)? as usize;
+        let fourcc = bytecode.get(chunk..chunk.checked_add(4)?)?;
+        if fourcc == b"SHDR" || fourcc == b"SHEX" {
+            // Program type lives in the high word; DXBC numbers pixel 0
+            // and vertex 1, the reverse of ShaderStage
+            return match read_u32(chunk + 8)? >> 16 {
+                0 => Some(1),
+                1 => Some(0),
+                stage @ 2..=5 => Some(stage),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+/// First property that keeps `src` from being copied whole into `dst`, or
+/// None if CopyResource accepts the pair
+fn copy_shape_mismatch(dst: &CopyShape, src: &CopyShape) -> Option<&'static str> {
+    match (dst, src) {
+        (CopyShape::Buffer { size: dst_size }, CopyShape::Buffer { size: src_size }) => {
+            (dst_size != src_size).then_some("size")
+        }
+        (
+            CopyShape::Texture2D {
+                width,
+                height,
+                mip_levels,
+                array_size,
+                sample_count,
+                format,
+            },
+            CopyShape::Texture2D {
+                width: src_width,
+                height: src_height,
+                mip_levels: src_mip_levels,
+                array_size: src_array_size,
+                sample_count: src_sample_count,
+                format: src_format,
+            },
+        ) => {
+            // UNORM, _SRGB and TYPELESS members of a family share a layout
+            let family = |f: DXGI_FORMAT| srgb::typeless_format(f).unwrap_or(f);
+            if (width, height) != (src_width, src_height) {
+                Some("dimensions")
+            } else if mip_levels != src_mip_levels {
+                Some("mip levels")
+            } else if array_size != src_array_size {
+                Some("array size")
+            } else if sample_count != src_sample_count {
+                Some("sample count")
+            } else if family(*format) != family(*src_format) {
+                Some("format")
+            } else {
+                None
+            }
+        }
+        _ => Some("resource type"),
+    }
+}
+
+/// D3D11 bind flags with their names, for validation messages
+const BIND_FLAG_NAMES: [(u32, &str); 8] = [
+    (D3D11_BIND_VERTEX_BUFFER.0 as u32, "VERTEX_BUFFER"),
+    (D3D11_BIND_INDEX_BUFFER.0 as u32, "INDEX_BUFFER"),
+    (D3D11_BIND_CONSTANT_BUFFER.0 as u32, "CONSTANT_BUFFER"),
+    (D3D11_BIND_SHADER_RESOURCE.0 as u32, "SHADER_RESOURCE"),
+    (D3D11_BIND_STREAM_OUTPUT.0 as u32, "STREAM_OUTPUT"),
+    (D3D11_BIND_RENDER_TARGET.0 as u32, "RENDER_TARGET"),
+    (D3D11_BIND_DEPTH_STENCIL.0 as u32, "DEPTH_STENCIL"),
+    (D3D11_BIND_UNORDERED_ACCESS.0 as u32, "UNORDERED_ACCESS"),
+];
+
+/// Every bind flag the backend knows
+const KNOWN_BIND_FLAGS: u32 = 0xFF;
+
+/// Misc flags a guest buffer may be created with
+const KNOWN_BUFFER_MISC_FLAGS: u32 = PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS
+    | PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS
+    | PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED;
+
+/// Bind flags an immutable resource may carry; the GPU never writes it
+const IMMUTABLE_BIND_FLAGS: u32 = (D3D11_BIND_VERTEX_BUFFER.0
+    | D3D11_BIND_INDEX_BUFFER.0
+    | D3D11_BIND_CONSTANT_BUFFER.0
+    | D3D11_BIND_SHADER_RESOURCE.0) as u32;
+
+/// Hash of a resource's creation parameters and initial data, used to find
+/// identical uploads
+fn upload_hash(params: &[u32], data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    params.hash(&mut hasher);
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Reject an immutable resource without initial data or with bind flags
+/// that would let the GPU write it
+fn check_immutable(command: &str, id: ResourceId, bind_flags: u32, has_data: bool) -> Result<()> {
+    if !has_data {
+        warn!(
+            "{}: immutable resource id={} needs initial data",
+            command, id
+        );
+        return Err(ProcessError::InvalidParameter(id).into());
+    }
+    let writable = bind_flags & !IMMUTABLE_BIND_FLAGS;
+    if writable != 0 {
+        warn!(
+            "{}: immutable resource id={} cannot be bound as {}",
+            command,
+            id,
+            bind_flag_names(writable)
+        );
+        return Err(ProcessError::InvalidParameter(writable).into());
+    }
+    Ok(())
+}
+
+/// Names of the bind flags set in `flags`, e.g. "RENDER_TARGET|0x200"
+fn bind_flag_names(flags: u32) -> String {
+    let mut names: Vec<String> = BIND_FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if flags & !KNOWN_BIND_FLAGS != 0 {
+        names.push(format!("{:#x}", flags & !KNOWN_BIND_FLAGS));
+    }
+    names.join("|")
+}
+
+/// Check a buffer's bind flags, or return the offending flags and why.
+///
+/// The backend creates no buffer render target or depth views, and
+/// feature level 11.0 forbids combining CONSTANT_BUFFER with other flags.
+fn buffer_bind_flags_valid(bind_flags: u32) -> Result<(), (u32, &'static str)> {
+    let unknown = bind_flags & !KNOWN_BIND_FLAGS;
+    if unknown != 0 {
+        return Err((unknown, "unknown bind flags"));
+    }
+    let target_flags =
+        bind_flags & (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_DEPTH_STENCIL.0) as u32;
+    if target_flags != 0 {
+        return Err((target_flags, "buffers cannot be render or depth targets"));
+    }
+    let constant = D3D11_BIND_CONSTANT_BUFFER.0 as u32;
+    if bind_flags & constant != 0 && bind_flags != constant {
+        return Err((
+            bind_flags,
+            "CONSTANT_BUFFER cannot be combined with other flags",
+        ));
+    }
+    Ok(())
+}
+
+/// Check a `size` byte buffer's misc flags (PVGPU_RESOURCE_MISC_*) and,
+/// for a structured buffer, its element stride, or return the offending
+/// flags (or stride) and why.
+fn buffer_misc_flags_valid(
+    misc_flags: u32,
+    size: u32,
+    stride: u32,
+) -> Result<(), (u32, &'static str)> {
+    let unknown = misc_flags & !KNOWN_BUFFER_MISC_FLAGS;
+    if unknown != 0 {
+        return Err((unknown, "unsupported buffer misc flags"));
+    }
+    if misc_flags & PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED == 0 {
+        return Ok(());
+    }
+    let exclusive = misc_flags
+        & (PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS | PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS);
+    if exclusive != 0 {
+        return Err((
+            exclusive,
+            "structured buffers cannot be raw or indirect args",
+        ));
+    }
+    if stride == 0
+        || !stride.is_multiple_of(4)
+        || stride > PVGPU_MAX_STRUCTURE_STRIDE
+        || !size.is_multiple_of(stride)
+    {
+        return Err((stride, "invalid structure stride"));
+    }
+    Ok(())
+}
+
+/// Check buffer UAV flags (PVGPU_UAV_FLAG_*) against the misc flags of the
+/// buffer viewed, or return why they do not fit.
+fn buffer_uav_flags_valid(flags: u32, misc_flags: u32) -> Result<(), &'static str> {
+    let counters = PVGPU_UAV_FLAG_APPEND | PVGPU_UAV_FLAG_COUNTER;
+    if flags & !(PVGPU_UAV_FLAG_RAW | counters) != 0 {
+        return Err("unknown UAV flags");
+    }
+    if flags & counters == counters {
+        return Err("APPEND and COUNTER are exclusive");
+    }
+    if flags & counters != 0 && misc_flags & PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED == 0 {
+        return Err("append and counter UAVs need a structured buffer");
+    }
+    if flags & PVGPU_UAV_FLAG_RAW != 0
+        && misc_flags & PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS == 0
+    {
+        return Err("raw UAVs need a buffer allowing raw views");
+    }
+    Ok(())
+}
+
+/// Estimated bytes of video memory behind a texture or buffer; 0 for
+/// shaders, views and state objects
+fn resource_size(resource: &D3D11Resource) -> u64 {
+    match resource {
+        D3D11Resource::Texture2D { texture, .. } => {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe { texture.GetDesc(&mut desc) };
+            let pixels = texture_pixels(
+                desc.Width,
+                desc.Height,
+                desc.MipLevels,
+                desc.ArraySize * desc.SampleDesc.Count,
+            );
+            pixels * format_bits_per_pixel(desc.Format) as u64 / 8
+        }
+        D3D11Resource::Buffer { size, .. } => *size as u64,
+        _ => 0,
+    }
+}
+
+/// Pixels in `layers` copies of a `width`x`height` mip chain of `mip_levels`
+fn texture_pixels(width: u32, height: u32, mip_levels: u32, layers: u32) -> u64 {
+    let chain: u64 = (0..mip_levels.max(1))
+        .map(|level| (width >> level).max(1) as u64 * (height >> level).max(1) as u64)
+        .sum();
+    chain * layers as u64
+}
+
+/// Copy rows `src_pitch` bytes apart to rows `dst_pitch` bytes apart, each
+/// truncated to the narrower pitch, until either side runs out. Both pitches
+/// must be nonzero. Returns the number of rows copied.
+fn copy_rows(dst: &mut [u8], dst_pitch: usize, src: &[u8], src_pitch: usize) -> usize {
+    let row_len = src_pitch.min(dst_pitch);
+    let mut rows = 0;
+    for (src_row, dst_row) in src.chunks(src_pitch).zip(dst.chunks_mut(dst_pitch)) {
+        let len = row_len.min(src_row.len()).min(dst_row.len());
+        dst_row[..len].copy_from_slice(&src_row[..len]);
+        rows += 1;
+    }
+    rows
+}
+
+/// Rows of mapped data in `subresource` of a texture `height` texels tall:
+/// block rows for block compressed formats, and both planes for NV12/P010
+fn mapped_rows(format: DXGI_FORMAT, height: u32, mip_levels: u32, subresource: u32) -> u32 {
+    let height = (height >> (subresource % mip_levels.max(1))).max(1);
+    match format.0 {
+        70..=84 | 94..=99 => height.div_ceil(4),  // BC1-BC7
+        103..=105 => height + height.div_ceil(2), // NV12, P010, P016
+        _ => height,
+    }
+}
+
+/// Bytes per row of a `width` texel wide upload of `format`: per row of
+/// 4x4 blocks for block compressed formats, per luma row for NV12/P010
+fn upload_row_pitch(format: DXGI_FORMAT, width: u32) -> u32 {
+    if let Some(bytes) = video::planar_bytes_per_sample(format) {
+        return width * bytes;
+    }
+    match format.0 {
+        // Bits per pixel times 16 texels per block
+        70..=84 | 94..=99 => width.div_ceil(4) * format_bits_per_pixel(format) * 2,
+        _ => (width * format_bits_per_pixel(format)).div_ceil(8),
+    }
+}
+
+/// Bits per pixel of a DXGI format, 32 for formats not listed. Block
+/// compressed formats give their average rate.
+fn format_bits_per_pixel(format: DXGI_FORMAT) -> u32 {
+    match format.0 {
+        1..=4 => 128,        // R32G32B32A32
+        5..=8 => 96,         // R32G32B32
+        9..=22 => 64,        // R16G16B16A16, R32G32, R32G8X24
+        48..=59 => 16,       // R8G8, R16
+        60..=65 => 8,        // R8, A8
+        66 => 1,             // R1
+        70..=72 => 4,        // BC1
+        73..=78 => 8,        // BC2, BC3
+        79..=81 => 4,        // BC4
+        82..=84 => 8,        // BC5
+        85 | 86 | 115 => 16, // B5G6R5, B5G5R5A1, B4G4R4A4
+        94..=99 => 8,        // BC6H, BC7
+        103 => 12,           // NV12
+        104 => 24,           // P010
+        _ => 32,
+    }
+}
+
+/// Whether `format` can back a depth-stencil view, and whether it can also
+/// be sampled (only the TYPELESS depth formats can)
+fn depth_format_sampleable(format: DXGI_FORMAT) -> Option<bool> {
+    match format {
+        DXGI_FORMAT_D16_UNORM
+        | DXGI_FORMAT_D24_UNORM_S8_UINT
+        | DXGI_FORMAT_D32_FLOAT
+        | DXGI_FORMAT_D32_FLOAT_S8X24_UINT => Some(false),
+        DXGI_FORMAT_R16_TYPELESS
+        | DXGI_FORMAT_R24G8_TYPELESS
+        | DXGI_FORMAT_R32_TYPELESS
+        | DXGI_FORMAT_R32G8X24_TYPELESS => Some(true),
+        _ => None,
+    }
+}
+
+/// Depth-stencil view format of a depth texture created as `format`
+fn depth_view_format(format: DXGI_FORMAT) -> DXGI_FORMAT {
+    match format {
+        DXGI_FORMAT_R16_TYPELESS => DXGI_FORMAT_D16_UNORM,
+        DXGI_FORMAT_R24G8_TYPELESS => DXGI_FORMAT_D24_UNORM_S8_UINT,
+        DXGI_FORMAT_R32_TYPELESS => DXGI_FORMAT_D32_FLOAT,
+        DXGI_FORMAT_R32G8X24_TYPELESS => DXGI_FORMAT_D32_FLOAT_S8X24_UINT,
+        format => format,
+    }
+}
+
+/// Check a 2D texture's bind flags against its format, or return the
+/// offending flags and why
+fn texture_bind_flags_valid(
+    bind_flags: u32,
+    format: DXGI_FORMAT,
+) -> Result<(), (u32, &'static str)> {
+    let unknown = bind_flags & !KNOWN_BIND_FLAGS;
+    if unknown != 0 {
+        return Err((unknown, "unknown bind flags"));
+    }
+    let buffer_flags = bind_flags
+        & (D3D11_BIND_VERTEX_BUFFER.0
+            | D3D11_BIND_INDEX_BUFFER.0
+            | D3D11_BIND_CONSTANT_BUFFER.0
+            | D3D11_BIND_STREAM_OUTPUT.0) as u32;
+    if buffer_flags != 0 {
+        return Err((buffer_flags, "buffer-only flags on a texture"));
+    }
+
+    let depth = D3D11_BIND_DEPTH_STENCIL.0 as u32;
+    if bind_flags & depth == 0 {
+        return Ok(());
+    }
+    let exclusive =
+        bind_flags & (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_UNORDERED_ACCESS.0) as u32;
+    if exclusive != 0 {
+        return Err((
+            depth | exclusive,
+            "DEPTH_STENCIL cannot be combined with render target or UAV flags",
+        ));
+    }
+    match depth_format_sampleable(format) {
+        None => Err((depth, "DEPTH_STENCIL requires a depth format")),
+        Some(false) if bind_flags & D3D11_BIND_SHADER_RESOURCE.0 as u32 != 0 => Err((
+            bind_flags,
+            "sampled depth textures need a TYPELESS depth format",
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// D3D11_FORMAT_SUPPORT bits a texture format needs for its `bind_flags`
+fn bind_format_support(bind_flags: u32) -> u32 {
+    [
+        (D3D11_BIND_RENDER_TARGET, D3D11_FORMAT_SUPPORT_RENDER_TARGET),
+        (D3D11_BIND_DEPTH_STENCIL, D3D11_FORMAT_SUPPORT_DEPTH_STENCIL),
+        (
+            D3D11_BIND_UNORDERED_ACCESS,
+            D3D11_FORMAT_SUPPORT_TYPED_UNORDERED_ACCESS_VIEW,
+        ),
+    ]
+    .into_iter()
+    .filter(|(bind, _)| bind_flags & bind.0 as u32 != 0)
+    .fold(0, |required, (_, support)| required | support.0 as u32)
+}
+
+/// Signedness of an integer render target format, or None if the format is
+/// not UINT/SINT
+fn integer_format_signed(format: DXGI_FORMAT) -> Option<bool> {
+    match format {
+        DXGI_FORMAT_R32G32B32A32_UINT
+        | DXGI_FORMAT_R32G32B32_UINT
+        | DXGI_FORMAT_R16G16B16A16_UINT
+        | DXGI_FORMAT_R32G32_UINT
+        | DXGI_FORMAT_R10G10B10A2_UINT
+        | DXGI_FORMAT_R8G8B8A8_UINT
+        | DXGI_FORMAT_R16G16_UINT
+        | DXGI_FORMAT_R32_UINT
+        | DXGI_FORMAT_R8G8_UINT
+        | DXGI_FORMAT_R16_UINT
+        | DXGI_FORMAT_R8_UINT => Some(false),
+        DXGI_FORMAT_R32G32B32A32_SINT
+        | DXGI_FORMAT_R32G32B32_SINT
+        | DXGI_FORMAT_R16G16B16A16_SINT
+        | DXGI_FORMAT_R32G32_SINT
+        | DXGI_FORMAT_R8G8B8A8_SINT
+        | DXGI_FORMAT_R16G16_SINT
+        | DXGI_FORMAT_R32_SINT
+        | DXGI_FORMAT_R8G8_SINT
+        | DXGI_FORMAT_R16_SINT
+        | DXGI_FORMAT_R8_SINT => Some(true),
+        _ => None,
+    }
+}
+
+/// Float color that an integer clear converts back to exactly `color`, or
+/// the first component whose magnitude exceeds f32's 24-bit mantissa
+fn exact_integer_clear(color: &[u32; 4], signed: bool) -> Result<[f32; 4], u32> {
+    const EXACT_LIMIT: u32 = 1 << 24;
+    let mut float_color = [0.0f32; 4];
+    for (out, &value) in float_color.iter_mut().zip(color) {
+        *out = if signed {
+            let value = value as i32;
+            if value.unsigned_abs() > EXACT_LIMIT {
+                return Err(value as u32);
+            }
+            value as f32
+        } else {
+            if value > EXACT_LIMIT {
+                return Err(value);
+            }
+            value as f32
+        };
+    }
+    Ok(float_color)
+}
+
+/// GPU preference for a config name such as `"high_performance"`
+pub fn gpu_preference(name: &str) -> Option<DXGI_GPU_PREFERENCE> {
+    match name {
+        "high_performance" => Some(DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE),
+        "minimum_power" => Some(DXGI_GPU_PREFERENCE_MINIMUM_POWER),
+        "unspecified" => Some(DXGI_GPU_PREFERENCE_UNSPECIFIED),
+        _ => None,
+    }
+}
+
+/// Adapter with the most free video memory among `candidates` (index, free
+/// bytes). One that cannot report is only picked if none can; ties go to
+/// the earlier candidate.
+pub fn least_loaded_adapter(candidates: &[(u32, Option<u64>)]) -> Option<u32> {
+    candidates
+        .iter()
+        .enumerate()
+        .max_by_key(|(position, (_, free))| (*free, std::cmp::Reverse(*position)))
+        .map(|(_, (index, _))| *index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Graphics::Direct3D11::{
+        D3D11_BUFFER_UAV_FLAG_APPEND, D3D11_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS,
+        D3D11_RESOURCE_MISC_BUFFER_STRUCTURED, D3D11_RESOURCE_MISC_DRAWINDIRECT_ARGS,
+    };
+    use windows::Win32::Graphics::Dxgi::Common::{
+        DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_BC1_UNORM, DXGI_FORMAT_NV12,
+        DXGI_FORMAT_R8G8B8A8_TYPELESS, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+    };
+
+    #[test]
+    fn test_least_loaded_adapter() {
+        const GB: u64 = 1 << 30;
+        assert_eq!(
+            least_loaded_adapter(&[(0, Some(2 * GB)), (1, Some(6 * GB)), (2, Some(4 * GB))]),
+            Some(1)
+        );
+
+        // Ties go to the earlier pool entry
+        assert_eq!(
+            least_loaded_adapter(&[(3, Some(GB)), (1, Some(GB))]),
+            Some(3)
+        );
+
+        // Adapters without IDXGIAdapter3 lose to any that report
+        assert_eq!(least_loaded_adapter(&[(0, None), (1, Some(0))]), Some(1));
+        assert_eq!(least_loaded_adapter(&[(2, None), (1, None)]), Some(2));
+        assert_eq!(least_loaded_adapter(&[]), None);
+    }
+
+    /// Minimal DXBC container holding one chunk with `version` as its
+    /// first token
+    fn dxbc_with_chunk(fourcc: &[u8; 4], version: u32) -> Vec<u8> {
+        let mut blob = b"DXBC".to_vec();
+        blob.extend_from_slice(&[0; 16]); // checksum
+        blob.extend_from_slice(&1u32.to_le_bytes());
+        blob.extend_from_slice(&48u32.to_le_bytes()); // total size
+        blob.extend_from_slice(&1u32.to_le_bytes()); // chunk count
+        blob.extend_from_slice(&36u32.to_le_bytes()); // chunk offset
+        blob.extend_from_slice(fourcc);
+        blob.extend_from_slice(&4u32.to_le_bytes());
+        blob.extend_from_slice(&version.to_le_bytes());
+        blob
+    }
+
+    #[test]
+    fn test_dxbc_shader_stage() {
+        // vs_5_0, ps_4_0 and cs_5_0 version tokens
+        assert_eq!(
+            dxbc_shader_stage(&dxbc_with_chunk(b"SHEX", 0x0001_0050)),
+            Some(0)
+        );
+        assert_eq!(
+            dxbc_shader_stage(&dxbc_with_chunk(b"SHDR", 0x0000_0040)),
+            Some(1)
+        );
+        assert_eq!(
+            dxbc_shader_stage(&dxbc_with_chunk(b"SHEX", 0x0005_0050)),
+            Some(5)
+        );
+
+        assert_eq!(
+            dxbc_shader_stage(&dxbc_with_chunk(b"ISGN", 0x0001_0050)),
+            None
+        );
+        assert_eq!(
+            dxbc_shader_stage(&dxbc_with_chunk(b"SHEX", 0x0007_0050)),
+            None
+        );
+        let mut truncated = dxbc_with_chunk(b"SHEX", 0x0001_0050);
+        truncated.truncate(40);
+        assert_eq!(dxbc_shader_stage(&truncated), None);
+        assert_eq!(dxbc_shader_stage(b"DXBD"), None);
+    }
+
+    #[test]
+    fn test_copy_shape_mismatch() {
+        let texture = |width, format| CopyShape::Texture2D {
+            width,
+            height: 64,
+            mip_levels: 1,
+            array_size: 1,
+            sample_count: 1,
+            format,
+        };
+        let rgba = texture(64, DXGI_FORMAT_R8G8B8A8_UNORM);
+
+        assert_eq!(copy_shape_mismatch(&rgba, &rgba), None);
+        assert_eq!(
+            copy_shape_mismatch(&rgba, &texture(64, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB)),
+            None
+        );
+        assert_eq!(
+            copy_shape_mismatch(&texture(64, DXGI_FORMAT_R8G8B8A8_TYPELESS), &rgba),
+            None
+        );
+
+        assert_eq!(
+            copy_shape_mismatch(&rgba, &CopyShape::Buffer { size: 64 * 64 * 4 }),
+            Some("resource type")
+        );
+        assert_eq!(
+            copy_shape_mismatch(&rgba, &texture(32, DXGI_FORMAT_R8G8B8A8_UNORM)),
+            Some("dimensions")
+        );
+        assert_eq!(
+            copy_shape_mismatch(&rgba, &texture(64, DXGI_FORMAT_B8G8R8A8_UNORM)),
+            Some("format")
+        );
+        assert_eq!(
+            copy_shape_mismatch(
+                &CopyShape::Buffer { size: 256 },
+                &CopyShape::Buffer { size: 128 }
+            ),
+            Some("size")
+        );
+    }
+
+    #[test]
+    fn test_bind_flag_validation() {
+        let vb = D3D11_BIND_VERTEX_BUFFER.0 as u32;
+        let cb = D3D11_BIND_CONSTANT_BUFFER.0 as u32;
+        let srv = D3D11_BIND_SHADER_RESOURCE.0 as u32;
+        let rt = D3D11_BIND_RENDER_TARGET.0 as u32;
+        let ds = D3D11_BIND_DEPTH_STENCIL.0 as u32;
+
+        assert!(buffer_bind_flags_valid(vb | srv).is_ok());
+        assert!(buffer_bind_flags_valid(cb).is_ok());
+        assert_eq!(buffer_bind_flags_valid(vb | rt).unwrap_err().0, rt);
+        assert_eq!(buffer_bind_flags_valid(cb | vb).unwrap_err().0, cb | vb);
+        assert_eq!(buffer_bind_flags_valid(0x200).unwrap_err().0, 0x200);
+
+        let indirect = PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS;
+        assert_eq!(indirect, D3D11_RESOURCE_MISC_DRAWINDIRECT_ARGS.0 as u32);
+        assert!(buffer_misc_flags_valid(0, 64, 0).is_ok());
+        assert!(buffer_misc_flags_valid(indirect, 64, 0).is_ok());
+        assert_eq!(
+            buffer_misc_flags_valid(indirect | PVGPU_RESOURCE_MISC_TEXTURECUBE, 64, 0)
+                .unwrap_err()
+                .0,
+            PVGPU_RESOURCE_MISC_TEXTURECUBE
+        );
+
+        // Structured buffers need a stride dividing the size
+        let structured = PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED;
+        let raw = PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS;
+        assert_eq!(structured, D3D11_RESOURCE_MISC_BUFFER_STRUCTURED.0 as u32);
+        assert_eq!(raw, D3D11_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS.0 as u32);
+        assert!(buffer_misc_flags_valid(structured, 64, 16).is_ok());
+        assert_eq!(buffer_misc_flags_valid(structured, 64, 0).unwrap_err().0, 0);
+        assert_eq!(buffer_misc_flags_valid(structured, 64, 6).unwrap_err().0, 6);
+        assert_eq!(buffer_misc_flags_valid(structured, 60, 8).unwrap_err().0, 8);
+        assert_eq!(
+            buffer_misc_flags_valid(structured | raw, 64, 16)
+                .unwrap_err()
+                .0,
+            raw
+        );
+        // The stride of other buffers is ignored
+        assert!(buffer_misc_flags_valid(raw, 64, 6).is_ok());
+
+        let append = PVGPU_UAV_FLAG_APPEND;
+        let counter = PVGPU_UAV_FLAG_COUNTER;
+        assert_eq!(append, D3D11_BUFFER_UAV_FLAG_APPEND.0 as u32);
+        assert!(buffer_uav_flags_valid(append, structured).is_ok());
+        assert!(buffer_uav_flags_valid(counter, structured).is_ok());
+        assert!(buffer_uav_flags_valid(PVGPU_UAV_FLAG_RAW, raw).is_ok());
+        assert!(buffer_uav_flags_valid(append, raw).is_err());
+        assert!(buffer_uav_flags_valid(append | counter, structured).is_err());
+        assert!(buffer_uav_flags_valid(PVGPU_UAV_FLAG_RAW, structured).is_err());
+        assert!(buffer_uav_flags_valid(1 << 3, structured).is_err());
+
+        let rgba = DXGI_FORMAT_R8G8B8A8_UNORM;
+        assert!(texture_bind_flags_valid(rt | srv, rgba).is_ok());
+        assert_eq!(texture_bind_flags_valid(vb | srv, rgba).unwrap_err().0, vb);
+        assert_eq!(texture_bind_flags_valid(ds, rgba).unwrap_err().0, ds);
+        assert!(texture_bind_flags_valid(ds, DXGI_FORMAT_D24_UNORM_S8_UINT).is_ok());
+        assert!(texture_bind_flags_valid(ds | srv, DXGI_FORMAT_R24G8_TYPELESS).is_ok());
+        assert!(texture_bind_flags_valid(ds | srv, DXGI_FORMAT_D24_UNORM_S8_UINT).is_err());
+        assert_eq!(
+            texture_bind_flags_valid(ds | rt, DXGI_FORMAT_D32_FLOAT)
+                .unwrap_err()
+                .0,
+            ds | rt
+        );
+
+        assert_eq!(bind_flag_names(rt | 0x200), "RENDER_TARGET|0x200");
+    }
+
+    #[test]
+    fn test_bind_format_support() {
+        let srv = D3D11_BIND_SHADER_RESOURCE.0 as u32;
+        let rt = D3D11_BIND_RENDER_TARGET.0 as u32;
+        let uav = D3D11_BIND_UNORDERED_ACCESS.0 as u32;
+
+        assert_eq!(bind_format_support(srv), 0);
+        assert_eq!(
+            bind_format_support(rt | srv),
+            D3D11_FORMAT_SUPPORT_RENDER_TARGET.0 as u32
+        );
+        assert_eq!(
+            bind_format_support(rt | uav),
+            (D3D11_FORMAT_SUPPORT_RENDER_TARGET.0
+                | D3D11_FORMAT_SUPPORT_TYPED_UNORDERED_ACCESS_VIEW.0) as u32
+        );
+        assert_eq!(
+            bind_format_support(D3D11_BIND_DEPTH_STENCIL.0 as u32),
+            D3D11_FORMAT_SUPPORT_DEPTH_STENCIL.0 as u32
+        );
+    }
+
+    #[test]
+    fn test_state_cache_forget() {
+        let mut cache = StateCache {
+            blend: Some((3, [0; 4], u32::MAX)),
+            rasterizer: Some(4),
+            depth_stencil: Some((3, 1)),
+            topology: Some(4),
+            input_layout: Some(5),
+            ..Default::default()
+        };
+
+        // Topology is a value, not a resource ID
+        cache.forget(4);
+        assert_eq!(cache.rasterizer, None);
+        assert_eq!(cache.topology, Some(4));
+
+        cache.forget(3);
+        assert_eq!(cache.blend, None);
+        assert_eq!(cache.depth_stencil, None);
+        assert_eq!(cache.input_layout, Some(5));
+    }
+
+    #[test]
+    fn test_state_cache_forget_bindings() {
+        let mut cache = StateCache::default();
+        cache.shaders[1] = Some(7);
+        cache.vertex_buffers.insert(0, (7, 16, 0));
+        cache.vertex_buffers.insert(1, (8, 16, 0));
+        cache.index_buffer = Some((7, DXGI_FORMAT(57), 0));
+        cache.constant_buffers.insert((0, 0), 7);
+        cache.samplers.insert((1, 0), 7);
+        cache.shader_resources.insert((1, 3), 7);
+
+        cache.forget(7);
+        assert_eq!(cache.shaders[1], None);
+        assert_eq!(cache.vertex_buffers.get(&0), None);
+        assert_eq!(cache.vertex_buffers.get(&1), Some(&(8, 16, 0)));
+        assert_eq!(cache.index_buffer, None);
+        assert!(cache.constant_buffers.is_empty());
+        assert!(cache.samplers.is_empty());
+        assert!(cache.shader_resources.is_empty());
+    }
+
+    #[test]
+    fn test_exact_integer_clear() {
+        assert_eq!(
+            exact_integer_clear(&[0, 1, 255, 1 << 24], false),
+            Ok([0.0, 1.0, 255.0, 16_777_216.0])
+        );
+        assert_eq!(
+            exact_integer_clear(&[(1 << 24) + 1, 0, 0, 0], false),
+            Err((1 << 24) + 1)
+        );
+
+        let minus_one = -1i32 as u32;
+        assert_eq!(
+            exact_integer_clear(&[minus_one, 7, 0, 0], true),
+            Ok([-1.0, 7.0, 0.0, 0.0])
+        );
+        // As unsigned the same bits are far out of range
+        assert_eq!(
+            exact_integer_clear(&[minus_one, 0, 0, 0], false),
+            Err(minus_one)
+        );
+
+        assert_eq!(integer_format_signed(DXGI_FORMAT_R32_UINT), Some(false));
+        assert_eq!(integer_format_signed(DXGI_FORMAT_R8G8_SINT), Some(true));
+        assert_eq!(
+            integer_format_signed(windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R32_FLOAT),
+            None
+        );
+    }
+
+    #[test]
+    fn test_gpu_preference_names() {
+        assert_eq!(
+            gpu_preference("high_performance"),
+            Some(DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE)
+        );
+        assert_eq!(
+            gpu_preference("minimum_power"),
+            Some(DXGI_GPU_PREFERENCE_MINIMUM_POWER)
+        );
+        assert_eq!(
+            gpu_preference("unspecified"),
+            Some(DXGI_GPU_PREFERENCE_UNSPECIFIED)
+        );
+        assert_eq!(gpu_preference("discrete"), None);
+    }
+
+    #[test]
+    fn test_array_view_descs() {
+        let rgba = DXGI_FORMAT_R8G8B8A8_UNORM;
+
+        // A 6-slice TEXTURECUBE is viewed as one cube
+        let cube = array_srv_desc(rgba, 6, true);
+        assert_eq!(cube.ViewDimension, D3D11_SRV_DIMENSION_TEXTURECUBE);
+        assert_eq!(unsafe { cube.Anonymous.TextureCube.MipLevels }, 1);
+
+        let cubes = array_srv_desc(rgba, 12, true);
+        assert_eq!(cubes.ViewDimension, D3D11_SRV_DIMENSION_TEXTURECUBEARRAY);
+        assert_eq!(unsafe { cubes.Anonymous.TextureCubeArray.NumCubes }, 2);
+
+        // The same slices without the cube flag are a plain array
+        let array = array_srv_desc(rgba, 6, false);
+        assert_eq!(array.ViewDimension, D3D11_SRV_DIMENSION_TEXTURE2DARRAY);
+        assert_eq!(unsafe { array.Anonymous.Texture2DArray.ArraySize }, 6);
+
+        let rtv = array_rtv_desc(rgba, 4);
+        assert_eq!(rtv.ViewDimension, D3D11_RTV_DIMENSION_TEXTURE2DARRAY);
+        assert_eq!(unsafe { rtv.Anonymous.Texture2DArray.ArraySize }, 4);
+        assert_eq!(unsafe { rtv.Anonymous.Texture2DArray.FirstArraySlice }, 0);
+    }
+
+    #[test]
+    fn test_texture_size() {
+        assert_eq!(texture_pixels(4, 4, 1, 1), 16);
+        // 4x4 + 2x2 + 1x1, twice
+        assert_eq!(texture_pixels(4, 4, 3, 2), 42);
+        // Non-square chains clamp each side at 1
+        assert_eq!(texture_pixels(4, 1, 3, 1), 7);
+
+        assert_eq!(format_bits_per_pixel(DXGI_FORMAT_R8G8B8A8_UNORM), 32);
+        assert_eq!(format_bits_per_pixel(DXGI_FORMAT_R32G32B32A32_UINT), 128);
+        assert_eq!(format_bits_per_pixel(DXGI_FORMAT_R16_UINT), 16);
+        assert_eq!(format_bits_per_pixel(DXGI_FORMAT_R8_UINT), 8);
+        assert_eq!(format_bits_per_pixel(DXGI_FORMAT_NV12), 12);
+    }
+
+    #[test]
+    fn test_patch_list_topologies() {
+        use crate::protocol::{
+            is_valid_topology, patch_control_points, PVGPU_MAX_PATCH_CONTROL_POINTS,
+            PVGPU_TOPOLOGY_PATCHLIST_1,
+        };
+        use windows::Win32::Graphics::Direct3D::{
+            D3D_PRIMITIVE_TOPOLOGY_16_CONTROL_POINT_PATCHLIST,
+            D3D_PRIMITIVE_TOPOLOGY_1_CONTROL_POINT_PATCHLIST,
+            D3D_PRIMITIVE_TOPOLOGY_32_CONTROL_POINT_PATCHLIST, D3D_PRIMITIVE_TOPOLOGY_LINELIST_ADJ,
+            D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP_ADJ,
+        };
+
+        // The raw value is handed to IASetPrimitiveTopology unchanged
+        assert_eq!(
+            PVGPU_TOPOLOGY_PATCHLIST_1,
+            D3D_PRIMITIVE_TOPOLOGY_1_CONTROL_POINT_PATCHLIST.0 as u32
+        );
+        for (topology, points) in [
+            (D3D_PRIMITIVE_TOPOLOGY_1_CONTROL_POINT_PATCHLIST, 1),
+            (D3D_PRIMITIVE_TOPOLOGY_16_CONTROL_POINT_PATCHLIST, 16),
+            (
+                D3D_PRIMITIVE_TOPOLOGY_32_CONTROL_POINT_PATCHLIST,
+                PVGPU_MAX_PATCH_CONTROL_POINTS,
+            ),
+        ] {
+            assert_eq!(patch_control_points(topology.0 as u32), Some(points));
+        }
+        assert!(is_valid_topology(
+            D3D_PRIMITIVE_TOPOLOGY_LINELIST_ADJ.0 as u32
+        ));
+        assert!(is_valid_topology(
+            D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP_ADJ.0 as u32
+        ));
+    }
+
+    #[test]
+    fn test_copy_rows_padded_pitch() {
+        // 13 RGBA8 texels: 52 packed bytes per row, padded to 64 when mapped
+        let (width, height, pitch) = (13 * 4, 3, 64);
+        let src: Vec<u8> = (0..width * height).map(|i| (i % 251) as u8).collect();
+        let mut dst = vec![0xAAu8; pitch * height];
+
+        assert_eq!(copy_rows(&mut dst, pitch, &src, width), height);
+        for row in 0..height {
+            let start = row * pitch;
+            assert_eq!(
+                &dst[start..start + width],
+                &src[row * width..(row + 1) * width]
+            );
+            // Padding is left alone
+            assert!(dst[start + width..start + pitch].iter().all(|&b| b == 0xAA));
+        }
+
+        // A flat copy would have shifted every row after the first
+        let mut flat = vec![0xAAu8; pitch * height];
+        flat[..src.len()].copy_from_slice(&src);
+        assert_ne!(flat, dst);
+
+        // A short last row and a destination with fewer rows
+        let mut dst = vec![0u8; pitch * 2];
+        assert_eq!(copy_rows(&mut dst, pitch, &src[..width + 10], width), 2);
+        assert_eq!(&dst[pitch..pitch + 10], &src[width..width + 10]);
+        assert_eq!(copy_rows(&mut dst, pitch, &src, width), 2);
+    }
+
+    #[test]
+    fn test_mapped_rows() {
+        assert_eq!(mapped_rows(DXGI_FORMAT_R8G8B8A8_UNORM, 64, 1, 0), 64);
+        // Subresource 4 of a 3-level chain is mip 1 of the second slice
+        assert_eq!(mapped_rows(DXGI_FORMAT_R8G8B8A8_UNORM, 64, 3, 4), 32);
+        assert_eq!(mapped_rows(DXGI_FORMAT_R8G8B8A8_UNORM, 2, 3, 2), 1);
+        assert_eq!(mapped_rows(DXGI_FORMAT_BC1_UNORM, 10, 1, 0), 3);
+        assert_eq!(mapped_rows(DXGI_FORMAT_NV12, 5, 1, 0), 8);
+    }
+
+    #[test]
+    fn test_scrub_subresources() {
+        // Uploads cover a row of texels, or of 4x4 blocks
+        assert_eq!(upload_row_pitch(DXGI_FORMAT_R8G8B8A8_UNORM, 10), 40);
+        assert_eq!(upload_row_pitch(DXGI_FORMAT_BC1_UNORM, 10), 24);
+        assert_eq!(upload_row_pitch(DXGI_FORMAT_BC1_UNORM, 1), 8);
+        assert_eq!(upload_row_pitch(DXGI_FORMAT_NV12, 64), 64);
+
+        // Sampleable depth textures are cleared through their depth format
+        assert_eq!(
+            depth_view_format(DXGI_FORMAT_R24G8_TYPELESS),
+            DXGI_FORMAT_D24_UNORM_S8_UINT
+        );
+        assert_eq!(
+            depth_view_format(DXGI_FORMAT_D32_FLOAT),
+            DXGI_FORMAT_D32_FLOAT
+        );
+
+        let dsv = scrub_dsv_desc(DXGI_FORMAT_D32_FLOAT, 2, 6, false);
+        assert_eq!(dsv.ViewDimension, D3D11_DSV_DIMENSION_TEXTURE2DARRAY);
+        assert_eq!(unsafe { dsv.Anonymous.Texture2DArray.MipSlice }, 2);
+        assert_eq!(unsafe { dsv.Anonymous.Texture2DArray.ArraySize }, 6);
+        let dsv = scrub_dsv_desc(DXGI_FORMAT_D32_FLOAT, 0, 1, true);
+        assert_eq!(dsv.ViewDimension, D3D11_DSV_DIMENSION_TEXTURE2DMSARRAY);
+
+        let rtv = scrub_rtv_desc(DXGI_FORMAT_R8G8B8A8_UNORM, 3, 4, false);
+        assert_eq!(unsafe { rtv.Anonymous.Texture2DArray.MipSlice }, 3);
+        let rtv = scrub_rtv_desc(DXGI_FORMAT_R8G8B8A8_UNORM, 0, 4, true);
+        assert_eq!(rtv.ViewDimension, D3D11_RTV_DIMENSION_TEXTURE2DMSARRAY);
+        assert_eq!(unsafe { rtv.Anonymous.Texture2DMSArray.ArraySize }, 4);
+    }
+
+    #[test]
+    fn test_upload_hash() {
+        let data = [1u8, 2, 3, 4];
+        assert_eq!(
+            upload_hash(&[1, 4, 8], &data),
+            upload_hash(&[1, 4, 8], &data)
+        );
+        assert_ne!(
+            upload_hash(&[1, 4, 8], &data),
+            upload_hash(&[1, 4, 0], &data)
+        );
+        assert_ne!(
+            upload_hash(&[1, 4, 8], &data),
+            upload_hash(&[1, 4, 8], &[1, 2, 3, 5])
+        );
+
+        // A hash match alone does not alias different content
+        let content = UploadContent {
+            id: 1,
+            params: vec![1, 4, 8],
+            data: data.into(),
+        };
+        fn key<'a>(params: &'a [u32], data: &'a [u8]) -> UploadKey<'a> {
+            UploadKey {
+                hash: 0,
+                params,
+                data,
+            }
+        }
+        assert!(content.matches(&key(&[1, 4, 8], &data)));
+        assert!(!content.matches(&key(&[1, 4, 8], &[1, 2, 3, 5])));
+        assert!(!content.matches(&key(&[1, 4, 0], &data)));
+    }
+}
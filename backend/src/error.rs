@@ -0,0 +1,119 @@
+//! Command Processing Errors
+//!
+//! Typed errors returned by `CommandProcessor::process_command`. The main
+//! loop matches on them to report the right PVGPU_ERROR_* code to the guest
+//! and to decide whether the failed command can be skipped.
+//!
+//! Handlers and the renderer return `anyhow::Result`; they raise these
+//! variants with `.into()` and `process_command` recovers them by downcast.
+
+use thiserror::Error;
+use windows::Win32::Foundation::E_OUTOFMEMORY;
+
+use crate::d3d11::ResourceId;
+use crate::protocol::*;
+
+/// Why a command failed
+#[derive(Debug, Error)]
+pub enum ProcessError {
+    /// Shader bytecode was missing or rejected by the driver
+    #[error("shader compilation failed for resource {0}")]
+    ShaderCompile(ResourceId),
+    /// Command type this backend does not implement
+    #[error("unknown command type 0x{0:04X}")]
+    UnknownCommand(u32),
+    /// Resource type or shader stage outside its enum
+    #[error("unknown resource or shader type {0}")]
+    UnknownType(u32),
+    /// A command field was out of range; the value identifies which
+    #[error("invalid parameter ({0})")]
+    InvalidParameter(u32),
+    /// Texture format the adapter cannot create
+    #[error("unsupported format for resource {0}")]
+    UnsupportedFormat(ResourceId),
+    /// Command referenced a resource that does not exist or has the wrong type
+    #[error("resource {0} not found")]
+    ResourceNotFound(ResourceId),
+    /// GPU or system memory exhausted
+    #[error("out of memory: {0}")]
+    OutOfMemory(String),
+    /// Anything else, usually a failed D3D11 call
+    #[error("{0:#}")]
+    Internal(anyhow::Error),
+}
+
+impl ProcessError {
+    /// PVGPU_ERROR_* code and data word to report in the control region
+    pub fn error_code(&self) -> (u32, u32) {
+        match self {
+            ProcessError::ShaderCompile(id) => (PVGPU_ERROR_SHADER_COMPILE, *id),
+            ProcessError::UnknownCommand(command_type) => {
+                (PVGPU_ERROR_INVALID_COMMAND, *command_type)
+            }
+            ProcessError::UnknownType(value) => (PVGPU_ERROR_INVALID_COMMAND, *value),
+            ProcessError::InvalidParameter(value) => (PVGPU_ERROR_INVALID_PARAMETER, *value),
+            ProcessError::UnsupportedFormat(id) => (PVGPU_ERROR_UNSUPPORTED_FORMAT, *id),
+            ProcessError::ResourceNotFound(id) => (PVGPU_ERROR_RESOURCE_NOT_FOUND, *id),
+            ProcessError::OutOfMemory(_) => (PVGPU_ERROR_OUT_OF_MEMORY, 0),
+            ProcessError::Internal(_) => (PVGPU_ERROR_INTERNAL, 0),
+        }
+    }
+
+    /// Whether the command can be skipped and processing continue. Out of
+    /// memory and internal errors may have left the device unusable.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(
+            self,
+            ProcessError::OutOfMemory(_) | ProcessError::Internal(_)
+        )
+    }
+}
+
+impl From<anyhow::Error> for ProcessError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<ProcessError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        let out_of_memory = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<windows::core::Error>()
+                .map(|e| e.code() == E_OUTOFMEMORY)
+                .unwrap_or(false)
+        });
+        if out_of_memory {
+            ProcessError::OutOfMemory(format!("{:#}", error))
+        } else {
+            ProcessError::Internal(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_round_trip() {
+        // Typed errors survive a trip through anyhow
+        let error: anyhow::Error = ProcessError::ShaderCompile(7).into();
+        let error = ProcessError::from(error);
+        assert!(matches!(error, ProcessError::ShaderCompile(7)));
+        assert_eq!(error.error_code(), (PVGPU_ERROR_SHADER_COMPILE, 7));
+        assert!(error.is_recoverable());
+
+        let error = ProcessError::UnknownType(42);
+        assert_eq!(error.error_code(), (PVGPU_ERROR_INVALID_COMMAND, 42));
+        assert!(error.is_recoverable());
+
+        let error = ProcessError::from(anyhow::anyhow!("CreateQuery failed"));
+        assert!(matches!(error, ProcessError::Internal(_)));
+        assert_eq!(error.error_code(), (PVGPU_ERROR_INTERNAL, 0));
+        assert!(!error.is_recoverable());
+
+        let oom: anyhow::Error = windows::core::Error::from(E_OUTOFMEMORY).into();
+        let error = ProcessError::from(oom.context("CreateTexture2D"));
+        assert!(matches!(error, ProcessError::OutOfMemory(_)));
+        assert!(!error.is_recoverable());
+    }
+}
@@ -0,0 +1,209 @@
+//! Frame Ring Module
+//!
+//! Keeps copies of the last few presented frames in GPU textures so they can
+//! be written to disk when something goes wrong (device lost, or on guest
+//! request). Frames are dumped as binary PPM images, oldest first.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use tracing::{info, warn};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_CPU_ACCESS_READ,
+    D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+    D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT_B8G8R8A8_TYPELESS, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_B8G8R8A8_UNORM_SRGB,
+    DXGI_FORMAT_R8G8B8A8_TYPELESS, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+    DXGI_SAMPLE_DESC,
+};
+
+/// Encode a 4-byte-per-pixel image as binary PPM (alpha dropped).
+///
+/// `bgra` selects B8G8R8A8 channel order instead of R8G8B8A8.
+pub fn encode_ppm(width: u32, height: u32, row_pitch: usize, data: &[u8], bgra: bool) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    out.reserve(width as usize * height as usize * 3);
+
+    for y in 0..height as usize {
+        let row = &data[y * row_pitch..y * row_pitch + width as usize * 4];
+        for pixel in row.chunks_exact(4) {
+            if bgra {
+                out.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            } else {
+                out.extend_from_slice(&pixel[..3]);
+            }
+        }
+    }
+    out
+}
+
+/// Ring of the most recently presented frames
+pub struct FrameRing {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    capacity: usize,
+    /// (texture, frame number) per slot, in insertion order modulo capacity
+    slots: Vec<(ID3D11Texture2D, u64)>,
+    /// Next slot to overwrite once the ring is full
+    next: usize,
+    desc: D3D11_TEXTURE2D_DESC,
+}
+
+impl FrameRing {
+    /// Create an empty ring holding up to `capacity` frames
+    pub fn new(device: ID3D11Device, context: ID3D11DeviceContext, capacity: usize) -> Self {
+        info!("Crash frame ring enabled: keeping last {} frames", capacity);
+        Self {
+            device,
+            context,
+            capacity,
+            slots: Vec::with_capacity(capacity),
+            next: 0,
+            desc: D3D11_TEXTURE2D_DESC::default(),
+        }
+    }
+
+    /// Copy a presented frame into the ring, evicting the oldest one
+    pub fn record(&mut self, frame: &ID3D11Texture2D, frame_number: u64) -> Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            frame.GetDesc(&mut desc);
+        }
+
+        // Frames of a different shape can't be copied into existing slots
+        if desc.Width != self.desc.Width
+            || desc.Height != self.desc.Height
+            || desc.Format != self.desc.Format
+        {
+            self.slots.clear();
+            self.next = 0;
+            self.desc = desc;
+        }
+
+        if self.slots.len() < self.capacity {
+            let slot_desc = D3D11_TEXTURE2D_DESC {
+                MipLevels: 1,
+                ArraySize: 1,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: 0,
+                CPUAccessFlags: 0,
+                MiscFlags: 0,
+                ..desc
+            };
+            let mut texture: Option<ID3D11Texture2D> = None;
+            unsafe {
+                self.device
+                    .CreateTexture2D(&slot_desc, None, Some(&mut texture))?;
+            }
+            let texture = texture.ok_or_else(|| anyhow!("Failed to create frame ring slot"))?;
+            unsafe {
+                self.context.CopyResource(&texture, frame);
+            }
+            self.slots.push((texture, frame_number));
+        } else {
+            let slot = &mut self.slots[self.next];
+            unsafe {
+                self.context.CopyResource(&slot.0, frame);
+            }
+            slot.1 = frame_number;
+            self.next = (self.next + 1) % self.capacity;
+        }
+        Ok(())
+    }
+
+    /// Write every frame in the ring to `dir`, oldest first.
+    ///
+    /// Returns the number of frames written.
+    pub fn dump(&self, dir: &Path) -> Result<usize> {
+        let bgra = match self.desc.Format {
+            DXGI_FORMAT_B8G8R8A8_UNORM
+            | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+            | DXGI_FORMAT_B8G8R8A8_TYPELESS => true,
+            DXGI_FORMAT_R8G8B8A8_UNORM
+            | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+            | DXGI_FORMAT_R8G8B8A8_TYPELESS => false,
+            format => {
+                warn!("Frame ring: cannot dump format {:?}", format);
+                return Ok(0);
+            }
+        };
+
+        std::fs::create_dir_all(dir)?;
+
+        let staging_desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: 0,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            MiscFlags: 0,
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            ..self.desc
+        };
+        let mut staging: Option<ID3D11Texture2D> = None;
+        unsafe {
+            self.device
+                .CreateTexture2D(&staging_desc, None, Some(&mut staging))?;
+        }
+        let staging = staging.ok_or_else(|| anyhow!("Failed to create frame dump staging"))?;
+
+        // Oldest frame sits at `next` once the ring has wrapped
+        let count = self.slots.len();
+        let mut written = 0;
+        for i in 0..count {
+            let (ref texture, frame_number) = self.slots[(self.next + i) % count];
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            let image = unsafe {
+                self.context.CopyResource(&staging, texture);
+                self.context
+                    .Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+                let data = std::slice::from_raw_parts(
+                    mapped.pData as *const u8,
+                    mapped.RowPitch as usize * self.desc.Height as usize,
+                );
+                let image = encode_ppm(
+                    self.desc.Width,
+                    self.desc.Height,
+                    mapped.RowPitch as usize,
+                    data,
+                    bgra,
+                );
+                self.context.Unmap(&staging, 0);
+                image
+            };
+
+            std::fs::write(dir.join(format!("frame_{:08}.ppm", frame_number)), image)?;
+            written += 1;
+        }
+
+        info!("Dumped {} recent frames to {}", written, dir.display());
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ppm() {
+        // 2x1 image with 4 bytes of row padding
+        let data = [1, 2, 3, 255, 4, 5, 6, 255, 0, 0, 0, 0];
+        let rgb = encode_ppm(2, 1, 12, &data, false);
+        assert_eq!(&rgb[..11], b"P6\n2 1\n255\n");
+        assert_eq!(&rgb[11..], &[1, 2, 3, 4, 5, 6]);
+
+        let bgr = encode_ppm(2, 1, 12, &data, true);
+        assert_eq!(&bgr[11..], &[3, 2, 1, 6, 5, 4]);
+    }
+}
@@ -7,10 +7,10 @@
 //! 3. Receive IRQ requests from host
 
 use anyhow::{anyhow, Result};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::{
-    CloseHandle, GetLastError, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+    CloseHandle, GetLastError, ERROR_MORE_DATA, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
 };
 use windows::Win32::Storage::FileSystem::{ReadFile, WriteFile, PIPE_ACCESS_DUPLEX};
 use windows::Win32::System::Pipes::{
@@ -24,19 +24,42 @@ use windows::Win32::System::Threading::{
 /// Messages from QEMU device to backend
 #[derive(Debug, Clone)]
 pub enum QemuMessage {
-    /// QEMU connected, provides shared memory handle name
-    Handshake { shmem_name: String, shmem_size: u64 },
+    /// QEMU connected, provides shared memory handle name and
+    /// HANDSHAKE_FLAG_* bits
+    Handshake {
+        shmem_name: String,
+        shmem_size: u64,
+        flags: u32,
+    },
     /// Doorbell notification - new commands in ring
     Doorbell,
     /// QEMU is shutting down
     Shutdown,
 }
 
+/// QEMU reads the shared memory sizes in HandshakeAck / HandshakeNack.
+/// Older QEMU copies the reply into a fixed 8-byte buffer, so the sizes are
+/// only sent when this is set.
+pub const HANDSHAKE_FLAG_EXTENDED_REPLY: u32 = 1 << 0;
+
 /// Messages from backend to QEMU device
 #[derive(Debug, Clone)]
 pub enum BackendMessage {
-    /// Handshake accepted, ready to process
-    HandshakeAck { features: u64 },
+    /// Handshake accepted, ready to process. With `extended` it also carries
+    /// the shared memory size the backend prefers and the largest it accepts.
+    HandshakeAck {
+        features: u64,
+        preferred_shmem_size: u64,
+        max_shmem_size: u64,
+        extended: bool,
+    },
+    /// Handshake rejected, carries a PVGPU_ERROR_* reason code and, with
+    /// `extended`, the largest shared memory size the backend accepts
+    HandshakeNack {
+        reason: u32,
+        max_shmem_size: u64,
+        extended: bool,
+    },
     /// Request QEMU to send IRQ to guest
     Irq { vector: u32 },
 }
@@ -51,6 +74,7 @@ enum MessageType {
     Doorbell = 3,
     Irq = 4,
     Shutdown = 5,
+    HandshakeNack = 6,
 }
 
 /// Wire protocol header
@@ -63,6 +87,188 @@ struct MessageHeader {
 
 const HEADER_SIZE: usize = std::mem::size_of::<MessageHeader>();
 
+/// Largest payload accepted from QEMU. Every message we understand is far
+/// smaller than this; anything bigger is a corrupt header and is rejected
+/// before allocating.
+const MAX_PAYLOAD_SIZE: u32 = 64 * 1024;
+
+/// Result of a single read from a message-mode pipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadChunk {
+    /// The read reached the end of the current message
+    Complete(usize),
+    /// The buffer was filled but the message has more bytes (ERROR_MORE_DATA)
+    MoreData(usize),
+}
+
+/// Source of raw pipe messages (the named pipe, or a mock in tests)
+trait MessageSource {
+    fn read_chunk(&self, buf: &mut [u8]) -> Result<ReadChunk>;
+}
+
+/// Discard the remainder of a message that did not fit the read buffer.
+fn drain_message(source: &impl MessageSource) -> Result<usize> {
+    let mut scratch = [0u8; 256];
+    let mut drained = 0;
+    loop {
+        match source.read_chunk(&mut scratch)? {
+            ReadChunk::Complete(n) => return Ok(drained + n),
+            ReadChunk::MoreData(n) => drained += n,
+        }
+    }
+}
+
+/// Read one header message and its payload message from the source.
+///
+/// Zero-byte messages are skipped, a header that is not exactly
+/// `HEADER_SIZE` bytes is rejected, and the payload is read in a loop until
+/// `payload_size` bytes have arrived so that ERROR_MORE_DATA splits are
+/// reassembled instead of silently truncated.
+fn read_frame(source: &impl MessageSource) -> Result<(u32, Vec<u8>)> {
+    let mut header_buf = [0u8; HEADER_SIZE];
+    loop {
+        match source.read_chunk(&mut header_buf)? {
+            ReadChunk::Complete(0) => {
+                debug!("Ignoring zero-byte pipe message");
+                continue;
+            }
+            ReadChunk::Complete(n) if n == HEADER_SIZE => break,
+            ReadChunk::Complete(n) => {
+                return Err(anyhow!("Incomplete header read: {} bytes", n));
+            }
+            ReadChunk::MoreData(n) => {
+                let extra = drain_message(source)?;
+                return Err(anyhow!("Oversized header message: {} bytes", n + extra));
+            }
+        }
+    }
+
+    // Parse header
+    let header: MessageHeader =
+        unsafe { std::ptr::read_unaligned(header_buf.as_ptr() as *const MessageHeader) };
+    let msg_type = header.msg_type;
+    let payload_size = header.payload_size;
+
+    if payload_size == 0 {
+        return Ok((msg_type, Vec::new()));
+    }
+
+    if payload_size > MAX_PAYLOAD_SIZE {
+        // The payload follows as its own message; consume it so the next
+        // read starts on a header boundary.
+        let discarded = drain_message(source)?;
+        return Err(anyhow!(
+            "Payload size {} exceeds maximum {} (discarded {} bytes)",
+            payload_size,
+            MAX_PAYLOAD_SIZE,
+            discarded
+        ));
+    }
+
+    let mut payload = vec![0u8; payload_size as usize];
+    let mut filled = 0;
+    while filled < payload.len() {
+        match source.read_chunk(&mut payload[filled..])? {
+            ReadChunk::Complete(n) => {
+                filled += n;
+                break;
+            }
+            ReadChunk::MoreData(n) => {
+                filled += n;
+                if filled == payload.len() {
+                    let extra = drain_message(source)?;
+                    return Err(anyhow!(
+                        "Payload larger than declared: {} extra bytes",
+                        extra
+                    ));
+                }
+            }
+        }
+    }
+
+    if filled < payload.len() {
+        return Err(anyhow!(
+            "Truncated payload: got {} of {} bytes",
+            filled,
+            payload.len()
+        ));
+    }
+
+    Ok((msg_type, payload))
+}
+
+/// Decode a message body into a `QemuMessage`.
+fn parse_message(msg_type: u32, payload: &[u8]) -> Result<QemuMessage> {
+    match msg_type {
+        1 => {
+            // Handshake
+            // Payload format: shmem_size (u64) + shmem_name (null-terminated
+            // string) + flags (u32, absent from older QEMU)
+            if payload.len() < 8 {
+                return Err(anyhow!("Handshake payload too small"));
+            }
+            let shmem_size = u64::from_le_bytes(payload[0..8].try_into()?);
+            let rest = &payload[8..];
+            let name_end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+            let shmem_name = String::from_utf8_lossy(&rest[..name_end]).to_string();
+            let flags = rest
+                .get(name_end + 1..name_end + 5)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .unwrap_or(0);
+            debug!(
+                "Received handshake: shmem_name={}, size={}, flags={:#x}",
+                shmem_name, shmem_size, flags
+            );
+            Ok(QemuMessage::Handshake {
+                shmem_name,
+                shmem_size,
+                flags,
+            })
+        }
+        3 => Ok(QemuMessage::Doorbell),
+        5 => {
+            // Shutdown
+            info!("Received shutdown from QEMU");
+            Ok(QemuMessage::Shutdown)
+        }
+        _ => Err(anyhow!("Unknown message type: {}", msg_type)),
+    }
+}
+
+/// Encode a `BackendMessage` as its message type and little-endian body.
+fn encode_message(msg: &BackendMessage) -> (u32, Vec<u8>) {
+    match *msg {
+        BackendMessage::HandshakeAck {
+            features,
+            preferred_shmem_size,
+            max_shmem_size,
+            extended,
+        } => {
+            // Payload format: features, then preferred and max size if
+            // extended (u64 each)
+            let mut payload = features.to_le_bytes().to_vec();
+            if extended {
+                payload.extend_from_slice(&preferred_shmem_size.to_le_bytes());
+                payload.extend_from_slice(&max_shmem_size.to_le_bytes());
+            }
+            (2, payload)
+        }
+        BackendMessage::Irq { vector } => (4, vector.to_le_bytes().to_vec()),
+        BackendMessage::HandshakeNack {
+            reason,
+            max_shmem_size,
+            extended,
+        } => {
+            // Payload format: reason (u32), then max size (u64) if extended
+            let mut payload = reason.to_le_bytes().to_vec();
+            if extended {
+                payload.extend_from_slice(&max_shmem_size.to_le_bytes());
+            }
+            (6, payload)
+        }
+    }
+}
+
 /// Named pipe server for QEMU communication
 pub struct PipeServer {
     pipe_path: String,
@@ -137,86 +343,21 @@ impl PipeServer {
 
     /// Read a message from QEMU
     pub fn read_message(&self) -> Result<QemuMessage> {
-        // Read header into a buffer
-        let mut header_buf = [0u8; HEADER_SIZE];
-        let mut bytes_read: u32 = 0;
-
-        unsafe {
-            ReadFile(
-                self.pipe_handle,
-                Some(&mut header_buf),
-                Some(&mut bytes_read),
-                None,
-            )?;
-        }
-
-        if bytes_read as usize != HEADER_SIZE {
-            return Err(anyhow!("Incomplete header read: {} bytes", bytes_read));
-        }
+        let (msg_type, payload) = read_frame(self)?;
+        let msg = parse_message(msg_type, &payload)?;
 
-        // Parse header
-        let header: MessageHeader =
-            unsafe { std::ptr::read_unaligned(header_buf.as_ptr() as *const MessageHeader) };
-
-        // Read payload if present
-        let mut payload = vec![0u8; header.payload_size as usize];
-        if header.payload_size > 0 {
-            let mut payload_read: u32 = 0;
-            unsafe {
-                ReadFile(
-                    self.pipe_handle,
-                    Some(payload.as_mut_slice()),
-                    Some(&mut payload_read),
-                    None,
-                )?;
-            }
+        if let QemuMessage::Doorbell = msg {
+            // Doorbell - signal the event so the main loop wakes up
+            debug!("Received doorbell");
+            self.signal_doorbell();
         }
 
-        // Parse message
-        match header.msg_type {
-            1 => {
-                // Handshake
-                // Payload format: shmem_size (u64) + shmem_name (null-terminated string)
-                if payload.len() < 8 {
-                    return Err(anyhow!("Handshake payload too small"));
-                }
-                let shmem_size = u64::from_le_bytes(payload[0..8].try_into()?);
-                let shmem_name = String::from_utf8_lossy(&payload[8..])
-                    .trim_end_matches('\0')
-                    .to_string();
-                debug!(
-                    "Received handshake: shmem_name={}, size={}",
-                    shmem_name, shmem_size
-                );
-                Ok(QemuMessage::Handshake {
-                    shmem_name,
-                    shmem_size,
-                })
-            }
-            3 => {
-                // Doorbell - signal the event so the main loop wakes up
-                debug!("Received doorbell");
-                self.signal_doorbell();
-                Ok(QemuMessage::Doorbell)
-            }
-            5 => {
-                // Shutdown
-                info!("Received shutdown from QEMU");
-                Ok(QemuMessage::Shutdown)
-            }
-            _ => {
-                let msg_type = header.msg_type;
-                Err(anyhow!("Unknown message type: {}", msg_type))
-            }
-        }
+        Ok(msg)
     }
 
     /// Send a message to QEMU
     pub fn send_message(&self, msg: BackendMessage) -> Result<()> {
-        let (msg_type, payload) = match msg {
-            BackendMessage::HandshakeAck { features } => (2u32, features.to_le_bytes().to_vec()),
-            BackendMessage::Irq { vector } => (4u32, vector.to_le_bytes().to_vec()),
-        };
+        let (msg_type, payload) = encode_message(&msg);
 
         let header = MessageHeader {
             msg_type,
@@ -303,6 +444,22 @@ impl PipeServer {
     }
 }
 
+impl MessageSource for PipeServer {
+    fn read_chunk(&self, buf: &mut [u8]) -> Result<ReadChunk> {
+        let mut bytes_read: u32 = 0;
+        let result = unsafe { ReadFile(self.pipe_handle, Some(buf), Some(&mut bytes_read), None) };
+
+        match result {
+            Ok(()) => Ok(ReadChunk::Complete(bytes_read as usize)),
+            Err(e) if e.code() == ERROR_MORE_DATA.to_hresult() => {
+                warn!("Pipe message larger than {} byte read buffer", buf.len());
+                Ok(ReadChunk::MoreData(bytes_read as usize))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 // SAFETY: Windows named pipe HANDLEs and event HANDLEs are safe
 // to use from multiple threads. ReadFile and WriteFile are thread-safe
 // on the same handle (they serialize internally in kernel mode).
@@ -324,3 +481,188 @@ impl Drop for PipeServer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Message-mode pipe mock: each queued Vec is one message, delivered in
+    /// pieces no larger than the caller's buffer.
+    struct MockPipe {
+        messages: RefCell<VecDeque<Vec<u8>>>,
+    }
+
+    impl MockPipe {
+        fn new(messages: Vec<Vec<u8>>) -> Self {
+            Self {
+                messages: RefCell::new(messages.into()),
+            }
+        }
+    }
+
+    impl MessageSource for MockPipe {
+        fn read_chunk(&self, buf: &mut [u8]) -> Result<ReadChunk> {
+            let mut messages = self.messages.borrow_mut();
+            let msg = messages.front_mut().ok_or_else(|| anyhow!("pipe closed"))?;
+            let n = msg.len().min(buf.len());
+            buf[..n].copy_from_slice(&msg[..n]);
+            msg.drain(..n);
+            if msg.is_empty() {
+                messages.pop_front();
+                Ok(ReadChunk::Complete(n))
+            } else {
+                Ok(ReadChunk::MoreData(n))
+            }
+        }
+    }
+
+    fn header(msg_type: u32, payload_size: u32) -> Vec<u8> {
+        let mut v = msg_type.to_le_bytes().to_vec();
+        v.extend_from_slice(&payload_size.to_le_bytes());
+        v
+    }
+
+    fn handshake_payload(size: u64, name: &str) -> Vec<u8> {
+        let mut v = size.to_le_bytes().to_vec();
+        v.extend_from_slice(name.as_bytes());
+        v.push(0);
+        v
+    }
+
+    #[test]
+    fn test_read_frame_handshake() {
+        let payload = handshake_payload(0x1000_0000, "pvgpu_shmem");
+        let pipe = MockPipe::new(vec![header(1, payload.len() as u32), payload]);
+        let (msg_type, payload) = read_frame(&pipe).unwrap();
+        match parse_message(msg_type, &payload).unwrap() {
+            QemuMessage::Handshake {
+                shmem_name,
+                shmem_size,
+                flags,
+            } => {
+                assert_eq!(shmem_name, "pvgpu_shmem");
+                assert_eq!(shmem_size, 0x1000_0000);
+                assert_eq!(flags, 0);
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_frame_skips_zero_byte_messages() {
+        let pipe = MockPipe::new(vec![Vec::new(), Vec::new(), header(3, 0)]);
+        let (msg_type, payload) = read_frame(&pipe).unwrap();
+        assert_eq!(msg_type, 3);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn test_read_frame_short_header() {
+        let pipe = MockPipe::new(vec![vec![3, 0, 0]]);
+        assert!(read_frame(&pipe).is_err());
+    }
+
+    #[test]
+    fn test_read_frame_oversized_header_is_drained() {
+        let mut long = header(3, 0);
+        long.extend_from_slice(&[0xAA; 12]);
+        let pipe = MockPipe::new(vec![long, header(5, 0)]);
+        assert!(read_frame(&pipe).is_err());
+        // The next read starts cleanly on the following message
+        let (msg_type, _) = read_frame(&pipe).unwrap();
+        assert_eq!(msg_type, 5);
+    }
+
+    #[test]
+    fn test_read_frame_truncated_payload() {
+        let pipe = MockPipe::new(vec![header(1, 32), vec![0u8; 10]]);
+        let err = read_frame(&pipe).unwrap_err();
+        assert!(err.to_string().contains("Truncated payload"));
+    }
+
+    #[test]
+    fn test_read_frame_payload_larger_than_declared() {
+        let pipe = MockPipe::new(vec![header(1, 8), vec![0u8; 20], header(3, 0)]);
+        let err = read_frame(&pipe).unwrap_err();
+        assert!(err.to_string().contains("larger than declared"));
+        let (msg_type, _) = read_frame(&pipe).unwrap();
+        assert_eq!(msg_type, 3);
+    }
+
+    #[test]
+    fn test_read_frame_rejects_oversized_payload() {
+        let pipe = MockPipe::new(vec![
+            header(1, MAX_PAYLOAD_SIZE + 1),
+            vec![0u8; 300],
+            header(3, 0),
+        ]);
+        let err = read_frame(&pipe).unwrap_err();
+        assert!(err.to_string().contains("exceeds maximum"));
+        let (msg_type, _) = read_frame(&pipe).unwrap();
+        assert_eq!(msg_type, 3);
+    }
+
+    #[test]
+    fn test_parse_handshake_flags() {
+        let mut payload = handshake_payload(0x1000_0000, "pvgpu_shmem");
+        payload.extend_from_slice(&HANDSHAKE_FLAG_EXTENDED_REPLY.to_le_bytes());
+        match parse_message(1, &payload).unwrap() {
+            QemuMessage::Handshake {
+                shmem_name, flags, ..
+            } => {
+                assert_eq!(shmem_name, "pvgpu_shmem");
+                assert_eq!(flags, HANDSHAKE_FLAG_EXTENDED_REPLY);
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode_handshake_replies() {
+        // QEMU that did not ask for the sizes gets the original payloads
+        let (msg_type, payload) = encode_message(&BackendMessage::HandshakeAck {
+            features: 0x7,
+            preferred_shmem_size: 0x1000_0000,
+            max_shmem_size: 0x4000_0000,
+            extended: false,
+        });
+        assert_eq!(msg_type, 2);
+        assert_eq!(payload, 0x7u64.to_le_bytes());
+        let (msg_type, payload) = encode_message(&BackendMessage::HandshakeNack {
+            reason: 0x4,
+            max_shmem_size: 0x4000_0000,
+            extended: false,
+        });
+        assert_eq!(msg_type, 6);
+        assert_eq!(payload, 0x4u32.to_le_bytes());
+
+        let (msg_type, payload) = encode_message(&BackendMessage::HandshakeAck {
+            features: 0x7,
+            preferred_shmem_size: 0x1000_0000,
+            max_shmem_size: 0x4000_0000,
+            extended: true,
+        });
+        assert_eq!(msg_type, 2);
+        assert_eq!(payload.len(), 24);
+        assert_eq!(payload[0..8], 0x7u64.to_le_bytes());
+        assert_eq!(payload[8..16], 0x1000_0000u64.to_le_bytes());
+        assert_eq!(payload[16..24], 0x4000_0000u64.to_le_bytes());
+
+        let (msg_type, payload) = encode_message(&BackendMessage::HandshakeNack {
+            reason: 0x4,
+            max_shmem_size: 0x4000_0000,
+            extended: true,
+        });
+        assert_eq!(msg_type, 6);
+        assert_eq!(payload.len(), 12);
+        assert_eq!(payload[0..4], 0x4u32.to_le_bytes());
+        assert_eq!(payload[4..12], 0x4000_0000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parse_unknown_message_type() {
+        assert!(parse_message(42, &[]).is_err());
+    }
+}
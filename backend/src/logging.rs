@@ -0,0 +1,123 @@
+//! Logging Module
+//!
+//! Installs the tracing subscriber behind a reloadable level filter, so the
+//! guest can raise or lower verbosity with CMD_SET_LOG_LEVEL while the
+//! backend keeps running. Output goes to the console and, optionally, to a
+//! daily rolling log file.
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::protocol::{
+    PVGPU_LOG_LEVEL_DEBUG, PVGPU_LOG_LEVEL_ERROR, PVGPU_LOG_LEVEL_INFO, PVGPU_LOG_LEVEL_TRACE,
+    PVGPU_LOG_LEVEL_WARN,
+};
+
+/// Filter handle shared by every backend instance in the process
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Install the global subscriber, logging at `level` and below unless
+/// RUST_LOG or PVGPU_DEBUG=1 override it. With `log_file`, output is also
+/// written to `<log_file>.YYYY-MM-DD`, starting a new file each day.
+///
+/// The returned guard flushes the log file when dropped, so it must be held
+/// until the process exits.
+pub fn init(level: Level, log_file: Option<&Path>) -> Result<Option<WorkerGuard>> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) => EnvFilter::try_new(directives)?,
+        Err(_) if std::env::var("PVGPU_DEBUG").is_ok_and(|v| v == "1") => {
+            level_filter(Level::DEBUG)
+        }
+        Err(_) => level_filter(level),
+    };
+
+    let (file_layer, guard) = match log_file {
+        Some(path) => {
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("Log file {} has no file name", path.display()))?;
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let appender = tracing_appender::rolling::daily(dir, file_name);
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .with_target(true)
+                .with_ansi(false)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_target(true))
+        .with(file_layer)
+        .init();
+    let _ = FILTER.set(handle);
+    Ok(guard)
+}
+
+/// Change the maximum level logged from now on
+pub fn set_level(level: Level) -> Result<()> {
+    let handle = FILTER
+        .get()
+        .ok_or_else(|| anyhow!("Logging not initialized"))?;
+    handle.reload(level_filter(level))?;
+    Ok(())
+}
+
+fn level_filter(level: Level) -> EnvFilter {
+    EnvFilter::default().add_directive(LevelFilter::from_level(level).into())
+}
+
+/// Tracing level for a config name such as `"info"`, in any case
+pub fn level_from_name(name: &str) -> Option<Level> {
+    name.parse().ok()
+}
+
+/// Tracing level for a PVGPU_LOG_LEVEL_* value
+pub fn level_from_protocol(level: u32) -> Option<Level> {
+    match level {
+        PVGPU_LOG_LEVEL_ERROR => Some(Level::ERROR),
+        PVGPU_LOG_LEVEL_WARN => Some(Level::WARN),
+        PVGPU_LOG_LEVEL_INFO => Some(Level::INFO),
+        PVGPU_LOG_LEVEL_DEBUG => Some(Level::DEBUG),
+        PVGPU_LOG_LEVEL_TRACE => Some(Level::TRACE),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_from_protocol() {
+        assert_eq!(
+            level_from_protocol(PVGPU_LOG_LEVEL_ERROR),
+            Some(Level::ERROR)
+        );
+        assert_eq!(
+            level_from_protocol(PVGPU_LOG_LEVEL_TRACE),
+            Some(Level::TRACE)
+        );
+        assert_eq!(level_from_protocol(0), None);
+        assert_eq!(level_from_protocol(PVGPU_LOG_LEVEL_TRACE + 1), None);
+    }
+
+    #[test]
+    fn test_level_from_name() {
+        assert_eq!(level_from_name("info"), Some(Level::INFO));
+        assert_eq!(level_from_name("WARN"), Some(Level::WARN));
+        assert_eq!(level_from_name("trace"), Some(Level::TRACE));
+        assert_eq!(level_from_name("verbose"), None);
+    }
+}
@@ -12,50 +12,83 @@
 
 mod command_processor;
 mod config;
+mod cross_adapter;
 mod d3d11;
+mod error;
+mod frame_ring;
 mod ipc;
+mod logging;
 mod presentation;
 mod protocol;
+mod scheduling;
+mod selftest;
 mod shmem;
+mod srgb;
+mod video;
+mod watchdog;
+mod winservice;
 
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use tracing::{error, info, trace, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R8G8B8A8_UNORM;
 
 use crate::command_processor::CommandProcessor;
 use crate::config::Config;
-use crate::d3d11::D3D11Renderer;
-use crate::ipc::{BackendMessage, PipeServer, QemuMessage};
-use crate::presentation::{PresentationConfig, PresentationMode, PresentationPipeline};
-use crate::shmem::SharedMemory;
+use crate::d3d11::{AdapterSelection, D3D11Renderer};
+use crate::error::ProcessError;
+use crate::ipc::{BackendMessage, PipeServer, QemuMessage, HANDSHAKE_FLAG_EXTENDED_REPLY};
+use crate::presentation::{
+    DisplayConfig, PresentBackend, PresentTargets, PresentationConfig, PresentationMode,
+    PresentationPipeline, DISPLAY_FORMATS,
+};
+use crate::shmem::{validate_shmem_size, SharedMemory};
+use crate::video::is_planar_yuv;
+use crate::watchdog::Watchdog;
 
 pub use protocol::*;
 
+/// How often open guest maps are checked for leaks
+const MAP_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often video memory figures are published to the guest
+const MEMORY_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a paused main loop checks for resume and window messages
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 /// Backend service state
 struct BackendService {
     config: Config,
+    /// Index of this guest among `Config::instances`
+    instance: u32,
     pipe_server: Option<Arc<PipeServer>>,
-    shared_memory: Option<SharedMemory>,
+    /// Shared with the GPU watchdog thread
+    shared_memory: Option<Arc<SharedMemory>>,
     command_processor: Option<CommandProcessor>,
     presentation: Option<PresentationPipeline>,
     shutdown: Arc<AtomicBool>,
+    /// Command processing is paused; the ring fills up untouched
+    paused: bool,
     pipe_reader_handle: Option<thread::JoinHandle<()>>,
 }
 
 impl BackendService {
-    fn new(config: Config) -> Self {
+    fn new(config: Config, instance: u32, shutdown: Arc<AtomicBool>) -> Self {
         Self {
             config,
+            instance,
             pipe_server: None,
             shared_memory: None,
             command_processor: None,
             presentation: None,
-            shutdown: Arc::new(AtomicBool::new(false)),
+            shutdown,
+            paused: false,
             pipe_reader_handle: None,
         }
     }
@@ -83,6 +116,7 @@ impl BackendService {
             QemuMessage::Handshake {
                 shmem_name,
                 shmem_size,
+                flags,
             } => {
                 info!(
                     "Handshake received: shmem_name={}, size={}MB",
@@ -90,14 +124,36 @@ impl BackendService {
                     shmem_size / (1024 * 1024)
                 );
 
+                let extended = flags & HANDSHAKE_FLAG_EXTENDED_REPLY != 0;
+                let max_shmem_size = self.config.max_shmem_size;
+                let reject = |e: anyhow::Error| -> Result<()> {
+                    error!("Rejecting handshake: {}", e);
+                    server.send_message(BackendMessage::HandshakeNack {
+                        reason: PVGPU_ERROR_INVALID_PARAMETER,
+                        max_shmem_size,
+                        extended,
+                    })?;
+                    Err(e)
+                };
+
+                let shmem_size = match validate_shmem_size(shmem_size, max_shmem_size) {
+                    Ok(size) => size,
+                    Err(e) => return reject(e),
+                };
+
                 // Open shared memory
-                let shmem = SharedMemory::open(&shmem_name, shmem_size as usize)?;
-                shmem.validate_control_region()?;
-                self.shared_memory = Some(shmem);
+                let shmem = SharedMemory::open(&shmem_name, shmem_size)?;
+                if let Err(e) = shmem.validate_control_region() {
+                    return reject(e);
+                }
+                self.shared_memory = Some(Arc::new(shmem));
 
                 // Send handshake acknowledgement
                 server.send_message(BackendMessage::HandshakeAck {
                     features: PVGPU_FEATURES_MVP,
+                    preferred_shmem_size: self.config.preferred_shmem_size.min(max_shmem_size),
+                    max_shmem_size,
+                    extended,
                 })?;
 
                 info!("Handshake complete!");
@@ -107,17 +163,113 @@ impl BackendService {
         }
     }
 
+    /// Replace `adapter_index` with the adapter DXGI picks for the configured
+    /// `gpu_preference`, if any. Keeps the index when IDXGIFactory6 is
+    /// unavailable or the lookup fails.
+    fn apply_gpu_preference(&mut self) {
+        let Some(name) = self.config.gpu_preference.as_deref() else {
+            return;
+        };
+        let Some(preference) = d3d11::gpu_preference(name) else {
+            warn!("Unknown gpu_preference '{}', using adapter index", name);
+            return;
+        };
+        match D3D11Renderer::adapter_index_by_preference(preference) {
+            Ok(Some(index)) => {
+                info!("gpu_preference {} selects adapter {}", name, index);
+                self.config.adapter_index = index;
+            }
+            Ok(None) => warn!(
+                "gpu_preference needs IDXGIFactory6, using adapter index {}",
+                self.config.adapter_index
+            ),
+            Err(e) => warn!(
+                "Adapter lookup for gpu_preference {} failed ({}), using adapter index {}",
+                name, e, self.config.adapter_index
+            ),
+        }
+    }
+
+    /// How the renderer picks its adapter: from `adapter_pool` unless
+    /// `render_adapter` is set, otherwise by index or `gpu_preference`
+    fn adapter_selection(&mut self) -> Result<AdapterSelection> {
+        if self.config.render_adapter.is_none() && !self.config.adapter_pool.is_empty() {
+            let pool = self.config.adapter_pool.clone();
+            return Ok(match self.config.adapter_policy.as_str() {
+                "round_robin" => AdapterSelection::RoundRobin {
+                    pool,
+                    slot: self.instance,
+                },
+                "least_loaded" => AdapterSelection::LeastLoaded(pool),
+                policy => {
+                    warn!("Unknown adapter_policy '{}', using least_loaded", policy);
+                    AdapterSelection::LeastLoaded(pool)
+                }
+            });
+        }
+
+        self.apply_gpu_preference();
+        let index = D3D11Renderer::resolve_adapter_index(
+            self.config.render_adapter_index(),
+            self.config.strict_adapter,
+        )?;
+        Ok(AdapterSelection::Index(index))
+    }
+
     /// Initialize D3D11 renderer and presentation pipeline
     fn init_renderer(&mut self) -> Result<()> {
         info!("Initializing D3D11 renderer...");
-        let renderer = D3D11Renderer::new(Some(self.config.adapter_index))?;
+        let selection = self.adapter_selection()?;
+        let mut renderer = D3D11Renderer::new(&selection)?;
+        let render_adapter = renderer.adapter_info().index;
+        if !matches!(selection, AdapterSelection::Index(_)) {
+            // Device recovery stays on the adapter the pool picked
+            info!("adapter_pool selects adapter {}", render_adapter);
+            self.config.adapter_index = render_adapter;
+            self.config.adapter_pool.clear();
+            self.config.gpu_preference = None;
+        }
+        let present_adapter = D3D11Renderer::resolve_adapter_index(
+            self.config.present_adapter_index(render_adapter),
+            self.config.strict_adapter,
+        )?;
+        renderer.set_scrub_on_destroy(self.config.scrub_on_destroy);
+        renderer.set_content_dedup(self.config.dedup_uploads);
+        renderer.set_validate_indirect_args(self.config.validate_indirect_args);
+        renderer.set_strict_dispatch_limits(self.config.strict_dispatch_limits);
+        if self.config.max_frame_latency != 0 {
+            renderer.set_max_frame_latency(self.config.max_frame_latency)?;
+        }
 
         // Get device and context for presentation pipeline before moving renderer
-        let device = renderer.device().clone();
-        let context = renderer.context().clone();
+        let render_device = renderer.device().clone();
+        let render_context = renderer.context().clone();
+
+        let cross_adapter = present_adapter != render_adapter;
+        let (device, context) = if cross_adapter {
+            info!(
+                "Rendering on adapter {}, presenting on adapter {}",
+                render_adapter, present_adapter
+            );
+            let (device, context, _) = D3D11Renderer::create_device_for_adapter(present_adapter)?;
+            (device, context)
+        } else {
+            (render_device.clone(), render_context.clone())
+        };
+
+        // The guest cannot render straight into another adapter's swapchain
+        let host_backbuffer = self.config.host_backbuffer && !cross_adapter;
+        if self.config.host_backbuffer && cross_adapter {
+            warn!("host_backbuffer is not supported across adapters, disabling");
+        }
 
         // Create command processor with the renderer
-        let processor = CommandProcessor::new(renderer);
+        let mut processor = CommandProcessor::new(renderer);
+        processor.set_slow_create_threshold(match self.config.slow_create_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        });
+        processor.set_guest_log_level(self.config.allow_guest_log_level);
         self.command_processor = Some(processor);
 
         // Initialize presentation pipeline from config
@@ -126,42 +278,461 @@ impl BackendService {
             "dual" => PresentationMode::Dual,
             _ => PresentationMode::Headless,
         };
+        let present_targets = match self.config.present_targets.as_str() {
+            "window" => PresentTargets::Window,
+            "shared" => PresentTargets::Shared,
+            _ => PresentTargets::Both,
+        };
+        let present_backend = match self.config.present_backend.as_str() {
+            "dcomp" => PresentBackend::DirectComposition,
+            _ => PresentBackend::Hwnd,
+        };
         let presentation_config = PresentationConfig {
             mode: presentation_mode,
+            targets: present_targets,
+            backend: present_backend,
+            transparent: self.config.transparent_window,
             width: self.config.width,
             height: self.config.height,
             vsync: self.config.vsync,
-            window_title: "PVGPU Output".to_string(),
-            frame_event_name: Some("Global\\PVGPU_FrameEvent".to_string()),
+            window_title: match self.instance {
+                0 => "PVGPU Output".to_string(),
+                instance => format!("PVGPU Output ({})", instance),
+            },
+            frame_event_name: Some(format!(
+                "Global\\PVGPU_FrameEvent{}",
+                Config::instance_suffix(self.instance)
+            )),
             buffer_count: self.config.buffer_count,
+            // The guest holds the host backbuffer, so its buffers cannot be recreated
+            adaptive_buffering: self.config.adaptive_buffering && !host_backbuffer,
+            occlusion_poll_interval: Duration::from_millis(self.config.occlusion_poll_ms),
+            // The guest holds the host backbuffer, which recovery would replace
+            display_change_recovery: self.config.display_change_recovery && !host_backbuffer,
             allow_tearing: !self.config.vsync,
+            host_backbuffer,
+            sample_count: self.config.swapchain_msaa,
+            srgb_backbuffer: self.config.srgb_backbuffer,
+            crash_frame_ring: self.config.crash_frame_ring,
+            letterbox_color: self.config.letterbox_color,
+            ..PresentationConfig::default()
         };
 
         info!("Initializing presentation pipeline...");
-        let presentation = PresentationPipeline::new(device, context, presentation_config)?;
+        let mut presentation = PresentationPipeline::new(device, context, presentation_config)?;
+        if cross_adapter {
+            presentation.set_render_device(render_device, render_context)?;
+        }
 
         if let Some(handle) = presentation.shared_handle() {
             info!("Shared texture handle: {:?}", handle);
         }
 
         self.presentation = Some(presentation);
+        self.attach_host_backbuffer()?;
+        self.publish_display_config();
+        self.publish_capabilities();
 
         info!("D3D11 renderer and presentation pipeline initialized");
         Ok(())
     }
 
+    /// Recreate the renderer and presentation pipeline on adapter `index`.
+    ///
+    /// Every guest resource is lost, as with CMD_RESET_DEVICE. If the new
+    /// adapter fails to initialize the previous one is restored.
+    fn switch_adapter(&mut self, index: u32) -> Result<()> {
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .set_status_flag(PVGPU_STATUS_RECOVERY);
+        }
+
+        let previous = (
+            self.config.adapter_index,
+            self.config.render_adapter,
+            self.config.gpu_preference.take(),
+            std::mem::take(&mut self.config.adapter_pool),
+        );
+        // The presentation pipeline shares the renderer's device
+        self.presentation = None;
+        self.command_processor = None;
+
+        self.config.adapter_index = index;
+        self.config.render_adapter = None;
+        let result = match self.init_renderer() {
+            Ok(()) => {
+                info!("Switched to adapter {}", index);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Adapter {} failed to initialize: {}", index, e);
+                self.presentation = None;
+                self.command_processor = None;
+                (
+                    self.config.adapter_index,
+                    self.config.render_adapter,
+                    self.config.gpu_preference,
+                    self.config.adapter_pool,
+                ) = previous;
+                self.init_renderer()
+            }
+        };
+
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .clear_status_flag(PVGPU_STATUS_RECOVERY);
+        }
+        result
+    }
+
+    /// Recreate the renderer and presentation pipeline on the same adapter
+    /// after the device was lost or the GPU watchdog fired.
+    ///
+    /// Every guest resource is lost. PVGPU_STATUS_DEVICE_LOST stays set until
+    /// the guest acknowledges with CMD_RESET_DEVICE.
+    fn recover_device(&mut self) -> Result<()> {
+        warn!("Recovering D3D11 device, all guest resources are lost");
+        let completed = match self.shared_memory {
+            Some(ref shmem) => {
+                shmem
+                    .control_region()
+                    .set_status_flag(PVGPU_STATUS_RECOVERY);
+                shmem.control_region().host_fence_completed()
+            }
+            None => 0,
+        };
+
+        // The presentation pipeline shares the renderer's device
+        self.presentation = None;
+        self.command_processor = None;
+        let result = self.init_renderer();
+
+        if let Some(processor) = self.command_processor.as_mut() {
+            // Keep fence numbering monotonic for the guest
+            processor.restart_fence(completed);
+        }
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .clear_status_flag(PVGPU_STATUS_RECOVERY);
+        }
+        match result {
+            Ok(()) => info!("D3D11 device recovered"),
+            Err(ref e) => error!("D3D11 device recovery failed: {}", e),
+        }
+        result
+    }
+
+    /// Hand the presentation backbuffer to the renderer under the reserved
+    /// resource ID and publish that ID to the guest.
+    fn attach_host_backbuffer(&mut self) -> Result<()> {
+        let texture = match self.presentation.as_ref() {
+            Some(presentation) => presentation.host_backbuffer()?,
+            None => None,
+        };
+
+        match (texture, self.command_processor.as_mut()) {
+            (Some(texture), Some(processor)) => {
+                processor
+                    .renderer_mut()
+                    .set_host_backbuffer(Some(texture), self.config.srgb_backbuffer)?;
+            }
+            _ => return Ok(()),
+        }
+
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .set_host_backbuffer_id(PVGPU_HOST_BACKBUFFER_ID);
+        }
+
+        info!(
+            "Host backbuffer exposed as resource {}",
+            PVGPU_HOST_BACKBUFFER_ID
+        );
+        Ok(())
+    }
+
+    /// Copy the frame about to be presented for the guest to read back as
+    /// PVGPU_PREVIOUS_FRAME_ID, publishing that ID after the first copy.
+    fn capture_previous_frame(&mut self, backbuffer_id: u32) {
+        let Some(processor) = self.command_processor.as_mut() else {
+            return;
+        };
+        let renderer = processor.renderer_mut();
+        let backbuffer_id = if renderer.has_host_backbuffer() && backbuffer_id == 0 {
+            PVGPU_HOST_BACKBUFFER_ID
+        } else {
+            backbuffer_id
+        };
+
+        if let Err(e) = renderer.capture_previous_frame(backbuffer_id) {
+            warn!("Failed to copy frame {} for readback: {}", backbuffer_id, e);
+            return;
+        }
+        if let Some(ref shmem) = self.shared_memory {
+            let control = shmem.control_region();
+            if control.previous_frame_id() != PVGPU_PREVIOUS_FRAME_ID {
+                control.set_previous_frame_id(PVGPU_PREVIOUS_FRAME_ID);
+                info!(
+                    "Previous frame exposed as resource {}",
+                    PVGPU_PREVIOUS_FRAME_ID
+                );
+            }
+        }
+    }
+
+    /// Release the renderer's references to the host backbuffer so the
+    /// swapchain can be resized.
+    fn detach_host_backbuffer(&mut self) -> Result<()> {
+        if let Some(processor) = self.command_processor.as_mut() {
+            if processor.renderer().has_host_backbuffer() {
+                processor.renderer_mut().set_host_backbuffer(None, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a display mode change as one transition.
+    ///
+    /// PVGPU_STATUS_RESIZING stays set from before the swapchain is touched
+    /// until the control region describes the new mode, so the guest never
+    /// observes a half-applied configuration.
+    fn reconfigure_display(&mut self, mode: DisplayConfig) {
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .set_status_flag(PVGPU_STATUS_RESIZING);
+        }
+
+        // The swapchain cannot resize while the renderer holds its buffer
+        if let Err(e) = self.detach_host_backbuffer() {
+            warn!("Failed to release host backbuffer: {}", e);
+        }
+
+        if let Some(presentation) = self.presentation.as_mut() {
+            if let Err(e) = presentation.apply_display_config(mode) {
+                error!("Display reconfiguration failed: {}", e);
+                if let Some(ref shmem) = self.shared_memory {
+                    shmem.control_region().set_error(
+                        PVGPU_ERROR_INTERNAL,
+                        (mode.width & 0xFFFF) | ((mode.height & 0xFFFF) << 16),
+                    );
+                }
+            } else {
+                info!("Display reconfigured to {}x{}", mode.width, mode.height);
+            }
+        }
+
+        if let Err(e) = self.attach_host_backbuffer() {
+            error!("Failed to reattach host backbuffer: {}", e);
+        }
+
+        self.publish_display_config();
+
+        if let Some(ref shmem) = self.shared_memory {
+            shmem
+                .control_region()
+                .clear_status_flag(PVGPU_STATUS_RESIZING);
+        }
+    }
+
+    /// Follow a resize of the host window and tell the guest, so it can
+    /// render at the new size
+    fn handle_window_resize(&mut self) {
+        let Some((width, height)) = self
+            .presentation
+            .as_mut()
+            .and_then(|p| p.handle_window_resize())
+        else {
+            return;
+        };
+        info!("Window resized to {}x{}", width, height);
+        self.reconfigure_display(DisplayConfig::size(width, height));
+        if let Some(ref shmem) = self.shared_memory {
+            shmem.control_region().notify_host_resize();
+        }
+    }
+
+    /// Write the presentation's current mode to the control region
+    fn publish_display_config(&self) {
+        if let (Some(presentation), Some(shmem)) =
+            (self.presentation.as_ref(), self.shared_memory.as_ref())
+        {
+            let mode = presentation.display_config();
+            shmem.control_region().set_display_config(
+                mode.width,
+                mode.height,
+                mode.refresh_rate,
+                mode.format.0 as u32,
+            );
+        }
+    }
+
+    /// Fill the control region's capability block. Runs before READY is set,
+    /// so the guest sees a complete block once it sees the device ready.
+    fn publish_capabilities(&mut self) {
+        let (Some(processor), Some(presentation), Some(shmem)) = (
+            self.command_processor.as_ref(),
+            self.presentation.as_ref(),
+            self.shared_memory.as_ref(),
+        ) else {
+            return;
+        };
+        let renderer = processor.renderer();
+        let adapter = renderer.adapter_info();
+
+        let mut caps = CapabilityBlock::new();
+        caps.feature_level = renderer.feature_level().0 as u32;
+        caps.max_texture_dimension = PVGPU_MAX_TEXTURE_DIMENSION;
+        caps.max_texture_array_size = PVGPU_MAX_TEXTURE_ARRAY_SIZE;
+        caps.msaa_sample_mask = renderer
+            .msaa_support(DXGI_FORMAT_R8G8B8A8_UNORM)
+            .sample_count_mask;
+        caps.vendor_id = adapter.vendor_id;
+        caps.device_id = adapter.device_id;
+        caps.dedicated_video_memory = adapter.dedicated_video_memory as u64;
+        caps.set_adapter_description(&adapter.description);
+        caps.adapter_index = adapter.index;
+        caps.adapter_luid = adapter.luid;
+
+        let formats: Vec<u32> = DISPLAY_FORMATS
+            .iter()
+            .filter(|format| renderer.supports_display(**format))
+            .map(|format| format.0 as u32)
+            .collect();
+        caps.set_display_formats(&formats);
+
+        if presentation.mode() != PresentationMode::Headless {
+            caps.output_modes |= PVGPU_OUTPUT_WINDOW;
+        }
+        if presentation.shared_handle().is_some() {
+            caps.output_modes |= PVGPU_OUTPUT_SHARED_TEXTURE;
+        }
+        if renderer.has_host_backbuffer() {
+            caps.output_modes |= PVGPU_OUTPUT_HOST_BACKBUFFER;
+        }
+        if self.config.previous_frame {
+            caps.output_modes |= PVGPU_OUTPUT_PREVIOUS_FRAME;
+        }
+        if presentation.tearing_supported() {
+            caps.output_modes |= PVGPU_OUTPUT_TEARING;
+        }
+
+        // SAFETY: only this thread writes the block or borrows it, and the
+        // guest only reads it after READY
+        unsafe { shmem.control_region().set_capabilities(caps) };
+    }
+
+    /// Write the recent-frame ring to the configured crash dump directory
+    fn dump_crash_frames(&self, reason: &str) {
+        if self.config.crash_frame_ring == 0 {
+            return;
+        }
+        if let Some(ref presentation) = self.presentation {
+            let dir = std::path::Path::new(&self.config.crash_dump_dir);
+            info!("Dumping recent frames ({})", reason);
+            if let Err(e) = presentation.dump_recent_frames(dir) {
+                warn!("Failed to dump recent frames: {}", e);
+            }
+        }
+    }
+
+    /// Stop or resume consuming the ring.
+    ///
+    /// Pausing only happens between batches, so no command is half-done and
+    /// resuming continues at the consumer pointer with nothing lost.
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        let pending = match self.shared_memory {
+            Some(ref shmem) => {
+                let control = shmem.control_region();
+                if paused {
+                    control.set_status_flag(PVGPU_STATUS_BACKEND_BUSY);
+                } else {
+                    control.clear_status_flag(PVGPU_STATUS_BACKEND_BUSY);
+                }
+                control.pending_bytes()
+            }
+            None => 0,
+        };
+        if paused {
+            info!("Command processing paused ({} bytes pending)", pending);
+        } else {
+            info!("Command processing resumed ({} bytes pending)", pending);
+        }
+    }
+
+    /// Publish the video memory budget and usage, and raise or clear
+    /// PVGPU_STATUS_MEMORY_PRESSURE
+    fn publish_memory_usage(&self) {
+        let (Some(processor), Some(shmem)) =
+            (self.command_processor.as_ref(), self.shared_memory.as_ref())
+        else {
+            return;
+        };
+        let renderer = processor.renderer();
+        let tracked = renderer.tracked_memory();
+        let (budget, usage) = renderer.query_video_memory().unwrap_or((
+            renderer.adapter_info().dedicated_video_memory as u64,
+            tracked,
+        ));
+
+        let control = shmem.control_region();
+        control.set_memory_usage(budget, usage, tracked);
+        let pressure =
+            memory_pressure(budget, usage.max(tracked), self.config.vram_warning_percent);
+        let flagged = control.get_status() & PVGPU_STATUS_MEMORY_PRESSURE != 0;
+        if pressure && !flagged {
+            warn!(
+                "Video memory pressure: {} MB used of {} MB budget ({} MB tracked)",
+                usage >> 20,
+                budget >> 20,
+                tracked >> 20
+            );
+            control.set_status_flag(PVGPU_STATUS_MEMORY_PRESSURE);
+        } else if !pressure && flagged {
+            info!("Video memory pressure cleared");
+            control.clear_status_flag(PVGPU_STATUS_MEMORY_PRESSURE);
+        }
+    }
+
     /// Main processing loop
     fn run_loop(&mut self) -> Result<()> {
         info!("Entering main processing loop...");
         let mut device_lost_reported = false;
         let mut last_irq_fence: u64 = 0;
+        // Ring offset of the last reported stream corruption, to report it once
+        let mut corrupt_offset: Option<u32> = None;
+        let mut crash_frames_dumped = false;
+        let mut last_map_sweep = Instant::now();
+        let mut last_stats_report = Instant::now();
+        let mut last_memory_report = Instant::now();
+        self.publish_memory_usage();
+
+        let watchdog = match (self.config.gpu_watchdog_ms, self.shared_memory.as_ref()) {
+            (0, _) | (_, None) => None,
+            (ms, Some(shmem)) => Some(Watchdog::start(Duration::from_millis(ms), shmem.clone())?),
+        };
+        let progress = watchdog.as_ref().map(Watchdog::state);
 
         loop {
-            // Check for shutdown
+            // Check for shutdown: the shared flag stops every instance,
+            // the pipe server's event only this one
             if self.shutdown.load(Ordering::Relaxed) {
                 info!("Shutdown requested");
                 break;
             }
+            if self
+                .pipe_server
+                .as_ref()
+                .is_some_and(|server| server.is_shutdown_signaled())
+            {
+                info!("Pipe closed, stopping instance {}", self.instance);
+                break;
+            }
 
             // Check for device lost state periodically (every iteration when idle)
             if let Some(ref processor) = self.command_processor {
@@ -176,14 +747,21 @@ impl BackendService {
                             .set_status_flag(PVGPU_STATUS_DEVICE_LOST);
                         shmem.control_region().set_error(PVGPU_ERROR_DEVICE_LOST, 0);
                     }
-
-                    // Note: Device recovery would require recreating the D3D11 device
-                    // and all resources. For now, we report the error and continue
-                    // processing (commands will fail but the VM won't crash).
-                    // Full recovery would be implemented in a future version.
-                    warn!("Device lost - continuing in degraded mode");
                 }
             }
+            if device_lost_reported && !crash_frames_dumped {
+                // Best effort: readback fails if the presenting device was removed too
+                self.dump_crash_frames("device lost");
+                crash_frames_dumped = true;
+            }
+
+            // The watchdog has already reported the hang to the guest
+            let hung = progress.is_some_and(|p| p.take_tripped());
+            if hung || device_lost_reported {
+                self.recover_device()?;
+                device_lost_reported = false;
+                crash_frames_dumped = false;
+            }
 
             // Process window messages if we have a presentation pipeline
             if let Some(ref mut presentation) = self.presentation {
@@ -191,11 +769,25 @@ impl BackendService {
                     info!("Window closed, shutting down...");
                     break;
                 }
+                if presentation.take_pause_toggle() {
+                    self.set_paused(!self.paused);
+                }
+            }
+            self.handle_window_resize();
+
+            // While paused, leave the ring to the producer; the guest sees
+            // BACKEND_BUSY and throttles once the ring is full
+            if self.paused {
+                std::thread::sleep(PAUSE_POLL_INTERVAL);
+                continue;
             }
 
             // Process pending commands from ring buffer
             let mut processed = 0u64;
-            let mut pending_present: Option<(u32, u32)> = None;
+            let batch_started = Instant::now();
+            let batch_budget = Duration::from_micros(self.config.batch_budget_us);
+            let mut pending_present: Option<(u32, u32, u32)> = None;
+            let mut pending_dirty_rects = None;
 
             // Scope for mutable borrows of processor and shmem
             {
@@ -214,89 +806,336 @@ impl BackendService {
                     None => return Err(anyhow::anyhow!("Pipe server not initialized")),
                 };
 
-                while let Some((data, _pending_count)) = shmem.read_pending_commands() {
+                while let Some((data, pending_count)) = shmem.read_pending_commands() {
                     if data.is_empty() {
                         break;
                     }
 
+                    let ring_size = shmem.control_region().ring_size as u64;
+                    let cmd_size = match command_extent(data.as_slice(), ring_size) {
+                        CommandExtent::Complete(size) => size as u64,
+                        CommandExtent::Incomplete => {
+                            // An unknown type claiming more bytes than are pending
+                            // is a garbage header, not a partial write. Stop
+                            // consuming rather than skip by a bogus size.
+                            if let Some(header) = peek_command_header(data.as_slice()) {
+                                if !is_known_command(header.command_type) {
+                                    report_corrupt_stream(
+                                        shmem,
+                                        processor,
+                                        &header,
+                                        &mut corrupt_offset,
+                                    );
+                                    break;
+                                }
+                            }
+                            // Producer is mid-write at the batch edge; leave the
+                            // consumer pointer alone and wait for the doorbell
+                            trace!("Incomplete command at ring edge, waiting for producer");
+                            break;
+                        }
+                        CommandExtent::Malformed => {
+                            // A size no command can have on an unknown type is
+                            // a garbage header; stop consuming, as above
+                            if let Some(header) = peek_command_header(data.as_slice()) {
+                                if !is_known_command(header.command_type) {
+                                    report_corrupt_stream(
+                                        shmem,
+                                        processor,
+                                        &header,
+                                        &mut corrupt_offset,
+                                    );
+                                    break;
+                                }
+                            }
+                            // No way to find the next command boundary, so drop
+                            // everything pending and let the guest resubmit
+                            error!(
+                                "Malformed command header, discarding {} pending bytes",
+                                pending_count
+                            );
+                            shmem.set_error(PVGPU_ERROR_INVALID_COMMAND, 0);
+                            shmem.advance_consumer(pending_count);
+                            break;
+                        }
+                    };
+
                     // Get the heap for data transfer commands
                     let heap = shmem.resource_heap();
 
-                    match processor.process_command(data.as_slice(), heap) {
+                    if let (Some(progress), Some(header)) =
+                        (progress, peek_command_header(data.as_slice()))
+                    {
+                        progress.begin(header.command_type, header.resource_id);
+                    }
+                    let result = processor.process_command(data.as_slice(), heap);
+                    if let Some(progress) = progress {
+                        progress.end();
+                    }
+
+                    match result {
                         Ok(consumed) => {
                             shmem.advance_consumer(consumed as u64);
+                            corrupt_offset = None;
                             processed += consumed as u64;
 
-                            // Update fence if needed — only send IRQ when a NEW
-                            // fence value is completed (not on every command)
-                            let fence = processor.current_fence();
-                            if fence > last_irq_fence {
-                                shmem.complete_fence(fence);
-                                last_irq_fence = fence;
-                                // Request IRQ to notify guest
+                            // A device reset restarts fence numbering at its ack
+                            // fence, which may be below anything published before
+                            if processor.take_device_reset() {
+                                last_irq_fence = 0;
+                                shmem.complete_fence(0);
+                                // The guest has acknowledged a lost device
+                                shmem
+                                    .control_region()
+                                    .clear_status_flag(PVGPU_STATUS_DEVICE_LOST);
+                            }
+
+                            // NO_FENCE only holds back publishing at this
+                            // command; the batch end publishes it regardless
+                            if !processor.fence_suppressed() {
+                                publish_fence(shmem, server, processor, &mut last_irq_fence);
+                            }
+
+                            // Answer fence queries after the fence above is published
+                            if let Some(offset) = processor.take_pending_fence_query() {
+                                let completed = shmem.control_region().host_fence_completed();
+                                if let Err(e) = shmem.write_heap_u64(offset as usize, completed) {
+                                    warn!("QueryFence: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some(offset) = processor.take_pending_status_query() {
+                                let status = shmem.control_region().device_status();
+                                let written = status.to_u64s().iter().enumerate().try_for_each(
+                                    |(i, &word)| {
+                                        shmem.write_heap_u64(offset as usize + i * 8, word)
+                                    },
+                                );
+                                if let Err(e) = written {
+                                    warn!("QueryDeviceStatus: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some((offset, data)) = processor.take_pending_map_readback() {
+                                if let Err(e) = shmem.write_heap(offset as usize, &data) {
+                                    warn!("MapResource: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some((offset, pitch)) = processor.take_pending_map_pitch() {
                                 if let Err(e) =
-                                    server.send_message(BackendMessage::Irq { vector: 0 })
+                                    shmem.write_heap_u64(offset as usize, pitch.to_u64())
+                                {
+                                    warn!("MapResource: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some((offset, support)) = processor.take_pending_msaa_support() {
+                                let written = support.to_u64s().iter().enumerate().try_for_each(
+                                    |(i, &word)| {
+                                        shmem.write_heap_u64(offset as usize + i * 8, word)
+                                    },
+                                );
+                                if let Err(e) = written {
+                                    warn!("QueryMsaaSupport: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some((format, support)) = processor.take_pending_format_support()
+                            {
+                                shmem.control_region().set_format_support(format, support);
+                            }
+
+                            if let Some((offset, result)) = processor.take_pending_selftest() {
+                                if let Err(e) = shmem.write_heap_u64(offset as usize, result as u64)
                                 {
-                                    warn!("Failed to send IRQ: {}", e);
+                                    warn!("Selftest: {}", e);
+                                    shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, offset);
+                                }
+                            }
+
+                            if let Some((backbuffer, subresource)) =
+                                processor.take_pending_torn_present()
+                            {
+                                shmem.set_diagnostic(
+                                    backbuffer,
+                                    &format!(
+                                        "Present skipped: subresource {} is still mapped",
+                                        subresource
+                                    ),
+                                );
+                                shmem.set_error(PVGPU_ERROR_INVALID_PARAMETER, backbuffer);
+                            }
+
+                            if let Some(level) = processor.take_pending_log_level() {
+                                match logging::set_level(level) {
+                                    Ok(()) => info!("Log level set to {} by guest", level),
+                                    Err(e) => warn!("SetLogLevel: {}", e),
                                 }
                             }
 
                             // Check for pending present
                             if let Some(present_info) = processor.take_pending_present() {
+                                // Only the last present of a batch reaches the
+                                // screen, so it must cover what the skipped
+                                // frames changed too
+                                let rects = processor.take_pending_dirty_rects();
+                                pending_dirty_rects =
+                                    match (pending_present, pending_dirty_rects.take(), rects) {
+                                        (None, _, rects) => rects,
+                                        (Some(_), Some(mut merged), Some(rects)) => {
+                                            merged.extend(rects);
+                                            Some(merged)
+                                        }
+                                        _ => None,
+                                    };
+                                // A guest presenting faster than the host can
+                                // display would otherwise build up latency
+                                if let Some((dropped, _, _)) = pending_present {
+                                    trace!("Present of backbuffer {} superseded", dropped);
+                                    processor.record_dropped_present();
+                                }
                                 pending_present = Some(present_info);
                             }
+
+                            // Present a completed frame before any of the next
+                            // frame's commands can touch its backbuffer
+                            if processor.take_frame_boundary() {
+                                break;
+                            }
+
+                            // Later commands belong to the new adapter's device
+                            if processor.adapter_switch_pending() {
+                                break;
+                            }
+
+                            // Nothing after SHUTDOWN is consumed
+                            if processor.shutdown_pending() {
+                                break;
+                            }
+
+                            // Later commands target the resized buffers
+                            if processor.has_pending_resize() {
+                                break;
+                            }
+
+                            // The device is recreated before anything else runs
+                            if progress.is_some_and(|p| p.tripped()) {
+                                break;
+                            }
                         }
                         Err(e) => {
-                            let err_str = e.to_string();
-                            error!("Error processing command: {}", err_str);
-
-                            // Parse error type and report via control region
-                            if err_str.starts_with("SHADER_COMPILE:") {
-                                // Shader compilation error - extract resource ID
-                                let resource_id: u32 = err_str
-                                    .strip_prefix("SHADER_COMPILE:")
-                                    .and_then(|s| s.parse().ok())
-                                    .unwrap_or(0);
-                                shmem
-                                    .control_region()
-                                    .set_error(PVGPU_ERROR_SHADER_COMPILE, resource_id);
-                                // Shader errors are non-fatal - continue processing
-                                // The guest should handle the missing shader gracefully
-                                warn!(
-                                    "Shader compilation failed for resource {}, continuing...",
-                                    resource_id
-                                );
-                            } else if err_str.contains("out of memory")
-                                || err_str.contains("OutOfMemory")
-                            {
-                                shmem
-                                    .control_region()
-                                    .set_error(PVGPU_ERROR_OUT_OF_MEMORY, 0);
-                                // OOM is potentially fatal - break the inner loop
-                                break;
-                            } else {
-                                // Generic internal error
-                                shmem.control_region().set_error(PVGPU_ERROR_INTERNAL, 0);
+                            error!("Error processing command: {}", e);
+
+                            // Publish the driver's reason before the error
+                            // the guest polls for
+                            if let ProcessError::ShaderCompile(id) = &e {
+                                let message = processor
+                                    .renderer_mut()
+                                    .take_shader_error()
+                                    .unwrap_or_else(|| e.to_string());
+                                shmem.set_diagnostic(*id, &message);
+                            }
+
+                            let (code, data) = e.error_code();
+                            shmem.set_error(code, data);
+                            if !e.is_recoverable() {
+                                // Potentially fatal - break the inner loop
                                 break;
                             }
+
+                            // Size was sane, so skipping the command keeps the
+                            // stream in sync; the guest handles the failure
+                            match e {
+                                ProcessError::ShaderCompile(id) => warn!(
+                                    "Shader compilation failed for resource {}, continuing...",
+                                    id
+                                ),
+                                ProcessError::UnsupportedFormat(id) => {
+                                    warn!("Unsupported format for resource {}, continuing...", id)
+                                }
+                                _ => {}
+                            }
+                            shmem.advance_consumer(cmd_size);
                         }
                     }
 
-                    // Don't process too many commands in one batch
-                    if processed > 1024 * 1024 {
+                    // Hand back to window messages and presents once the
+                    // batch has used its time, however cheap its commands
+                    if !batch_budget.is_zero() && batch_started.elapsed() >= batch_budget {
                         break;
                     }
                 }
+
+                // The guest only needs periodic progress; publish it once
+                // per batch rather than after every command
+                shmem.commit_consumer();
+
+                // With a GPU fence, fences complete once the GPU passes the
+                // batch's signal, possibly on a later idle iteration
+                processor.signal_fence();
+                publish_fence(shmem, server, processor, &mut last_irq_fence);
             }
 
             // Handle presentation outside the borrow scope
-            if let Some((backbuffer_id, _sync_interval)) = pending_present {
+            if let Some((backbuffer_id, _sync_interval, flags)) = pending_present {
+                // Before Present, which discards FLIP_DISCARD buffer contents
+                if self.config.previous_frame {
+                    self.capture_previous_frame(backbuffer_id);
+                }
+                if let Some(progress) = progress {
+                    progress.begin(PVGPU_CMD_PRESENT, backbuffer_id);
+                }
                 if let (Some(presentation), Some(processor)) =
                     (self.presentation.as_mut(), self.command_processor.as_ref())
                 {
-                    // Get the texture from the renderer
-                    if let Some(texture) = processor.renderer().get_texture(backbuffer_id) {
-                        if let Err(e) = presentation.present(texture) {
+                    presentation.set_dirty_rects(pending_dirty_rects);
+                    presentation.set_present_flags(flags);
+
+                    // With a host-owned backbuffer the guest may present id 0
+                    let host_owned = processor.renderer().has_host_backbuffer()
+                        && (backbuffer_id == 0 || backbuffer_id == PVGPU_HOST_BACKBUFFER_ID);
+
+                    if host_owned {
+                        if let Err(e) = presentation.present_host_backbuffer() {
+                            error!("Presentation failed: {}", e);
+                            if let Some(ref shmem) = self.shared_memory {
+                                shmem
+                                    .control_region()
+                                    .set_error(PVGPU_ERROR_DEVICE_LOST, backbuffer_id);
+                            }
+                        }
+                    } else if processor
+                        .renderer()
+                        .get_texture(backbuffer_id)
+                        .is_some_and(|texture| presentation.is_stale_frame(texture))
+                    {
+                        // Rendered before the guest's last resize; copying it
+                        // would show a frame of the old size
+                        warn!("Present: backbuffer {} predates the resize", backbuffer_id);
+                        if let Some(ref shmem) = self.shared_memory {
+                            shmem
+                                .control_region()
+                                .set_error(PVGPU_ERROR_INVALID_PARAMETER, backbuffer_id);
+                        }
+                    } else if let Some(texture) = processor.renderer().get_texture(backbuffer_id) {
+                        // Decoded video frames need YUV -> RGB conversion first
+                        let is_yuv = processor
+                            .renderer()
+                            .get_texture_format(backbuffer_id)
+                            .map(is_planar_yuv)
+                            .unwrap_or(false);
+                        let result = if is_yuv {
+                            presentation.present_yuv(texture)
+                        } else {
+                            presentation.present(texture)
+                        };
+                        if let Err(e) = result {
                             error!("Presentation failed: {}", e);
                             // Report presentation error via control region
                             if let Some(ref shmem) = self.shared_memory {
@@ -315,42 +1154,91 @@ impl BackendService {
                         }
                     }
                 }
+                if let Some(progress) = progress {
+                    progress.end();
+                }
             }
 
-            // Handle pending resize outside the borrow scope
-            if let Some(processor) = self.command_processor.as_mut() {
-                if let Some((width, height)) = processor.take_pending_resize() {
-                    // Set resizing status
-                    if let Some(ref shmem) = self.shared_memory {
-                        shmem
-                            .control_region()
-                            .set_status_flag(PVGPU_STATUS_RESIZING);
-                    }
+            // The final frame has been presented above
+            if self
+                .command_processor
+                .as_ref()
+                .is_some_and(|p| p.shutdown_pending())
+            {
+                self.finish_guest_shutdown(&mut last_irq_fence);
+                break;
+            }
 
-                    if let Some(presentation) = self.presentation.as_mut() {
-                        if let Err(e) = presentation.resize(width, height) {
-                            error!("Resize failed: {}", e);
-                            // Report resize error
-                            if let Some(ref shmem) = self.shared_memory {
-                                shmem.control_region().set_error(
-                                    PVGPU_ERROR_INTERNAL,
-                                    (width & 0xFFFF) | ((height & 0xFFFF) << 16),
-                                );
-                            }
-                        } else {
-                            info!("Resized presentation to {}x{}", width, height);
-                        }
-                    }
+            let adapter_switch = self
+                .command_processor
+                .as_mut()
+                .and_then(|p| p.take_pending_adapter_switch());
+            if let Some((index, fence)) = adapter_switch {
+                self.switch_adapter(index)?;
+                if let (Some(processor), Some(shmem)) =
+                    (self.command_processor.as_mut(), self.shared_memory.as_ref())
+                {
+                    // Fence numbering restarts at the ack fence, as after a reset
+                    processor.restart_fence(fence);
+                    last_irq_fence = 0;
+                    shmem.complete_fence(0);
+                }
+            }
 
-                    // Clear resizing status
-                    if let Some(ref shmem) = self.shared_memory {
-                        shmem
-                            .control_region()
-                            .clear_status_flag(PVGPU_STATUS_RESIZING);
-                    }
+            // Dump the crash frame ring if the guest asked for it
+            let dump_requested = self
+                .command_processor
+                .as_mut()
+                .map(|p| p.take_pending_frame_dump())
+                .unwrap_or(false);
+            if dump_requested {
+                self.dump_crash_frames("guest request");
+            }
+
+            // Handle pending display changes outside the borrow scope
+            let (display_config, resize) = match self.command_processor.as_mut() {
+                Some(processor) => (
+                    processor.take_pending_display_config(),
+                    processor.take_pending_resize(),
+                ),
+                None => (None, None),
+            };
+            if let Some(mode) = display_config {
+                self.reconfigure_display(mode);
+            }
+            if let Some((width, height)) = resize {
+                // Every fence before the resize completes against the old
+                // buffers, before any command after it runs
+                self.drain_fences(&mut last_irq_fence);
+                let old_size = self.presentation.as_ref().map(|p| p.dimensions());
+                self.reconfigure_display(DisplayConfig::size(width, height));
+                if let (Some(presentation), Some(old_size)) = (self.presentation.as_mut(), old_size)
+                {
+                    presentation.set_stale_size(old_size);
                 }
             }
 
+            // Reclaim staging memory pinned by maps the guest never unmapped
+            if self.config.map_timeout_secs > 0 && last_map_sweep.elapsed() >= MAP_SWEEP_INTERVAL {
+                last_map_sweep = Instant::now();
+                if let Some(processor) = self.command_processor.as_mut() {
+                    processor.sweep_stale_maps(Duration::from_secs(self.config.map_timeout_secs));
+                }
+            }
+
+            if last_memory_report.elapsed() >= MEMORY_REPORT_INTERVAL {
+                last_memory_report = Instant::now();
+                self.publish_memory_usage();
+            }
+
+            if self.config.stats_interval_secs > 0
+                && last_stats_report.elapsed()
+                    >= Duration::from_secs(self.config.stats_interval_secs)
+            {
+                last_stats_report = Instant::now();
+                self.report_stats();
+            }
+
             // If we processed commands, continue immediately
             if processed > 0 {
                 continue;
@@ -370,6 +1258,52 @@ impl BackendService {
         Ok(())
     }
 
+    /// Log command and frame-time statistics, then start a new interval
+    fn report_stats(&mut self) {
+        if let Some(processor) = self.command_processor.as_mut() {
+            processor.log_and_reset_stats();
+        }
+        if let Some(presentation) = self.presentation.as_mut() {
+            let stats = presentation.frame_stats();
+            info!(
+                "Frame stats: frames={}, fps={:.1}, avg={:.2}ms, min={:.2}ms, max={:.2}ms, p50={:.2}ms, p95={:.2}ms, p99={:.2}ms",
+                stats.frame_count,
+                stats.fps,
+                stats.avg_frame_time_ms,
+                stats.min_frame_time_ms,
+                stats.max_frame_time_ms,
+                stats.p50_frame_time_ms,
+                stats.p95_frame_time_ms,
+                stats.p99_frame_time_ms
+            );
+            presentation.reset_frame_histogram();
+        }
+    }
+
+    /// Complete a SHUTDOWN from the guest: wait for the GPU, publish the
+    /// last fence and stop the pipe reader. The shared shutdown flag is left
+    /// alone so other instances keep running.
+    fn finish_guest_shutdown(&mut self, last_irq_fence: &mut u64) {
+        self.drain_fences(last_irq_fence);
+        if let Some(server) = self.pipe_server.as_ref() {
+            server.signal_shutdown();
+        }
+        info!("Guest shutdown complete");
+    }
+
+    /// Wait for the GPU to finish all submitted work and publish the
+    /// current fence as completed
+    fn drain_fences(&mut self, last_irq_fence: &mut u64) {
+        if let (Some(processor), Some(shmem), Some(server)) = (
+            self.command_processor.as_mut(),
+            self.shared_memory.as_ref(),
+            self.pipe_server.as_ref(),
+        ) {
+            processor.wait_idle();
+            publish_fence(shmem, server, processor, last_irq_fence);
+        }
+    }
+
     /// Request shutdown
     fn request_shutdown(&self) {
         self.shutdown.store(true, Ordering::Relaxed);
@@ -382,7 +1316,9 @@ impl BackendService {
     ///
     /// Reads messages from the QEMU pipe in a loop. Doorbell messages
     /// are automatically handled by PipeServer::read_message() which
-    /// signals the doorbell event. Other messages are logged.
+    /// signals the doorbell event. Other messages are logged. A shutdown
+    /// message or read error stops only this instance, through its pipe
+    /// server's shutdown event.
     fn start_pipe_reader(&mut self) {
         let server = self
             .pipe_server
@@ -390,11 +1326,14 @@ impl BackendService {
             .expect("Pipe server not initialized")
             .clone();
         let shutdown = self.shutdown.clone();
+        let priority = self.config.pipe_thread_priority.clone();
+        let affinity = self.config.pipe_thread_affinity;
 
         let handle = thread::Builder::new()
             .name("pvgpu-pipe-reader".to_string())
             .spawn(move || {
                 info!("Pipe reader thread started");
+                scheduling::apply_to_current_thread("Pipe reader", priority.as_deref(), affinity);
                 loop {
                     if shutdown.load(Ordering::Relaxed) {
                         break;
@@ -410,7 +1349,6 @@ impl BackendService {
                             }
                             QemuMessage::Shutdown => {
                                 info!("Shutdown message received from QEMU");
-                                shutdown.store(true, Ordering::Relaxed);
                                 server.signal_shutdown();
                                 break;
                             }
@@ -421,8 +1359,7 @@ impl BackendService {
                         Err(e) => {
                             if !shutdown.load(Ordering::Relaxed) {
                                 error!("Pipe read error: {}", e);
-                                // On read error, signal shutdown
-                                shutdown.store(true, Ordering::Relaxed);
+                                // On read error, stop this instance
                                 server.signal_shutdown();
                             }
                             break;
@@ -437,34 +1374,133 @@ impl BackendService {
     }
 }
 
+/// Report a garbage command header at the consumer pointer, once per ring
+/// offset, without consuming it: the ring offset goes to `error_data` and
+/// the command is counted as unknown
+fn report_corrupt_stream(
+    shmem: &SharedMemory,
+    processor: &mut CommandProcessor,
+    header: &CommandHeader,
+    corrupt_offset: &mut Option<u32>,
+) {
+    let ring_size = shmem.control_region().ring_size as u64;
+    let offset = (shmem.consumer_ptr() % ring_size) as u32;
+    if *corrupt_offset != Some(offset) {
+        error!(
+            "Command stream corrupt at ring offset {}: type=0x{:04X}, size={}",
+            offset, header.command_type, header.command_size
+        );
+        processor.record_unknown_command();
+        shmem.set_error(PVGPU_ERROR_INVALID_COMMAND, offset);
+        *corrupt_offset = Some(offset);
+    }
+}
+
+/// Publish the completed fence and notify the guest, but only when a NEW
+/// fence value has completed (not on every command)
+fn publish_fence(
+    shmem: &SharedMemory,
+    server: &PipeServer,
+    processor: &mut CommandProcessor,
+    last_irq_fence: &mut u64,
+) {
+    let fence = processor.completed_fence();
+    if fence > *last_irq_fence {
+        shmem.complete_fence(fence);
+        *last_irq_fence = fence;
+        processor.renderer_mut().release_destroyed(fence);
+        // Request IRQ to notify guest
+        if let Err(e) = server.send_message(BackendMessage::Irq { vector: 0 }) {
+            warn!("Failed to send IRQ: {}", e);
+        }
+    }
+}
+
 fn main() -> Result<()> {
+    // Load or create default config; it configures logging, so it comes first
+    let config = match std::env::var("PVGPU_CONFIG") {
+        Ok(path) => Config::load(path)?,
+        Err(_) => Config::default(),
+    };
+
     // Initialize logging
-    FmtSubscriber::builder()
-        .with_max_level(Level::DEBUG)
-        .with_target(true)
-        .init();
+    let level = logging::level_from_name(&config.log_level);
+    let _log_guard = logging::init(
+        level.unwrap_or(Level::INFO),
+        config.log_file.as_deref().map(Path::new),
+    )?;
+    if level.is_none() {
+        warn!("Unknown log level '{}', using info", config.log_level);
+    }
 
     info!("PVGPU Backend Service starting...");
     info!(
         "Protocol version: {}.{}",
         PVGPU_VERSION_MAJOR, PVGPU_VERSION_MINOR
     );
-
-    // Load or create default config
-    let config = Config::default();
     info!("Configuration loaded: {:?}", config);
 
-    // Create service
-    let mut service = BackendService::new(config);
+    // Under the Service Control Manager, STOP/SHUTDOWN replace Ctrl+C
+    if std::env::args().skip(1).any(|arg| arg == "--service") {
+        return winservice::run_dispatcher(config, run_instances);
+    }
 
     // Setup Ctrl+C handler
-    let shutdown = service.shutdown.clone();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let ctrlc_shutdown = shutdown.clone();
     ctrlc::set_handler(move || {
         info!("Ctrl+C received, shutting down...");
-        shutdown.store(true, Ordering::Relaxed);
+        ctrlc_shutdown.store(true, Ordering::Relaxed);
     })
     .expect("Error setting Ctrl+C handler");
 
+    run_instances(config, shutdown)
+}
+
+/// Serve `config.instances` guests, each on its own thread with its own pipe,
+/// D3D11 device and presentation pipeline. Returns once every instance has
+/// stopped, with the first error any of them hit.
+fn run_instances(config: Config, shutdown: Arc<AtomicBool>) -> Result<()> {
+    if config.instances <= 1 {
+        return run_backend(config, 0, shutdown);
+    }
+
+    info!("Starting {} backend instances", config.instances);
+    let handles: Vec<_> = (0..config.instances)
+        .map(|instance| {
+            let config = config.for_instance(instance);
+            let shutdown = shutdown.clone();
+            thread::Builder::new()
+                .name(format!("pvgpu-instance-{}", instance))
+                .spawn(move || {
+                    let _span = tracing::info_span!("instance", id = instance).entered();
+                    let result = run_backend(config, instance, shutdown);
+                    if let Err(ref e) = result {
+                        error!("Instance {} stopped: {}", instance, e);
+                    }
+                    result
+                })
+        })
+        .collect::<std::io::Result<_>>()?;
+
+    let mut first_error = None;
+    for handle in handles {
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Instance thread panicked")));
+        if let Err(e) = result {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Connect to QEMU, run the command loop until `shutdown` is set or the
+/// guest disconnects, then clean up
+fn run_backend(config: Config, instance: u32, shutdown: Arc<AtomicBool>) -> Result<()> {
+    // Create service
+    let mut service = BackendService::new(config, instance, shutdown);
+
     // Initialize pipe server and wait for connection
     service.init_pipe_server()?;
 
@@ -483,6 +1519,14 @@ fn main() -> Result<()> {
         info!("Device status set to READY");
     }
 
+    // The main loop runs on this thread
+    scheduling::apply_process_priority(service.config.process_priority.as_deref());
+    scheduling::apply_to_current_thread(
+        "Command processing",
+        service.config.main_thread_priority.as_deref(),
+        service.config.main_thread_affinity,
+    );
+
     // Run main loop
     info!("Backend service ready. Processing commands...");
     let result = service.run_loop();
@@ -493,6 +1537,16 @@ fn main() -> Result<()> {
         info!("Device status set to SHUTDOWN");
     }
 
+    // Scrub GPU resources and the heap before the next guest can connect
+    if service.config.scrub_on_destroy {
+        if let Some(processor) = service.command_processor.as_mut() {
+            processor.renderer_mut().scrub_all_resources();
+        }
+        if let Some(ref shmem) = service.shared_memory {
+            shmem.scrub_resource_heap();
+        }
+    }
+
     info!("Backend service shutting down");
 
     // Wait for pipe reader thread to finish
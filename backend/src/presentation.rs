@@ -5,34 +5,100 @@
 //! - Windowed mode: Creates a Win32 window with DXGI swapchain
 //! - Headless mode: Shared texture only (for streaming tools like Parsec/Moonlight)
 //! - Dual mode: Both window and shared texture
+//!
+//! The window's swapchain is either bound to the window directly or, with
+//! the DirectComposition backend, shown through a composition visual.
+//!
+//! The pipeline is deliberately not `Send`: the window belongs to the thread
+//! that created it, which is the only one that receives its messages. A
+//! present thread must create its pipeline itself rather than take over one.
 
 use anyhow::{anyhow, Result};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tracing::{debug, info};
-use windows::core::{w, Interface, PCWSTR};
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+use tracing::{debug, info, warn};
+use windows::core::{w, Interface, HRESULT, PCWSTR};
+use windows::Win32::Foundation::{
+    GetLastError, BOOL, DXGI_STATUS_OCCLUDED, ERROR_CLASS_ALREADY_EXISTS, HWND, LPARAM, LRESULT,
+    RECT, WAIT_ABANDONED, WAIT_TIMEOUT, WPARAM,
+};
 use windows::Win32::Graphics::Direct3D11::{
     ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D,
     D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_BOX, D3D11_RESOURCE_MISC_SHARED,
-    D3D11_RESOURCE_MISC_SHARED_NTHANDLE, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+    D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX, D3D11_RESOURCE_MISC_SHARED_NTHANDLE,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+};
+use windows::Win32::Graphics::DirectComposition::{
+    DCompositionCreateDevice, IDCompositionDevice, IDCompositionTarget, IDCompositionVisual,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
-    DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC,
+    DXGI_ALPHA_MODE_IGNORE, DXGI_ALPHA_MODE_PREMULTIPLIED, DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM,
+    DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM,
+    DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    IDXGIFactory2, IDXGIFactory5, IDXGISwapChain1, DXGI_FEATURE_PRESENT_ALLOW_TEARING,
-    DXGI_PRESENT, DXGI_PRESENT_ALLOW_TEARING, DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_CHAIN_FLAG,
-    DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING, DXGI_SWAP_EFFECT_FLIP_DISCARD,
+    IDXGIDevice, IDXGIFactory2, IDXGIFactory5, IDXGIKeyedMutex, IDXGIOutput, IDXGISwapChain1,
+    DXGI_ERROR_WAS_STILL_DRAWING, DXGI_FEATURE_PRESENT_ALLOW_TEARING, DXGI_PRESENT,
+    DXGI_PRESENT_ALLOW_TEARING, DXGI_PRESENT_PARAMETERS, DXGI_PRESENT_TEST, DXGI_SWAP_CHAIN_DESC1,
+    DXGI_SWAP_CHAIN_FLAG, DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING, DXGI_SWAP_EFFECT_FLIP_DISCARD,
     DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
+use windows::Win32::Graphics::Gdi::{
+    GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONULL,
+    MONITOR_DEFAULTTOPRIMARY,
+};
 use windows::Win32::System::Threading::{CreateEventW, SetEvent};
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_PAUSE, VK_SCROLL};
 use windows::Win32::UI::WindowsAndMessaging::{
     AdjustWindowRect, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
-    PeekMessageW, PostQuitMessage, RegisterClassExW, ShowWindow, TranslateMessage, CS_HREDRAW,
-    CS_VREDRAW, CW_USEDEFAULT, MSG, PM_REMOVE, SW_SHOW, WM_CLOSE, WM_DESTROY, WM_ERASEBKGND,
-    WM_PAINT, WM_SIZE, WNDCLASSEXW, WS_EX_APPWINDOW, WS_OVERLAPPEDWINDOW,
+    GetWindowLongPtrW, PeekMessageW, PostQuitMessage, RegisterClassExW, SetWindowLongPtrW,
+    SetWindowPos, ShowWindow, TranslateMessage, CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW,
+    CW_USEDEFAULT, GWLP_USERDATA, MSG, PM_REMOVE, SIZE_MINIMIZED, SWP_NOACTIVATE, SWP_NOSIZE,
+    SWP_NOZORDER, SW_SHOW, WA_INACTIVE, WM_ACTIVATE, WM_CLOSE, WM_DESTROY, WM_DISPLAYCHANGE,
+    WM_ERASEBKGND, WM_KEYDOWN, WM_NCCREATE, WM_PAINT, WM_SIZE, WNDCLASSEXW, WS_EX_APPWINDOW,
+    WS_EX_NOREDIRECTIONBITMAP, WS_OVERLAPPEDWINDOW,
+};
+
+use crate::cross_adapter::{adapter_luid, CrossAdapterBridge};
+use crate::frame_ring::FrameRing;
+use crate::protocol::{
+    PVGPU_MAX_DIRTY_RECTS, PVGPU_PRESENT_DO_NOT_SEQUENCE, PVGPU_PRESENT_FLAGS_MASK,
 };
+use crate::srgb;
+use crate::video::YuvConverter;
+
+/// Frame times kept for statistics and adaptive buffering
+const FRAME_TIME_SAMPLES: usize = 120;
+
+/// Width of one frame-time histogram bucket, in microseconds
+const HISTOGRAM_BUCKET_US: u64 = 100;
+
+/// Frame-time histogram buckets; covers 0-250ms, longer frames share the last
+const HISTOGRAM_BUCKETS: usize = 2500;
+
+/// Frame-time jitter (standard deviation / mean) above which adaptive
+/// buffering moves to triple buffering
+const JITTERY_FRAME_TIMES: f64 = 0.25;
+
+/// Jitter below which adaptive buffering returns to double buffering
+const STEADY_FRAME_TIMES: f64 = 0.10;
+
+/// Minimum time between adaptive buffer count switches
+const BUFFER_SWITCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Messages the window procedure leaves for its pipeline to act on. Each
+/// window points at its own through GWLP_USERDATA; the box keeps that
+/// address stable while the pipeline moves.
+#[derive(Default)]
+struct WindowEvents {
+    /// WM_SIZE, except when minimized
+    resized: AtomicBool,
+    /// WM_DISPLAYCHANGE
+    display_changed: AtomicBool,
+    /// WM_ACTIVATE, with the new state in `active`
+    activation_changed: AtomicBool,
+    active: AtomicBool,
+}
 
 /// Presentation mode
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,37 +111,226 @@ pub enum PresentationMode {
     Dual,
 }
 
+/// Outputs a Dual-mode present writes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentTargets {
+    /// Window swapchain only
+    Window,
+    /// Shared texture only
+    Shared,
+    /// Window swapchain and shared texture
+    Both,
+}
+
+impl PresentTargets {
+    /// Whether presents reach the window
+    pub fn window(self) -> bool {
+        self != PresentTargets::Shared
+    }
+
+    /// Whether presents reach the shared texture
+    pub fn shared(self) -> bool {
+        self != PresentTargets::Window
+    }
+
+    /// Next targets in the hotkey cycle: both, window, shared
+    pub fn next(self) -> Self {
+        match self {
+            PresentTargets::Both => PresentTargets::Window,
+            PresentTargets::Window => PresentTargets::Shared,
+            PresentTargets::Shared => PresentTargets::Both,
+        }
+    }
+}
+
+/// How the window's swapchain reaches the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentBackend {
+    /// Swapchain bound to the window
+    Hwnd,
+    /// Swapchain shown through a DirectComposition visual, composited with
+    /// the desktop
+    DirectComposition,
+}
+
 /// Configuration for presentation pipeline
 #[derive(Debug, Clone)]
 pub struct PresentationConfig {
     pub mode: PresentationMode,
+    /// Outputs presents write to; only Dual mode has more than one
+    pub targets: PresentTargets,
+    /// Window swapchain backend (ignored in headless mode)
+    pub backend: PresentBackend,
+    /// Let frame alpha show the desktop through the window (DirectComposition only)
+    pub transparent: bool,
     pub width: u32,
     pub height: u32,
+    /// Refresh rate reported to the guest, in Hz
+    pub refresh_rate: u32,
+    /// Swapchain and shared texture format
+    pub format: DXGI_FORMAT,
     pub vsync: bool,
     pub window_title: String,
     /// Name for the shared texture event (e.g., "Global\\PVGPU_FrameEvent")
     pub frame_event_name: Option<String>,
     /// Number of buffers for swapchain (2 = double buffer, 3 = triple buffer)
     pub buffer_count: u32,
+    /// Switch between 2 and 3 buffers based on frame-time jitter
+    pub adaptive_buffering: bool,
+    /// Time between visibility checks while the window is occluded
+    pub occlusion_poll_interval: std::time::Duration,
+    /// Recreate the swapchain when the display topology changes
+    pub display_change_recovery: bool,
     /// Allow tearing (for variable refresh rate displays)
     pub allow_tearing: bool,
+    /// Let the guest render straight into the host's backbuffer
+    pub host_backbuffer: bool,
+    /// Sample count of the host backbuffer; above 1 the guest renders into
+    /// an MSAA texture resolved into the swapchain at present
+    pub sample_count: u32,
+    /// View the (UNORM) backbuffer through an `_SRGB` render target view
+    pub srgb_backbuffer: bool,
+    /// Number of recent frames kept for crash dumps (0 = disabled)
+    pub crash_frame_ring: u32,
+    /// RGBA color around frames smaller than the output
+    pub letterbox_color: [f32; 4],
 }
 
 impl Default for PresentationConfig {
     fn default() -> Self {
         Self {
             mode: PresentationMode::Windowed,
+            targets: PresentTargets::Both,
+            backend: PresentBackend::Hwnd,
+            transparent: false,
             width: 1920,
             height: 1080,
+            refresh_rate: 60,
+            format: DXGI_FORMAT_R8G8B8A8_UNORM,
             vsync: true,
             window_title: "PVGPU Output".to_string(),
             frame_event_name: Some("Global\\PVGPU_FrameEvent".to_string()),
             buffer_count: 2, // Double buffering by default
+            adaptive_buffering: false,
+            occlusion_poll_interval: std::time::Duration::from_millis(100),
+            display_change_recovery: true,
             allow_tearing: false,
+            host_backbuffer: false,
+            sample_count: 1,
+            srgb_backbuffer: false,
+            crash_frame_ring: 0,
+            letterbox_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Smallest display mode accepted from the guest
+pub const MIN_DISPLAY_WIDTH: u32 = 320;
+pub const MIN_DISPLAY_HEIGHT: u32 = 200;
+/// Largest display mode (D3D11 maximum texture dimension)
+pub const MAX_DISPLAY_DIMENSION: u32 = 16384;
+/// Accepted refresh rate range in Hz
+pub const MIN_REFRESH_RATE: u32 = 24;
+pub const MAX_REFRESH_RATE: u32 = 360;
+
+/// Display mode applied as a single transition.
+///
+/// `refresh_rate` and `format` of 0 keep the current values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayConfig {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+    pub format: DXGI_FORMAT,
+}
+
+impl DisplayConfig {
+    /// Size-only change keeping the current refresh rate and format
+    pub fn size(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            refresh_rate: 0,
+            format: DXGI_FORMAT(0),
         }
     }
 }
 
+/// Formats a FLIP-model swapchain can use for its buffers
+pub const DISPLAY_FORMATS: [DXGI_FORMAT; 4] = [
+    DXGI_FORMAT_R8G8B8A8_UNORM,
+    DXGI_FORMAT_B8G8R8A8_UNORM,
+    DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT,
+];
+
+/// Whether a FLIP-model swapchain can use `format` for its buffers
+pub fn is_display_format(format: DXGI_FORMAT) -> bool {
+    DISPLAY_FORMATS.contains(&format)
+}
+
+/// Swapchain formats to try, in order, when `requested` is not supported by
+/// the output: 10-bit, then plain 8-bit, which every output takes
+pub fn format_fallbacks(requested: DXGI_FORMAT) -> Vec<DXGI_FORMAT> {
+    match requested {
+        DXGI_FORMAT_R8G8B8A8_UNORM => vec![requested],
+        DXGI_FORMAT_R10G10B10A2_UNORM => vec![requested, DXGI_FORMAT_R8G8B8A8_UNORM],
+        _ => vec![
+            requested,
+            DXGI_FORMAT_R10G10B10A2_UNORM,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+        ],
+    }
+}
+
+/// Run `attempt` with each format from `format_fallbacks`, returning the
+/// first that succeeds along with the format it used
+fn with_format_fallback<T>(
+    requested: DXGI_FORMAT,
+    mut attempt: impl FnMut(DXGI_FORMAT) -> windows::core::Result<T>,
+) -> Result<(DXGI_FORMAT, T)> {
+    let mut last_error = None;
+    for format in format_fallbacks(requested) {
+        match attempt(format) {
+            Ok(value) => {
+                if format != requested {
+                    warn!(
+                        "Swapchain format {:?} unsupported, using {:?}",
+                        requested, format
+                    );
+                }
+                return Ok((format, value));
+            }
+            Err(e) => {
+                warn!("Swapchain format {:?} failed: {}", format, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(anyhow!(
+        "No usable swapchain format: {}",
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    ))
+}
+
+/// Check a requested display mode against the modes the backend supports
+pub fn validate_display_config(mode: &DisplayConfig) -> Result<(), &'static str> {
+    if mode.width < MIN_DISPLAY_WIDTH || mode.height < MIN_DISPLAY_HEIGHT {
+        return Err("Display mode too small");
+    }
+    if mode.width > MAX_DISPLAY_DIMENSION || mode.height > MAX_DISPLAY_DIMENSION {
+        return Err("Display mode too large");
+    }
+    if mode.refresh_rate != 0 && !(MIN_REFRESH_RATE..=MAX_REFRESH_RATE).contains(&mode.refresh_rate)
+    {
+        return Err("Unsupported refresh rate");
+    }
+    if mode.format != DXGI_FORMAT(0) && !is_display_format(mode.format) {
+        return Err("Unsupported display format");
+    }
+    Ok(())
+}
+
 /// Manages frame presentation
 pub struct PresentationPipeline {
     config: PresentationConfig,
@@ -84,12 +339,19 @@ pub struct PresentationPipeline {
 
     // Window resources
     hwnd: Option<HWND>,
+    window_events: Box<WindowEvents>,
     swapchain: Option<IDXGISwapChain1>,
     backbuffer_rtv: Option<ID3D11RenderTargetView>,
 
     // Shared texture for streaming
     shared_texture: Option<ID3D11Texture2D>,
+    shared_rtv: Option<ID3D11RenderTargetView>,
     shared_handle: Option<windows::Win32::Foundation::HANDLE>,
+    /// Keyed mutex guarding the shared texture against consumers reading it
+    /// mid-copy; none when the guest renders into it directly
+    shared_mutex: Option<IDXGIKeyedMutex>,
+    /// Format of the shared texture; follows the presented frames' format
+    shared_format: DXGI_FORMAT,
 
     // Frame signaling
     frame_event: Option<windows::Win32::Foundation::HANDLE>,
@@ -97,16 +359,62 @@ pub struct PresentationPipeline {
     // Window class registered flag
     window_class_registered: bool,
 
+    // Composition tree showing the swapchain (DirectComposition backend)
+    composition: Option<Composition>,
+
     // Shutdown flag
     shutdown: Arc<AtomicBool>,
 
+    // Pause key pressed since the last take_pause_toggle()
+    pause_toggled: bool,
+
     // Tearing support (for VRR displays)
     tearing_supported: bool,
 
+    // YUV -> RGB converter for video frames, created on first use
+    yuv_converter: Option<YuvConverter>,
+
+    // Render device when it lives on a different adapter, and the bridge
+    // that copies its frames onto this pipeline's device
+    render_device: Option<(ID3D11Device, ID3D11DeviceContext)>,
+    cross_adapter: Option<CrossAdapterBridge>,
+
+    // Recent frames kept for crash dumps
+    frame_ring: Option<FrameRing>,
+
+    // Regions the guest changed in the next frame (empty = whole frame)
+    dirty_rects: Vec<RECT>,
+
+    // PVGPU_PRESENT_* flags the guest requested for the next frame
+    present_flags: u32,
+
     // Frame timing
     frame_count: u64,
     last_present_time: std::time::Instant,
     frame_times: Vec<std::time::Duration>,
+    frame_histogram: FrameTimeHistogram,
+
+    // Last adaptive buffer count switch (time, frame_count)
+    last_buffer_switch: (std::time::Instant, u64),
+
+    // Time of the last visibility check while the window is occluded
+    occluded_check: Option<std::time::Instant>,
+
+    // A frame was skipped, so the next present must cover the whole surface
+    frame_skipped: bool,
+
+    // The shared texture does not hold the last frame, so dirty rects alone
+    // cannot bring it up to date
+    shared_stale: bool,
+
+    // Exclusive fullscreen was left on focus loss and is re-entered on regain
+    fullscreen_suspended: bool,
+
+    // Multisampled host backbuffer, resolved into the swapchain at present
+    msaa_backbuffer: Option<ID3D11Texture2D>,
+
+    // Size the guest resized away from; frames still that size are stale
+    stale_size: Option<(u32, u32)>,
 }
 
 impl PresentationPipeline {
@@ -127,22 +435,51 @@ impl PresentationPipeline {
             info!("Variable refresh rate (tearing) is supported");
         }
 
+        let mut config = config;
+        if config.mode != PresentationMode::Dual && config.targets != PresentTargets::Both {
+            warn!(
+                "Present targets {:?} need dual mode, ignoring",
+                config.targets
+            );
+            config.targets = PresentTargets::Both;
+        }
+
         let mut pipeline = Self {
             config: config.clone(),
             device,
             context,
             hwnd: None,
+            window_events: Box::default(),
             swapchain: None,
             backbuffer_rtv: None,
             shared_texture: None,
+            shared_rtv: None,
             shared_handle: None,
+            shared_mutex: None,
+            shared_format: config.format,
             frame_event: None,
             window_class_registered: false,
+            composition: None,
             shutdown: Arc::new(AtomicBool::new(false)),
+            pause_toggled: false,
             tearing_supported,
+            yuv_converter: None,
+            render_device: None,
+            cross_adapter: None,
+            frame_ring: None,
+            dirty_rects: Vec::new(),
+            present_flags: 0,
             frame_count: 0,
             last_present_time: std::time::Instant::now(),
-            frame_times: Vec::with_capacity(120), // Store last ~2 seconds at 60fps
+            frame_times: Vec::with_capacity(FRAME_TIME_SAMPLES), // Store last ~2 seconds at 60fps
+            frame_histogram: FrameTimeHistogram::new(),
+            last_buffer_switch: (std::time::Instant::now(), 0),
+            occluded_check: None,
+            frame_skipped: false,
+            shared_stale: true,
+            fullscreen_suspended: false,
+            msaa_backbuffer: None,
+            stale_size: None,
         };
 
         // Create window if needed
@@ -151,16 +488,28 @@ impl PresentationPipeline {
             pipeline.create_swapchain()?;
         }
 
-        // Create shared texture if needed
+        // Create shared texture if needed, in the format the swapchain
+        // settled on until frames say otherwise
         if config.mode == PresentationMode::Headless || config.mode == PresentationMode::Dual {
+            pipeline.shared_format = pipeline.config.format;
             pipeline.create_shared_texture()?;
         }
 
+        pipeline.create_msaa_backbuffer()?;
+
         // Create frame event for signaling
         if let Some(ref event_name) = config.frame_event_name {
             pipeline.create_frame_event(event_name)?;
         }
 
+        if config.crash_frame_ring > 0 {
+            pipeline.frame_ring = Some(FrameRing::new(
+                pipeline.device.clone(),
+                pipeline.context.clone(),
+                config.crash_frame_ring as usize,
+            ));
+        }
+
         Ok(pipeline)
     }
 
@@ -190,9 +539,14 @@ impl PresentationPipeline {
                 hIconSm: Default::default(),
             };
 
+            // The class is process-wide; another pipeline may have
+            // registered it already
             let atom = unsafe { RegisterClassExW(&wc) };
             if atom == 0 {
-                return Err(anyhow!("Failed to register window class"));
+                let error = unsafe { GetLastError() };
+                if error != ERROR_CLASS_ALREADY_EXISTS {
+                    return Err(anyhow!("Failed to register window class: {:?}", error));
+                }
             }
             self.window_class_registered = true;
         }
@@ -220,10 +574,16 @@ impl PresentationPipeline {
             .chain(std::iter::once(0))
             .collect();
 
+        // A composition swapchain replaces the window's redirection surface
+        let ex_style = match self.config.backend {
+            PresentBackend::Hwnd => WS_EX_APPWINDOW,
+            PresentBackend::DirectComposition => WS_EX_APPWINDOW | WS_EX_NOREDIRECTIONBITMAP,
+        };
+
         // Create window
         let hwnd = unsafe {
             CreateWindowExW(
-                WS_EX_APPWINDOW,
+                ex_style,
                 class_name,
                 PCWSTR(title.as_ptr()),
                 WS_OVERLAPPEDWINDOW,
@@ -234,7 +594,7 @@ impl PresentationPipeline {
                 None,
                 None,
                 windows::Win32::System::LibraryLoader::GetModuleHandleW(None)?,
-                None,
+                Some(&*self.window_events as *const WindowEvents as *const std::ffi::c_void),
             )?
         };
 
@@ -257,15 +617,14 @@ impl PresentationPipeline {
         let hwnd = self.hwnd.ok_or_else(|| anyhow!("No window created"))?;
 
         info!(
-            "Creating swapchain: {} buffers, tearing={}",
+            "Creating swapchain: {} buffers, {:?}, tearing={}",
             self.config.buffer_count,
+            self.config.backend,
             self.config.allow_tearing && self.tearing_supported
         );
 
         // Get DXGI device and factory
-        let dxgi_device: windows::Win32::Graphics::Dxgi::IDXGIDevice = self.device.cast()?;
-        let dxgi_adapter = unsafe { dxgi_device.GetAdapter()? };
-        let dxgi_factory: IDXGIFactory2 = unsafe { dxgi_adapter.GetParent()? };
+        let dxgi_factory = self.dxgi_factory()?;
 
         // Determine swapchain flags
         let use_tearing = self.config.allow_tearing && self.tearing_supported;
@@ -276,10 +635,10 @@ impl PresentationPipeline {
         };
 
         // Swapchain description using FLIP model for better performance
-        let desc = DXGI_SWAP_CHAIN_DESC1 {
+        let mut desc = DXGI_SWAP_CHAIN_DESC1 {
             Width: self.config.width,
             Height: self.config.height,
-            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            Format: self.config.format,
             Stereo: false.into(),
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
@@ -292,26 +651,164 @@ impl PresentationPipeline {
             AlphaMode: DXGI_ALPHA_MODE_IGNORE,
             Flags: flags,
         };
+        let composition = self.config.backend == PresentBackend::DirectComposition;
+        if composition && self.config.transparent {
+            desc.AlphaMode = DXGI_ALPHA_MODE_PREMULTIPLIED;
+        }
+
+        // The output may not take the requested format (HDR on an SDR
+        // monitor); fall back rather than fail to start
+        let (format, swapchain) = with_format_fallback(self.config.format, |format| {
+            desc.Format = format;
+            if composition {
+                unsafe { dxgi_factory.CreateSwapChainForComposition(&self.device, &desc, None) }
+            } else {
+                unsafe {
+                    dxgi_factory.CreateSwapChainForHwnd(&self.device, hwnd, &desc, None, None)
+                }
+            }
+        })?;
+        self.config.format = format;
 
-        let swapchain =
-            unsafe { dxgi_factory.CreateSwapChainForHwnd(&self.device, hwnd, &desc, None, None)? };
+        if composition {
+            if self.composition.is_none() {
+                self.composition = Some(Composition::new(&self.device, hwnd)?);
+            }
+            if let Some(ref tree) = self.composition {
+                tree.show(&swapchain)?;
+            }
+        }
 
         // Create RTV for backbuffer
         let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+        self.backbuffer_rtv = Some(self.create_backbuffer_rtv(&backbuffer)?);
+        self.swapchain = Some(swapchain);
+
+        info!(
+            "Swapchain created: {} buffers, {:?}, FLIP_DISCARD, {:?}, tearing={}",
+            self.config.buffer_count, self.config.format, self.config.backend, use_tearing
+        );
+
+        Ok(())
+    }
+
+    /// Factory of the adapter the presenting device was created on; swapchains
+    /// for the device must come from it
+    fn dxgi_factory(&self) -> Result<IDXGIFactory2> {
+        let dxgi_device: IDXGIDevice = self.device.cast()?;
+        let dxgi_adapter = unsafe { dxgi_device.GetAdapter()? };
+        Ok(unsafe { dxgi_adapter.GetParent()? })
+    }
+
+    /// Rebuild the swapchain after a display topology change.
+    ///
+    /// The old swapchain may be bound to an output that no longer exists.
+    /// The device is kept, so guest resources survive; only the window
+    /// surface is recreated, after moving the window back onto a monitor.
+    fn recover_display_change(&mut self) -> Result<()> {
+        let Some(hwnd) = self.hwnd else {
+            return Ok(());
+        };
+
+        info!("Display topology changed, recreating swapchain");
+        if !unsafe { self.dxgi_factory()?.IsCurrent() }.as_bool() {
+            debug!("DXGI factory is stale, output list will be refreshed");
+        }
+
+        reposition_on_monitor(hwnd);
+
+        // The window can only have one flip-model swapchain, so release the
+        // old one and its buffer view before creating the new one
+        self.backbuffer_rtv = None;
+        self.swapchain = None;
+        // Flush so the deferred release of the old swapchain completes
+        unsafe {
+            self.context.Flush();
+        }
+
+        self.tearing_supported = check_tearing_support(&self.device);
+        self.create_swapchain()?;
+
+        self.occluded_check = None;
+        self.frame_skipped = true;
+        Ok(())
+    }
+
+    /// Create the backbuffer RTV, with an `_SRGB` view format if configured
+    /// and available for the display format.
+    ///
+    /// FLIP-model swapchains reject `_SRGB` buffer formats, so gamma
+    /// encoding is applied through the view instead.
+    fn create_backbuffer_rtv(
+        &self,
+        backbuffer: &ID3D11Texture2D,
+    ) -> Result<ID3D11RenderTargetView> {
+        // Formats without an sRGB variant (10-bit, float) keep a plain view
+        let desc = if self.config.srgb_backbuffer {
+            srgb::srgb_view_format(self.config.format).map(srgb::rtv_desc)
+        } else {
+            None
+        };
         let mut rtv: Option<ID3D11RenderTargetView> = None;
         unsafe {
+            self.device.CreateRenderTargetView(
+                backbuffer,
+                desc.as_ref().map(|d| d as *const _),
+                Some(&mut rtv),
+            )?;
+        }
+        rtv.ok_or_else(|| anyhow!("Failed to create backbuffer RTV"))
+    }
+
+    /// Create the MSAA texture the guest renders into in place of the
+    /// single-sampled host backbuffer. Falls back to no MSAA when the
+    /// format does not support the configured sample count.
+    fn create_msaa_backbuffer(&mut self) -> Result<()> {
+        self.msaa_backbuffer = None;
+        let count = self.config.sample_count;
+        if !self.config.host_backbuffer || count <= 1 {
+            return Ok(());
+        }
+
+        let quality_levels = unsafe {
             self.device
-                .CreateRenderTargetView(&backbuffer, None, Some(&mut rtv))?;
+                .CheckMultisampleQualityLevels(self.config.format, count)
+        }
+        .unwrap_or(0);
+        if quality_levels == 0 {
+            warn!(
+                "{}x MSAA unsupported for {:?}, host backbuffer is single-sampled",
+                count, self.config.format
+            );
+            return Ok(());
         }
 
-        self.swapchain = Some(swapchain);
-        self.backbuffer_rtv = rtv;
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: self.config.width,
+            Height: self.config.height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: self.config.format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: count,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_RENDER_TARGET.0 as u32,
+            CPUAccessFlags: Default::default(),
+            MiscFlags: Default::default(),
+        };
 
+        let mut texture: Option<ID3D11Texture2D> = None;
+        unsafe {
+            self.device
+                .CreateTexture2D(&desc, None, Some(&mut texture))?;
+        }
         info!(
-            "Swapchain created: {} buffers, FLIP_DISCARD, tearing={}",
-            self.config.buffer_count, use_tearing
+            "Created {}x MSAA host backbuffer {}x{}",
+            count, self.config.width, self.config.height
         );
-
+        self.msaa_backbuffer = texture;
         Ok(())
     }
 
@@ -319,12 +816,22 @@ impl PresentationPipeline {
     fn create_shared_texture(&mut self) -> Result<()> {
         info!("Creating shared texture for streaming");
 
+        // A headless host backbuffer is the shared texture itself, rendered
+        // into all frame long, so it cannot be handed over per frame
+        let keyed_mutex =
+            !(self.config.host_backbuffer && self.config.mode == PresentationMode::Headless);
+        let share_flag = if keyed_mutex {
+            D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX
+        } else {
+            D3D11_RESOURCE_MISC_SHARED
+        };
+
         let desc = D3D11_TEXTURE2D_DESC {
             Width: self.config.width,
             Height: self.config.height,
             MipLevels: 1,
             ArraySize: 1,
-            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            Format: self.shared_format,
             SampleDesc: DXGI_SAMPLE_DESC {
                 Count: 1,
                 Quality: 0,
@@ -332,8 +839,7 @@ impl PresentationPipeline {
             Usage: D3D11_USAGE_DEFAULT,
             BindFlags: (D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0) as u32,
             CPUAccessFlags: Default::default(),
-            MiscFlags: (D3D11_RESOURCE_MISC_SHARED.0 | D3D11_RESOURCE_MISC_SHARED_NTHANDLE.0)
-                as u32,
+            MiscFlags: (share_flag.0 | D3D11_RESOURCE_MISC_SHARED_NTHANDLE.0) as u32,
         };
 
         let mut texture: Option<ID3D11Texture2D> = None;
@@ -355,9 +861,25 @@ impl PresentationPipeline {
             )?
         };
 
-        info!("Shared texture created with handle: {:?}", handle);
+        info!(
+            "Shared texture created with handle: {:?}, format={:?}, keyed_mutex={}",
+            handle, self.shared_format, keyed_mutex
+        );
 
+        // Letterboxing clears the bars through this view
+        let mut rtv: Option<ID3D11RenderTargetView> = None;
+        unsafe {
+            self.device
+                .CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+        }
+        self.shared_rtv = rtv;
+        self.shared_mutex = if keyed_mutex {
+            Some(texture.cast()?)
+        } else {
+            None
+        };
         self.shared_texture = Some(texture);
+        self.shared_stale = true;
         self.shared_handle = Some(handle);
 
         Ok(())
@@ -376,38 +898,118 @@ impl PresentationPipeline {
         Ok(())
     }
 
+    /// Render frames on a device attached to a different adapter.
+    ///
+    /// Textures passed to `present` then belong to `device` and are copied
+    /// across adapters first. Does nothing when both devices share an adapter.
+    pub fn set_render_device(
+        &mut self,
+        device: ID3D11Device,
+        context: ID3D11DeviceContext,
+    ) -> Result<()> {
+        if adapter_luid(&device)? == adapter_luid(&self.device)? {
+            info!("Render and present devices share an adapter, skipping cross-adapter copy");
+            return Ok(());
+        }
+        info!("Presenting frames from a different render adapter");
+        self.render_device = Some((device, context));
+        self.cross_adapter = None;
+        Ok(())
+    }
+
+    /// Whether frames are copied in from another adapter before presenting
+    pub fn is_cross_adapter(&self) -> bool {
+        self.render_device.is_some()
+    }
+
+    /// Bring a render-device frame onto this pipeline's device
+    fn import_frame(&mut self, source_texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        let (render_device, render_context) = match self.render_device {
+            Some((ref device, ref context)) => (device, context),
+            None => return Ok(source_texture.clone()),
+        };
+
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            source_texture.GetDesc(&mut desc);
+        }
+
+        let stale = match self.cross_adapter {
+            Some(ref bridge) => !bridge.matches(&desc),
+            None => true,
+        };
+        if stale {
+            self.cross_adapter = Some(CrossAdapterBridge::new(
+                render_device,
+                render_context,
+                &self.device,
+                &desc,
+            )?);
+        }
+
+        let bridge = self
+            .cross_adapter
+            .as_ref()
+            .ok_or_else(|| anyhow!("Cross-adapter bridge unavailable"))?;
+        Ok(bridge.transfer(source_texture, &self.context)?.clone())
+    }
+
+    /// Keep a copy of the frame in the crash frame ring, if enabled
+    fn record_frame(&mut self, frame: &ID3D11Texture2D) {
+        let frame_number = self.frame_count;
+        if let Some(ref mut ring) = self.frame_ring {
+            if let Err(e) = ring.record(frame, frame_number) {
+                debug!("Frame ring capture failed: {}", e);
+            }
+        }
+    }
+
+    /// Write the recent frames kept for crash diagnosis to `dir`.
+    ///
+    /// Returns the number of frames written (0 if the ring is disabled).
+    pub fn dump_recent_frames(&self, dir: &std::path::Path) -> Result<usize> {
+        match self.frame_ring {
+            Some(ref ring) => ring.dump(dir),
+            None => Ok(0),
+        }
+    }
+
     /// Present a frame from the renderer's texture.
     ///
     /// This copies the source texture to the swapchain backbuffer and/or shared texture,
-    /// then presents and signals the frame event.
+    /// then presents and signals the frame event. UNORM, `_SRGB` and TYPELESS
+    /// sources of the same family copy byte-for-byte, so sRGB-encoded content
+    /// is presented unchanged whatever view the guest rendered it through.
     pub fn present(&mut self, source_texture: &ID3D11Texture2D) -> Result<()> {
         debug!("Presenting frame {}", self.frame_count);
 
+        let source_texture = &self.import_frame(source_texture)?;
+        self.record_frame(source_texture);
+
         let now = std::time::Instant::now();
         let frame_time = now - self.last_present_time;
 
         // Copy to swapchain backbuffer if in windowed/dual mode
-        if let Some(ref swapchain) = self.swapchain {
+        if let Some(swapchain) = self.window_target() {
             let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
 
-            unsafe {
-                self.context.CopyResource(&backbuffer, source_texture);
+            if self.copy_frame(&backbuffer, self.backbuffer_rtv.as_ref(), source_texture) {
+                // Dirty rects are in frame coordinates, not output ones
+                self.dirty_rects.clear();
             }
 
-            // Present with appropriate flags
-            let (sync_interval, present_flags) = self.get_present_params();
-            unsafe {
-                swapchain
-                    .Present(sync_interval, DXGI_PRESENT(present_flags))
-                    .ok()?;
-            }
+            self.present_to_swapchain(&swapchain)?;
         }
 
         // Copy to shared texture if in headless/dual mode
-        if let Some(ref shared_texture) = self.shared_texture {
-            unsafe {
-                self.context.CopyResource(shared_texture, source_texture);
+        if self.shared_target().is_some() {
+            self.match_shared_format(source_texture)?;
+        }
+        if self.shared_target().is_some() && self.acquire_shared()? {
+            if let Some(shared_texture) = self.shared_target().cloned() {
+                self.copy_to_shared(&shared_texture, source_texture);
             }
+            self.release_shared();
         }
 
         // Signal frame event
@@ -421,72 +1023,378 @@ impl PresentationPipeline {
         self.update_frame_timing(frame_time);
         self.last_present_time = now;
         self.frame_count += 1;
+        self.dirty_rects.clear();
+        self.present_flags = 0;
+        self.adapt_buffer_count()?;
 
         Ok(())
     }
 
-    /// Present using a specific subregion of the source texture
-    pub fn present_region(
-        &mut self,
-        source_texture: &ID3D11Texture2D,
-        src_x: u32,
-        src_y: u32,
-        width: u32,
-        height: u32,
-    ) -> Result<()> {
-        let now = std::time::Instant::now();
-        let frame_time = now - self.last_present_time;
+    /// Swapchain to present to, unless the window target is switched off
+    fn window_target(&self) -> Option<IDXGISwapChain1> {
+        self.swapchain
+            .clone()
+            .filter(|_| self.config.targets.window())
+    }
 
-        let src_box = D3D11_BOX {
-            left: src_x,
-            top: src_y,
-            front: 0,
-            right: src_x + width,
-            bottom: src_y + height,
-            back: 1,
+    /// Shared texture to copy frames to, unless that target is switched off
+    fn shared_target(&self) -> Option<&ID3D11Texture2D> {
+        self.shared_texture
+            .as_ref()
+            .filter(|_| self.config.targets.shared())
+    }
+
+    /// Switch which outputs presents write to. Only Dual mode has both;
+    /// no resources are created or released.
+    pub fn set_present_targets(&mut self, targets: PresentTargets) {
+        if self.config.mode != PresentationMode::Dual {
+            warn!("Present targets {:?} need dual mode, ignoring", targets);
+            return;
+        }
+        if targets != self.config.targets {
+            info!("Present targets: {:?}", targets);
+            // Frames presented meanwhile never reached the shared texture
+            if !self.config.targets.shared() {
+                self.shared_stale = true;
+            }
+            self.config.targets = targets;
+        }
+    }
+
+    /// Recreate the shared texture in the format of `frame` when copying
+    /// between the two would fail, e.g. a BGRA frame into an RGBA texture.
+    /// The shared handle changes. With a host backbuffer the guest renders
+    /// into the shared texture itself, so its format is left alone.
+    fn match_shared_format(&mut self, frame: &ID3D11Texture2D) -> Result<()> {
+        if self.config.host_backbuffer || self.shared_texture.is_none() {
+            return Ok(());
+        }
+        let mut frame_desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { frame.GetDesc(&mut frame_desc) };
+        let Some(format) = shared_format_for(frame_desc.Format, self.shared_format) else {
+            return Ok(());
         };
 
-        // Copy to swapchain backbuffer if in windowed/dual mode
-        if let Some(ref swapchain) = self.swapchain {
-            let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+        info!(
+            "Presented frames are {:?}; recreating shared texture as {:?} (was {:?})",
+            frame_desc.Format, format, self.shared_format
+        );
+        self.shared_format = format;
+        self.release_shared_texture();
+        self.create_shared_texture()
+    }
 
+    /// Drop the shared texture and close its handle
+    fn release_shared_texture(&mut self) {
+        self.shared_mutex = None;
+        self.shared_rtv = None;
+        self.shared_texture = None;
+        if let Some(handle) = self.shared_handle.take() {
             unsafe {
-                self.context.CopySubresourceRegion(
-                    &backbuffer,
-                    0,
-                    0,
-                    0,
-                    0,
-                    source_texture,
-                    0,
-                    Some(&src_box),
-                );
+                let _ = windows::Win32::Foundation::CloseHandle(handle);
+            }
+        }
+    }
+
+    /// Take the shared texture's keyed mutex with the producer key, without
+    /// waiting. If the consumer never picked up the last frame, it is
+    /// reclaimed with the consumer key and overwritten. Returns false, and
+    /// the frame is not copied, while the consumer holds the texture.
+    fn acquire_shared(&mut self) -> Result<bool> {
+        let Some(mutex) = self.shared_mutex.clone() else {
+            return Ok(true);
+        };
+        for key in [SHARED_KEY_PRODUCER, SHARED_KEY_CONSUMER] {
+            let hr = unsafe {
+                (Interface::vtable(&mutex).AcquireSync)(Interface::as_raw(&mutex), key, 0)
+            };
+            match shared_acquire_status(hr)? {
+                SharedAcquire::Acquired => return Ok(true),
+                SharedAcquire::Busy => continue,
+                SharedAcquire::Abandoned => {
+                    // A consumer exited while holding it; the texture's
+                    // contents and mutex can no longer be trusted
+                    warn!("Shared texture keyed mutex abandoned, recreating the shared texture");
+                    self.release_shared_texture();
+                    self.create_shared_texture()?;
+                    return self.acquire_shared();
+                }
             }
+        }
+        debug!("Shared texture held by its consumer, skipping frame");
+        // The next copy must be a full frame again
+        self.shared_stale = true;
+        Ok(false)
+    }
 
-            // Present with appropriate flags
-            let (sync_interval, present_flags) = self.get_present_params();
+    /// Hand the shared texture to the consumer after a copy
+    fn release_shared(&self) {
+        if let Some(ref mutex) = self.shared_mutex {
             unsafe {
-                swapchain
-                    .Present(sync_interval, DXGI_PRESENT(present_flags))
-                    .ok()?;
+                if let Err(e) = mutex.ReleaseSync(SHARED_KEY_CONSUMER) {
+                    warn!("Failed to release shared texture keyed mutex: {}", e);
+                }
             }
         }
+    }
 
-        // Copy to shared texture if in headless/dual mode
-        if let Some(ref shared_texture) = self.shared_texture {
+    /// Copy a frame into the shared texture. Only the dirty rects are copied
+    /// when the texture already holds the previous frame at the same size,
+    /// so streaming encoders see untouched pixels stay untouched.
+    fn copy_to_shared(&mut self, shared_texture: &ID3D11Texture2D, frame: &ID3D11Texture2D) {
+        let mut shared_desc = D3D11_TEXTURE2D_DESC::default();
+        let mut frame_desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            shared_texture.GetDesc(&mut shared_desc);
+            frame.GetDesc(&mut frame_desc);
+        }
+        let same_size =
+            (shared_desc.Width, shared_desc.Height) == (frame_desc.Width, frame_desc.Height);
+
+        if self.shared_stale || !same_size || self.dirty_rects.is_empty() {
+            self.copy_frame(shared_texture, self.shared_rtv.as_ref(), frame);
+            self.shared_stale = false;
+            return;
+        }
+
+        for rect in &self.dirty_rects {
+            let src_box = D3D11_BOX {
+                left: rect.left as u32,
+                top: rect.top as u32,
+                front: 0,
+                right: rect.right as u32,
+                bottom: rect.bottom as u32,
+                back: 1,
+            };
             unsafe {
                 self.context.CopySubresourceRegion(
                     shared_texture,
                     0,
+                    src_box.left,
+                    src_box.top,
                     0,
-                    0,
-                    0,
-                    source_texture,
+                    frame,
                     0,
                     Some(&src_box),
                 );
             }
         }
+    }
+
+    /// Copy a frame into an output surface. A frame of a different size is
+    /// centered unscaled (cropped if larger) on the letterbox color.
+    ///
+    /// Returns true if the frame was letterboxed.
+    fn copy_frame(
+        &self,
+        target: &ID3D11Texture2D,
+        target_rtv: Option<&ID3D11RenderTargetView>,
+        frame: &ID3D11Texture2D,
+    ) -> bool {
+        let mut target_desc = D3D11_TEXTURE2D_DESC::default();
+        let mut frame_desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            target.GetDesc(&mut target_desc);
+            frame.GetDesc(&mut frame_desc);
+        }
+        let target_size = (target_desc.Width, target_desc.Height);
+        let frame_size = (frame_desc.Width, frame_desc.Height);
+        if target_size == frame_size {
+            unsafe {
+                self.context.CopyResource(target, frame);
+            }
+            return false;
+        }
+
+        debug!(
+            "Letterboxing {:?} frame into {:?} output",
+            frame_size, target_size
+        );
+        let (x, y, src_box) = letterbox_placement(frame_size, target_size);
+        unsafe {
+            if let Some(rtv) = target_rtv {
+                self.context
+                    .ClearRenderTargetView(rtv, &self.config.letterbox_color);
+            }
+            self.context
+                .CopySubresourceRegion(target, 0, x, y, 0, frame, 0, Some(&src_box));
+        }
+        true
+    }
+
+    /// Present a planar YUV (NV12/P010) frame.
+    ///
+    /// The frame is colour-converted to RGBA by a pixel shader, then
+    /// presented through the normal copy path.
+    pub fn present_yuv(&mut self, source_texture: &ID3D11Texture2D) -> Result<()> {
+        // Convert on the device that owns the frame
+        let (device, context) = match self.render_device {
+            Some((ref device, ref context)) => (device, context),
+            None => (&self.device, &self.context),
+        };
+        if self.yuv_converter.is_none() {
+            self.yuv_converter = Some(YuvConverter::new(device)?);
+        }
+        let converter = self
+            .yuv_converter
+            .as_mut()
+            .ok_or_else(|| anyhow!("YUV converter unavailable"))?;
+        let rgba = converter.convert(device, context, source_texture)?;
+        self.present(&rgba)
+    }
+
+    /// Texture the guest renders into when host-owned backbuffers are enabled.
+    ///
+    /// This is the MSAA backbuffer if there is one, otherwise the swapchain's
+    /// current buffer in windowed/dual mode, or the shared texture in
+    /// headless mode.
+    pub fn host_backbuffer(&self) -> Result<Option<ID3D11Texture2D>> {
+        if !self.config.host_backbuffer {
+            return Ok(None);
+        }
+        if let Some(ref msaa) = self.msaa_backbuffer {
+            return Ok(Some(msaa.clone()));
+        }
+        self.resolve_target()
+    }
+
+    /// Single-sampled surface holding the host backbuffer's frame at present
+    fn resolve_target(&self) -> Result<Option<ID3D11Texture2D>> {
+        if let Some(ref swapchain) = self.swapchain {
+            let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+            return Ok(Some(backbuffer));
+        }
+        Ok(self.shared_texture.clone())
+    }
+
+    /// Whether the guest renders directly into the host backbuffer
+    pub fn uses_host_backbuffer(&self) -> bool {
+        self.config.host_backbuffer
+    }
+
+    /// Present the host-owned backbuffer the guest rendered into.
+    ///
+    /// No copy is needed into the swapchain; in dual mode the frame is still
+    /// copied to the shared texture for streaming tools.
+    pub fn present_host_backbuffer(&mut self) -> Result<()> {
+        debug!("Presenting host backbuffer, frame {}", self.frame_count);
+
+        let frame = self.resolve_target()?;
+        if let (Some(msaa), Some(frame)) = (self.msaa_backbuffer.as_ref(), frame.as_ref()) {
+            unsafe {
+                self.context
+                    .ResolveSubresource(frame, 0, msaa, 0, self.config.format);
+            }
+        }
+
+        // Capture before Present, which discards FLIP_DISCARD buffer contents
+        if let Some(ref frame) = frame {
+            self.record_frame(frame);
+        }
+
+        let now = std::time::Instant::now();
+        let frame_time = now - self.last_present_time;
+
+        if let Some(swapchain) = self.swapchain.clone() {
+            if self.shared_target().is_some() {
+                let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+                if self.acquire_shared()? {
+                    if let Some(shared_texture) = self.shared_target() {
+                        unsafe {
+                            self.context.CopyResource(shared_texture, &backbuffer);
+                        }
+                    }
+                    self.release_shared();
+                }
+            }
+
+            if self.config.targets.window() {
+                self.present_to_swapchain(&swapchain)?;
+            }
+        }
+
+        // Signal frame event
+        if let Some(event) = self.frame_event {
+            unsafe {
+                let _ = SetEvent(event);
+            }
+        }
+
+        // Update frame timing
+        self.update_frame_timing(frame_time);
+        self.last_present_time = now;
+        self.frame_count += 1;
+        self.dirty_rects.clear();
+        self.present_flags = 0;
+        self.adapt_buffer_count()?;
+
+        Ok(())
+    }
+
+    /// Present using a specific subregion of the source texture
+    pub fn present_region(
+        &mut self,
+        source_texture: &ID3D11Texture2D,
+        src_x: u32,
+        src_y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        let source_texture = &self.import_frame(source_texture)?;
+        self.record_frame(source_texture);
+
+        let now = std::time::Instant::now();
+        let frame_time = now - self.last_present_time;
+
+        let src_box = D3D11_BOX {
+            left: src_x,
+            top: src_y,
+            front: 0,
+            right: src_x + width,
+            bottom: src_y + height,
+            back: 1,
+        };
+
+        // Copy to swapchain backbuffer if in windowed/dual mode
+        if let Some(swapchain) = self.window_target() {
+            let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+
+            unsafe {
+                self.context.CopySubresourceRegion(
+                    &backbuffer,
+                    0,
+                    0,
+                    0,
+                    0,
+                    source_texture,
+                    0,
+                    Some(&src_box),
+                );
+            }
+
+            self.present_to_swapchain(&swapchain)?;
+        }
+
+        // Copy to shared texture if in headless/dual mode. The region lands
+        // at the origin, so later dirty rects would not line up with it.
+        if self.shared_target().is_some() && self.acquire_shared()? {
+            if let Some(shared_texture) = self.shared_target() {
+                unsafe {
+                    self.context.CopySubresourceRegion(
+                        shared_texture,
+                        0,
+                        0,
+                        0,
+                        0,
+                        source_texture,
+                        0,
+                        Some(&src_box),
+                    );
+                }
+            }
+            self.release_shared();
+        }
+        self.shared_stale = true;
 
         // Signal frame event
         if let Some(event) = self.frame_event {
@@ -499,59 +1407,140 @@ impl PresentationPipeline {
         self.update_frame_timing(frame_time);
         self.last_present_time = now;
         self.frame_count += 1;
+        self.dirty_rects.clear();
+        self.present_flags = 0;
+        self.adapt_buffer_count()?;
 
         Ok(())
     }
 
+    /// Limit the next present to the regions the guest changed.
+    ///
+    /// Rects are clipped to the surface; `None`, an empty list, or more than
+    /// PVGPU_MAX_DIRTY_RECTS rects after merging presents the whole frame.
+    pub fn set_dirty_rects(&mut self, rects: Option<Vec<RECT>>) {
+        let (width, height) = (self.config.width, self.config.height);
+        self.dirty_rects = match rects {
+            Some(rects) if rects.len() <= PVGPU_MAX_DIRTY_RECTS as usize => rects
+                .into_iter()
+                .filter_map(|rect| clip_dirty_rect(rect, width, height))
+                .collect(),
+            _ => Vec::new(),
+        };
+    }
+
+    /// Apply the guest's PVGPU_PRESENT_* flags to the next present
+    pub fn set_present_flags(&mut self, flags: u32) {
+        if flags & !PVGPU_PRESENT_FLAGS_MASK != 0 {
+            debug!(
+                "Ignoring unsupported present flags {:#x}",
+                flags & !PVGPU_PRESENT_FLAGS_MASK
+            );
+        }
+        self.present_flags = flags;
+    }
+
     /// Resize the presentation surface.
     pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
-        if width == self.config.width && height == self.config.height {
+        self.apply_display_config(DisplayConfig::size(width, height))
+    }
+
+    /// Current display mode
+    pub fn display_config(&self) -> DisplayConfig {
+        DisplayConfig {
+            width: self.config.width,
+            height: self.config.height,
+            refresh_rate: self.config.refresh_rate,
+            format: self.config.format,
+        }
+    }
+
+    /// Switch size, refresh rate and format together.
+    ///
+    /// The swapchain buffers and shared texture are recreated once for the
+    /// whole change rather than once per parameter.
+    pub fn apply_display_config(&mut self, mode: DisplayConfig) -> Result<()> {
+        let refresh_rate = match mode.refresh_rate {
+            0 => self.config.refresh_rate,
+            rate => rate,
+        };
+        let format = match mode.format {
+            DXGI_FORMAT(0) => self.config.format,
+            format => format,
+        };
+
+        // Refresh rate is only reported to the guest; no buffers depend on it
+        self.config.refresh_rate = refresh_rate;
+
+        if mode.width == self.config.width
+            && mode.height == self.config.height
+            && format == self.config.format
+        {
             return Ok(());
         }
 
-        info!("Resizing presentation to {}x{}", width, height);
+        info!(
+            "Reconfiguring presentation to {}x{}@{}Hz, format={:?}",
+            mode.width, mode.height, refresh_rate, format
+        );
 
+        let (width, height) = (mode.width, mode.height);
         self.config.width = width;
         self.config.height = height;
+        self.config.format = format;
 
         // Release old resources
         self.backbuffer_rtv = None;
+        self.dirty_rects.clear();
 
         // Resize swapchain if exists
-        if let Some(ref swapchain) = self.swapchain {
-            let flags = if self.config.allow_tearing && self.tearing_supported {
-                DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING
-            } else {
-                DXGI_SWAP_CHAIN_FLAG(0)
-            };
-
-            unsafe {
-                swapchain.ResizeBuffers(
-                    self.config.buffer_count,
-                    width,
-                    height,
-                    DXGI_FORMAT_R8G8B8A8_UNORM,
-                    flags,
-                )?;
-            }
+        self.resize_swapchain_buffers()?;
 
-            // Recreate RTV
-            let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
-            let mut rtv: Option<ID3D11RenderTargetView> = None;
-            unsafe {
-                self.device
-                    .CreateRenderTargetView(&backbuffer, None, Some(&mut rtv))?;
-            }
-            self.backbuffer_rtv = rtv;
+        // Recreate shared texture if exists. A host backbuffer shared
+        // texture is rendered into as the display format.
+        if self.config.host_backbuffer {
+            self.shared_format = format;
         }
-
-        // Recreate shared texture if exists
         if self.shared_texture.is_some() {
-            self.shared_texture = None;
-            self.shared_handle = None;
+            self.release_shared_texture();
             self.create_shared_texture()?;
         }
 
+        if self.msaa_backbuffer.is_some() {
+            self.create_msaa_backbuffer()?;
+        }
+
+        Ok(())
+    }
+
+    /// Recreate the swapchain buffers from the current config's size,
+    /// format and buffer count
+    fn resize_swapchain_buffers(&mut self) -> Result<()> {
+        let swapchain = match self.swapchain {
+            Some(ref swapchain) => swapchain.clone(),
+            None => return Ok(()),
+        };
+        let flags = if self.config.allow_tearing && self.tearing_supported {
+            DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING
+        } else {
+            DXGI_SWAP_CHAIN_FLAG(0)
+        };
+
+        // All references to the old buffers must be gone
+        self.backbuffer_rtv = None;
+        let (buffer_count, width, height) = (
+            self.config.buffer_count,
+            self.config.width,
+            self.config.height,
+        );
+        let (format, ()) = with_format_fallback(self.config.format, |format| unsafe {
+            swapchain.ResizeBuffers(buffer_count, width, height, format, flags)
+        })?;
+        self.config.format = format;
+
+        // Recreate RTV
+        let backbuffer: ID3D11Texture2D = unsafe { swapchain.GetBuffer(0)? };
+        self.backbuffer_rtv = Some(self.create_backbuffer_rtv(&backbuffer)?);
         Ok(())
     }
 
@@ -567,6 +1556,15 @@ impl PresentationPipeline {
                 self.shutdown.store(true, Ordering::SeqCst);
                 return false;
             }
+            if msg.message == WM_KEYDOWN && msg.wParam.0 == VK_PAUSE.0 as usize {
+                self.pause_toggled = true;
+            }
+            if msg.message == WM_KEYDOWN
+                && msg.wParam.0 == VK_SCROLL.0 as usize
+                && self.config.mode == PresentationMode::Dual
+            {
+                self.set_present_targets(self.config.targets.next());
+            }
 
             unsafe {
                 let _ = TranslateMessage(&msg);
@@ -574,9 +1572,69 @@ impl PresentationPipeline {
             }
         }
 
+        // SetFullscreenState sends window messages itself, so it cannot be
+        // called from inside the window procedure
+        if self
+            .window_events
+            .activation_changed
+            .swap(false, Ordering::SeqCst)
+        {
+            let active = self.window_events.active.load(Ordering::SeqCst);
+            if let Err(e) = self.handle_activation(active) {
+                warn!("Failed to update fullscreen state on activation: {}", e);
+            }
+        }
+
+        // WM_DISPLAYCHANGE is sent straight to the window procedure, so it
+        // never shows up in the loop above
+        if self
+            .window_events
+            .display_changed
+            .swap(false, Ordering::SeqCst)
+            && self.config.display_change_recovery
+        {
+            if let Err(e) = self.recover_display_change() {
+                warn!("Failed to recover from display change: {}", e);
+            }
+        }
+
         !self.shutdown.load(Ordering::SeqCst)
     }
 
+    /// Leave exclusive fullscreen when the window loses focus and re-enter
+    /// it on regain; presenting to a fullscreen swapchain whose window is
+    /// inactive removes the device.
+    fn handle_activation(&mut self, active: bool) -> Result<()> {
+        let Some(swapchain) = self.swapchain.clone() else {
+            return Ok(());
+        };
+
+        if active {
+            if !std::mem::take(&mut self.fullscreen_suspended) {
+                return Ok(());
+            }
+            info!("Window activated, re-entering exclusive fullscreen");
+            unsafe { swapchain.SetFullscreenState(BOOL::from(true), None::<&IDXGIOutput>)? };
+        } else {
+            let mut fullscreen = BOOL::default();
+            unsafe { swapchain.GetFullscreenState(Some(&mut fullscreen), None)? };
+            if !fullscreen.as_bool() {
+                return Ok(());
+            }
+            info!("Window deactivated, leaving exclusive fullscreen");
+            unsafe { swapchain.SetFullscreenState(BOOL::from(false), None::<&IDXGIOutput>)? };
+            self.fullscreen_suspended = true;
+        }
+
+        // The mode change invalidates the buffer size
+        self.resize_swapchain_buffers()
+    }
+
+    /// Whether the Pause key was pressed in the window since the last call
+    pub fn take_pause_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.pause_toggled)
+    }
+
     /// Check if shutdown was requested
     pub fn should_shutdown(&self) -> bool {
         self.shutdown.load(Ordering::SeqCst)
@@ -587,6 +1645,21 @@ impl PresentationPipeline {
         (self.config.width, self.config.height)
     }
 
+    /// Record that the guest resized the swapchain away from `size`, so
+    /// frames it rendered before the resize are no longer presented
+    pub fn set_stale_size(&mut self, size: (u32, u32)) {
+        self.stale_size = Some(size);
+    }
+
+    /// Whether `frame` still has the size the guest resized away from. The
+    /// first frame of the current size ends the check.
+    pub fn is_stale_frame(&mut self, frame: &ID3D11Texture2D) -> bool {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { frame.GetDesc(&mut desc) };
+        let display = self.dimensions();
+        stale_frame_size((desc.Width, desc.Height), display, &mut self.stale_size)
+    }
+
     /// Get the presentation mode.
     #[allow(dead_code)]
     pub fn mode(&self) -> PresentationMode {
@@ -635,14 +1708,89 @@ impl PresentationPipeline {
         }
     }
 
+    /// Present the swapchain, skipping frames while the window is occluded.
+    ///
+    /// An occluded window is polled with DXGI_PRESENT_TEST every
+    /// `occlusion_poll_interval` until it is visible again.
+    fn present_to_swapchain(&mut self, swapchain: &IDXGISwapChain1) -> Result<()> {
+        if let Some(checked) = self.occluded_check {
+            if checked.elapsed() < self.config.occlusion_poll_interval {
+                self.frame_skipped = true;
+                return Ok(());
+            }
+            let test = unsafe { swapchain.Present(0, DXGI_PRESENT_TEST) };
+            if present_status(test)? == PresentStatus::Occluded {
+                self.occluded_check = Some(std::time::Instant::now());
+                self.frame_skipped = true;
+                return Ok(());
+            }
+            info!("Output window visible again, resuming presents");
+            self.occluded_check = None;
+        }
+
+        // Skipped frames never reached the screen, so the dirty rects no
+        // longer cover everything that changed since the last shown frame
+        let dirty_rects: &[RECT] = if self.frame_skipped {
+            &[]
+        } else {
+            &self.dirty_rects
+        };
+        let (sync_interval, present_flags) = self.get_present_params();
+        let present_flags =
+            present_flags | guest_present_flags(self.present_flags, sync_interval, present_flags);
+        match present_swapchain(swapchain, sync_interval, present_flags, dirty_rects)? {
+            PresentStatus::Presented => self.frame_skipped = false,
+            PresentStatus::Occluded => {
+                info!("Output window occluded, skipping frames");
+                self.occluded_check = Some(std::time::Instant::now());
+                self.frame_skipped = true;
+            }
+            PresentStatus::StillDrawing => {
+                debug!("GPU still drawing, frame {} skipped", self.frame_count);
+                self.frame_skipped = true;
+            }
+        }
+        Ok(())
+    }
+
     /// Update frame timing statistics
     fn update_frame_timing(&mut self, frame_time: std::time::Duration) {
-        const MAX_SAMPLES: usize = 120;
-
         self.frame_times.push(frame_time);
-        if self.frame_times.len() > MAX_SAMPLES {
+        if self.frame_times.len() > FRAME_TIME_SAMPLES {
             self.frame_times.remove(0);
         }
+        self.frame_histogram.record(frame_time);
+    }
+
+    /// Switch between double and triple buffering when adaptive buffering
+    /// is enabled and recent frame pacing calls for it.
+    ///
+    /// Switches are debounced: at least BUFFER_SWITCH_INTERVAL apart, and
+    /// only once a full window of frames has been presented with the
+    /// current count.
+    fn adapt_buffer_count(&mut self) -> Result<()> {
+        if !self.config.adaptive_buffering || self.swapchain.is_none() {
+            return Ok(());
+        }
+        let (switched_at, switched_frame) = self.last_buffer_switch;
+        if switched_at.elapsed() < BUFFER_SWITCH_INTERVAL
+            || self.frame_count - switched_frame < FRAME_TIME_SAMPLES as u64
+        {
+            return Ok(());
+        }
+
+        let buffer_count = match adaptive_buffer_count(&self.frame_times, self.config.buffer_count)
+        {
+            Some(count) => count,
+            None => return Ok(()),
+        };
+        info!(
+            "Adaptive buffering: switching from {} to {} buffers",
+            self.config.buffer_count, buffer_count
+        );
+        self.config.buffer_count = buffer_count;
+        self.last_buffer_switch = (std::time::Instant::now(), self.frame_count);
+        self.resize_swapchain_buffers()
     }
 
     /// Get average FPS over the last N frames
@@ -675,6 +1823,9 @@ impl PresentationPipeline {
     }
 
     /// Get frame timing statistics
+    ///
+    /// Average, min and max cover the last FRAME_TIME_SAMPLES frames;
+    /// percentiles cover every frame since the last `reset_frame_histogram`.
     pub fn frame_stats(&self) -> FrameStats {
         if self.frame_times.is_empty() {
             return FrameStats::default();
@@ -694,10 +1845,18 @@ impl PresentationPipeline {
             avg_frame_time_ms: avg.as_secs_f64() * 1000.0,
             min_frame_time_ms: min.as_secs_f64() * 1000.0,
             max_frame_time_ms: max.as_secs_f64() * 1000.0,
+            p50_frame_time_ms: self.frame_histogram.percentile_ms(0.50),
+            p95_frame_time_ms: self.frame_histogram.percentile_ms(0.95),
+            p99_frame_time_ms: self.frame_histogram.percentile_ms(0.99),
             frame_count: self.frame_count,
         }
     }
 
+    /// Start a new percentile window
+    pub fn reset_frame_histogram(&mut self) {
+        self.frame_histogram.clear();
+    }
+
     /// Set vsync mode at runtime
     pub fn set_vsync(&mut self, enabled: bool) {
         if self.config.vsync != enabled {
@@ -726,9 +1885,13 @@ impl PresentationPipeline {
         self.tearing_supported
     }
 
-    /// Handle window resize from WM_SIZE message
-    /// Returns the new size if it changed
+    /// Client area size after the window was resized (WM_SIZE), if it
+    /// differs from the current size. The caller applies it, so the host
+    /// backbuffer can be released before the swapchain resizes.
     pub fn handle_window_resize(&mut self) -> Option<(u32, u32)> {
+        if !self.window_events.resized.swap(false, Ordering::SeqCst) {
+            return None;
+        }
         let hwnd = self.hwnd?;
 
         let mut rect = RECT::default();
@@ -745,10 +1908,6 @@ impl PresentationPipeline {
             && new_height > 0
             && (new_width != self.config.width || new_height != self.config.height)
         {
-            if let Err(e) = self.resize(new_width, new_height) {
-                tracing::error!("Failed to resize presentation: {}", e);
-                return None;
-            }
             return Some((new_width, new_height));
         }
 
@@ -767,10 +1926,120 @@ pub struct FrameStats {
     pub min_frame_time_ms: f64,
     /// Maximum frame time in milliseconds (worst frame)
     pub max_frame_time_ms: f64,
+    /// Median frame time in milliseconds
+    pub p50_frame_time_ms: f64,
+    /// 95th-percentile frame time in milliseconds
+    pub p95_frame_time_ms: f64,
+    /// 99th-percentile frame time in milliseconds (hitches)
+    pub p99_frame_time_ms: f64,
     /// Total frame count
     pub frame_count: u64,
 }
 
+/// Fixed-size histogram of frame times with HISTOGRAM_BUCKET_US resolution.
+///
+/// Recording is O(1) and the memory use is constant however many frames are
+/// recorded; percentiles are accurate to one bucket.
+pub struct FrameTimeHistogram {
+    buckets: Box<[u32; HISTOGRAM_BUCKETS]>,
+    count: u64,
+    // Longest frame, reported for percentiles in the last bucket
+    max: std::time::Duration,
+}
+
+impl FrameTimeHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: Box::new([0; HISTOGRAM_BUCKETS]),
+            count: 0,
+            max: std::time::Duration::ZERO,
+        }
+    }
+
+    /// Count one frame
+    pub fn record(&mut self, frame_time: std::time::Duration) {
+        let bucket = (frame_time.as_micros() as u64 / HISTOGRAM_BUCKET_US) as usize;
+        let bucket = &mut self.buckets[bucket.min(HISTOGRAM_BUCKETS - 1)];
+        *bucket = bucket.saturating_add(1);
+        self.count += 1;
+        self.max = self.max.max(frame_time);
+    }
+
+    /// Frame time in milliseconds that `fraction` (0.0-1.0) of frames are at
+    /// or below, or 0 with no frames recorded
+    pub fn percentile_ms(&self, fraction: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((self.count as f64 * fraction).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0u64;
+        for (index, &frames) in self.buckets.iter().enumerate() {
+            seen += frames as u64;
+            if seen >= rank {
+                if index == HISTOGRAM_BUCKETS - 1 {
+                    return self.max.as_secs_f64() * 1000.0;
+                }
+                // Upper edge of the bucket, so a percentile never under-reports
+                let upper_us = (index as u64 + 1) * HISTOGRAM_BUCKET_US;
+                return (upper_us as f64 / 1000.0).min(self.max.as_secs_f64() * 1000.0);
+            }
+        }
+        self.max.as_secs_f64() * 1000.0
+    }
+
+    /// Frames recorded since the last clear
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Forget every recorded frame
+    pub fn clear(&mut self) {
+        self.buckets.fill(0);
+        self.count = 0;
+        self.max = std::time::Duration::ZERO;
+    }
+}
+
+impl Default for FrameTimeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DirectComposition objects that put a composition swapchain in a window.
+/// The device and target must outlive the visual for it to stay on screen.
+struct Composition {
+    device: IDCompositionDevice,
+    _target: IDCompositionTarget,
+    visual: IDCompositionVisual,
+}
+
+impl Composition {
+    /// Create a composition device and a root visual targeting `hwnd`
+    fn new(device: &ID3D11Device, hwnd: HWND) -> Result<Self> {
+        let dxgi_device: IDXGIDevice = device.cast()?;
+        let device: IDCompositionDevice = unsafe { DCompositionCreateDevice(&dxgi_device)? };
+        let target = unsafe { device.CreateTargetForHwnd(hwnd, true)? };
+        let visual = unsafe { device.CreateVisual()? };
+        unsafe { target.SetRoot(&visual)? };
+        info!("DirectComposition target created for {:?}", hwnd);
+        Ok(Self {
+            device,
+            _target: target,
+            visual,
+        })
+    }
+
+    /// Show `swapchain` as the visual's content
+    fn show(&self, swapchain: &IDXGISwapChain1) -> Result<()> {
+        unsafe {
+            self.visual.SetContent(swapchain)?;
+            self.device.Commit()?;
+        }
+        Ok(())
+    }
+}
+
 /// Check if the system supports tearing (DXGI_FEATURE_PRESENT_ALLOW_TEARING)
 fn check_tearing_support(device: &ID3D11Device) -> bool {
     // Try to get IDXGIFactory5 which supports tearing query
@@ -807,6 +2076,7 @@ impl Drop for PresentationPipeline {
         // Clean up resources
         self.backbuffer_rtv = None;
         self.swapchain = None;
+        self.shared_rtv = None;
         self.shared_texture = None;
 
         // Close handles
@@ -831,9 +2101,58 @@ impl Drop for PresentationPipeline {
     }
 }
 
+/// Move the window onto the primary monitor if it no longer overlaps any
+/// monitor (e.g. the one it was on was unplugged)
+fn reposition_on_monitor(hwnd: HWND) {
+    if !unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONULL) }.is_invalid() {
+        return;
+    }
+
+    let monitor = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) };
+    let mut info = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    if !unsafe { GetMonitorInfoW(monitor, &mut info) }.as_bool() {
+        warn!("Failed to query the primary monitor");
+        return;
+    }
+
+    info!(
+        "Window is off-screen, moving it to ({}, {})",
+        info.rcWork.left, info.rcWork.top
+    );
+    unsafe {
+        let _ = SetWindowPos(
+            hwnd,
+            None,
+            info.rcWork.left,
+            info.rcWork.top,
+            0,
+            0,
+            SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+    }
+}
+
+/// The pipeline's WindowEvents stored on `hwnd`, if WM_NCCREATE has run
+fn window_events<'a>(hwnd: HWND) -> Option<&'a WindowEvents> {
+    let events = unsafe { GetWindowLongPtrW(hwnd, GWLP_USERDATA) } as *const WindowEvents;
+    // The pipeline destroys its window before dropping the events
+    unsafe { events.as_ref() }
+}
+
 /// Window procedure for handling window messages
 extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     match msg {
+        WM_NCCREATE => {
+            // CreateWindowExW passes the creating pipeline's WindowEvents
+            let create = unsafe { &*(lparam.0 as *const CREATESTRUCTW) };
+            unsafe {
+                SetWindowLongPtrW(hwnd, GWLP_USERDATA, create.lpCreateParams as isize);
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+        }
         WM_CLOSE => {
             unsafe {
                 PostQuitMessage(0);
@@ -858,18 +2177,300 @@ extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPA
             LRESULT(0)
         }
         WM_SIZE => {
-            // Handle resize if needed
-            // The main loop should call resize() based on window size changes
+            // Picked up by handle_window_resize(); a minimized window has a
+            // 0x0 client area and keeps its buffers
+            if wparam.0 as u32 != SIZE_MINIMIZED {
+                if let Some(events) = window_events(hwnd) {
+                    events.resized.store(true, Ordering::SeqCst);
+                }
+            }
             LRESULT(0)
         }
+        WM_DISPLAYCHANGE => {
+            // Recovered from process_messages(), outside the window procedure
+            if let Some(events) = window_events(hwnd) {
+                events.display_changed.store(true, Ordering::SeqCst);
+            }
+            LRESULT(0)
+        }
+        WM_ACTIVATE => {
+            // Fullscreen state is updated from process_messages()
+            let active = (wparam.0 & 0xFFFF) as u32 != WA_INACTIVE;
+            if let Some(events) = window_events(hwnd) {
+                events.active.store(active, Ordering::SeqCst);
+                events.activation_changed.store(true, Ordering::SeqCst);
+            }
+            unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+        }
         _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
     }
 }
 
+/// Buffer count suited to recent frame times: 3 when pacing is jittery, 2
+/// when it is steady. Returns None to keep `current`; counts other than 2
+/// and 3 are never changed.
+pub fn adaptive_buffer_count(frame_times: &[std::time::Duration], current: u32) -> Option<u32> {
+    if frame_times.len() < 2 {
+        return None;
+    }
+    let samples = frame_times.len() as f64;
+    let mean = frame_times.iter().map(|t| t.as_secs_f64()).sum::<f64>() / samples;
+    if mean <= 0.0 {
+        return None;
+    }
+    let variance = frame_times
+        .iter()
+        .map(|t| (t.as_secs_f64() - mean).powi(2))
+        .sum::<f64>()
+        / samples;
+    let jitter = variance.sqrt() / mean;
+
+    match current {
+        2 if jitter > JITTERY_FRAME_TIMES => Some(3),
+        3 if jitter < STEADY_FRAME_TIMES => Some(2),
+        _ => None,
+    }
+}
+
+/// Format to recreate a shared texture of format `current` in so frames of
+/// `frame` can be copied into it, or None if they already can. Copies only
+/// need the same format family, so UNORM and `_SRGB` frames share a
+/// texture; a TYPELESS frame gets its family's UNORM format.
+pub fn shared_format_for(frame: DXGI_FORMAT, current: DXGI_FORMAT) -> Option<DXGI_FORMAT> {
+    let frame = srgb::unorm_format(frame).unwrap_or(frame);
+    let current = srgb::unorm_format(current).unwrap_or(current);
+    (frame != current).then_some(frame)
+}
+
+/// Where a `frame` (width, height) lands centered in a `target` surface: the
+/// destination corner and the part of the frame that fits. Each axis is
+/// centered if the frame is smaller and center-cropped if it is larger.
+pub fn letterbox_placement(frame: (u32, u32), target: (u32, u32)) -> (u32, u32, D3D11_BOX) {
+    // (destination offset, first source texel, texels copied)
+    let axis = |frame: u32, target: u32| {
+        if frame <= target {
+            ((target - frame) / 2, 0, frame)
+        } else {
+            (0, (frame - target) / 2, target)
+        }
+    };
+    let (x, left, width) = axis(frame.0, target.0);
+    let (y, top, height) = axis(frame.1, target.1);
+    let src_box = D3D11_BOX {
+        left,
+        top,
+        front: 0,
+        right: left + width,
+        bottom: top + height,
+        back: 1,
+    };
+    (x, y, src_box)
+}
+
+/// Whether a `frame` (width, height) presented to a `display` of that size
+/// is left over from before a guest resize away from `stale`. A frame that
+/// matches the display clears `stale`; other sizes are letterboxed as usual.
+pub fn stale_frame_size(
+    frame: (u32, u32),
+    display: (u32, u32),
+    stale: &mut Option<(u32, u32)>,
+) -> bool {
+    if frame == display {
+        *stale = None;
+        return false;
+    }
+    *stale == Some(frame)
+}
+
+/// Clip a dirty rect to a `width` x `height` surface, or None if nothing
+/// of it remains
+pub fn clip_dirty_rect(rect: RECT, width: u32, height: u32) -> Option<RECT> {
+    let clipped = RECT {
+        left: rect.left.max(0),
+        top: rect.top.max(0),
+        right: rect.right.min(width as i32),
+        bottom: rect.bottom.min(height as i32),
+    };
+    if clipped.left < clipped.right && clipped.top < clipped.bottom {
+        Some(clipped)
+    } else {
+        None
+    }
+}
+
+/// Present a swapchain, passing dirty rects to the compositor when the
+/// guest supplied any.
+///
+/// FLIP_DISCARD does not preserve the backbuffer, so the rects are only a
+/// hint for composition; the guest still renders the full frame.
+fn present_swapchain(
+    swapchain: &IDXGISwapChain1,
+    sync_interval: u32,
+    present_flags: u32,
+    dirty_rects: &[RECT],
+) -> Result<PresentStatus> {
+    let hr = if dirty_rects.is_empty() {
+        unsafe { swapchain.Present(sync_interval, DXGI_PRESENT(present_flags)) }
+    } else {
+        let params = DXGI_PRESENT_PARAMETERS {
+            DirtyRectsCount: dirty_rects.len() as u32,
+            pDirtyRects: dirty_rects.as_ptr() as *mut RECT,
+            pScrollRect: std::ptr::null_mut(),
+            pScrollOffset: std::ptr::null_mut(),
+        };
+        unsafe { swapchain.Present1(sync_interval, DXGI_PRESENT(present_flags), &params) }
+    };
+    Ok(present_status(hr)?)
+}
+
+/// DXGI_PRESENT bits for the guest's PVGPU_PRESENT_* `flags`, given the
+/// host's own sync interval and present flags for the frame.
+///
+/// Unknown bits are dropped, as is DO_NOT_SEQUENCE when the frame is
+/// presented immediately (sync interval 0 or tearing), which DXGI rejects.
+pub fn guest_present_flags(flags: u32, sync_interval: u32, host_flags: u32) -> u32 {
+    let mut flags = flags & PVGPU_PRESENT_FLAGS_MASK;
+    if sync_interval == 0 || host_flags & DXGI_PRESENT_ALLOW_TEARING.0 != 0 {
+        flags &= !PVGPU_PRESENT_DO_NOT_SEQUENCE;
+    }
+    flags
+}
+
+/// Result of a swapchain present that did not fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentStatus {
+    /// The frame was queued for display
+    Presented,
+    /// The window is minimized or covered; nothing was shown
+    Occluded,
+    /// The GPU was still busy with an earlier frame; this one was dropped
+    StillDrawing,
+}
+
+/// Keyed mutex key the backend acquires the shared texture with; the
+/// consumer releases it with this key after reading a frame
+pub const SHARED_KEY_PRODUCER: u64 = 0;
+/// Keyed mutex key the backend releases the shared texture with after
+/// copying a frame; the consumer acquires it with this key
+pub const SHARED_KEY_CONSUMER: u64 = 1;
+
+/// Outcome of a non-blocking AcquireSync on the shared texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedAcquire {
+    Acquired,
+    /// Held by the other side, or released with the other key
+    Busy,
+    /// The holder exited without releasing it
+    Abandoned,
+}
+
+/// Classify an AcquireSync HRESULT. WAIT_TIMEOUT and WAIT_ABANDONED are
+/// success codes, so they must be told apart before `.ok()`.
+pub fn shared_acquire_status(hr: HRESULT) -> windows::core::Result<SharedAcquire> {
+    const TIMEOUT: HRESULT = HRESULT(WAIT_TIMEOUT.0 as i32);
+    const ABANDONED: HRESULT = HRESULT(WAIT_ABANDONED.0 as i32);
+    match hr {
+        TIMEOUT => Ok(SharedAcquire::Busy),
+        ABANDONED => Ok(SharedAcquire::Abandoned),
+        hr => hr.ok().map(|()| SharedAcquire::Acquired),
+    }
+}
+
+/// Classify a Present HRESULT. Occlusion and a busy GPU are transient;
+/// anything else that is not a success (e.g. device removal) is an error.
+pub fn present_status(hr: HRESULT) -> windows::core::Result<PresentStatus> {
+    match hr {
+        DXGI_STATUS_OCCLUDED => Ok(PresentStatus::Occluded),
+        DXGI_ERROR_WAS_STILL_DRAWING => Ok(PresentStatus::StillDrawing),
+        hr => hr.ok().map(|()| PresentStatus::Presented),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_present_status() {
+        use windows::Win32::Foundation::S_OK;
+        use windows::Win32::Graphics::Dxgi::DXGI_ERROR_DEVICE_REMOVED;
+
+        assert_eq!(present_status(S_OK).unwrap(), PresentStatus::Presented);
+        assert_eq!(
+            present_status(DXGI_STATUS_OCCLUDED).unwrap(),
+            PresentStatus::Occluded
+        );
+        assert_eq!(
+            present_status(DXGI_ERROR_WAS_STILL_DRAWING).unwrap(),
+            PresentStatus::StillDrawing
+        );
+        assert!(present_status(DXGI_ERROR_DEVICE_REMOVED).is_err());
+    }
+
+    #[test]
+    fn test_shared_acquire_status() {
+        use windows::Win32::Foundation::{E_INVALIDARG, S_OK};
+
+        assert_eq!(
+            shared_acquire_status(S_OK).unwrap(),
+            SharedAcquire::Acquired
+        );
+        assert_eq!(
+            shared_acquire_status(HRESULT(0x102)).unwrap(),
+            SharedAcquire::Busy
+        );
+        assert_eq!(
+            shared_acquire_status(HRESULT(0x80)).unwrap(),
+            SharedAcquire::Abandoned
+        );
+        assert!(shared_acquire_status(E_INVALIDARG).is_err());
+    }
+
+    #[test]
+    fn test_guest_present_flags() {
+        use crate::protocol::{PVGPU_PRESENT_DO_NOT_WAIT, PVGPU_PRESENT_RESTART};
+
+        let all = PVGPU_PRESENT_DO_NOT_SEQUENCE | PVGPU_PRESENT_RESTART | PVGPU_PRESENT_DO_NOT_WAIT;
+        assert_eq!(guest_present_flags(all | 0x100, 1, 0), all);
+        assert_eq!(
+            guest_present_flags(all, 0, 0),
+            PVGPU_PRESENT_RESTART | PVGPU_PRESENT_DO_NOT_WAIT
+        );
+        assert_eq!(
+            guest_present_flags(
+                PVGPU_PRESENT_DO_NOT_SEQUENCE,
+                1,
+                DXGI_PRESENT_ALLOW_TEARING.0
+            ),
+            0
+        );
+        // The values pass straight through to DXGI
+        assert_eq!(
+            PVGPU_PRESENT_DO_NOT_WAIT,
+            windows::Win32::Graphics::Dxgi::DXGI_PRESENT_DO_NOT_WAIT.0
+        );
+    }
+
+    #[test]
+    fn test_format_fallbacks() {
+        assert_eq!(
+            format_fallbacks(DXGI_FORMAT_R16G16B16A16_FLOAT),
+            vec![
+                DXGI_FORMAT_R16G16B16A16_FLOAT,
+                DXGI_FORMAT_R10G10B10A2_UNORM,
+                DXGI_FORMAT_R8G8B8A8_UNORM,
+            ]
+        );
+        assert_eq!(
+            format_fallbacks(DXGI_FORMAT_R10G10B10A2_UNORM),
+            vec![DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM]
+        );
+        assert_eq!(
+            format_fallbacks(DXGI_FORMAT_R8G8B8A8_UNORM),
+            vec![DXGI_FORMAT_R8G8B8A8_UNORM]
+        );
+    }
+
     #[test]
     fn test_presentation_config_default() {
         let config = PresentationConfig::default();
@@ -878,7 +2479,188 @@ mod tests {
         assert!(config.vsync);
         assert_eq!(config.mode, PresentationMode::Windowed);
         assert_eq!(config.buffer_count, 2);
+        assert!(!config.adaptive_buffering);
         assert!(!config.allow_tearing);
+        assert!(!config.host_backbuffer);
+        assert!(!config.srgb_backbuffer);
+        assert_eq!(config.crash_frame_ring, 0);
+        assert_eq!(config.refresh_rate, 60);
+        assert_eq!(config.format, DXGI_FORMAT_R8G8B8A8_UNORM);
+    }
+
+    #[test]
+    fn test_present_targets_cycle() {
+        let mut targets = PresentTargets::Both;
+        assert!(targets.window() && targets.shared());
+        targets = targets.next();
+        assert_eq!(targets, PresentTargets::Window);
+        assert!(targets.window() && !targets.shared());
+        targets = targets.next();
+        assert_eq!(targets, PresentTargets::Shared);
+        assert!(!targets.window() && targets.shared());
+        assert_eq!(targets.next(), PresentTargets::Both);
+    }
+
+    #[test]
+    fn test_adaptive_buffer_count() {
+        use std::time::Duration;
+
+        let steady = vec![Duration::from_micros(16_667); FRAME_TIME_SAMPLES];
+        assert_eq!(adaptive_buffer_count(&steady, 3), Some(2));
+        assert_eq!(adaptive_buffer_count(&steady, 2), None);
+
+        // Alternating 10ms / 25ms frames: ~43% jitter
+        let jittery: Vec<Duration> = (0..FRAME_TIME_SAMPLES)
+            .map(|i| Duration::from_millis(if i % 2 == 0 { 10 } else { 25 }))
+            .collect();
+        assert_eq!(adaptive_buffer_count(&jittery, 2), Some(3));
+        assert_eq!(adaptive_buffer_count(&jittery, 3), None);
+
+        // Between the thresholds the current count is kept either way
+        let moderate: Vec<Duration> = (0..FRAME_TIME_SAMPLES)
+            .map(|i| Duration::from_millis(if i % 2 == 0 { 14 } else { 19 }))
+            .collect();
+        assert_eq!(adaptive_buffer_count(&moderate, 2), None);
+        assert_eq!(adaptive_buffer_count(&moderate, 3), None);
+
+        // Other counts are left alone
+        assert_eq!(adaptive_buffer_count(&jittery, 4), None);
+        assert_eq!(adaptive_buffer_count(&[], 2), None);
+    }
+
+    #[test]
+    fn test_clip_dirty_rect() {
+        let rect = |left, top, right, bottom| RECT {
+            left,
+            top,
+            right,
+            bottom,
+        };
+
+        assert_eq!(
+            clip_dirty_rect(rect(10, 20, 110, 120), 1920, 1080),
+            Some(rect(10, 20, 110, 120))
+        );
+        // Partially off-surface rects are trimmed to the visible part
+        assert_eq!(
+            clip_dirty_rect(rect(-5, 1000, 2000, 1200), 1920, 1080),
+            Some(rect(0, 1000, 1920, 1080))
+        );
+        // Off-surface and inverted rects are dropped
+        assert_eq!(clip_dirty_rect(rect(1920, 0, 2000, 10), 1920, 1080), None);
+        assert_eq!(clip_dirty_rect(rect(50, 50, 40, 60), 1920, 1080), None);
+    }
+
+    #[test]
+    fn test_validate_display_config() {
+        let mode = DisplayConfig {
+            width: 2560,
+            height: 1440,
+            refresh_rate: 144,
+            format: DXGI_FORMAT_R10G10B10A2_UNORM,
+        };
+        assert!(validate_display_config(&mode).is_ok());
+
+        // Zero refresh/format keep the current values
+        let keep = DisplayConfig {
+            refresh_rate: 0,
+            format: DXGI_FORMAT(0),
+            ..mode
+        };
+        assert!(validate_display_config(&keep).is_ok());
+
+        let small = DisplayConfig { width: 100, ..mode };
+        assert!(validate_display_config(&small).is_err());
+        let huge = DisplayConfig {
+            height: 20000,
+            ..mode
+        };
+        assert!(validate_display_config(&huge).is_err());
+        let fast = DisplayConfig {
+            refresh_rate: 1000,
+            ..mode
+        };
+        assert!(validate_display_config(&fast).is_err());
+        // sRGB is applied through the view, never as a buffer format
+        let srgb = DisplayConfig {
+            format: windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+            ..mode
+        };
+        assert!(validate_display_config(&srgb).is_err());
+    }
+
+    #[test]
+    fn test_shared_format_for() {
+        use windows::Win32::Graphics::Dxgi::Common::{
+            DXGI_FORMAT_B8G8R8A8_TYPELESS, DXGI_FORMAT_B8G8R8A8_UNORM,
+        };
+
+        assert_eq!(
+            shared_format_for(DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM),
+            Some(DXGI_FORMAT_B8G8R8A8_UNORM)
+        );
+        assert_eq!(
+            shared_format_for(DXGI_FORMAT_B8G8R8A8_TYPELESS, DXGI_FORMAT_R8G8B8A8_UNORM),
+            Some(DXGI_FORMAT_B8G8R8A8_UNORM)
+        );
+        // Same family: copies already work
+        assert_eq!(
+            shared_format_for(
+                windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+                DXGI_FORMAT_R8G8B8A8_UNORM
+            ),
+            None
+        );
+        assert_eq!(
+            shared_format_for(DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM),
+            Some(DXGI_FORMAT_R10G10B10A2_UNORM)
+        );
+    }
+
+    #[test]
+    fn test_stale_frame_size() {
+        let old = (1280, 720);
+        let new = (1920, 1080);
+
+        // Present before the resize: the frame matches the swapchain
+        let mut stale = None;
+        assert!(!stale_frame_size(old, old, &mut stale));
+
+        // Resize, then a present of a backbuffer rendered at the old size
+        stale = Some(old);
+        assert!(stale_frame_size(old, new, &mut stale));
+        assert_eq!(stale, Some(old));
+
+        // Unrelated sizes are still letterboxed
+        assert!(!stale_frame_size((800, 600), new, &mut stale));
+
+        // The first frame at the new size ends the check
+        assert!(!stale_frame_size(new, new, &mut stale));
+        assert_eq!(stale, None);
+        assert!(!stale_frame_size(old, new, &mut stale));
+    }
+
+    #[test]
+    fn test_letterbox_placement() {
+        // 4:3 frame pillarboxed in a 16:9 output
+        let (x, y, src) = letterbox_placement((1440, 1080), (1920, 1080));
+        assert_eq!((x, y), (240, 0));
+        assert_eq!(
+            (src.left, src.top, src.right, src.bottom),
+            (0, 0, 1440, 1080)
+        );
+
+        // Oversized frame is center-cropped
+        let (x, y, src) = letterbox_placement((2000, 1000), (1920, 1080));
+        assert_eq!((x, y), (0, 40));
+        assert_eq!(
+            (src.left, src.top, src.right, src.bottom),
+            (40, 0, 1960, 1000)
+        );
+
+        let (x, y, src) = letterbox_placement((1920, 1080), (1920, 1080));
+        assert_eq!((x, y), (0, 0));
+        assert_eq!((src.right, src.bottom), (1920, 1080));
     }
 
     #[test]
@@ -886,5 +2668,31 @@ mod tests {
         let stats = FrameStats::default();
         assert_eq!(stats.fps, 0.0);
         assert_eq!(stats.frame_count, 0);
+        assert_eq!(stats.p99_frame_time_ms, 0.0);
+    }
+
+    #[test]
+    fn test_frame_time_histogram_percentiles() {
+        let ms = |v: u64| std::time::Duration::from_micros(v * 1000 + 50);
+        let mut histogram = FrameTimeHistogram::new();
+        assert_eq!(histogram.percentile_ms(0.99), 0.0);
+
+        // 98 smooth frames and two hitches
+        for _ in 0..98 {
+            histogram.record(ms(16));
+        }
+        histogram.record(ms(40));
+        histogram.record(ms(400));
+        assert_eq!(histogram.count(), 100);
+
+        assert!((histogram.percentile_ms(0.50) - 16.1).abs() < 1e-9);
+        assert!((histogram.percentile_ms(0.98) - 16.1).abs() < 1e-9);
+        assert!((histogram.percentile_ms(0.99) - 40.1).abs() < 1e-9);
+        // Past the last bucket the exact longest frame is reported
+        assert!((histogram.percentile_ms(1.0) - 400.05).abs() < 1e-9);
+
+        histogram.clear();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.percentile_ms(0.50), 0.0);
     }
 }
@@ -3,18 +3,22 @@
 //! Manual Rust bindings for the PVGPU protocol defined in pvgpu_protocol.h.
 //! These match the C structures for shared memory communication.
 
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
 
 /// Magic number: "PVGP" in little-endian
 pub const PVGPU_MAGIC: u32 = 0x50564750;
 pub const PVGPU_VERSION_MAJOR: u32 = 1;
-pub const PVGPU_VERSION_MINOR: u32 = 0;
+pub const PVGPU_VERSION_MINOR: u32 = 1;
 pub const PVGPU_VERSION: u32 = (PVGPU_VERSION_MAJOR << 16) | PVGPU_VERSION_MINOR;
 
 /// Default sizes
 pub const PVGPU_CONTROL_REGION_SIZE: usize = 0x1000; // 4KB
 pub const PVGPU_COMMAND_RING_SIZE: usize = 0x1000000; // 16MB
 pub const PVGPU_DEFAULT_SHMEM_SIZE: usize = 0x10000000; // 256MB
+pub const PVGPU_MIN_HEAP_SIZE: usize = 0x100000; // 1MB
+pub const PVGPU_MIN_SHMEM_SIZE: usize =
+    PVGPU_CONTROL_REGION_SIZE + PVGPU_COMMAND_RING_SIZE + PVGPU_MIN_HEAP_SIZE;
 
 // =============================================================================
 // Feature Flags
@@ -48,6 +52,19 @@ pub const PVGPU_CMD_UNMAP_RESOURCE: u32 = 0x0004;
 pub const PVGPU_CMD_UPDATE_RESOURCE: u32 = 0x0005;
 pub const PVGPU_CMD_COPY_RESOURCE: u32 = 0x0006;
 pub const PVGPU_CMD_OPEN_RESOURCE: u32 = 0x0007;
+pub const PVGPU_CMD_QUERY_MSAA_SUPPORT: u32 = 0x0008;
+pub const PVGPU_CMD_CHECK_FORMAT_SUPPORT: u32 = 0x0009;
+pub const PVGPU_CMD_COPY_STRUCTURE_COUNT: u32 = 0x000A;
+
+/// Sample counts QUERY_MSAA_SUPPORT reports on, in result order
+pub const PVGPU_MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+// State object creation commands: 0x0010 - 0x002F
+pub const PVGPU_CMD_CREATE_BLEND_STATE: u32 = 0x0010;
+
+// View creation commands: 0x0020 - 0x002F
+pub const PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW: u32 = 0x0026;
+pub const PVGPU_CMD_DESTROY_UNORDERED_ACCESS_VIEW: u32 = 0x0027;
 
 // State commands: 0x0100 - 0x01FF
 pub const PVGPU_CMD_SET_RENDER_TARGET: u32 = 0x0101;
@@ -64,6 +81,7 @@ pub const PVGPU_CMD_SET_INDEX_BUFFER: u32 = 0x010B;
 pub const PVGPU_CMD_SET_INPUT_LAYOUT: u32 = 0x010C;
 pub const PVGPU_CMD_SET_PRIMITIVE_TOPOLOGY: u32 = 0x010D;
 pub const PVGPU_CMD_SET_SHADER_RESOURCE: u32 = 0x010E;
+pub const PVGPU_CMD_SET_CONSTANT_BUFFERS: u32 = 0x010F;
 
 // Draw commands: 0x0200 - 0x02FF
 pub const PVGPU_CMD_DRAW: u32 = 0x0201;
@@ -73,10 +91,25 @@ pub const PVGPU_CMD_DRAW_INDEXED_INSTANCED: u32 = 0x0204;
 pub const PVGPU_CMD_DISPATCH: u32 = 0x0205;
 pub const PVGPU_CMD_CLEAR_RENDER_TARGET: u32 = 0x0206;
 pub const PVGPU_CMD_CLEAR_DEPTH_STENCIL: u32 = 0x0207;
+pub const PVGPU_CMD_DRAW_INSTANCED_INDIRECT: u32 = 0x0208;
+pub const PVGPU_CMD_DRAW_INDEXED_INSTANCED_INDIRECT: u32 = 0x0209;
+pub const PVGPU_CMD_CLEAR_RENDER_TARGET_UINT: u32 = 0x020A;
+
+/// CmdDispatch.thread_group_count_z value marking an indirect dispatch;
+/// x then holds the args buffer ID and y the byte offset
+pub const PVGPU_DISPATCH_INDIRECT: u32 = 0xFFFF_FFFF;
+
+/// D3D11 limit on thread groups per dispatch dimension
+pub const PVGPU_MAX_DISPATCH_THREAD_GROUPS: u32 = 65535;
+
+/// Total thread groups a single dispatch may launch with strict dispatch
+/// limits enabled
+pub const PVGPU_STRICT_DISPATCH_GROUPS: u64 = 1 << 22;
 
 // Shader commands: 0x0030 - 0x003F
 pub const PVGPU_CMD_CREATE_SHADER: u32 = 0x0030;
 pub const PVGPU_CMD_DESTROY_SHADER: u32 = 0x0031;
+pub const PVGPU_CMD_REPLACE_SHADER: u32 = 0x0032;
 
 // Sync commands: 0x0300 - 0x03FF
 pub const PVGPU_CMD_FENCE: u32 = 0x0301;
@@ -84,6 +117,45 @@ pub const PVGPU_CMD_PRESENT: u32 = 0x0302;
 pub const PVGPU_CMD_FLUSH: u32 = 0x0303;
 pub const PVGPU_CMD_WAIT_FENCE: u32 = 0x0304;
 pub const PVGPU_CMD_RESIZE_BUFFERS: u32 = 0x0305;
+pub const PVGPU_CMD_QUERY_FENCE: u32 = 0x0306;
+pub const PVGPU_CMD_RESET_DEVICE: u32 = 0x0307;
+pub const PVGPU_CMD_SET_DISPLAY_CONFIG: u32 = 0x0308;
+pub const PVGPU_CMD_BEGIN_FRAME: u32 = 0x0309;
+pub const PVGPU_CMD_SET_FRAME_LATENCY: u32 = 0x030A;
+pub const PVGPU_CMD_QUERY_DEVICE_STATUS: u32 = 0x030B;
+pub const PVGPU_CMD_SELECT_ADAPTER: u32 = 0x030C;
+pub const PVGPU_CMD_SHUTDOWN: u32 = 0x030D;
+
+/// CmdSelectAdapter.flags: pick the adapter by LUID instead of index
+pub const PVGPU_SELECT_ADAPTER_BY_LUID: u32 = 1 << 0;
+
+/// Largest frame latency DXGI accepts
+pub const PVGPU_MAX_FRAME_LATENCY: u32 = 16;
+
+/// Maximum fence values in a single WAIT_FENCE batch
+pub const PVGPU_MAX_WAIT_FENCES: u32 = 64;
+
+// Debug commands: 0x0400 - 0x04FF
+pub const PVGPU_CMD_BEGIN_EVENT: u32 = 0x0401;
+pub const PVGPU_CMD_END_EVENT: u32 = 0x0402;
+pub const PVGPU_CMD_SET_MARKER: u32 = 0x0403;
+pub const PVGPU_CMD_DUMP_FRAMES: u32 = 0x0404;
+pub const PVGPU_CMD_SELFTEST: u32 = 0x0405;
+pub const PVGPU_CMD_SET_LOG_LEVEL: u32 = 0x0406;
+pub const PVGPU_CMD_SET_DEBUG_NAME: u32 = 0x0407;
+
+// SET_LOG_LEVEL levels, most to least severe
+pub const PVGPU_LOG_LEVEL_ERROR: u32 = 1;
+pub const PVGPU_LOG_LEVEL_WARN: u32 = 2;
+pub const PVGPU_LOG_LEVEL_INFO: u32 = 3;
+pub const PVGPU_LOG_LEVEL_DEBUG: u32 = 4;
+pub const PVGPU_LOG_LEVEL_TRACE: u32 = 5;
+
+// SELFTEST result codes, written to the heap as a u64
+pub const PVGPU_SELFTEST_PASS: u32 = 0;
+pub const PVGPU_SELFTEST_FAIL_CREATE: u32 = 1;
+pub const PVGPU_SELFTEST_FAIL_READBACK: u32 = 2;
+pub const PVGPU_SELFTEST_FAIL_MISMATCH: u32 = 3;
 
 // =============================================================================
 // Error Codes
@@ -115,6 +187,7 @@ pub const PVGPU_STATUS_BACKEND_BUSY: u32 = 1 << 3;
 pub const PVGPU_STATUS_RESIZING: u32 = 1 << 4;
 pub const PVGPU_STATUS_RECOVERY: u32 = 1 << 5;
 pub const PVGPU_STATUS_SHUTDOWN: u32 = 1 << 6;
+pub const PVGPU_STATUS_MEMORY_PRESSURE: u32 = 1 << 7;
 
 // =============================================================================
 // Resource Types
@@ -122,7 +195,6 @@ pub const PVGPU_STATUS_SHUTDOWN: u32 = 1 << 6;
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum ResourceType {
     Texture1D = 1,
     Texture2D = 2,
@@ -147,7 +219,6 @@ pub enum ResourceType {
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum ShaderStage {
     Vertex = 0,
     Pixel = 1,
@@ -157,10 +228,208 @@ pub enum ShaderStage {
     Compute = 5,
 }
 
+impl TryFrom<u32> for ResourceType {
+    /// The unknown value
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, u32> {
+        Ok(match value {
+            1 => ResourceType::Texture1D,
+            2 => ResourceType::Texture2D,
+            3 => ResourceType::Texture3D,
+            4 => ResourceType::Buffer,
+            5 => ResourceType::VertexShader,
+            6 => ResourceType::PixelShader,
+            7 => ResourceType::GeometryShader,
+            8 => ResourceType::HullShader,
+            9 => ResourceType::DomainShader,
+            10 => ResourceType::ComputeShader,
+            11 => ResourceType::InputLayout,
+            12 => ResourceType::BlendState,
+            13 => ResourceType::RasterizerState,
+            14 => ResourceType::DepthStencilState,
+            15 => ResourceType::SamplerState,
+            16 => ResourceType::RenderTargetView,
+            17 => ResourceType::DepthStencilView,
+            18 => ResourceType::ShaderResourceView,
+            19 => ResourceType::UnorderedAccessView,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl ResourceType {
+    /// Stage of a shader resource type. CREATE_RESOURCE numbers shaders
+    /// from 5, CREATE_SHADER by ShaderStage from 0.
+    pub fn shader_stage(self) -> Option<ShaderStage> {
+        match self {
+            ResourceType::VertexShader => Some(ShaderStage::Vertex),
+            ResourceType::PixelShader => Some(ShaderStage::Pixel),
+            ResourceType::GeometryShader => Some(ShaderStage::Geometry),
+            ResourceType::HullShader => Some(ShaderStage::Hull),
+            ResourceType::DomainShader => Some(ShaderStage::Domain),
+            ResourceType::ComputeShader => Some(ShaderStage::Compute),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u32> for ShaderStage {
+    /// The unknown value
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, u32> {
+        Ok(match value {
+            0 => ShaderStage::Vertex,
+            1 => ShaderStage::Pixel,
+            2 => ShaderStage::Geometry,
+            3 => ShaderStage::Hull,
+            4 => ShaderStage::Domain,
+            5 => ShaderStage::Compute,
+            _ => return Err(value),
+        })
+    }
+}
+
 // =============================================================================
 // Control Region (matches C struct layout)
 // =============================================================================
 
+/// Number of records in the control region's error log
+pub const PVGPU_ERROR_LOG_ENTRIES: usize = 16;
+
+/// Bytes of the control region's diagnostic message buffer
+pub const PVGPU_DIAGNOSTIC_SIZE: usize = 512;
+
+/// One error log slot in the control region (matches PvgpuErrorRecord in C).
+#[repr(C)]
+struct ErrorLogSlot {
+    code: AtomicU32,
+    data: AtomicU32,
+    command_offset: AtomicU64,
+    timestamp_us: AtomicU64,
+}
+
+/// A copy of one error log record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorRecord {
+    /// PVGPU_ERROR_* code
+    pub code: u32,
+    /// Same meaning as `error_data`
+    pub data: u32,
+    /// Consumer pointer when the error was raised; the failing command's
+    /// position for command errors
+    pub command_offset: u64,
+    /// Host wall-clock time, microseconds since the Unix epoch
+    pub timestamp_us: u64,
+}
+
+/// Capability block layout version; 0 means the host has not written one
+pub const PVGPU_CAPS_VERSION: u32 = 2;
+
+/// Largest 2D texture width or height D3D11 allows
+pub const PVGPU_MAX_TEXTURE_DIMENSION: u32 = 16384;
+
+/// Display formats a capability block can list
+pub const PVGPU_CAPS_MAX_DISPLAY_FORMATS: usize = 8;
+
+// CapabilityBlock.output_modes bits
+/// Frames are shown in a host window
+pub const PVGPU_OUTPUT_WINDOW: u32 = 1 << 0;
+/// Frames are copied to a shared texture for streaming tools
+pub const PVGPU_OUTPUT_SHARED_TEXTURE: u32 = 1 << 1;
+/// PVGPU_HOST_BACKBUFFER_ID can be rendered to directly
+pub const PVGPU_OUTPUT_HOST_BACKBUFFER: u32 = 1 << 2;
+/// PVGPU_PREVIOUS_FRAME_ID holds the last presented frame
+pub const PVGPU_OUTPUT_PREVIOUS_FRAME: u32 = 1 << 3;
+/// The display supports tearing presents (variable refresh rate)
+pub const PVGPU_OUTPUT_TEARING: u32 = 1 << 4;
+
+/// What the host device can do, written into the control region before
+/// PVGPU_STATUS_READY is set and again when SELECT_ADAPTER switches the
+/// device (matches PvgpuCapabilityBlock in C).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityBlock {
+    /// PVGPU_CAPS_VERSION of the host that wrote it
+    pub version: u32,
+    /// size_of::<CapabilityBlock>() of the host that wrote it
+    pub size: u32,
+    /// D3D_FEATURE_LEVEL of the device
+    pub feature_level: u32,
+    pub max_texture_dimension: u32,
+    pub max_texture_array_size: u32,
+    /// Bit i set when PVGPU_MSAA_SAMPLE_COUNTS[i] samples work for R8G8B8A8_UNORM
+    pub msaa_sample_mask: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Dedicated VRAM in bytes
+    pub dedicated_video_memory: u64,
+    /// PVGPU_OUTPUT_* bits
+    pub output_modes: u32,
+    pub display_format_count: u32,
+    /// DXGI_FORMATs the swapchain can use, first `display_format_count` valid
+    pub display_formats: [u32; PVGPU_CAPS_MAX_DISPLAY_FORMATS],
+    /// Adapter name, UTF-16 and NUL-terminated
+    pub adapter_description: [u16; 128],
+    /// DXGI enumeration index of the adapter (version 2)
+    pub adapter_index: u32,
+    pub _reserved: u32,
+    /// Adapter LUID, low part in the low 32 bits (version 2)
+    pub adapter_luid: u64,
+}
+
+impl CapabilityBlock {
+    /// An empty block stamped with this host's version and size
+    pub fn new() -> Self {
+        Self {
+            version: PVGPU_CAPS_VERSION,
+            size: std::mem::size_of::<Self>() as u32,
+            feature_level: 0,
+            max_texture_dimension: 0,
+            max_texture_array_size: 0,
+            msaa_sample_mask: 0,
+            vendor_id: 0,
+            device_id: 0,
+            dedicated_video_memory: 0,
+            output_modes: 0,
+            display_format_count: 0,
+            display_formats: [0; PVGPU_CAPS_MAX_DISPLAY_FORMATS],
+            adapter_description: [0; 128],
+            adapter_index: 0,
+            _reserved: 0,
+            adapter_luid: 0,
+        }
+    }
+
+    /// Store `description`, truncated to leave room for the terminator
+    pub fn set_adapter_description(&mut self, description: &str) {
+        self.adapter_description = [0; 128];
+        let limit = self.adapter_description.len() - 1;
+        for (slot, unit) in self
+            .adapter_description
+            .iter_mut()
+            .zip(description.encode_utf16().take(limit))
+        {
+            *slot = unit;
+        }
+    }
+
+    /// Store up to PVGPU_CAPS_MAX_DISPLAY_FORMATS display formats
+    pub fn set_display_formats(&mut self, formats: &[u32]) {
+        let count = formats.len().min(PVGPU_CAPS_MAX_DISPLAY_FORMATS);
+        self.display_formats = [0; PVGPU_CAPS_MAX_DISPLAY_FORMATS];
+        self.display_formats[..count].copy_from_slice(&formats[..count]);
+        self.display_format_count = count as u32;
+    }
+}
+
+impl Default for CapabilityBlock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Control Region at offset 0 of shared memory.
 ///
 /// SAFETY: This struct must match the exact memory layout of PvgpuControlRegion in C.
@@ -205,16 +474,58 @@ pub struct ControlRegion {
     status: AtomicU32,
     error_code: AtomicU32,
     error_data: AtomicU32,
-    _reserved1: u32,
+    // Bumped when the host resizes the display on its own (window resize)
+    host_resize_sequence: AtomicU32,
 
     // Display configuration - 0x130
-    pub display_width: u32,
-    pub display_height: u32,
-    pub display_refresh: u32,
-    pub display_format: u32,
-
-    // Reserved - 0x140 to 0xFFF
-    _reserved: [u8; 0xEC0],
+    display_width: AtomicU32,
+    display_height: AtomicU32,
+    display_refresh: AtomicU32,
+    display_format: AtomicU32,
+
+    // Host-owned presentation - 0x140
+    host_backbuffer_id: AtomicU32,
+    previous_frame_id: AtomicU32,
+
+    // Error log - 0x148
+    // The host writes record `producer % PVGPU_ERROR_LOG_ENTRIES` and then
+    // advances the producer; the guest advances the consumer as it reads
+    error_log_producer: AtomicU32,
+    error_log_consumer: AtomicU32,
+    error_log: [ErrorLogSlot; PVGPU_ERROR_LOG_ENTRIES],
+
+    // Capabilities - 0x2D0, written before READY and on adapter switches.
+    // UnsafeCell (same layout) so the host can write it through &self.
+    capabilities: UnsafeCell<CapabilityBlock>,
+
+    // Diagnostic message - 0x430, the driver's reason for the last shader
+    // compile error. The host writes the message, then bumps the sequence.
+    diagnostic_sequence: AtomicU32,
+    diagnostic_resource_id: AtomicU32,
+    diagnostic_length: AtomicU32,
+    _reserved2: u32,
+    diagnostic: [AtomicU8; PVGPU_DIAGNOSTIC_SIZE],
+
+    // Video memory - 0x640, refreshed periodically by the host
+    /// DXGI local memory budget of the process, in bytes
+    vram_budget: AtomicU64,
+    /// DXGI local memory in use by the process, in bytes
+    vram_usage: AtomicU64,
+    /// Estimated bytes of the guest's live resources
+    vram_tracked: AtomicU64,
+    _reserved3: u64,
+
+    // Format support - 0x660, answered by CHECK_FORMAT_SUPPORT. The host
+    // writes the answer, then bumps the sequence.
+    format_query_sequence: AtomicU32,
+    /// DXGI_FORMAT the answer is for
+    format_query_format: AtomicU32,
+    /// D3D11_FORMAT_SUPPORT bits (0 = unsupported)
+    format_support: AtomicU32,
+    _reserved4: u32,
+
+    // Reserved - 0x670 to 0xFFF
+    _reserved: [u8; 0x990],
 }
 
 impl ControlRegion {
@@ -238,6 +549,15 @@ impl ControlRegion {
         }
     }
 
+    /// Set producer pointer atomically (the guest's side, for tests).
+    #[cfg(test)]
+    pub fn set_producer_ptr(&self, value: u64) {
+        unsafe {
+            let ptr = &self.producer_ptr_raw as *const u64 as *const AtomicU64;
+            (*ptr).store(value, Ordering::Release);
+        }
+    }
+
     /// Get consumer pointer atomically.
     pub fn consumer_ptr(&self) -> u64 {
         unsafe {
@@ -305,13 +625,56 @@ impl ControlRegion {
     }
 
     /// Set error code and data, also sets the ERROR status flag.
+    ///
+    /// The error is also appended to the error log, stamped with the current
+    /// consumer pointer and time.
     pub fn set_error(&self, code: u32, data: u32) {
         self.error_code.store(code, Ordering::Release);
         self.error_data.store(data, Ordering::Release);
+        let timestamp_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        self.push_error_record(code, data, self.consumer_ptr(), timestamp_us);
         // Also set error status flag
         self.set_status_flag(PVGPU_STATUS_ERROR);
     }
 
+    /// Append a record to the error log (called by host only).
+    ///
+    /// The oldest record is overwritten once the guest falls
+    /// PVGPU_ERROR_LOG_ENTRIES records behind.
+    pub fn push_error_record(&self, code: u32, data: u32, command_offset: u64, timestamp_us: u64) {
+        let index = self.error_log_producer.load(Ordering::Relaxed);
+        let slot = &self.error_log[index as usize % PVGPU_ERROR_LOG_ENTRIES];
+        slot.code.store(code, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.command_offset.store(command_offset, Ordering::Relaxed);
+        slot.timestamp_us.store(timestamp_us, Ordering::Relaxed);
+        self.error_log_producer
+            .store(index.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Number of error records ever written (wraps at u32::MAX).
+    pub fn error_log_producer(&self) -> u32 {
+        self.error_log_producer.load(Ordering::Acquire)
+    }
+
+    /// Number of error records the guest has read.
+    pub fn error_log_consumer(&self) -> u32 {
+        self.error_log_consumer.load(Ordering::Acquire)
+    }
+
+    /// Read the record with the given sequence number.
+    pub fn error_record(&self, index: u32) -> ErrorRecord {
+        let slot = &self.error_log[index as usize % PVGPU_ERROR_LOG_ENTRIES];
+        ErrorRecord {
+            code: slot.code.load(Ordering::Acquire),
+            data: slot.data.load(Ordering::Acquire),
+            command_offset: slot.command_offset.load(Ordering::Acquire),
+            timestamp_us: slot.timestamp_us.load(Ordering::Acquire),
+        }
+    }
+
     /// Get current error code.
     pub fn get_error_code(&self) -> u32 {
         self.error_code.load(Ordering::Acquire)
@@ -322,6 +685,20 @@ impl ControlRegion {
         self.error_data.load(Ordering::Acquire)
     }
 
+    /// Snapshot status, error and completed fence together.
+    ///
+    /// Only the command thread writes these fields, so a snapshot taken on
+    /// it cannot interleave with a host update, unlike separate guest reads.
+    pub fn device_status(&self) -> DeviceStatus {
+        DeviceStatus {
+            status: self.get_status(),
+            error_code: self.get_error_code(),
+            error_data: self.get_error_data(),
+            _reserved: 0,
+            host_fence_completed: self.host_fence_completed(),
+        }
+    }
+
     /// Clear error state (sets error code and data to 0, clears ERROR flag).
     pub fn clear_error(&self) {
         self.error_code.store(0, Ordering::Release);
@@ -329,6 +706,144 @@ impl ControlRegion {
         self.clear_status_flag(PVGPU_STATUS_ERROR);
     }
 
+    /// Publish a UTF-8 diagnostic message about `resource_id`, truncated at
+    /// a character boundary to fit with a NUL terminator
+    pub fn set_diagnostic(&self, resource_id: u32, message: &str) {
+        let mut len = message.len().min(PVGPU_DIAGNOSTIC_SIZE - 1);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        for (slot, &byte) in self.diagnostic.iter().zip(&message.as_bytes()[..len]) {
+            slot.store(byte, Ordering::Relaxed);
+        }
+        self.diagnostic[len].store(0, Ordering::Relaxed);
+        self.diagnostic_resource_id
+            .store(resource_id, Ordering::Relaxed);
+        self.diagnostic_length.store(len as u32, Ordering::Relaxed);
+        self.diagnostic_sequence.fetch_add(1, Ordering::Release);
+    }
+
+    /// Number of diagnostic messages published (0 = none yet)
+    pub fn diagnostic_sequence(&self) -> u32 {
+        self.diagnostic_sequence.load(Ordering::Acquire)
+    }
+
+    /// The last diagnostic message and the resource it is about
+    pub fn diagnostic(&self) -> (u32, String) {
+        let len =
+            (self.diagnostic_length.load(Ordering::Acquire) as usize).min(PVGPU_DIAGNOSTIC_SIZE);
+        let bytes: Vec<u8> = self.diagnostic[..len]
+            .iter()
+            .map(|byte| byte.load(Ordering::Relaxed))
+            .collect();
+        (
+            self.diagnostic_resource_id.load(Ordering::Acquire),
+            String::from_utf8_lossy(&bytes).into_owned(),
+        )
+    }
+
+    /// Publish the video memory budget, the usage DXGI reports and the
+    /// usage tracked for the guest's resources, all in bytes.
+    pub fn set_memory_usage(&self, budget: u64, usage: u64, tracked: u64) {
+        self.vram_budget.store(budget, Ordering::Release);
+        self.vram_usage.store(usage, Ordering::Release);
+        self.vram_tracked.store(tracked, Ordering::Release);
+    }
+
+    /// Get the published (budget, usage, tracked) video memory figures.
+    pub fn memory_usage(&self) -> (u64, u64, u64) {
+        (
+            self.vram_budget.load(Ordering::Acquire),
+            self.vram_usage.load(Ordering::Acquire),
+            self.vram_tracked.load(Ordering::Acquire),
+        )
+    }
+
+    /// Publish the D3D11_FORMAT_SUPPORT bits of `format`
+    pub fn set_format_support(&self, format: u32, support: u32) {
+        self.format_query_format.store(format, Ordering::Relaxed);
+        self.format_support.store(support, Ordering::Relaxed);
+        self.format_query_sequence.fetch_add(1, Ordering::Release);
+    }
+
+    /// Number of format queries answered, and the last (format, support)
+    pub fn format_support(&self) -> (u32, u32, u32) {
+        (
+            self.format_query_sequence.load(Ordering::Acquire),
+            self.format_query_format.load(Ordering::Relaxed),
+            self.format_support.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Write the capability block; call before setting PVGPU_STATUS_READY.
+    ///
+    /// # Safety
+    /// No reference from `capabilities()` may be live, and only one thread
+    /// may write the block at a time.
+    pub unsafe fn set_capabilities(&self, capabilities: CapabilityBlock) {
+        *self.capabilities.get() = capabilities;
+        std::sync::atomic::fence(Ordering::Release);
+    }
+
+    /// Get the capability block.
+    pub fn capabilities(&self) -> &CapabilityBlock {
+        // SAFETY: the block is only replaced by `set_capabilities`, whose
+        // caller guarantees no reference is live
+        unsafe { &*self.capabilities.get() }
+    }
+
+    /// Publish the host-owned backbuffer resource ID (0 = none).
+    pub fn set_host_backbuffer_id(&self, id: u32) {
+        self.host_backbuffer_id.store(id, Ordering::Release);
+    }
+
+    /// Get the host-owned backbuffer resource ID.
+    pub fn host_backbuffer_id(&self) -> u32 {
+        self.host_backbuffer_id.load(Ordering::Acquire)
+    }
+
+    /// Publish the previous-frame copy's resource ID (0 = none).
+    pub fn set_previous_frame_id(&self, id: u32) {
+        self.previous_frame_id.store(id, Ordering::Release);
+    }
+
+    /// Get the previous-frame copy's resource ID.
+    pub fn previous_frame_id(&self) -> u32 {
+        self.previous_frame_id.load(Ordering::Acquire)
+    }
+
+    /// Publish the current display configuration.
+    ///
+    /// Fields are stored one at a time; the guest should ignore them while
+    /// PVGPU_STATUS_RESIZING is set.
+    pub fn set_display_config(&self, width: u32, height: u32, refresh: u32, format: u32) {
+        self.display_width.store(width, Ordering::Release);
+        self.display_height.store(height, Ordering::Release);
+        self.display_refresh.store(refresh, Ordering::Release);
+        self.display_format.store(format, Ordering::Release);
+    }
+
+    /// Tell the guest the host changed the display size on its own; call
+    /// after publishing the new configuration.
+    pub fn notify_host_resize(&self) {
+        self.host_resize_sequence.fetch_add(1, Ordering::Release);
+    }
+
+    /// Number of host-initiated display size changes.
+    pub fn host_resize_sequence(&self) -> u32 {
+        self.host_resize_sequence.load(Ordering::Acquire)
+    }
+
+    /// Get the published display configuration (width, height, refresh, format).
+    pub fn display_config(&self) -> (u32, u32, u32, u32) {
+        (
+            self.display_width.load(Ordering::Acquire),
+            self.display_height.load(Ordering::Acquire),
+            self.display_refresh.load(Ordering::Acquire),
+            self.display_format.load(Ordering::Acquire),
+        )
+    }
+
     /// Check if device is in ready state.
     pub fn is_ready(&self) -> bool {
         (self.get_status() & PVGPU_STATUS_READY) != 0
@@ -363,10 +878,61 @@ pub struct CommandHeader {
 
 pub const PVGPU_CMD_HEADER_SIZE: usize = std::mem::size_of::<CommandHeader>();
 
+/// Reserved resource ID for the host-owned backbuffer. The guest renders into
+/// it directly and presents it without a copy.
+pub const PVGPU_HOST_BACKBUFFER_ID: u32 = 0xFFFF_FFFF;
+
+/// Reserved resource ID for the host's copy of the last presented frame
+pub const PVGPU_PREVIOUS_FRAME_ID: u32 = 0xFFFF_FFFE;
+
+/// CmdCreateResource.misc_flags bit: create a UNORM texture's default render
+/// target and shader resource views with the matching `_SRGB` format
+pub const PVGPU_RESOURCE_MISC_SRGB_VIEW: u32 = 1 << 31;
+
+/// CmdCreateResource.misc_flags bit: the 2D texture array (`depth` slices,
+/// a multiple of 6) is a cube map or cube map array. Same value as
+/// D3D11_RESOURCE_MISC_TEXTURECUBE.
+pub const PVGPU_RESOURCE_MISC_TEXTURECUBE: u32 = 1 << 2;
+
+/// CmdCreateResource.misc_flags bit: the buffer holds arguments for
+/// DRAW_INSTANCED_INDIRECT, DRAW_INDEXED_INSTANCED_INDIRECT or
+/// DISPATCH_INDIRECT. Same value as D3D11_RESOURCE_MISC_DRAWINDIRECT_ARGS.
+pub const PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS: u32 = 1 << 4;
+
+/// CmdCreateResource.misc_flags bit: the buffer may be viewed as raw bytes
+/// (ByteAddressBuffer). Same value as D3D11_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS.
+pub const PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS: u32 = 1 << 5;
+
+/// CmdCreateResource.misc_flags bit: the buffer is a structured buffer of
+/// `height`-byte elements. Same value as D3D11_RESOURCE_MISC_BUFFER_STRUCTURED.
+pub const PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED: u32 = 1 << 6;
+
+/// Largest structured buffer element D3D11 accepts
+pub const PVGPU_MAX_STRUCTURE_STRIDE: u32 = 2048;
+
+/// CmdOpenResource.misc_flags bit: `shared_handle` is an NT handle, valid in
+/// the backend process, to a texture created with
+/// D3D11_RESOURCE_MISC_SHARED_NTHANDLE (same value). Without it,
+/// `shared_handle` is the ID of a resource the backend already holds.
+pub const PVGPU_RESOURCE_MISC_SHARED_NTHANDLE: u32 = 1 << 11;
+
+/// D3D11 limit on 2D texture array slices
+pub const PVGPU_MAX_TEXTURE_ARRAY_SIZE: u32 = 2048;
+
+/// Whether `array_size` slices of `width`x`height` make a valid 2D texture
+/// array, or cube map (array) when `cube`
+pub fn texture_array_valid(width: u32, height: u32, array_size: u32, cube: bool) -> bool {
+    if array_size == 0 || array_size > PVGPU_MAX_TEXTURE_ARRAY_SIZE {
+        return false;
+    }
+    !cube || (array_size.is_multiple_of(6) && width == height)
+}
+
 // Command flags
-#[allow(dead_code)]
+/// Wait for the GPU to finish the command, then publish the fence and raise
+/// an IRQ before processing the next one
 pub const PVGPU_CMD_FLAG_SYNC: u32 = 1 << 0;
-#[allow(dead_code)]
+/// Don't publish the completed fence or raise an IRQ after this command
 pub const PVGPU_CMD_FLAG_NO_FENCE: u32 = 1 << 1;
 
 // =============================================================================
@@ -389,8 +955,16 @@ pub struct CmdCreateResource {
     pub misc_flags: u32,
     pub heap_offset: u32,
     pub data_size: u32,
+    pub usage: u32, // PVGPU_USAGE_*; commands that end before it read as DEFAULT
+    pub _reserved: u32,
 }
 
+/// CmdCreateResource.usage values (same as D3D11_USAGE)
+pub const PVGPU_USAGE_DEFAULT: u32 = 0;
+/// Contents fixed by the initial data; updates, write maps and copies into
+/// the resource are rejected
+pub const PVGPU_USAGE_IMMUTABLE: u32 = 1;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdOpenResource {
@@ -412,6 +986,33 @@ pub struct CmdSetRenderTarget {
     pub num_rtvs: u32,
     pub dsv_id: u32,
     pub rtv_ids: [u32; 8],
+    /// PVGPU_DSV_READ_ONLY_* bits
+    pub dsv_flags: u32,
+    /// First UAV slot; must be at least `num_rtvs`
+    pub uav_start_slot: u32,
+    /// UAVs bound from `uav_start_slot` (0 = render targets only)
+    pub num_uavs: u32,
+    pub uav_ids: [u32; PVGPU_MAX_OM_UAVS as usize],
+}
+
+/// Render target and UAV slots shared by the output merger
+pub const PVGPU_MAX_OM_UAVS: u32 = 8;
+
+/// CmdSetRenderTarget.dsv_flags bits: bind a view of the depth and/or
+/// stencil plane that shaders may sample while it is bound. Same values as
+/// D3D11_DSV_READ_ONLY_DEPTH / _STENCIL.
+pub const PVGPU_DSV_READ_ONLY_DEPTH: u32 = 1 << 0;
+pub const PVGPU_DSV_READ_ONLY_STENCIL: u32 = 1 << 1;
+
+/// Whether `num_rtvs` render targets and `num_uavs` UAVs starting at
+/// `uav_start_slot` fit the output merger without overlapping
+pub fn om_slots_valid(num_rtvs: u32, uav_start_slot: u32, num_uavs: u32) -> bool {
+    num_rtvs <= PVGPU_MAX_OM_UAVS
+        && (num_uavs == 0
+            || (uav_start_slot >= num_rtvs
+                && uav_start_slot
+                    .checked_add(num_uavs)
+                    .is_some_and(|end| end <= PVGPU_MAX_OM_UAVS)))
 }
 
 #[repr(C)]
@@ -425,6 +1026,24 @@ pub struct Viewport {
     pub max_depth: f32,
 }
 
+/// Maximum viewports / scissor rects per command (D3D11 limit)
+pub const PVGPU_MAX_VIEWPORTS: u32 = 16;
+
+impl Viewport {
+    /// Whether the viewport has a usable size and depth range
+    pub fn is_valid(&self) -> bool {
+        self.width > 0.0
+            && self.height > 0.0
+            && self.x.is_finite()
+            && self.y.is_finite()
+            && self.width.is_finite()
+            && self.height.is_finite()
+            && (0.0..=1.0).contains(&self.min_depth)
+            && (0.0..=1.0).contains(&self.max_depth)
+            && self.min_depth <= self.max_depth
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetViewport {
@@ -451,6 +1070,10 @@ pub struct CmdCreateShader {
     pub bytecode_offset: u32,
 }
 
+/// Replaces the bytecode of an existing shader, keeping its ID. Same layout
+/// as CmdCreateShader; `shader_type` must match the shader being replaced.
+pub type CmdReplaceShader = CmdCreateShader;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdDestroyShader {
@@ -485,16 +1108,130 @@ pub struct CmdFence {
     pub fence_value: u64,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdWaitFence {
+    pub header: CommandHeader,
+    pub fence_count: u32, // Number of u64 fence values
+    pub heap_offset: u32, // Heap offset of the fence value array
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdQueryFence {
+    pub header: CommandHeader,
+    pub heap_offset: u32, // Heap offset (8-byte aligned) to receive host_fence_completed
+    pub _reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdQueryDeviceStatus {
+    pub header: CommandHeader,
+    pub heap_offset: u32, // Heap offset (8-byte aligned) to receive DeviceStatus
+    pub _reserved: u32,
+}
+
+/// Written to the heap at CmdQueryDeviceStatus.heap_offset.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceStatus {
+    pub status: u32,
+    pub error_code: u32,
+    pub error_data: u32,
+    pub _reserved: u32,
+    pub host_fence_completed: u64,
+}
+
+impl DeviceStatus {
+    /// Heap representation as little-endian u64 words, in struct order
+    pub fn to_u64s(self) -> [u64; 3] {
+        let pair = |low: u32, high: u32| (high as u64) << 32 | low as u64;
+        [
+            pair(self.status, self.error_code),
+            pair(self.error_data, self._reserved),
+            self.host_fence_completed,
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdResetDevice {
+    pub header: CommandHeader,
+    pub fence_value: u64, // Fence signalled once the reset is done (0 = none)
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdShutdown {
+    pub header: CommandHeader,
+    pub fence_value: u64, // Fence signalled once earlier work has finished (0 = none)
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSelectAdapter {
+    pub header: CommandHeader,
+    pub adapter_index: u32, // DXGI adapter index, unless BY_LUID is set
+    pub flags: u32,         // PVGPU_SELECT_ADAPTER_*
+    pub adapter_luid: u64,  // Adapter LUID with PVGPU_SELECT_ADAPTER_BY_LUID
+    pub fence_value: u64,   // Fence signalled once the switch is done (0 = none)
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSetDisplayConfig {
+    pub header: CommandHeader,
+    pub width: u32,        // New display width in pixels
+    pub height: u32,       // New display height in pixels
+    pub refresh_rate: u32, // Refresh rate in Hz (0 = keep current)
+    pub format: u32,       // DXGI_FORMAT (0 = keep current)
+}
+
+/// Opens a frame; the next PRESENT closes it. Once a guest sends BEGIN_FRAME,
+/// presents outside a BEGIN_FRAME/PRESENT pair are dropped.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdBeginFrame {
+    pub header: CommandHeader,
+    pub frame_id: u64,    // Guest frame counter, for logging
+    pub fence_value: u64, // Fence completed once the frame is presented or dropped
+}
+
+/// Sets how many frames the driver may queue ahead of the GPU
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSetFrameLatency {
+    pub header: CommandHeader,
+    pub max_frame_latency: u32, // 1..=16 frames (0 = driver default of 3)
+    pub _reserved: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdPresent {
     pub header: CommandHeader,
     pub backbuffer_id: u32,
     pub sync_interval: u32,
-    pub flags: u32,
-    pub _reserved: u32,
+    pub flags: u32,              // PVGPU_PRESENT_* bits
+    pub dirty_rects_offset: u32, // Heap offset of a dirty rect list (0 = whole frame)
 }
 
+// CmdPresent.flags bits, equal to the DXGI_PRESENT flags they request
+/// Show the frame without advancing the present sequence (synced presents only)
+pub const PVGPU_PRESENT_DO_NOT_SEQUENCE: u32 = 0x2;
+/// Discard presents still queued before this one
+pub const PVGPU_PRESENT_RESTART: u32 = 0x4;
+/// Drop the frame instead of blocking when the present queue is full
+pub const PVGPU_PRESENT_DO_NOT_WAIT: u32 = 0x8;
+/// Present flags the host honours; other bits are ignored
+pub const PVGPU_PRESENT_FLAGS_MASK: u32 =
+    PVGPU_PRESENT_DO_NOT_SEQUENCE | PVGPU_PRESENT_RESTART | PVGPU_PRESENT_DO_NOT_WAIT;
+
+/// Maximum dirty rects per present; longer lists present the whole frame
+pub const PVGPU_MAX_DIRTY_RECTS: u32 = 64;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdClearRenderTarget {
@@ -503,6 +1240,16 @@ pub struct CmdClearRenderTarget {
     pub color: [f32; 4],
 }
 
+/// Exact clear of an integer (UINT/SINT) render target; SINT values are
+/// passed as their two's complement bits
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdClearRenderTargetUint {
+    pub header: CommandHeader,
+    pub rtv_id: u32,
+    pub color: [u32; 4],
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct VertexBufferBinding {
@@ -541,6 +1288,27 @@ pub struct CmdSetConstantBuffer {
     pub size: u32,
 }
 
+/// D3D11 constant buffer slots per shader stage
+pub const PVGPU_MAX_CONSTANT_BUFFERS: u32 = 14;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSetConstantBuffers {
+    pub header: CommandHeader,
+    pub stage: u32, // ShaderStage enum
+    pub start_slot: u32,
+    pub num_buffers: u32,
+    pub buffer_ids: [u32; PVGPU_MAX_CONSTANT_BUFFERS as usize],
+}
+
+/// Whether `count` constant buffers starting at `start_slot` fit in a stage's
+/// slots
+pub fn constant_buffer_range_valid(start_slot: u32, count: u32) -> bool {
+    start_slot
+        .checked_add(count)
+        .is_some_and(|end| end <= PVGPU_MAX_CONSTANT_BUFFERS)
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetInputLayout {
@@ -557,6 +1325,24 @@ pub struct CmdSetPrimitiveTopology {
     pub _reserved: [u32; 3],
 }
 
+/// D3D11_PRIMITIVE_TOPOLOGY_1_CONTROL_POINT_PATCHLIST; a patch list with N
+/// control points is this plus N - 1
+pub const PVGPU_TOPOLOGY_PATCHLIST_1: u32 = 33;
+pub const PVGPU_MAX_PATCH_CONTROL_POINTS: u32 = 32;
+
+/// Whether `topology` is a D3D11_PRIMITIVE_TOPOLOGY value: undefined, the
+/// point/line/triangle lists and strips, their adjacency variants, or a
+/// patch list
+pub fn is_valid_topology(topology: u32) -> bool {
+    matches!(topology, 0..=5 | 10..=13) || patch_control_points(topology).is_some()
+}
+
+/// Control points per patch of a patch list topology
+pub fn patch_control_points(topology: u32) -> Option<u32> {
+    let points = topology.checked_sub(PVGPU_TOPOLOGY_PATCHLIST_1)? + 1;
+    (points <= PVGPU_MAX_PATCH_CONTROL_POINTS).then_some(points)
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetSamplers {
@@ -567,6 +1353,9 @@ pub struct CmdSetSamplers {
     pub sampler_ids: [u32; 16],
 }
 
+/// D3D11 shader resource slots per shader stage
+pub const PVGPU_MAX_SHADER_RESOURCES: u32 = 128;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetShaderResources {
@@ -586,6 +1375,92 @@ pub struct CmdSetBlendState {
     pub sample_mask: u32,
 }
 
+/// D3D11 limit on render targets, each of which can blend differently
+pub const PVGPU_MAX_BLEND_TARGETS: u32 = 8;
+
+/// One render target's blend, as D3D11 enum values
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderTargetBlend {
+    pub blend_enable: u32,
+    pub src_blend: u32,
+    pub dest_blend: u32,
+    pub blend_op: u32,
+    pub src_blend_alpha: u32,
+    pub dest_blend_alpha: u32,
+    pub blend_op_alpha: u32,
+    pub write_mask: u32, // D3D11_COLOR_WRITE_ENABLE bits
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdCreateBlendState {
+    pub header: CommandHeader,
+    pub state_id: u32,
+    pub alpha_to_coverage: u32,
+    /// Blend each target with its own description rather than the first's
+    pub independent_blend: u32,
+    pub render_targets: [RenderTargetBlend; PVGPU_MAX_BLEND_TARGETS as usize],
+    /// Render targets described, 1-8; older guests send the command without
+    /// it and describe all 8 (0)
+    pub num_targets: u32,
+    /// D3D11.1 logic op for every target, instead of blending; requires
+    /// `independent_blend` off
+    pub logic_op_enable: u32,
+    pub logic_op: u32, // D3D11_LOGIC_OP
+    pub _reserved: u32,
+}
+
+impl CmdCreateBlendState {
+    /// Descriptions D3D11 uses: the first `num_targets` with independent
+    /// blend, otherwise only the first
+    pub fn used_targets(&self) -> &[RenderTargetBlend] {
+        let count = if self.independent_blend != 0 {
+            self.num_targets.clamp(1, PVGPU_MAX_BLEND_TARGETS)
+        } else {
+            1
+        };
+        &self.render_targets[..count as usize]
+    }
+}
+
+/// Whether `blend` is a D3D11_BLEND value
+fn is_valid_blend(blend: u32) -> bool {
+    matches!(blend, 1..=11 | 14..=19)
+}
+
+/// Whether `op` is a D3D11_BLEND_OP value
+fn is_valid_blend_op(op: u32) -> bool {
+    matches!(op, 1..=5)
+}
+
+/// Whether D3D11 accepts `cmd` (with `num_targets` already defaulted): 1 to
+/// 8 targets, valid factors and ops wherever blending is enabled, and a
+/// logic op only without independent blend or blending
+pub fn blend_state_valid(cmd: &CmdCreateBlendState) -> bool {
+    if cmd.num_targets == 0 || cmd.num_targets > PVGPU_MAX_BLEND_TARGETS {
+        return false;
+    }
+    let targets_valid = cmd.used_targets().iter().all(|target| {
+        let factors = [
+            target.src_blend,
+            target.dest_blend,
+            target.src_blend_alpha,
+            target.dest_blend_alpha,
+        ];
+        target.write_mask <= 0xF
+            && (target.blend_enable == 0
+                || (factors.into_iter().all(is_valid_blend)
+                    && is_valid_blend_op(target.blend_op)
+                    && is_valid_blend_op(target.blend_op_alpha)))
+    });
+    let logic_op_valid = cmd.logic_op_enable == 0
+        || (cmd.logic_op <= 15
+            && cmd.independent_blend == 0
+            && cmd.render_targets[0].blend_enable == 0);
+    targets_valid && logic_op_valid
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetRasterizerState {
@@ -612,6 +1487,13 @@ pub struct ScissorRect {
     pub bottom: i32,
 }
 
+impl ScissorRect {
+    /// Whether the rect encloses no pixels
+    pub fn is_empty(&self) -> bool {
+        self.right <= self.left || self.bottom <= self.top
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdSetScissor {
@@ -642,6 +1524,15 @@ pub struct CmdDrawIndexedInstanced {
     pub _reserved: [u32; 3],
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdDrawIndirect {
+    pub header: CommandHeader,
+    pub args_buffer_id: u32, // Buffer holding D3D11 indirect draw args
+    pub args_offset: u32,    // Byte offset of the args (4-byte aligned)
+    pub _reserved: [u32; 2],
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdDispatch {
@@ -672,6 +1563,51 @@ pub struct CmdCopyResource {
     pub _reserved: [u32; 2],
 }
 
+/// Copy the hidden counter of an append or counter UAV into a buffer
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdCopyStructureCount {
+    pub header: CommandHeader,
+    /// Buffer created with PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS
+    pub dst_buffer_id: u32,
+    /// Byte offset of the u32 written, 4-byte aligned
+    pub dst_offset: u32,
+    /// Buffer UAV created with PVGPU_UAV_FLAG_APPEND or _COUNTER
+    pub src_uav_id: u32,
+    pub _reserved: u32,
+}
+
+/// CmdCreateUnorderedAccessView.view_dimension values (same as
+/// D3D11_UAV_DIMENSION)
+pub const PVGPU_UAV_DIMENSION_BUFFER: u32 = 1;
+pub const PVGPU_UAV_DIMENSION_TEXTURE2D: u32 = 4;
+pub const PVGPU_UAV_DIMENSION_TEXTURE2DARRAY: u32 = 5;
+
+/// Buffer UAV flags (same as D3D11_BUFFER_UAV_FLAG). RAW needs a buffer
+/// allowing raw views and R32_TYPELESS; APPEND and COUNTER a structured one.
+pub const PVGPU_UAV_FLAG_RAW: u32 = 1 << 0;
+pub const PVGPU_UAV_FLAG_APPEND: u32 = 1 << 1;
+pub const PVGPU_UAV_FLAG_COUNTER: u32 = 1 << 2;
+
+/// Create unordered access view `view_id` of a buffer or 2D texture. The
+/// last three fields follow the C header's union: buffers use them as
+/// named, TEXTURE2D reads `first_element` as the mip slice, and
+/// TEXTURE2DARRAY reads mip slice, first array slice and array size.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdCreateUnorderedAccessView {
+    pub header: CommandHeader,
+    pub view_id: u32,
+    pub resource_id: u32,
+    /// DXGI_FORMAT; UNKNOWN for structured buffers
+    pub format: u32,
+    pub view_dimension: u32,
+    pub first_element: u32,
+    pub num_elements: u32,
+    /// PVGPU_UAV_FLAG_* for buffers
+    pub flags: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdResizeBuffers {
@@ -685,10 +1621,87 @@ pub struct CmdResizeBuffers {
     pub _reserved: [u32; 2],
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdDebugEvent {
+    pub header: CommandHeader,
+    pub label_offset: u32, // Heap offset of UTF-8 label
+    pub label_size: u32,   // Label size in bytes (0 for END_EVENT)
+    pub _reserved: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSelftest {
+    pub header: CommandHeader,
+    pub heap_offset: u32, // Heap offset (8-byte aligned) to receive the result code
+    pub _reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdSetLogLevel {
+    pub header: CommandHeader,
+    pub level: u32, // PVGPU_LOG_LEVEL_*
+    pub _reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdQueryMsaaSupport {
+    pub header: CommandHeader,
+    pub format: u32,      // DXGI_FORMAT to check
+    pub heap_offset: u32, // Heap offset (8-byte aligned) to receive MsaaSupport
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CmdCheckFormatSupport {
+    pub header: CommandHeader,
+    pub format: u32, // DXGI_FORMAT to check
+    pub _reserved: [u32; 3],
+}
+
+/// Written to the heap at CmdQueryMsaaSupport.heap_offset.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MsaaSupport {
+    /// Bit i set when PVGPU_MSAA_SAMPLE_COUNTS[i] samples are supported
+    pub sample_count_mask: u32,
+    pub _reserved: u32,
+    /// Quality levels per entry of PVGPU_MSAA_SAMPLE_COUNTS (0 = unsupported)
+    pub quality_levels: [u32; 4],
+}
+
+impl MsaaSupport {
+    /// Build from the quality levels reported for each sample count
+    pub fn from_quality_levels(quality_levels: [u32; 4]) -> Self {
+        let sample_count_mask = quality_levels
+            .iter()
+            .enumerate()
+            .filter(|(_, &levels)| levels > 0)
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+        Self {
+            sample_count_mask,
+            _reserved: 0,
+            quality_levels,
+        }
+    }
+
+    /// Heap representation as little-endian u64 words, in struct order
+    pub fn to_u64s(self) -> [u64; 3] {
+        let pair = |low: u32, high: u32| (high as u64) << 32 | low as u64;
+        [
+            pair(self.sample_count_mask, self._reserved),
+            pair(self.quality_levels[0], self.quality_levels[1]),
+            pair(self.quality_levels[2], self.quality_levels[3]),
+        ]
+    }
+}
+
 /// Map access type
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum MapType {
     Read = 1,
     Write = 2,
@@ -697,6 +1710,34 @@ pub enum MapType {
     WriteNoOverwrite = 5,
 }
 
+impl TryFrom<u32> for MapType {
+    /// The unknown value
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, u32> {
+        Ok(match value {
+            1 => MapType::Read,
+            2 => MapType::Write,
+            3 => MapType::ReadWrite,
+            4 => MapType::WriteDiscard,
+            5 => MapType::WriteNoOverwrite,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl MapType {
+    /// Whether the map returns the resource's contents to the guest
+    pub fn reads(self) -> bool {
+        matches!(self, MapType::Read | MapType::ReadWrite)
+    }
+
+    /// Whether the guest's data is copied back to the resource on unmap
+    pub fn writes(self) -> bool {
+        self != MapType::Read
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct CmdMapResource {
@@ -705,8 +1746,26 @@ pub struct CmdMapResource {
     pub subresource: u32,
     pub map_type: u32, // MapType enum
     pub map_flags: u32,
-    pub heap_offset: u32, // Output: where mapped data will be written/read
-    pub _reserved: [u32; 3],
+    pub heap_offset: u32,  // Output: where mapped data will be written/read
+    pub pitch_offset: u32, // Heap offset (8-byte aligned) for MapPitch, 0 = none
+    pub _reserved: [u32; 2],
+}
+
+/// Row and depth pitch of a mapped subresource, written to the heap at
+/// CmdMapResource.pitch_offset. Mapped data uses these strides, which may
+/// be padded past width * bytes per pixel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapPitch {
+    pub row_pitch: u32,
+    pub depth_pitch: u32,
+}
+
+impl MapPitch {
+    /// Heap representation: row pitch in the low word, depth pitch in the high
+    pub fn to_u64(self) -> u64 {
+        (self.depth_pitch as u64) << 32 | self.row_pitch as u64
+    }
 }
 
 #[repr(C)]
@@ -715,8 +1774,10 @@ pub struct CmdUnmapResource {
     pub header: CommandHeader,
     pub resource_id: u32,
     pub subresource: u32,
-    pub heap_offset: u32, // Where the data was mapped
-    pub data_size: u32,   // Size of data to copy back (for write maps)
+    pub heap_offset: u32,   // Where the data was mapped
+    pub data_size: u32,     // Size of data to copy back (for write maps)
+    pub src_row_pitch: u32, // Row pitch of texture data in the heap, 0 = the mapped pitch
+    pub map_type: u32,      // MapType of the map, 0 = not stated
 }
 
 #[repr(C)]
@@ -742,23 +1803,731 @@ pub struct CmdUpdateResource {
 // Helper Functions
 // =============================================================================
 
+/// How much of a command is present at the start of a ring slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandExtent {
+    /// A full command of this many bytes is available
+    Complete(usize),
+    /// The producer has not finished writing this command yet; retry later
+    Incomplete,
+    /// The header's command_size is smaller than a header or larger than the
+    /// ring; the stream is corrupt
+    Malformed,
+}
+
+/// Classify the command at the start of `data`, read from a ring of
+/// `ring_size` bytes, without consuming it.
+pub fn command_extent(data: &[u8], ring_size: u64) -> CommandExtent {
+    if data.len() < PVGPU_CMD_HEADER_SIZE {
+        return CommandExtent::Incomplete;
+    }
+
+    // SAFETY: data holds at least PVGPU_CMD_HEADER_SIZE bytes
+    let header: CommandHeader =
+        unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CommandHeader) };
+    let size = header.command_size as usize;
+
+    // A command larger than the ring can never be fully written
+    if size < PVGPU_CMD_HEADER_SIZE || size as u64 > ring_size {
+        CommandExtent::Malformed
+    } else if size > data.len() {
+        CommandExtent::Incomplete
+    } else {
+        CommandExtent::Complete(size)
+    }
+}
+
+/// Whether `command_type` is defined by this protocol version.
+pub fn is_known_command(command_type: u32) -> bool {
+    matches!(
+        command_type,
+        PVGPU_CMD_CREATE_RESOURCE
+            | PVGPU_CMD_DESTROY_RESOURCE
+            | PVGPU_CMD_MAP_RESOURCE
+            | PVGPU_CMD_UNMAP_RESOURCE
+            | PVGPU_CMD_UPDATE_RESOURCE
+            | PVGPU_CMD_COPY_RESOURCE
+            | PVGPU_CMD_OPEN_RESOURCE
+            | PVGPU_CMD_QUERY_MSAA_SUPPORT
+            | PVGPU_CMD_CHECK_FORMAT_SUPPORT
+            | PVGPU_CMD_COPY_STRUCTURE_COUNT
+            | PVGPU_CMD_CREATE_BLEND_STATE
+            | PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW
+            | PVGPU_CMD_DESTROY_UNORDERED_ACCESS_VIEW
+            | PVGPU_CMD_SET_RENDER_TARGET
+            | PVGPU_CMD_SET_VIEWPORT
+            | PVGPU_CMD_SET_SCISSOR
+            | PVGPU_CMD_SET_BLEND_STATE
+            | PVGPU_CMD_SET_RASTERIZER_STATE
+            | PVGPU_CMD_SET_DEPTH_STENCIL
+            | PVGPU_CMD_SET_SHADER
+            | PVGPU_CMD_SET_SAMPLER
+            | PVGPU_CMD_SET_CONSTANT_BUFFER
+            | PVGPU_CMD_SET_CONSTANT_BUFFERS
+            | PVGPU_CMD_SET_VERTEX_BUFFER
+            | PVGPU_CMD_SET_INDEX_BUFFER
+            | PVGPU_CMD_SET_INPUT_LAYOUT
+            | PVGPU_CMD_SET_PRIMITIVE_TOPOLOGY
+            | PVGPU_CMD_SET_SHADER_RESOURCE
+            | PVGPU_CMD_DRAW
+            | PVGPU_CMD_DRAW_INDEXED
+            | PVGPU_CMD_DRAW_INSTANCED
+            | PVGPU_CMD_DRAW_INDEXED_INSTANCED
+            | PVGPU_CMD_DISPATCH
+            | PVGPU_CMD_CLEAR_RENDER_TARGET
+            | PVGPU_CMD_CLEAR_DEPTH_STENCIL
+            | PVGPU_CMD_CLEAR_RENDER_TARGET_UINT
+            | PVGPU_CMD_DRAW_INSTANCED_INDIRECT
+            | PVGPU_CMD_DRAW_INDEXED_INSTANCED_INDIRECT
+            | PVGPU_CMD_CREATE_SHADER
+            | PVGPU_CMD_DESTROY_SHADER
+            | PVGPU_CMD_REPLACE_SHADER
+            | PVGPU_CMD_FENCE
+            | PVGPU_CMD_PRESENT
+            | PVGPU_CMD_FLUSH
+            | PVGPU_CMD_WAIT_FENCE
+            | PVGPU_CMD_RESIZE_BUFFERS
+            | PVGPU_CMD_QUERY_FENCE
+            | PVGPU_CMD_RESET_DEVICE
+            | PVGPU_CMD_SET_DISPLAY_CONFIG
+            | PVGPU_CMD_BEGIN_FRAME
+            | PVGPU_CMD_SET_FRAME_LATENCY
+            | PVGPU_CMD_QUERY_DEVICE_STATUS
+            | PVGPU_CMD_SELECT_ADAPTER
+            | PVGPU_CMD_SHUTDOWN
+            | PVGPU_CMD_BEGIN_EVENT
+            | PVGPU_CMD_END_EVENT
+            | PVGPU_CMD_SET_MARKER
+            | PVGPU_CMD_DUMP_FRAMES
+            | PVGPU_CMD_SELFTEST
+            | PVGPU_CMD_SET_LOG_LEVEL
+            | PVGPU_CMD_SET_DEBUG_NAME
+    )
+}
+
+/// Read the command header at the start of `data`, if one fits.
+pub fn peek_command_header(data: &[u8]) -> Option<CommandHeader> {
+    if data.len() < PVGPU_CMD_HEADER_SIZE {
+        return None;
+    }
+    // SAFETY: data holds at least PVGPU_CMD_HEADER_SIZE bytes
+    Some(unsafe { std::ptr::read_unaligned(data.as_ptr() as *const CommandHeader) })
+}
+
+/// Read the dirty rect list at `offset` in the heap.
+///
+/// The list is a u32 count, a u32 pad, then `count` rects laid out like
+/// `ScissorRect`. Returns None if the list does not fit in the heap.
+pub fn read_dirty_rects(heap: &[u8], offset: usize) -> Option<Vec<ScissorRect>> {
+    let count_bytes = heap.get(offset..offset.checked_add(4)?)?;
+    let count = u32::from_le_bytes(count_bytes.try_into().ok()?) as usize;
+
+    let start = offset.checked_add(8)?;
+    let size = count.checked_mul(std::mem::size_of::<ScissorRect>())?;
+    let bytes = heap.get(start..start.checked_add(size)?)?;
+
+    Some(
+        bytes
+            .chunks_exact(std::mem::size_of::<ScissorRect>())
+            .map(|chunk| {
+                let field = |i: usize| {
+                    i32::from_le_bytes([chunk[i], chunk[i + 1], chunk[i + 2], chunk[i + 3]])
+                };
+                ScissorRect {
+                    left: field(0),
+                    top: field(4),
+                    right: field(8),
+                    bottom: field(12),
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Required alignment of heap offsets for bulk data (initial data, shader
+/// bytecode, map/update data, fence arrays and dirty rect lists). Strings
+/// such as debug labels and semantic names are exempt.
+pub const PVGPU_HEAP_ALIGNMENT: usize = 16;
+
+/// Whether a dispatch of `groups` thread groups is within D3D11 limits and,
+/// when `strict`, the total thread group budget
+pub fn dispatch_groups_valid(groups: [u32; 3], strict: bool) -> bool {
+    if groups.iter().any(|&g| g > PVGPU_MAX_DISPATCH_THREAD_GROUPS) {
+        return false;
+    }
+    let total = groups.iter().map(|&g| g as u64).product::<u64>();
+    !strict || total <= PVGPU_STRICT_DISPATCH_GROUPS
+}
+
+/// Whether `usage` bytes reach `percent` of a video memory `budget`.
+/// A zero budget or percent never does.
+pub fn memory_pressure(budget: u64, usage: u64, percent: u32) -> bool {
+    budget > 0 && percent > 0 && usage as u128 * 100 >= budget as u128 * percent as u128
+}
+
 /// Align a value to 16-byte boundary.
-#[allow(dead_code)]
 pub const fn align16(x: usize) -> usize {
     (x + 15) & !15
 }
 
+/// Whether a heap offset meets `PVGPU_HEAP_ALIGNMENT`
+pub const fn is_heap_aligned(offset: usize) -> bool {
+    align16(offset) == offset
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_resource_type_from_u32() {
+        for value in 1..=19 {
+            assert_eq!(ResourceType::try_from(value).map(|t| t as u32), Ok(value));
+        }
+        assert_eq!(ResourceType::try_from(0), Err(0));
+        assert_eq!(ResourceType::try_from(20), Err(20));
+
+        for value in 0..=5 {
+            assert_eq!(ShaderStage::try_from(value).map(|s| s as u32), Ok(value));
+        }
+        assert_eq!(ShaderStage::try_from(6), Err(6));
+
+        // The two numberings differ; both name the same stage
+        assert_eq!(
+            ResourceType::try_from(5).unwrap().shader_stage(),
+            Some(ShaderStage::Vertex)
+        );
+        assert_eq!(
+            ResourceType::try_from(10).unwrap().shader_stage(),
+            Some(ShaderStage::Compute)
+        );
+        assert_eq!(ResourceType::Texture2D.shader_stage(), None);
+    }
+
+    #[test]
+    fn test_map_type_direction() {
+        let read = MapType::try_from(1).unwrap();
+        assert!(read.reads() && !read.writes());
+        let read_write = MapType::try_from(3).unwrap();
+        assert!(read_write.reads() && read_write.writes());
+        for value in [2, 4, 5] {
+            let map_type = MapType::try_from(value).unwrap();
+            assert!(!map_type.reads() && map_type.writes());
+        }
+        assert_eq!(MapType::try_from(0), Err(0));
+        assert_eq!(MapType::try_from(6), Err(6));
+    }
+
     #[test]
     fn test_control_region_size() {
         assert_eq!(std::mem::size_of::<ControlRegion>(), 4096);
     }
 
+    #[test]
+    fn test_error_log_wraps() {
+        // SAFETY: the control region is plain integers and atomics
+        let control: Box<ControlRegion> = Box::new(unsafe { std::mem::zeroed() });
+        assert_eq!(std::mem::size_of::<ErrorLogSlot>(), 24);
+
+        let total = PVGPU_ERROR_LOG_ENTRIES as u32 + 3;
+        for i in 0..total {
+            control.push_error_record(
+                PVGPU_ERROR_INVALID_COMMAND,
+                i,
+                i as u64 * 16,
+                1000 + i as u64,
+            );
+        }
+        assert_eq!(control.error_log_producer(), total);
+        assert_eq!(control.error_log_consumer(), 0);
+
+        // The oldest records were overwritten by the newest
+        let oldest = total - PVGPU_ERROR_LOG_ENTRIES as u32;
+        for i in oldest..total {
+            let record = control.error_record(i);
+            assert_eq!(record.data, i);
+            assert_eq!(record.command_offset, i as u64 * 16);
+            assert_eq!(record.timestamp_us, 1000 + i as u64);
+        }
+    }
+
+    #[test]
+    fn test_diagnostic_message() {
+        // SAFETY: the control region is plain integers and atomics
+        let control: Box<ControlRegion> = Box::new(unsafe { std::mem::zeroed() });
+        assert_eq!(std::mem::offset_of!(ControlRegion, diagnostic), 0x440);
+        assert_eq!(control.diagnostic_sequence(), 0);
+
+        control.set_diagnostic(7, "E_INVALIDARG");
+        assert_eq!(control.diagnostic_sequence(), 1);
+        assert_eq!(control.diagnostic(), (7, "E_INVALIDARG".to_string()));
+
+        // Truncated whole characters, leaving room for the terminator
+        let long = "\u{e9}".repeat(PVGPU_DIAGNOSTIC_SIZE);
+        control.set_diagnostic(8, &long);
+        let (id, message) = control.diagnostic();
+        assert_eq!(id, 8);
+        assert_eq!(message.len(), PVGPU_DIAGNOSTIC_SIZE - 2);
+        assert!(message.chars().all(|c| c == '\u{e9}'));
+        assert_eq!(
+            control.diagnostic[PVGPU_DIAGNOSTIC_SIZE - 2].load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        // SAFETY: the control region is plain integers and atomics
+        let control: Box<ControlRegion> = Box::new(unsafe { std::mem::zeroed() });
+        assert_eq!(std::mem::offset_of!(ControlRegion, vram_budget), 0x640);
+        control.set_memory_usage(1 << 30, 600 << 20, 500 << 20);
+        assert_eq!(control.memory_usage(), (1 << 30, 600 << 20, 500 << 20));
+
+        assert!(!memory_pressure(1000, 899, 90));
+        assert!(memory_pressure(1000, 900, 90));
+        assert!(memory_pressure(u64::MAX, u64::MAX, 90));
+        assert!(!memory_pressure(0, 900, 90));
+        assert!(!memory_pressure(1000, 1000, 0));
+    }
+
+    #[test]
+    fn test_format_support() {
+        // Must match PvgpuCmdCheckFormatSupport in the C header
+        assert_eq!(std::mem::size_of::<CmdCheckFormatSupport>(), 32);
+
+        // SAFETY: the control region is plain integers and atomics
+        let control: Box<ControlRegion> = Box::new(unsafe { std::mem::zeroed() });
+        assert_eq!(
+            std::mem::offset_of!(ControlRegion, format_query_sequence),
+            0x660
+        );
+        assert_eq!(control.format_support(), (0, 0, 0));
+        control.set_format_support(28, 0x1234);
+        assert_eq!(control.format_support(), (1, 28, 0x1234));
+        control.set_format_support(0xFFFF, 0);
+        assert_eq!(control.format_support(), (2, 0xFFFF, 0));
+    }
+
+    #[test]
+    fn test_set_render_target() {
+        // Must match PvgpuCmdSetRenderTarget in the C header
+        assert_eq!(std::mem::size_of::<CmdSetRenderTarget>(), 100);
+
+        assert!(om_slots_valid(8, 0, 0));
+        assert!(om_slots_valid(2, 2, 6));
+        assert!(om_slots_valid(0, 0, 8));
+        assert!(!om_slots_valid(9, 0, 0));
+        // UAVs may not share a slot with a render target
+        assert!(!om_slots_valid(2, 1, 1));
+        assert!(!om_slots_valid(2, 4, 5));
+        assert!(!om_slots_valid(0, u32::MAX, 2));
+    }
+
+    #[test]
+    fn test_blend_state_valid() {
+        // Must match PvgpuCmdCreateBlendState in the C header; the fields
+        // after render_targets were appended later
+        assert_eq!(std::mem::size_of::<CmdCreateBlendState>(), 300);
+        assert_eq!(std::mem::offset_of!(CmdCreateBlendState, num_targets), 284);
+
+        // SRC_ALPHA / INV_SRC_ALPHA / ADD on target 0, target 1 writes RGB only
+        let mut cmd = CmdCreateBlendState {
+            header: CommandHeader {
+                command_type: PVGPU_CMD_CREATE_BLEND_STATE,
+                command_size: 300,
+                resource_id: 0,
+                flags: 0,
+            },
+            state_id: 1,
+            alpha_to_coverage: 0,
+            independent_blend: 1,
+            render_targets: [RenderTargetBlend {
+                write_mask: 0xF,
+                ..Default::default()
+            }; 8],
+            num_targets: 2,
+            logic_op_enable: 0,
+            logic_op: 0,
+            _reserved: 0,
+        };
+        cmd.render_targets[0] = RenderTargetBlend {
+            blend_enable: 1,
+            src_blend: 5,
+            dest_blend: 6,
+            blend_op: 1,
+            src_blend_alpha: 2,
+            dest_blend_alpha: 1,
+            blend_op_alpha: 1,
+            write_mask: 0xF,
+        };
+        cmd.render_targets[1].write_mask = 0x7;
+        assert!(blend_state_valid(&cmd));
+        assert_eq!(cmd.used_targets().len(), 2);
+
+        let mut bad = cmd;
+        bad.num_targets = 0;
+        assert!(!blend_state_valid(&bad));
+        bad.num_targets = PVGPU_MAX_BLEND_TARGETS + 1;
+        assert!(!blend_state_valid(&bad));
+
+        // D3D11_BLEND 12 and 13 are unused; only enabled targets are checked
+        let mut bad = cmd;
+        bad.render_targets[0].src_blend = 12;
+        assert!(!blend_state_valid(&bad));
+        bad.render_targets[0].blend_enable = 0;
+        assert!(blend_state_valid(&bad));
+
+        // Targets past num_targets, or past the first without independent
+        // blend, are ignored
+        let mut bad = cmd;
+        bad.render_targets[2].blend_enable = 1;
+        bad.render_targets[2].blend_op = 6;
+        assert!(blend_state_valid(&bad));
+        bad.num_targets = 3;
+        assert!(!blend_state_valid(&bad));
+        bad.independent_blend = 0;
+        assert!(blend_state_valid(&bad));
+        assert_eq!(bad.used_targets().len(), 1);
+
+        // Logic ops need a shared, non-blending description
+        let mut logic = cmd;
+        logic.render_targets[0].blend_enable = 0;
+        logic.logic_op_enable = 1;
+        logic.logic_op = 15;
+        assert!(!blend_state_valid(&logic));
+        logic.independent_blend = 0;
+        assert!(blend_state_valid(&logic));
+        logic.logic_op = 16;
+        assert!(!blend_state_valid(&logic));
+        logic.logic_op = 15;
+        logic.render_targets[0].blend_enable = 1;
+        assert!(!blend_state_valid(&logic));
+    }
+
+    #[test]
+    fn test_constant_buffer_range() {
+        // Must match PvgpuCmdSetConstantBuffers in the C header
+        assert_eq!(std::mem::size_of::<CmdSetConstantBuffers>(), 84);
+
+        assert!(constant_buffer_range_valid(0, 4));
+        assert!(constant_buffer_range_valid(0, PVGPU_MAX_CONSTANT_BUFFERS));
+        assert!(constant_buffer_range_valid(13, 1));
+        assert!(constant_buffer_range_valid(14, 0));
+        assert!(!constant_buffer_range_valid(13, 2));
+        assert!(!constant_buffer_range_valid(
+            0,
+            PVGPU_MAX_CONSTANT_BUFFERS + 1
+        ));
+        assert!(!constant_buffer_range_valid(u32::MAX, 2));
+    }
+
+    #[test]
+    fn test_primitive_topology() {
+        for topology in [0, 1, 4, 5, 10, 13] {
+            assert!(is_valid_topology(topology), "{}", topology);
+        }
+        for topology in [6, 9, 14, 32, 65, u32::MAX] {
+            assert!(!is_valid_topology(topology), "{}", topology);
+        }
+
+        assert_eq!(patch_control_points(4), None);
+        assert_eq!(patch_control_points(32), None);
+        assert_eq!(patch_control_points(33), Some(1));
+        assert_eq!(patch_control_points(35), Some(3));
+        assert_eq!(patch_control_points(64), Some(32));
+        assert_eq!(patch_control_points(65), None);
+        assert!(is_valid_topology(64));
+    }
+
+    #[test]
+    fn test_unmap_resource_size() {
+        // Must match PvgpuCmdUnmapResource in the C header
+        assert_eq!(std::mem::size_of::<CmdUnmapResource>(), 40);
+        assert_eq!(std::mem::offset_of!(CmdUnmapResource, src_row_pitch), 32);
+    }
+
+    #[test]
+    fn test_create_resource_size() {
+        // Must match PvgpuCmdCreateResource in the C header
+        assert_eq!(std::mem::size_of::<CmdCreateResource>(), 72);
+    }
+
+    #[test]
+    fn test_capability_block() {
+        // Must match PvgpuCapabilityBlock in the C header
+        assert_eq!(std::mem::size_of::<CapabilityBlock>(), 0x160);
+        assert_eq!(std::mem::offset_of!(CapabilityBlock, adapter_luid), 0x158);
+        assert_eq!(std::mem::offset_of!(ControlRegion, capabilities), 0x2D0);
+
+        let mut caps = CapabilityBlock::new();
+        assert_eq!(caps.version, PVGPU_CAPS_VERSION);
+        assert_eq!(caps.size, 0x160);
+
+        caps.set_adapter_description("GPU \u{e9}");
+        assert_eq!(&caps.adapter_description[..6], &[71, 80, 85, 32, 0xE9, 0]);
+        caps.set_adapter_description(&"x".repeat(200));
+        assert_eq!(caps.adapter_description[126], b'x' as u16);
+        assert_eq!(caps.adapter_description[127], 0);
+
+        caps.set_display_formats(&[28; 10]);
+        assert_eq!(
+            caps.display_format_count,
+            PVGPU_CAPS_MAX_DISPLAY_FORMATS as u32
+        );
+        caps.set_display_formats(&[28, 87]);
+        assert_eq!(caps.display_format_count, 2);
+        assert_eq!(caps.display_formats[2], 0);
+    }
+
     #[test]
     fn test_command_header_size() {
         assert_eq!(std::mem::size_of::<CommandHeader>(), 16);
     }
+
+    #[test]
+    fn test_is_known_command() {
+        assert!(is_known_command(PVGPU_CMD_DRAW));
+        assert!(is_known_command(PVGPU_CMD_SET_MARKER));
+        assert!(is_known_command(PVGPU_CMD_SET_DEBUG_NAME));
+        assert!(is_known_command(PVGPU_CMD_CREATE_BLEND_STATE));
+        assert!(is_known_command(PVGPU_CMD_SET_CONSTANT_BUFFERS));
+        assert!(is_known_command(PVGPU_CMD_CHECK_FORMAT_SUPPORT));
+        assert!(is_known_command(PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW));
+        assert!(is_known_command(PVGPU_CMD_COPY_STRUCTURE_COUNT));
+        assert!(!is_known_command(0));
+        assert!(!is_known_command(0xDEAD));
+    }
+
+    #[test]
+    fn test_uav_command_sizes() {
+        // Must match PvgpuCmdCreateUnorderedAccessView and
+        // PvgpuCmdCopyStructureCount in the C header
+        assert_eq!(std::mem::size_of::<CmdCreateUnorderedAccessView>(), 44);
+        assert_eq!(
+            std::mem::offset_of!(CmdCreateUnorderedAccessView, flags),
+            40
+        );
+        assert_eq!(std::mem::size_of::<CmdCopyStructureCount>(), 32);
+    }
+
+    #[test]
+    fn test_fence_command_sizes() {
+        // Must match PvgpuCmdWaitFence / PvgpuCmdQueryFence / PvgpuCmdSelftest /
+        // PvgpuCmdSetLogLevel / PvgpuCmdQueryDeviceStatus / PvgpuDeviceStatus
+        // in the C header
+        assert_eq!(std::mem::size_of::<CmdWaitFence>(), 24);
+        assert_eq!(std::mem::size_of::<CmdQueryFence>(), 24);
+        assert_eq!(std::mem::size_of::<CmdQueryDeviceStatus>(), 24);
+        assert_eq!(std::mem::size_of::<DeviceStatus>(), 24);
+        assert_eq!(std::mem::size_of::<CmdSelftest>(), 24);
+        assert_eq!(std::mem::size_of::<CmdSetLogLevel>(), 24);
+    }
+
+    #[test]
+    fn test_viewport_and_scissor_validity() {
+        let viewport = Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: 1024.0,
+            height: 1024.0,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        assert!(viewport.is_valid());
+        assert!(!Viewport {
+            width: 0.0,
+            ..viewport
+        }
+        .is_valid());
+        assert!(!Viewport {
+            height: f32::NAN,
+            ..viewport
+        }
+        .is_valid());
+        assert!(!Viewport {
+            min_depth: 0.8,
+            max_depth: 0.2,
+            ..viewport
+        }
+        .is_valid());
+
+        let rect = ScissorRect {
+            left: 0,
+            top: 0,
+            right: 640,
+            bottom: 480,
+        };
+        assert!(!rect.is_empty());
+        assert!(ScissorRect { right: 0, ..rect }.is_empty());
+        assert!(ScissorRect { bottom: -1, ..rect }.is_empty());
+    }
+
+    #[test]
+    fn test_read_dirty_rects() {
+        let mut heap = vec![0u8; 64];
+        heap[8..12].copy_from_slice(&2u32.to_le_bytes());
+        for (i, v) in [0i32, 0, 64, 32, 100, 50, 200, 150].iter().enumerate() {
+            heap[16 + i * 4..20 + i * 4].copy_from_slice(&v.to_le_bytes());
+        }
+
+        let rects = read_dirty_rects(&heap, 8).unwrap();
+        assert_eq!(rects.len(), 2);
+        assert_eq!((rects[0].right, rects[0].bottom), (64, 32));
+        assert_eq!((rects[1].left, rects[1].top), (100, 50));
+
+        // List runs past the end of the heap
+        assert!(read_dirty_rects(&heap[..40], 8).is_none());
+        assert!(read_dirty_rects(&heap, 62).is_none());
+
+        // Counts are checked against the heap, not trusted
+        heap[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_dirty_rects(&heap, 8).is_none());
+    }
+
+    #[test]
+    fn test_set_display_config_size() {
+        // Must match PvgpuCmdSetDisplayConfig in the C header
+        assert_eq!(std::mem::size_of::<CmdSetDisplayConfig>(), 32);
+    }
+
+    #[test]
+    fn test_shutdown_size() {
+        // Must match PvgpuCmdShutdown in the C header
+        assert_eq!(std::mem::size_of::<CmdShutdown>(), 24);
+    }
+
+    #[test]
+    fn test_select_adapter_size() {
+        // Must match PvgpuCmdSelectAdapter in the C header
+        assert_eq!(std::mem::size_of::<CmdSelectAdapter>(), 40);
+        assert_eq!(std::mem::offset_of!(CmdSelectAdapter, adapter_luid), 24);
+    }
+
+    #[test]
+    fn test_clear_render_target_uint_size() {
+        assert_eq!(
+            std::mem::size_of::<CmdClearRenderTargetUint>(),
+            std::mem::size_of::<CmdClearRenderTarget>()
+        );
+    }
+
+    #[test]
+    fn test_set_frame_latency_size() {
+        // Must match PvgpuCmdSetFrameLatency in the C header
+        assert_eq!(std::mem::size_of::<CmdSetFrameLatency>(), 24);
+    }
+
+    #[test]
+    fn test_msaa_support_layout() {
+        // Must match PvgpuCmdQueryMsaaSupport / PvgpuMsaaSupport in the C header
+        assert_eq!(std::mem::size_of::<CmdQueryMsaaSupport>(), 24);
+        assert_eq!(std::mem::size_of::<MsaaSupport>(), 24);
+
+        let support = MsaaSupport::from_quality_levels([1, 1, 32, 0]);
+        assert_eq!(support.sample_count_mask, 0b0111);
+        assert_eq!(support.to_u64s(), [0b0111, 1 << 32 | 1, 32]);
+        assert_eq!(
+            MsaaSupport::from_quality_levels([0; 4]).sample_count_mask,
+            0
+        );
+    }
+
+    #[test]
+    fn test_begin_frame_size() {
+        // Must match PvgpuCmdBeginFrame in the C header
+        assert_eq!(std::mem::size_of::<CmdBeginFrame>(), 32);
+    }
+
+    fn header_bytes(command_size: u32) -> Vec<u8> {
+        let mut v = PVGPU_CMD_DRAW.to_le_bytes().to_vec();
+        v.extend_from_slice(&command_size.to_le_bytes());
+        v.extend_from_slice(&[0u8; 8]);
+        v
+    }
+
+    #[test]
+    fn test_command_extent() {
+        // Not even a full header yet
+        assert_eq!(command_extent(&[0u8; 4], 256), CommandExtent::Incomplete);
+
+        // Header claims more bytes than were written
+        let mut data = header_bytes(32);
+        assert_eq!(command_extent(&data, 256), CommandExtent::Incomplete);
+
+        // Full command plus the start of the next one
+        data.resize(40, 0);
+        assert_eq!(command_extent(&data, 256), CommandExtent::Complete(32));
+
+        // Size smaller than the header itself
+        assert_eq!(
+            command_extent(&header_bytes(0), 256),
+            CommandExtent::Malformed
+        );
+        assert_eq!(
+            command_extent(&header_bytes(8), 256),
+            CommandExtent::Malformed
+        );
+
+        // Size larger than the ring it has to fit in
+        assert_eq!(
+            command_extent(&header_bytes(256), 256),
+            CommandExtent::Incomplete
+        );
+        assert_eq!(
+            command_extent(&header_bytes(257), 256),
+            CommandExtent::Malformed
+        );
+    }
+
+    #[test]
+    fn test_dispatch_groups_valid() {
+        assert!(dispatch_groups_valid([65535, 1, 1], false));
+        assert!(dispatch_groups_valid([0, 0, 0], true));
+        assert!(!dispatch_groups_valid([65536, 1, 1], false));
+        assert!(!dispatch_groups_valid([65536, 65536, 65536], false));
+
+        // Legal per dimension but too many groups in total for strict mode
+        assert!(dispatch_groups_valid([65535, 65535, 1], false));
+        assert!(!dispatch_groups_valid([65535, 65535, 1], true));
+        assert!(dispatch_groups_valid([2048, 2048, 1], true));
+    }
+
+    #[test]
+    fn test_texture_array_valid() {
+        assert!(texture_array_valid(256, 256, 1, false));
+        assert!(texture_array_valid(1024, 512, 2048, false));
+        assert!(!texture_array_valid(256, 256, 0, false));
+        assert!(!texture_array_valid(256, 256, 2049, false));
+
+        // Cube maps need square faces and whole cubes
+        assert!(texture_array_valid(256, 256, 6, true));
+        assert!(texture_array_valid(256, 256, 12, true));
+        assert!(!texture_array_valid(256, 256, 4, true));
+        assert!(!texture_array_valid(256, 128, 6, true));
+    }
+
+    #[test]
+    fn test_map_pitch_layout() {
+        assert_eq!(std::mem::size_of::<CmdMapResource>(), 48);
+        let pitch = MapPitch {
+            row_pitch: 0x1100,
+            depth_pitch: 0x11_0000,
+        };
+        // Little-endian u64 puts row_pitch first, matching PvgpuMapPitch
+        let bytes = pitch.to_u64().to_le_bytes();
+        assert_eq!(&bytes[..4], &0x1100u32.to_le_bytes());
+        assert_eq!(&bytes[4..], &0x11_0000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_heap_alignment() {
+        assert_eq!(align16(0), 0);
+        assert_eq!(align16(1), 16);
+        assert_eq!(align16(16), 16);
+        assert_eq!(align16(17), 32);
+        assert!(is_heap_aligned(0));
+        assert!(is_heap_aligned(0x1000));
+        assert!(!is_heap_aligned(8));
+        assert!(!is_heap_aligned(0x1004));
+    }
 }
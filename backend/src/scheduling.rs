@@ -0,0 +1,113 @@
+//! Scheduling Module
+//!
+//! Optional process priority class and per-thread priority / CPU affinity for
+//! the command-processing and pipe-reader threads, so they can be kept off
+//! the cores QEMU's vCPU threads are pinned to. Nothing changes unless the
+//! corresponding config option is set.
+
+use tracing::{info, warn};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, GetCurrentThread, SetPriorityClass, SetThreadAffinityMask,
+    SetThreadPriority, ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS,
+    HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, PROCESS_CREATION_FLAGS,
+    REALTIME_PRIORITY_CLASS, THREAD_PRIORITY, THREAD_PRIORITY_ABOVE_NORMAL,
+    THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_IDLE,
+    THREAD_PRIORITY_LOWEST, THREAD_PRIORITY_NORMAL, THREAD_PRIORITY_TIME_CRITICAL,
+};
+
+/// Thread priority for a config name such as `"above_normal"`
+pub fn thread_priority(name: &str) -> Option<THREAD_PRIORITY> {
+    match name {
+        "idle" => Some(THREAD_PRIORITY_IDLE),
+        "lowest" => Some(THREAD_PRIORITY_LOWEST),
+        "below_normal" => Some(THREAD_PRIORITY_BELOW_NORMAL),
+        "normal" => Some(THREAD_PRIORITY_NORMAL),
+        "above_normal" => Some(THREAD_PRIORITY_ABOVE_NORMAL),
+        "highest" => Some(THREAD_PRIORITY_HIGHEST),
+        "time_critical" => Some(THREAD_PRIORITY_TIME_CRITICAL),
+        _ => None,
+    }
+}
+
+/// Process priority class for a config name such as `"high"`
+pub fn priority_class(name: &str) -> Option<PROCESS_CREATION_FLAGS> {
+    match name {
+        "idle" => Some(IDLE_PRIORITY_CLASS),
+        "below_normal" => Some(BELOW_NORMAL_PRIORITY_CLASS),
+        "normal" => Some(NORMAL_PRIORITY_CLASS),
+        "above_normal" => Some(ABOVE_NORMAL_PRIORITY_CLASS),
+        "high" => Some(HIGH_PRIORITY_CLASS),
+        "realtime" => Some(REALTIME_PRIORITY_CLASS),
+        _ => None,
+    }
+}
+
+/// Set the priority class of the whole backend process
+pub fn apply_process_priority(name: Option<&str>) {
+    let name = match name {
+        Some(name) => name,
+        None => return,
+    };
+    let class = match priority_class(name) {
+        Some(class) => class,
+        None => {
+            warn!("Unknown process priority '{}', leaving unchanged", name);
+            return;
+        }
+    };
+    match unsafe { SetPriorityClass(GetCurrentProcess(), class) } {
+        Ok(()) => info!("Process priority class set to {}", name),
+        Err(e) => warn!("Failed to set process priority to {}: {}", name, e),
+    }
+}
+
+/// Apply priority and affinity to the calling thread.
+///
+/// `label` names the thread in log messages. An affinity mask of 0 is
+/// rejected since it would leave the thread no CPU to run on.
+pub fn apply_to_current_thread(label: &str, priority: Option<&str>, affinity: Option<u64>) {
+    if let Some(name) = priority {
+        match thread_priority(name) {
+            Some(level) => match unsafe { SetThreadPriority(GetCurrentThread(), level) } {
+                Ok(()) => info!("{} thread priority set to {}", label, name),
+                Err(e) => warn!("Failed to set {} thread priority: {}", label, e),
+            },
+            None => warn!("Unknown thread priority '{}' for {} thread", name, label),
+        }
+    }
+
+    match affinity {
+        None => {}
+        Some(0) => warn!("Ignoring empty CPU affinity mask for {} thread", label),
+        Some(mask) => {
+            let previous = unsafe { SetThreadAffinityMask(GetCurrentThread(), mask as usize) };
+            if previous == 0 {
+                warn!(
+                    "Failed to set {} thread affinity to 0x{:x}: {}",
+                    label,
+                    mask,
+                    std::io::Error::last_os_error()
+                );
+            } else {
+                info!("{} thread pinned to CPU mask 0x{:x}", label, mask);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_names() {
+        assert_eq!(thread_priority("normal"), Some(THREAD_PRIORITY_NORMAL));
+        assert_eq!(
+            thread_priority("time_critical"),
+            Some(THREAD_PRIORITY_TIME_CRITICAL)
+        );
+        assert_eq!(thread_priority("realtime"), None);
+        assert_eq!(priority_class("high"), Some(HIGH_PRIORITY_CLASS));
+        assert_eq!(priority_class("time_critical"), None);
+    }
+}
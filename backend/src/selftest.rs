@@ -0,0 +1,162 @@
+//! Self-Test Module
+//!
+//! End-to-end health check for the GPU pipeline: clear a small render target
+//! to a known color, copy it to a staging texture, read it back and compare.
+//! Needs no guest shaders or resources and leaves the guest's bound pipeline
+//! state untouched.
+
+use tracing::{info, warn};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D,
+    D3D11_BIND_RENDER_TARGET, D3D11_CPU_ACCESS_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC};
+
+use crate::protocol::{
+    PVGPU_SELFTEST_FAIL_CREATE, PVGPU_SELFTEST_FAIL_MISMATCH, PVGPU_SELFTEST_FAIL_READBACK,
+    PVGPU_SELFTEST_PASS,
+};
+
+/// Size of the test render target
+const TEST_SIZE: u32 = 4;
+
+/// Clear color; each channel is an exact multiple of 1/255
+const TEST_COLOR: [f32; 4] = [0.2, 0.4, 0.6, 1.0];
+
+/// Expected R8G8B8A8 bytes for `TEST_COLOR`
+const TEST_PIXEL: [u8; 4] = [51, 102, 153, 255];
+
+/// Check every pixel of an R8G8B8A8 image against `expected`, allowing one
+/// step of rounding per channel
+pub fn pixels_match(
+    data: &[u8],
+    row_pitch: usize,
+    width: u32,
+    height: u32,
+    expected: [u8; 4],
+) -> bool {
+    (0..height as usize).all(|y| {
+        let start = y * row_pitch;
+        match data.get(start..start + width as usize * 4) {
+            Some(row) => row.chunks_exact(4).all(|pixel| {
+                pixel
+                    .iter()
+                    .zip(expected.iter())
+                    .all(|(&actual, &wanted)| actual.abs_diff(wanted) <= 1)
+            }),
+            None => false,
+        }
+    })
+}
+
+/// Create a texture, logging failures
+fn create_texture(device: &ID3D11Device, desc: &D3D11_TEXTURE2D_DESC) -> Option<ID3D11Texture2D> {
+    let mut texture: Option<ID3D11Texture2D> = None;
+    if let Err(e) = unsafe { device.CreateTexture2D(desc, None, Some(&mut texture)) } {
+        warn!("Self-test: texture creation failed: {:?}", e);
+        return None;
+    }
+    texture
+}
+
+/// Run the clear/readback self-test, returning a PVGPU_SELFTEST_* code
+pub fn run(device: &ID3D11Device, context: &ID3D11DeviceContext) -> u32 {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: TEST_SIZE,
+        Height: TEST_SIZE,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: D3D11_BIND_RENDER_TARGET.0 as u32,
+        CPUAccessFlags: 0,
+        MiscFlags: 0,
+    };
+    let staging_desc = D3D11_TEXTURE2D_DESC {
+        Usage: D3D11_USAGE_STAGING,
+        BindFlags: 0,
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+        ..desc
+    };
+
+    let target = match create_texture(device, &desc) {
+        Some(texture) => texture,
+        None => return PVGPU_SELFTEST_FAIL_CREATE,
+    };
+    let mut rtv: Option<ID3D11RenderTargetView> = None;
+    if let Err(e) = unsafe { device.CreateRenderTargetView(&target, None, Some(&mut rtv)) } {
+        warn!("Self-test: render target view creation failed: {:?}", e);
+        return PVGPU_SELFTEST_FAIL_CREATE;
+    }
+    let rtv = match rtv {
+        Some(rtv) => rtv,
+        None => return PVGPU_SELFTEST_FAIL_CREATE,
+    };
+    let staging = match create_texture(device, &staging_desc) {
+        Some(texture) => texture,
+        None => return PVGPU_SELFTEST_FAIL_READBACK,
+    };
+
+    let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+    let matched = unsafe {
+        context.ClearRenderTargetView(&rtv, &TEST_COLOR);
+        context.CopyResource(&staging, &target);
+        if let Err(e) = context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped)) {
+            warn!("Self-test: readback map failed: {:?}", e);
+            return PVGPU_SELFTEST_FAIL_READBACK;
+        }
+        let data = std::slice::from_raw_parts(
+            mapped.pData as *const u8,
+            mapped.RowPitch as usize * TEST_SIZE as usize,
+        );
+        let matched = pixels_match(
+            data,
+            mapped.RowPitch as usize,
+            TEST_SIZE,
+            TEST_SIZE,
+            TEST_PIXEL,
+        );
+        context.Unmap(&staging, 0);
+        matched
+    };
+
+    if matched {
+        info!("Self-test passed");
+        PVGPU_SELFTEST_PASS
+    } else {
+        warn!("Self-test failed: readback does not match clear color");
+        PVGPU_SELFTEST_FAIL_MISMATCH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels_match() {
+        // 2x2 image with 4 bytes of row padding
+        let mut data = vec![0u8; 24];
+        for y in 0..2 {
+            for x in 0..2 {
+                data[y * 12 + x * 4..y * 12 + x * 4 + 4].copy_from_slice(&TEST_PIXEL);
+            }
+        }
+        assert!(pixels_match(&data, 12, 2, 2, TEST_PIXEL));
+
+        // Off-by-one rounding is tolerated
+        data[0] = 52;
+        assert!(pixels_match(&data, 12, 2, 2, TEST_PIXEL));
+
+        data[13] = 0;
+        assert!(!pixels_match(&data, 12, 2, 2, TEST_PIXEL));
+
+        // Truncated readback never matches
+        assert!(!pixels_match(&data[..16], 12, 2, 2, TEST_PIXEL));
+    }
+}
@@ -4,7 +4,7 @@
 //! and resource heap access.
 
 use std::slice;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use anyhow::{anyhow, Result};
 use tracing::{debug, info};
@@ -15,7 +15,92 @@ use windows::Win32::System::Memory::{
     MEMORY_MAPPED_VIEW_ADDRESS,
 };
 
-use crate::protocol::{ControlRegion, PVGPU_MAGIC, PVGPU_VERSION_MAJOR};
+use crate::protocol::{
+    is_heap_aligned, ControlRegion, PVGPU_CONTROL_REGION_SIZE, PVGPU_HEAP_ALIGNMENT, PVGPU_MAGIC,
+    PVGPU_MIN_SHMEM_SIZE, PVGPU_VERSION_MAJOR,
+};
+
+/// Consumed bytes are published to the guest once they reach this fraction
+/// of the ring, even mid-batch, so a guest waiting for space is not starved
+const CONSUMER_COMMIT_FRACTION: u64 = 4;
+
+/// Windows allocation granularity for file mapping views
+pub const SHMEM_ALLOCATION_GRANULARITY: u64 = 0x10000; // 64KB
+
+/// Check a shared memory size announced by QEMU before mapping it.
+///
+/// The size must hold the control region, command ring and a minimal heap,
+/// be a multiple of the allocation granularity, and not exceed `max_size`.
+pub fn validate_shmem_size(size: u64, max_size: u64) -> Result<usize> {
+    if size < PVGPU_MIN_SHMEM_SIZE as u64 {
+        return Err(anyhow!(
+            "Shared memory size {} is below minimum {}",
+            size,
+            PVGPU_MIN_SHMEM_SIZE
+        ));
+    }
+    if !size.is_multiple_of(SHMEM_ALLOCATION_GRANULARITY) {
+        return Err(anyhow!(
+            "Shared memory size {} is not a multiple of {}",
+            size,
+            SHMEM_ALLOCATION_GRANULARITY
+        ));
+    }
+    if size > max_size {
+        return Err(anyhow!(
+            "Shared memory size {} exceeds maximum {}",
+            size,
+            max_size
+        ));
+    }
+    usize::try_from(size).map_err(|_| anyhow!("Shared memory size {} overflows usize", size))
+}
+
+/// Check that the ring and heap the control region describes lie within a
+/// `size` byte mapping, after the control region and without overlapping.
+pub fn validate_region_layout(
+    ring_offset: u32,
+    ring_size: u32,
+    heap_offset: u32,
+    heap_size: u32,
+    size: usize,
+) -> Result<()> {
+    let ring = ring_offset as u64..ring_offset as u64 + ring_size as u64;
+    let heap = heap_offset as u64..heap_offset as u64 + heap_size as u64;
+    if ring_size == 0 || ring.start < PVGPU_CONTROL_REGION_SIZE as u64 || ring.end > size as u64 {
+        return Err(anyhow!(
+            "Command ring {:#x}+{:#x} lies outside the {:#x} byte region",
+            ring_offset,
+            ring_size,
+            size
+        ));
+    }
+    if heap.start < PVGPU_CONTROL_REGION_SIZE as u64 || heap.end > size as u64 {
+        return Err(anyhow!(
+            "Heap {:#x}+{:#x} lies outside the {:#x} byte region",
+            heap_offset,
+            heap_size,
+            size
+        ));
+    }
+    if !is_heap_aligned(heap_offset as usize) {
+        return Err(anyhow!(
+            "Heap offset {:#x} is not {}-byte aligned",
+            heap_offset,
+            PVGPU_HEAP_ALIGNMENT
+        ));
+    }
+    if ring.start < heap.end && heap.start < ring.end {
+        return Err(anyhow!(
+            "Command ring {:#x}+{:#x} overlaps heap {:#x}+{:#x}",
+            ring_offset,
+            ring_size,
+            heap_offset,
+            heap_size
+        ));
+    }
+    Ok(())
+}
 
 /// Result of reading pending commands from the ring buffer.
 /// Can either be a direct reference to contiguous ring data,
@@ -50,6 +135,12 @@ pub struct SharedMemory {
     size: usize,
     /// Whether the region is valid and initialized
     initialized: AtomicBool,
+    /// Bytes consumed but not yet written to the control region's consumer
+    /// pointer, which shares a cache line with the guest
+    uncommitted: AtomicU64,
+    /// Owned memory standing in for the mapping (tests only); u64s keep the
+    /// control region's atomics aligned
+    backing: Option<Box<[u64]>>,
 }
 
 // SAFETY: SharedMemory handles are valid across threads
@@ -92,9 +183,41 @@ impl SharedMemory {
             base_addr: view.Value as *mut u8,
             size: expected_size,
             initialized: AtomicBool::new(false),
+            uncommitted: AtomicU64::new(0),
+            backing: None,
         })
     }
 
+    /// Back a region with owned memory instead of a QEMU file mapping.
+    ///
+    /// The control region is initialized as QEMU would: a `ring_size` byte
+    /// command ring right after it, then a `heap_size` byte heap.
+    #[cfg(test)]
+    pub fn from_vec(ring_size: u32, heap_size: u32) -> Self {
+        use crate::protocol::PVGPU_VERSION;
+
+        let size = PVGPU_CONTROL_REGION_SIZE + ring_size as usize + heap_size as usize;
+        let mut backing = vec![0u64; size.div_ceil(8)].into_boxed_slice();
+        let mut shmem = Self {
+            mapping_handle: HANDLE::default(),
+            base_addr: backing.as_mut_ptr() as *mut u8,
+            size,
+            initialized: AtomicBool::new(false),
+            uncommitted: AtomicU64::new(0),
+            backing: Some(backing),
+        };
+
+        // SAFETY: nothing else references the fresh region
+        let control = unsafe { shmem.control_region_mut() };
+        control.magic = PVGPU_MAGIC;
+        control.version = PVGPU_VERSION;
+        control.ring_offset = PVGPU_CONTROL_REGION_SIZE as u32;
+        control.ring_size = ring_size;
+        control.heap_offset = PVGPU_CONTROL_REGION_SIZE as u32 + ring_size;
+        control.heap_size = heap_size;
+        shmem
+    }
+
     /// Validate and initialize the control region
     pub fn validate_control_region(&self) -> Result<()> {
         let control = self.control_region();
@@ -118,6 +241,14 @@ impl SharedMemory {
             ));
         }
 
+        validate_region_layout(
+            control.ring_offset,
+            control.ring_size,
+            control.heap_offset,
+            control.heap_size,
+            self.size,
+        )?;
+
         info!(
             "Control region validated: version {}.{}, features 0x{:016X}",
             major,
@@ -143,14 +274,15 @@ impl SharedMemory {
         &mut *(self.base_addr as *mut ControlRegion)
     }
 
-    /// Get a slice of the command ring
+    /// Get a slice of the command ring.
+    ///
+    /// Empty if the control region places the ring outside the mapping.
     pub fn command_ring(&self) -> &[u8] {
         let control = self.control_region();
         let offset = control.ring_offset as usize;
         let size = control.ring_size as usize;
 
-        // SAFETY: Ring buffer is within the mapped region
-        unsafe { slice::from_raw_parts(self.base_addr.add(offset), size) }
+        self.slice_at_offset(offset, size).unwrap_or(&[])
     }
 
     /// Get a mutable slice of the command ring
@@ -162,17 +294,19 @@ impl SharedMemory {
         let offset = control.ring_offset as usize;
         let size = control.ring_size as usize;
 
-        slice::from_raw_parts_mut(self.base_addr.add(offset), size)
+        self.slice_at_offset_mut(offset, size).unwrap_or(&mut [])
     }
 
-    /// Get a slice of the resource heap
+    /// Get a slice of the resource heap.
+    ///
+    /// Empty if the control region places the heap outside the mapping, so
+    /// every heap access a command makes fails its bounds check.
     pub fn resource_heap(&self) -> &[u8] {
         let control = self.control_region();
         let offset = control.heap_offset as usize;
         let size = control.heap_size as usize;
 
-        // SAFETY: Heap is within the mapped region
-        unsafe { slice::from_raw_parts(self.base_addr.add(offset), size) }
+        self.slice_at_offset(offset, size).unwrap_or(&[])
     }
 
     /// Get a mutable slice of the resource heap
@@ -184,7 +318,82 @@ impl SharedMemory {
         let offset = control.heap_offset as usize;
         let size = control.heap_size as usize;
 
-        slice::from_raw_parts_mut(self.base_addr.add(offset), size)
+        self.slice_at_offset_mut(offset, size).unwrap_or(&mut [])
+    }
+
+    /// Atomically store a u64 into the resource heap for the guest to poll.
+    ///
+    /// The offset must be 8-byte aligned so the guest never observes a torn
+    /// value.
+    pub fn write_heap_u64(&self, offset: usize, value: u64) -> Result<()> {
+        let control = self.control_region();
+        let heap_offset = control.heap_offset as usize;
+        let heap_size = control.heap_size as usize;
+
+        if !offset.is_multiple_of(8) || offset.saturating_add(8) > heap_size {
+            return Err(anyhow!(
+                "Heap write at offset {} is misaligned or out of bounds",
+                offset
+            ));
+        }
+        if heap_offset.saturating_add(heap_size) > self.size {
+            return Err(anyhow!("Heap lies outside the mapped region"));
+        }
+
+        // SAFETY: Bounds checked above; the mapping base is page aligned and
+        // heap_offset is validated at handshake, so the address is 8-byte aligned
+        unsafe {
+            let ptr = self.base_addr.add(heap_offset + offset) as *const AtomicU64;
+            (*ptr).store(value, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Copy `data` into the resource heap at `offset`
+    pub fn write_heap(&self, offset: usize, data: &[u8]) -> Result<()> {
+        let control = self.control_region();
+        let heap_offset = control.heap_offset as usize;
+        let heap_size = control.heap_size as usize;
+
+        if offset.saturating_add(data.len()) > heap_size {
+            return Err(anyhow!(
+                "Heap write of {} bytes at offset {} is out of bounds",
+                data.len(),
+                offset
+            ));
+        }
+        if heap_offset.saturating_add(heap_size) > self.size {
+            return Err(anyhow!("Heap lies outside the mapped region"));
+        }
+
+        // SAFETY: Bounds checked above; `data` is host memory, so it cannot
+        // overlap the mapping
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.base_addr.add(heap_offset + offset),
+                data.len(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Zero the entire resource heap so no guest data survives into the
+    /// next session.
+    pub fn scrub_resource_heap(&self) {
+        let control = self.control_region();
+        let offset = control.heap_offset as usize;
+        let size = control.heap_size as usize;
+
+        if offset.saturating_add(size) > self.size {
+            return;
+        }
+
+        info!("Scrubbing resource heap ({} bytes)", size);
+        // SAFETY: Heap is within the mapped region, which is writable
+        unsafe {
+            std::ptr::write_bytes(self.base_addr.add(offset), 0, size);
+        }
     }
 
     /// Read commands from the ring buffer starting at the consumer pointer.
@@ -195,16 +404,21 @@ impl SharedMemory {
     ///
     /// Returns None when there are no pending commands.
     pub fn read_pending_commands(&self) -> Option<(RingData<'_>, u64)> {
-        let control = self.control_region();
-        let pending = control.pending_bytes();
+        let consumer = self.consumer_ptr();
+        let pending = self
+            .control_region()
+            .producer_ptr()
+            .saturating_sub(consumer);
 
         if pending == 0 {
             return None;
         }
 
         let ring = self.command_ring();
+        if ring.is_empty() {
+            return None;
+        }
         let ring_size = ring.len() as u64;
-        let consumer = control.consumer_ptr();
 
         // Calculate offset within ring (wrap around)
         let offset = (consumer % ring_size) as usize;
@@ -219,17 +433,17 @@ impl SharedMemory {
             ))
         } else {
             // Command straddles the wrap boundary — we need to assemble it.
-            // We need at least a command header (8 bytes) to know the command size.
+            // We need at least a command header (16 bytes) to know the command size.
             // Read the header, potentially across the wrap boundary.
             use crate::protocol::{CommandHeader, PVGPU_CMD_HEADER_SIZE};
 
             if (pending as usize) < PVGPU_CMD_HEADER_SIZE {
-                // Not enough data for even a header — shouldn't happen in practice
+                // Header not fully written yet — retry after the next doorbell
                 return None;
             }
 
             // Read the header (may straddle wrap)
-            let mut header_bytes = [0u8; 8]; // PVGPU_CMD_HEADER_SIZE = 8
+            let mut header_bytes = [0u8; PVGPU_CMD_HEADER_SIZE];
             for (i, byte) in header_bytes.iter_mut().enumerate() {
                 let idx = (offset + i) % ring.len();
                 *byte = ring[idx];
@@ -238,8 +452,13 @@ impl SharedMemory {
                 unsafe { std::ptr::read_unaligned(header_bytes.as_ptr() as *const CommandHeader) };
 
             let cmd_size = header.command_size as usize;
-            if cmd_size > pending as usize || cmd_size < PVGPU_CMD_HEADER_SIZE {
-                // Malformed command or not enough data yet
+            if cmd_size < PVGPU_CMD_HEADER_SIZE || cmd_size > ring.len() {
+                // Malformed: hand back the bare header so the caller can
+                // classify it and resynchronize
+                return Some((RingData::Wrapped(header_bytes.to_vec()), pending));
+            }
+            if cmd_size > pending as usize {
+                // Command not fully written yet
                 return None;
             }
 
@@ -254,14 +473,50 @@ impl SharedMemory {
         }
     }
 
-    /// Advance the consumer pointer after processing commands
+    /// Consumer pointer including bytes consumed but not yet committed
+    pub fn consumer_ptr(&self) -> u64 {
+        self.control_region().consumer_ptr() + self.uncommitted.load(Ordering::Relaxed)
+    }
+
+    /// Advance the consumer pointer after processing commands.
+    ///
+    /// The guest-visible pointer is only written by commit_consumer(), or
+    /// here once a quarter of the ring has been consumed.
     pub fn advance_consumer(&self, bytes: u64) {
+        let uncommitted = self.uncommitted.load(Ordering::Relaxed) + bytes;
+        self.uncommitted.store(uncommitted, Ordering::Relaxed);
+        let ring_size = self.control_region().ring_size as u64;
+        if uncommitted >= ring_size / CONSUMER_COMMIT_FRACTION {
+            self.commit_consumer();
+        }
+    }
+
+    /// Publish consumed bytes to the control region's consumer pointer.
+    ///
+    /// Call at the end of every batch, before waiting on the doorbell.
+    pub fn commit_consumer(&self) {
+        let bytes = self.uncommitted.swap(0, Ordering::Relaxed);
+        if bytes == 0 {
+            return;
+        }
         let control = self.control_region();
         let new_consumer = control.consumer_ptr() + bytes;
         control.set_consumer_ptr(new_consumer);
         debug!("Consumer pointer advanced to {}", new_consumer);
     }
 
+    /// Raise an error in the control region, committing the consumer pointer
+    /// first so the error log records where processing stopped
+    pub fn set_error(&self, code: u32, data: u32) {
+        self.commit_consumer();
+        self.control_region().set_error(code, data);
+    }
+
+    /// Publish a diagnostic message about `resource_id` for the guest
+    pub fn set_diagnostic(&self, resource_id: u32, message: &str) {
+        self.control_region().set_diagnostic(resource_id, message);
+    }
+
     /// Update the host fence completed value
     pub fn complete_fence(&self, fence_value: u64) {
         let control = self.control_region();
@@ -279,7 +534,32 @@ impl SharedMemory {
         self.initialized.load(Ordering::Acquire)
     }
 
-    /// Get a raw pointer to an offset within the shared memory
+    /// Slice of `len` bytes at `offset` in the mapped region, or None if any
+    /// of it lies outside the region
+    pub fn slice_at_offset(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        if offset.checked_add(len)? > self.size {
+            return None;
+        }
+        // SAFETY: Bounds checked above
+        Some(unsafe { slice::from_raw_parts(self.base_addr.add(offset), len) })
+    }
+
+    /// Mutable slice of `len` bytes at `offset` in the mapped region, or
+    /// None if any of it lies outside the region
+    ///
+    /// # Safety
+    /// Caller must ensure proper synchronization
+    pub unsafe fn slice_at_offset_mut(&mut self, offset: usize, len: usize) -> Option<&mut [u8]> {
+        if offset.checked_add(len)? > self.size {
+            return None;
+        }
+        Some(slice::from_raw_parts_mut(self.base_addr.add(offset), len))
+    }
+
+    /// Get a raw pointer to an offset within the shared memory.
+    ///
+    /// Unchecked in release builds; offsets that come from the guest go
+    /// through `slice_at_offset` instead.
     ///
     /// # Safety
     /// Caller must ensure offset is within bounds
@@ -291,7 +571,7 @@ impl SharedMemory {
 
 impl Drop for SharedMemory {
     fn drop(&mut self) {
-        if !self.base_addr.is_null() {
+        if !self.base_addr.is_null() && self.backing.is_none() {
             info!("Unmapping shared memory");
             unsafe {
                 let view = MEMORY_MAPPED_VIEW_ADDRESS {
@@ -318,4 +598,245 @@ mod tests {
         // Just verify the struct is properly sized
         assert!(std::mem::size_of::<SharedMemory>() > 0);
     }
+
+    /// Write a `size` byte command of `command_type` at the producer pointer,
+    /// wrapping at the end of the ring, and publish it
+    fn produce(shmem: &mut SharedMemory, command_type: u32, size: u32) {
+        let mut command = vec![0xABu8; size as usize];
+        command[0..4].copy_from_slice(&command_type.to_le_bytes());
+        command[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let producer = shmem.control_region().producer_ptr();
+        // SAFETY: the test is the only user of the region
+        let ring = unsafe { shmem.command_ring_mut() };
+        let ring_size = ring.len();
+        for (i, byte) in command.iter().enumerate() {
+            ring[(producer as usize + i) % ring_size] = *byte;
+        }
+        shmem
+            .control_region()
+            .set_producer_ptr(producer + size as u64);
+    }
+
+    #[test]
+    fn test_from_vec_control_region() {
+        let shmem = SharedMemory::from_vec(0x1000, 0x1000);
+        shmem.validate_control_region().unwrap();
+        assert!(shmem.is_initialized());
+        assert_eq!(shmem.command_ring().len(), 0x1000);
+        assert_eq!(shmem.resource_heap().len(), 0x1000);
+        assert!(shmem.read_pending_commands().is_none());
+    }
+
+    #[test]
+    fn test_slice_at_offset_bounds() {
+        let mut shmem = SharedMemory::from_vec(0x1000, 0x1000);
+        let size = shmem.size();
+        assert_eq!(shmem.slice_at_offset(0, size).map(<[u8]>::len), Some(size));
+        assert_eq!(shmem.slice_at_offset(size, 0).map(<[u8]>::len), Some(0));
+        assert!(shmem.slice_at_offset(size - 4, 8).is_none());
+        assert!(shmem.slice_at_offset(usize::MAX, 2).is_none());
+        // SAFETY: the test is the only user of the region
+        assert!(unsafe { shmem.slice_at_offset_mut(size, 1) }.is_none());
+
+        // A heap placed past the mapping reads as empty
+        unsafe { shmem.control_region_mut() }.heap_offset = size as u32;
+        assert!(shmem.resource_heap().is_empty());
+        assert!(shmem.write_heap_u64(0, 1).is_err());
+    }
+
+    #[test]
+    fn test_ring_wraparound() {
+        let mut shmem = SharedMemory::from_vec(256, 64);
+
+        // Fill up to 32 bytes short of the end of the ring
+        produce(&mut shmem, 1, 224);
+        let (data, pending) = shmem.read_pending_commands().unwrap();
+        assert!(matches!(data, RingData::Contiguous(_)));
+        assert_eq!(pending, 224);
+        shmem.advance_consumer(224);
+
+        // A 64-byte command straddles the wrap and comes back in one piece
+        produce(&mut shmem, 2, 64);
+        let (data, pending) = shmem.read_pending_commands().unwrap();
+        assert_eq!(pending, 64);
+        match data {
+            RingData::Wrapped(bytes) => {
+                assert_eq!(bytes.len(), 64);
+                assert_eq!(&bytes[0..8], &[2, 0, 0, 0, 64, 0, 0, 0]);
+                assert!(bytes[8..].iter().all(|&b| b == 0xAB));
+            }
+            RingData::Contiguous(_) => panic!("command across the wrap was not copied"),
+        }
+        shmem.advance_consumer(64);
+        assert!(shmem.read_pending_commands().is_none());
+        assert_eq!(shmem.consumer_ptr(), 288);
+    }
+
+    #[test]
+    fn test_ring_incomplete_command() {
+        let mut shmem = SharedMemory::from_vec(256, 64);
+        produce(&mut shmem, 1, 248);
+        shmem.advance_consumer(248);
+
+        // Less than a header across the wrap
+        produce(&mut shmem, 2, 64);
+        let producer = shmem.control_region().producer_ptr();
+        shmem.control_region().set_producer_ptr(248 + 12);
+        assert!(shmem.read_pending_commands().is_none());
+
+        // A header, but only part of the command it announces
+        shmem.control_region().set_producer_ptr(248 + 32);
+        assert!(shmem.read_pending_commands().is_none());
+
+        shmem.control_region().set_producer_ptr(producer);
+        assert_eq!(shmem.read_pending_commands().unwrap().1, 64);
+    }
+
+    #[test]
+    fn test_ring_malformed_size_across_wrap() {
+        let mut shmem = SharedMemory::from_vec(256, 64);
+        produce(&mut shmem, 1, 248);
+        shmem.advance_consumer(248);
+
+        // A header claiming less than its own size is handed back bare
+        produce(&mut shmem, 2, 16);
+        // SAFETY: the test is the only user of the region
+        let ring = unsafe { shmem.command_ring_mut() };
+        ring[252..256].copy_from_slice(&4u32.to_le_bytes());
+        match shmem.read_pending_commands().unwrap().0 {
+            RingData::Wrapped(bytes) => assert_eq!(bytes.len(), 16),
+            RingData::Contiguous(_) => panic!("expected the bare header"),
+        }
+    }
+
+    #[test]
+    fn test_ring_oversized_command() {
+        use crate::protocol::{command_extent, CommandExtent};
+
+        let mut shmem = SharedMemory::from_vec(256, 64);
+        let ring_size = shmem.control_region().ring_size as u64;
+
+        // A header claiming more than the whole ring is malformed rather
+        // than forever incomplete
+        produce(&mut shmem, 2, 16);
+        // SAFETY: the test is the only user of the region
+        let ring = unsafe { shmem.command_ring_mut() };
+        ring[4..8].copy_from_slice(&512u32.to_le_bytes());
+        let (data, pending) = shmem.read_pending_commands().unwrap();
+        assert_eq!(
+            command_extent(data.as_slice(), ring_size),
+            CommandExtent::Malformed
+        );
+        shmem.advance_consumer(pending);
+
+        // The same across the wrap
+        produce(&mut shmem, 1, 232);
+        shmem.advance_consumer(232);
+        produce(&mut shmem, 2, 16);
+        // SAFETY: the test is the only user of the region
+        let ring = unsafe { shmem.command_ring_mut() };
+        ring[252..256].copy_from_slice(&512u32.to_le_bytes());
+        let (data, _) = shmem.read_pending_commands().unwrap();
+        assert_eq!(data.as_slice().len(), 16);
+        assert_eq!(
+            command_extent(data.as_slice(), ring_size),
+            CommandExtent::Malformed
+        );
+    }
+
+    #[test]
+    fn test_consumer_commit_batching() {
+        let mut shmem = SharedMemory::from_vec(256, 64);
+        for _ in 0..4 {
+            produce(&mut shmem, 1, 16);
+        }
+
+        // Below a quarter of the ring, progress stays local
+        shmem.advance_consumer(16);
+        shmem.advance_consumer(16);
+        assert_eq!(shmem.control_region().consumer_ptr(), 0);
+        assert_eq!(shmem.consumer_ptr(), 32);
+        assert_eq!(shmem.read_pending_commands().unwrap().1, 32);
+
+        // A quarter of the ring forces a commit
+        shmem.advance_consumer(32);
+        assert_eq!(shmem.control_region().consumer_ptr(), 64);
+
+        produce(&mut shmem, 1, 16);
+        shmem.advance_consumer(16);
+        shmem.commit_consumer();
+        assert_eq!(shmem.control_region().consumer_ptr(), 80);
+        assert_eq!(shmem.control_region().pending_bytes(), 0);
+
+        // Errors record the committed position
+        produce(&mut shmem, 1, 16);
+        shmem.advance_consumer(16);
+        shmem.set_error(crate::protocol::PVGPU_ERROR_INVALID_COMMAND, 0);
+        assert_eq!(shmem.control_region().error_record(0).command_offset, 96);
+    }
+
+    #[test]
+    fn test_write_heap_u64_bounds() {
+        let shmem = SharedMemory::from_vec(256, 64);
+        shmem.write_heap_u64(56, 0x1122_3344_5566_7788).unwrap();
+        assert_eq!(
+            &shmem.resource_heap()[56..64],
+            &0x1122_3344_5566_7788u64.to_le_bytes()
+        );
+        // Past the end of the heap, or misaligned
+        assert!(shmem.write_heap_u64(64, 1).is_err());
+        assert!(shmem.write_heap_u64(usize::MAX - 4, 1).is_err());
+        assert!(shmem.write_heap_u64(4, 1).is_err());
+    }
+
+    #[test]
+    fn test_write_heap_bounds() {
+        let shmem = SharedMemory::from_vec(256, 64);
+        shmem.write_heap(60, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(&shmem.resource_heap()[60..64], &[1, 2, 3, 4]);
+        shmem.write_heap(64, &[]).unwrap();
+        assert!(shmem.write_heap(61, &[1, 2, 3, 4]).is_err());
+        assert!(shmem.write_heap(usize::MAX, &[1]).is_err());
+    }
+
+    #[test]
+    fn test_validate_region_layout() {
+        let control = PVGPU_CONTROL_REGION_SIZE as u32;
+        let size = 0x10_0000;
+        validate_region_layout(control, 0x1000, control + 0x1000, 0x1000, size).unwrap();
+        // Heap reaching the end of the mapping exactly
+        validate_region_layout(control, 0x1000, control + 0x1000, 0xE000, size).unwrap();
+
+        // Past the end of the mapping
+        assert!(validate_region_layout(control, 0x1000, control + 0x1000, 0xF_F000, size).is_err());
+        assert!(validate_region_layout(control, u32::MAX, control, 0x1000, size).is_err());
+        // Inside the control region, or an empty ring
+        assert!(validate_region_layout(0, 0x1000, control + 0x1000, 0x1000, size).is_err());
+        assert!(validate_region_layout(control, 0, control, 0x1000, size).is_err());
+        // Overlapping
+        assert!(validate_region_layout(control, 0x1000, control + 0x800, 0x1000, size).is_err());
+        // Misaligned heap
+        assert!(validate_region_layout(control, 0x1000, control + 0x1004, 0x1000, size).is_err());
+
+        let mut shmem = SharedMemory::from_vec(0x1000, 0x1000);
+        unsafe { shmem.control_region_mut() }.heap_size = 0x2000;
+        assert!(shmem.validate_control_region().is_err());
+        assert!(!shmem.is_initialized());
+    }
+
+    #[test]
+    fn test_validate_shmem_size() {
+        let max = 1024 * 1024 * 1024;
+        assert_eq!(
+            validate_shmem_size(0x10000000, max).unwrap(),
+            0x10000000usize
+        );
+        // Too small to hold control region + ring + heap
+        assert!(validate_shmem_size(0x1000, max).is_err());
+        // Not granularity aligned
+        assert!(validate_shmem_size(0x10000000 + 0x1000, max).is_err());
+        // Above configured maximum
+        assert!(validate_shmem_size(0x100_0000_0000, max).is_err());
+    }
 }
@@ -0,0 +1,130 @@
+//! sRGB View Module
+//!
+//! Helpers for viewing 8-bit UNORM textures through their `_SRGB` format.
+//! D3D11 only allows a view format that differs from the resource format when
+//! the resource is TYPELESS, so sRGB-viewable textures are created typeless.
+//! The FLIP-model swapchain cannot use an `_SRGB` buffer format at all, so its
+//! backbuffer stays UNORM and only the render target view is sRGB.
+//!
+//! UNORM and `_SRGB` share a format family and store identical bytes, so
+//! copies between them (e.g. when presenting) need no conversion.
+
+use windows::Win32::Graphics::Direct3D::D3D11_SRV_DIMENSION_TEXTURE2D;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_RENDER_TARGET_VIEW_DESC, D3D11_RENDER_TARGET_VIEW_DESC_0, D3D11_RTV_DIMENSION_TEXTURE2D,
+    D3D11_SHADER_RESOURCE_VIEW_DESC, D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_TEX2D_RTV,
+    D3D11_TEX2D_SRV,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_TYPELESS, DXGI_FORMAT_B8G8R8A8_UNORM,
+    DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, DXGI_FORMAT_B8G8R8X8_TYPELESS, DXGI_FORMAT_B8G8R8X8_UNORM,
+    DXGI_FORMAT_B8G8R8X8_UNORM_SRGB, DXGI_FORMAT_R8G8B8A8_TYPELESS, DXGI_FORMAT_R8G8B8A8_UNORM,
+    DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+};
+
+/// `_SRGB` view format for an 8-bit UNORM (or already sRGB) format
+pub fn srgb_view_format(format: DXGI_FORMAT) -> Option<DXGI_FORMAT> {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB)
+        }
+        DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_B8G8R8A8_UNORM_SRGB)
+        }
+        DXGI_FORMAT_B8G8R8X8_UNORM | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_B8G8R8X8_UNORM_SRGB)
+        }
+        _ => None,
+    }
+}
+
+/// TYPELESS resource format that accepts both UNORM and `_SRGB` views
+pub fn typeless_format(format: DXGI_FORMAT) -> Option<DXGI_FORMAT> {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_R8G8B8A8_TYPELESS)
+        }
+        DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_B8G8R8A8_TYPELESS)
+        }
+        DXGI_FORMAT_B8G8R8X8_UNORM | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => {
+            Some(DXGI_FORMAT_B8G8R8X8_TYPELESS)
+        }
+        _ => None,
+    }
+}
+
+/// UNORM member of an 8-bit format's family (UNORM, `_SRGB` or TYPELESS)
+pub fn unorm_format(format: DXGI_FORMAT) -> Option<DXGI_FORMAT> {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM
+        | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        | DXGI_FORMAT_R8G8B8A8_TYPELESS => Some(DXGI_FORMAT_R8G8B8A8_UNORM),
+        DXGI_FORMAT_B8G8R8A8_UNORM
+        | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        | DXGI_FORMAT_B8G8R8A8_TYPELESS => Some(DXGI_FORMAT_B8G8R8A8_UNORM),
+        DXGI_FORMAT_B8G8R8X8_UNORM
+        | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB
+        | DXGI_FORMAT_B8G8R8X8_TYPELESS => Some(DXGI_FORMAT_B8G8R8X8_UNORM),
+        _ => None,
+    }
+}
+
+/// Render target view description for mip 0 of a 2D texture
+pub fn rtv_desc(format: DXGI_FORMAT) -> D3D11_RENDER_TARGET_VIEW_DESC {
+    D3D11_RENDER_TARGET_VIEW_DESC {
+        Format: format,
+        ViewDimension: D3D11_RTV_DIMENSION_TEXTURE2D,
+        Anonymous: D3D11_RENDER_TARGET_VIEW_DESC_0 {
+            Texture2D: D3D11_TEX2D_RTV { MipSlice: 0 },
+        },
+    }
+}
+
+/// Shader resource view description covering a single-mip 2D texture
+pub fn srv_desc(format: DXGI_FORMAT) -> D3D11_SHADER_RESOURCE_VIEW_DESC {
+    D3D11_SHADER_RESOURCE_VIEW_DESC {
+        Format: format,
+        ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
+        Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+            Texture2D: D3D11_TEX2D_SRV {
+                MostDetailedMip: 0,
+                MipLevels: 1,
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R16G16B16A16_FLOAT;
+
+    #[test]
+    fn test_srgb_formats() {
+        assert_eq!(
+            srgb_view_format(DXGI_FORMAT_R8G8B8A8_UNORM),
+            Some(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB)
+        );
+        assert_eq!(
+            srgb_view_format(DXGI_FORMAT_B8G8R8A8_UNORM_SRGB),
+            Some(DXGI_FORMAT_B8G8R8A8_UNORM_SRGB)
+        );
+        assert_eq!(
+            typeless_format(DXGI_FORMAT_R8G8B8A8_UNORM),
+            Some(DXGI_FORMAT_R8G8B8A8_TYPELESS)
+        );
+        // Float formats are linear; there is no sRGB view
+        assert_eq!(srgb_view_format(DXGI_FORMAT_R16G16B16A16_FLOAT), None);
+        assert_eq!(typeless_format(DXGI_FORMAT_R16G16B16A16_FLOAT), None);
+        assert_eq!(
+            unorm_format(DXGI_FORMAT_B8G8R8A8_TYPELESS),
+            Some(DXGI_FORMAT_B8G8R8A8_UNORM)
+        );
+        assert_eq!(
+            unorm_format(DXGI_FORMAT_R8G8B8A8_UNORM_SRGB),
+            Some(DXGI_FORMAT_R8G8B8A8_UNORM)
+        );
+        assert_eq!(unorm_format(DXGI_FORMAT_R16G16B16A16_FLOAT), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Video Format Module
+//!
+//! Support for planar YUV textures (NV12, P010) produced by guest video
+//! decoders. Provides plane layout helpers for texture upload and a small
+//! shader pipeline that converts YUV to RGB before presentation.
+
+use anyhow::{anyhow, Result};
+use tracing::{debug, info};
+use windows::core::{s, Interface, PCSTR};
+use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
+use windows::Win32::Graphics::Direct3D::{
+    ID3DBlob, D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D11_SRV_DIMENSION_TEXTURE2D,
+    D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_11_1,
+};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device, ID3D11Device1, ID3D11DeviceContext, ID3D11DeviceContext1, ID3D11PixelShader,
+    ID3D11RenderTargetView, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D,
+    ID3D11VertexShader, ID3DDeviceContextState, D3D11_BIND_RENDER_TARGET,
+    D3D11_BIND_SHADER_RESOURCE, D3D11_COMPARISON_NEVER, D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+    D3D11_SAMPLER_DESC, D3D11_SDK_VERSION, D3D11_SHADER_RESOURCE_VIEW_DESC,
+    D3D11_SHADER_RESOURCE_VIEW_DESC_0, D3D11_TEX2D_SRV, D3D11_TEXTURE2D_DESC,
+    D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_USAGE_DEFAULT, D3D11_VIEWPORT,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_NV12, DXGI_FORMAT_P010, DXGI_FORMAT_R16G16_UNORM,
+    DXGI_FORMAT_R16_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8_UNORM,
+    DXGI_FORMAT_R8_UNORM, DXGI_SAMPLE_DESC,
+};
+
+// ============================================================================
+// Format Helpers
+// ============================================================================
+
+/// Whether the format is a planar YUV video format handled by this module
+pub fn is_planar_yuv(format: DXGI_FORMAT) -> bool {
+    format == DXGI_FORMAT_NV12 || format == DXGI_FORMAT_P010
+}
+
+/// Bytes per luma sample for planar YUV formats (NV12: 1, P010: 2)
+pub fn planar_bytes_per_sample(format: DXGI_FORMAT) -> Option<u32> {
+    match format {
+        DXGI_FORMAT_NV12 => Some(1),
+        DXGI_FORMAT_P010 => Some(2),
+        _ => None,
+    }
+}
+
+/// View formats for the (luma, chroma) planes of a planar YUV texture
+pub fn plane_view_formats(format: DXGI_FORMAT) -> Option<(DXGI_FORMAT, DXGI_FORMAT)> {
+    match format {
+        DXGI_FORMAT_NV12 => Some((DXGI_FORMAT_R8_UNORM, DXGI_FORMAT_R8G8_UNORM)),
+        DXGI_FORMAT_P010 => Some((DXGI_FORMAT_R16_UNORM, DXGI_FORMAT_R16G16_UNORM)),
+        _ => None,
+    }
+}
+
+/// Total upload size of a tightly packed planar YUV image.
+///
+/// The luma plane is `height` rows of `pitch` bytes, followed immediately by
+/// the interleaved chroma plane of `height / 2` rows with the same pitch.
+pub fn planar_data_size(format: DXGI_FORMAT, width: u32, height: u32) -> Option<usize> {
+    let pitch = width as usize * planar_bytes_per_sample(format)? as usize;
+    Some(pitch * height as usize + pitch * (height as usize / 2))
+}
+
+// ============================================================================
+// YUV → RGB Conversion
+// ============================================================================
+
+/// Fullscreen-triangle conversion shader (BT.709, limited range).
+///
+/// P010 stores 10-bit samples in the high bits of each 16-bit word, so the
+/// UNORM view yields the same normalized value as an 8-bit sample and one
+/// shader serves both formats.
+const YUV_SHADER_HLSL: &str = r#"
+Texture2D<float>  LumaPlane   : register(t0);
+Texture2D<float2> ChromaPlane : register(t1);
+SamplerState      LinearClamp : register(s0);
+
+struct VsOut {
+    float4 pos : SV_Position;
+    float2 uv  : TEXCOORD0;
+};
+
+VsOut vs_main(uint id : SV_VertexID) {
+    VsOut o;
+    o.uv = float2((id << 1) & 2, id & 2);
+    o.pos = float4(o.uv * float2(2.0, -2.0) + float2(-1.0, 1.0), 0.0, 1.0);
+    return o;
+}
+
+float4 ps_main(VsOut i) : SV_Target {
+    float y = (LumaPlane.Sample(LinearClamp, i.uv) - 16.0 / 255.0) * (255.0 / 219.0);
+    float2 c = (ChromaPlane.Sample(LinearClamp, i.uv) - 128.0 / 255.0) * (255.0 / 224.0);
+    float3 rgb = float3(
+        y + 1.5748 * c.y,
+        y - 0.1873 * c.x - 0.4681 * c.y,
+        y + 1.8556 * c.x);
+    return float4(saturate(rgb), 1.0);
+}
+"#;
+
+/// Compile one entry point of the conversion shader
+fn compile_shader(entry: PCSTR, target: PCSTR) -> Result<Vec<u8>> {
+    let mut code: Option<ID3DBlob> = None;
+    let mut errors: Option<ID3DBlob> = None;
+    let result = unsafe {
+        D3DCompile(
+            YUV_SHADER_HLSL.as_ptr() as *const _,
+            YUV_SHADER_HLSL.len(),
+            s!("pvgpu_yuv"),
+            None,
+            None,
+            entry,
+            target,
+            0,
+            0,
+            &mut code,
+            Some(&mut errors),
+        )
+    };
+
+    if let Err(e) = result {
+        let message = errors
+            .map(|blob| unsafe {
+                let bytes = std::slice::from_raw_parts(
+                    blob.GetBufferPointer() as *const u8,
+                    blob.GetBufferSize(),
+                );
+                String::from_utf8_lossy(bytes).into_owned()
+            })
+            .unwrap_or_default();
+        return Err(anyhow!("YUV shader compile failed: {:?} {}", e, message));
+    }
+
+    let code = code.ok_or_else(|| anyhow!("YUV shader compile returned no bytecode"))?;
+    let bytes = unsafe {
+        std::slice::from_raw_parts(code.GetBufferPointer() as *const u8, code.GetBufferSize())
+    };
+    Ok(bytes.to_vec())
+}
+
+/// Converts planar YUV textures to RGBA for presentation.
+///
+/// Draws run inside a private device context state so the guest's pipeline
+/// bindings on the shared immediate context are left untouched.
+pub struct YuvConverter {
+    vertex_shader: ID3D11VertexShader,
+    pixel_shader: ID3D11PixelShader,
+    sampler: ID3D11SamplerState,
+    context_state: ID3DDeviceContextState,
+    /// RGBA output texture, recreated when the video size changes
+    output: Option<(ID3D11Texture2D, ID3D11RenderTargetView, u32, u32)>,
+}
+
+impl YuvConverter {
+    /// Compile the conversion shaders and create pipeline objects
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let vs_bytecode = compile_shader(s!("vs_main"), s!("vs_5_0"))?;
+        let ps_bytecode = compile_shader(s!("ps_main"), s!("ps_5_0"))?;
+
+        let mut vertex_shader: Option<ID3D11VertexShader> = None;
+        let mut pixel_shader: Option<ID3D11PixelShader> = None;
+        unsafe {
+            device.CreateVertexShader(&vs_bytecode, None, Some(&mut vertex_shader))?;
+            device.CreatePixelShader(&ps_bytecode, None, Some(&mut pixel_shader))?;
+        }
+
+        let sampler_desc = D3D11_SAMPLER_DESC {
+            Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+            AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: 1,
+            ComparisonFunc: D3D11_COMPARISON_NEVER,
+            BorderColor: [0.0; 4],
+            MinLOD: 0.0,
+            MaxLOD: f32::MAX,
+        };
+        let mut sampler: Option<ID3D11SamplerState> = None;
+        unsafe {
+            device.CreateSamplerState(&sampler_desc, Some(&mut sampler))?;
+        }
+
+        let device1: ID3D11Device1 = device.cast()?;
+        let mut context_state: Option<ID3DDeviceContextState> = None;
+        unsafe {
+            device1.CreateDeviceContextState(
+                0,
+                &[D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0],
+                D3D11_SDK_VERSION,
+                &ID3D11Device1::IID,
+                None,
+                Some(&mut context_state),
+            )?;
+        }
+
+        info!("YUV converter initialized");
+
+        Ok(Self {
+            vertex_shader: vertex_shader
+                .ok_or_else(|| anyhow!("Failed to create YUV vertex shader"))?,
+            pixel_shader: pixel_shader
+                .ok_or_else(|| anyhow!("Failed to create YUV pixel shader"))?,
+            sampler: sampler.ok_or_else(|| anyhow!("Failed to create YUV sampler"))?,
+            context_state: context_state
+                .ok_or_else(|| anyhow!("Failed to create YUV context state"))?,
+            output: None,
+        })
+    }
+
+    /// Make sure the RGBA output texture matches the video dimensions
+    fn ensure_output(
+        &mut self,
+        device: &ID3D11Device,
+        width: u32,
+        height: u32,
+    ) -> Result<(ID3D11Texture2D, ID3D11RenderTargetView)> {
+        if let Some((ref texture, ref rtv, w, h)) = self.output {
+            if w == width && h == height {
+                return Ok((texture.clone(), rtv.clone()));
+            }
+        }
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: (D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0) as u32,
+            CPUAccessFlags: Default::default(),
+            MiscFlags: Default::default(),
+        };
+
+        let mut texture: Option<ID3D11Texture2D> = None;
+        unsafe {
+            device.CreateTexture2D(&desc, None, Some(&mut texture))?;
+        }
+        let texture = texture.ok_or_else(|| anyhow!("Failed to create YUV output texture"))?;
+
+        let mut rtv: Option<ID3D11RenderTargetView> = None;
+        unsafe {
+            device.CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+        }
+        let rtv = rtv.ok_or_else(|| anyhow!("Failed to create YUV output RTV"))?;
+
+        debug!("YUV output texture resized to {}x{}", width, height);
+        self.output = Some((texture.clone(), rtv.clone(), width, height));
+        Ok((texture, rtv))
+    }
+
+    /// Create a shader resource view over one plane of the source texture
+    fn plane_view(
+        device: &ID3D11Device,
+        source: &ID3D11Texture2D,
+        format: DXGI_FORMAT,
+    ) -> Result<ID3D11ShaderResourceView> {
+        let desc = D3D11_SHADER_RESOURCE_VIEW_DESC {
+            Format: format,
+            ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
+            Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2D: D3D11_TEX2D_SRV {
+                    MostDetailedMip: 0,
+                    MipLevels: 1,
+                },
+            },
+        };
+        let mut srv: Option<ID3D11ShaderResourceView> = None;
+        unsafe {
+            device.CreateShaderResourceView(source, Some(&desc), Some(&mut srv))?;
+        }
+        srv.ok_or_else(|| anyhow!("Failed to create plane view"))
+    }
+
+    /// Convert a planar YUV texture into an RGBA texture of the same size
+    pub fn convert(
+        &mut self,
+        device: &ID3D11Device,
+        context: &ID3D11DeviceContext,
+        source: &ID3D11Texture2D,
+    ) -> Result<ID3D11Texture2D> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            source.GetDesc(&mut desc);
+        }
+
+        let (luma_format, chroma_format) = plane_view_formats(desc.Format)
+            .ok_or_else(|| anyhow!("Not a planar YUV format: {:?}", desc.Format))?;
+
+        let luma = Self::plane_view(device, source, luma_format)?;
+        let chroma = Self::plane_view(device, source, chroma_format)?;
+        let (output, rtv) = self.ensure_output(device, desc.Width, desc.Height)?;
+
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: desc.Width as f32,
+            Height: desc.Height as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+
+        let context1: ID3D11DeviceContext1 = context.cast()?;
+        let mut previous_state: Option<ID3DDeviceContextState> = None;
+        unsafe {
+            context1.SwapDeviceContextState(&self.context_state, Some(&mut previous_state));
+
+            context.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            context.IASetInputLayout(None);
+            context.VSSetShader(&self.vertex_shader, None);
+            context.PSSetShader(&self.pixel_shader, None);
+            context.PSSetShaderResources(0, Some(&[Some(luma), Some(chroma)]));
+            context.PSSetSamplers(0, Some(&[Some(self.sampler.clone())]));
+            context.OMSetRenderTargets(Some(&[Some(rtv)]), None);
+            context.RSSetViewports(Some(&[viewport]));
+            context.Draw(3, 0);
+
+            // Drop our references to the source before handing state back
+            context.PSSetShaderResources(0, Some(&[None, None]));
+            context.OMSetRenderTargets(None, None);
+
+            context1.SwapDeviceContextState(previous_state.as_ref(), None);
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM;
+
+    #[test]
+    fn test_planar_data_size() {
+        // NV12 1920x1080: 1920*1080 luma + 1920*540 chroma
+        assert_eq!(
+            planar_data_size(DXGI_FORMAT_NV12, 1920, 1080),
+            Some(1920 * 1080 * 3 / 2)
+        );
+        // P010 uses 16-bit samples
+        assert_eq!(
+            planar_data_size(DXGI_FORMAT_P010, 1920, 1080),
+            Some(1920 * 2 * 1080 * 3 / 2)
+        );
+        assert_eq!(planar_data_size(DXGI_FORMAT_B8G8R8A8_UNORM, 16, 16), None);
+        assert!(is_planar_yuv(DXGI_FORMAT_NV12));
+        assert!(!is_planar_yuv(DXGI_FORMAT_R8G8B8A8_UNORM));
+    }
+}
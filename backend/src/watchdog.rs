@@ -0,0 +1,184 @@
+//! Watchdog Module
+//!
+//! Detects a command loop stuck inside a D3D11 call, e.g. on a draw whose
+//! shader never terminates. The main loop brackets each command and present
+//! with `begin`/`end`; a background thread reports the device lost to the
+//! guest once one of them has run longer than the timeout, since the consumer
+//! pointer cannot advance meanwhile. The main loop recovers the device when
+//! the call finally returns, normally after Windows resets the GPU.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tracing::{error, info};
+
+use crate::protocol::{PVGPU_ERROR_DEVICE_LOST, PVGPU_STATUS_DEVICE_LOST};
+use crate::shmem::SharedMemory;
+
+/// Bounds on how often the watchdog thread wakes up
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Progress of the command loop, shared with the watchdog thread
+pub struct WatchdogState {
+    epoch: Instant,
+    /// Milliseconds after `epoch` the current command began, plus one
+    /// (0 = no command in flight)
+    started_ms: AtomicU64,
+    command_type: AtomicU32,
+    resource_id: AtomicU32,
+    tripped: AtomicBool,
+}
+
+impl WatchdogState {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            started_ms: AtomicU64::new(0),
+            command_type: AtomicU32::new(0),
+            resource_id: AtomicU32::new(0),
+            tripped: AtomicBool::new(false),
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    /// Mark a command as in flight
+    pub fn begin(&self, command_type: u32, resource_id: u32) {
+        self.command_type.store(command_type, Ordering::Relaxed);
+        self.resource_id.store(resource_id, Ordering::Relaxed);
+        self.started_ms.store(self.now_ms() + 1, Ordering::Release);
+    }
+
+    /// Mark the command in flight as finished
+    pub fn end(&self) {
+        self.started_ms.store(0, Ordering::Release);
+    }
+
+    /// The command in flight for at least `timeout` as of `now_ms`, as
+    /// (command_type, resource_id, elapsed ms)
+    fn stalled(&self, now_ms: u64, timeout: Duration) -> Option<(u32, u32, u64)> {
+        let started = self.started_ms.load(Ordering::Acquire);
+        if started == 0 {
+            return None;
+        }
+        let elapsed = now_ms.saturating_sub(started - 1);
+        if elapsed < timeout.as_millis() as u64 {
+            return None;
+        }
+        Some((
+            self.command_type.load(Ordering::Relaxed),
+            self.resource_id.load(Ordering::Relaxed),
+            elapsed,
+        ))
+    }
+
+    /// Whether the watchdog has fired and the device is not yet recovered
+    pub fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::Acquire)
+    }
+
+    /// Whether the watchdog fired since the last call; the device must be
+    /// recovered if so
+    pub fn take_tripped(&self) -> bool {
+        self.tripped.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Background thread watching a `WatchdogState`; stopped on drop
+pub struct Watchdog {
+    state: Arc<WatchdogState>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start watching. A command running for `timeout` sets
+    /// PVGPU_STATUS_DEVICE_LOST and reports PVGPU_ERROR_DEVICE_LOST with the
+    /// command's resource ID.
+    pub fn start(timeout: Duration, shmem: Arc<SharedMemory>) -> Result<Self> {
+        let state = Arc::new(WatchdogState::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let poll_interval = (timeout / 4).clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL);
+
+        let thread = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("pvgpu-watchdog".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        thread::sleep(poll_interval);
+                        // Report a stuck command once, until the main loop
+                        // has recovered from it
+                        if state.tripped() {
+                            continue;
+                        }
+                        let Some((command_type, resource_id, elapsed)) =
+                            state.stalled(state.now_ms(), timeout)
+                        else {
+                            continue;
+                        };
+                        error!(
+                            "GPU watchdog: command 0x{:04X} (resource {}) has not completed after {} ms, reporting device lost",
+                            command_type, resource_id, elapsed
+                        );
+                        let control = shmem.control_region();
+                        control.set_status_flag(PVGPU_STATUS_DEVICE_LOST);
+                        control.set_error(PVGPU_ERROR_DEVICE_LOST, resource_id);
+                        state.tripped.store(true, Ordering::Release);
+                    }
+                })?
+        };
+
+        info!("GPU watchdog started ({} ms timeout)", timeout.as_millis());
+        Ok(Self {
+            state,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Progress state the main loop updates
+    pub fn state(&self) -> &WatchdogState {
+        &self.state
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stalled() {
+        let state = WatchdogState::new();
+        let timeout = Duration::from_millis(100);
+        assert_eq!(state.stalled(1000, timeout), None);
+
+        state.begin(0x0201, 7);
+        let started = state.started_ms.load(Ordering::Relaxed) - 1;
+        assert_eq!(state.stalled(started + 99, timeout), None);
+        assert_eq!(
+            state.stalled(started + 100, timeout),
+            Some((0x0201, 7, 100))
+        );
+
+        state.end();
+        assert_eq!(state.stalled(started + 1000, timeout), None);
+        assert!(!state.take_tripped());
+    }
+}
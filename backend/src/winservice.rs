@@ -0,0 +1,215 @@
+//! Windows Service Module
+//!
+//! Runs the backend under the Service Control Manager when started with
+//! `--service`, so it can start at boot and keep running without a logged-in
+//! session. STOP and SHUTDOWN controls set the same shutdown flag as Ctrl+C
+//! does in console mode.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{anyhow, Result};
+use tracing::{error, info, warn};
+use windows::core::{w, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, GENERIC_READ,
+    GENERIC_WRITE, NO_ERROR,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_NONE, OPEN_EXISTING,
+};
+use windows::Win32::System::Services::{
+    RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS,
+    SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+    SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+};
+
+use crate::config::Config;
+
+/// Name the service is registered under
+pub const SERVICE_NAME: PCWSTR = w!("PvgpuBackend");
+
+/// Time the SCM should allow for the main loop to wind down
+const STOP_WAIT_HINT_MS: u32 = 10_000;
+
+/// Backend entry point run on the service thread
+pub type ServiceBody = fn(Config, Arc<AtomicBool>) -> Result<()>;
+
+/// State shared between the service thread and the control handler, which
+/// the SCM calls without any user context
+struct ServiceContext {
+    run: ServiceBody,
+    config: Config,
+    shutdown: Arc<AtomicBool>,
+    status_handle: AtomicUsize,
+    checkpoint: AtomicU32,
+}
+
+static CONTEXT: OnceLock<ServiceContext> = OnceLock::new();
+
+impl ServiceContext {
+    /// Report a state change to the SCM
+    fn report(&self, state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+        let handle = self.status_handle.load(Ordering::Acquire);
+        if handle == 0 {
+            return;
+        }
+
+        let controls_accepted = match state {
+            SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+            _ => 0,
+        };
+        let (checkpoint, wait_hint) = match state {
+            SERVICE_STOP_PENDING => (
+                self.checkpoint.fetch_add(1, Ordering::Relaxed) + 1,
+                STOP_WAIT_HINT_MS,
+            ),
+            _ => (0, 0),
+        };
+        let (win32_exit_code, specific_exit_code) = match exit_code {
+            0 => (NO_ERROR.0, 0),
+            code => (ERROR_SERVICE_SPECIFIC_ERROR.0, code),
+        };
+
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: controls_accepted,
+            dwWin32ExitCode: win32_exit_code,
+            dwServiceSpecificExitCode: specific_exit_code,
+            dwCheckPoint: checkpoint,
+            dwWaitHint: wait_hint,
+        };
+        if let Err(e) =
+            unsafe { SetServiceStatus(SERVICE_STATUS_HANDLE(handle as *mut _), &status) }
+        {
+            warn!("Failed to report service status {:?}: {}", state, e);
+        }
+    }
+}
+
+/// Hand the process over to the Service Control Manager.
+///
+/// Blocks until the service stops. Fails if the process was not started by
+/// the SCM.
+pub fn run_dispatcher(config: Config, run: ServiceBody) -> Result<()> {
+    let context = ServiceContext {
+        run,
+        config,
+        shutdown: Arc::new(AtomicBool::new(false)),
+        status_handle: AtomicUsize::new(0),
+        checkpoint: AtomicU32::new(0),
+    };
+    if CONTEXT.set(context).is_err() {
+        return Err(anyhow!("Service dispatcher already started"));
+    }
+
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(SERVICE_NAME.0 as *mut u16),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+
+    info!("Starting service dispatcher");
+    unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }.map_err(|e| {
+        anyhow!(
+            "Failed to start service dispatcher (not run by the SCM?): {}",
+            e
+        )
+    })
+}
+
+/// Service thread: register for control requests, then run the backend
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let context = match CONTEXT.get() {
+        Some(context) => context,
+        None => return,
+    };
+
+    let handle =
+        match unsafe { RegisterServiceCtrlHandlerExW(SERVICE_NAME, Some(control_handler), None) } {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to register service control handler: {}", e);
+                return;
+            }
+        };
+    context
+        .status_handle
+        .store(handle.0 as usize, Ordering::Release);
+
+    // Waiting for QEMU to connect is normal running state
+    context.report(SERVICE_RUNNING, 0);
+    info!("Service running");
+
+    let exit_code = match (context.run)(context.config.clone(), context.shutdown.clone()) {
+        Ok(()) => 0,
+        Err(e) if context.shutdown.load(Ordering::Relaxed) => {
+            info!(
+                "Service stopped before the backend finished starting: {}",
+                e
+            );
+            0
+        }
+        Err(e) => {
+            error!("Backend failed: {}", e);
+            1
+        }
+    };
+
+    info!("Service stopped");
+    context.report(SERVICE_STOPPED, exit_code);
+}
+
+/// Control handler, called by the SCM on the dispatcher thread
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            if let Some(context) = CONTEXT.get() {
+                info!("Service stop requested");
+                context.report(SERVICE_STOP_PENDING, 0);
+                context.shutdown.store(true, Ordering::Relaxed);
+                // Every instance may still be waiting for QEMU on its own pipe
+                for instance in 0..context.config.instances.max(1) {
+                    wake_pipe_listener(&context.config.for_instance(instance).pipe_path);
+                }
+            }
+            NO_ERROR.0
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR.0,
+        _ => ERROR_CALL_NOT_IMPLEMENTED.0,
+    }
+}
+
+/// Connect to our own pipe so a startup still blocked waiting for QEMU
+/// returns and sees the shutdown flag. Fails harmlessly once QEMU holds the
+/// pipe.
+fn wake_pipe_listener(pipe_path: &str) {
+    let wide_path: Vec<u16> = pipe_path.encode_utf16().chain(std::iter::once(0)).collect();
+    let pipe = unsafe {
+        CreateFileW(
+            PCWSTR(wide_path.as_ptr()),
+            (GENERIC_READ | GENERIC_WRITE).0,
+            FILE_SHARE_NONE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    };
+    if let Ok(pipe) = pipe {
+        unsafe {
+            let _ = CloseHandle(pipe);
+        }
+    }
+}
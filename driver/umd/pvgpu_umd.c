@@ -270,6 +270,7 @@ HRESULT APIENTRY PvgpuCreateDevice(
     /* Resource operations */
     pDeviceFuncs->pfnResourceCopy = PvgpuResourceCopy;
     pDeviceFuncs->pfnResourceCopyRegion = PvgpuResourceCopyRegion;
+    pDeviceFuncs->pfnCopyStructureCount = PvgpuCopyStructureCount;
     pDeviceFuncs->pfnResourceUpdateSubresourceUP = PvgpuResourceUpdateSubresourceUP;
     pDeviceFuncs->pfnResourceMap = PvgpuResourceMap;
     pDeviceFuncs->pfnResourceUnmap = PvgpuResourceUnmap;
@@ -712,6 +713,7 @@ void APIENTRY PvgpuCreateResource(
     pResource->Format = pCreateResource->Format;
     pResource->BindFlags = pCreateResource->BindFlags;
     pResource->MiscFlags = pCreateResource->MiscFlags;
+    pResource->StructureByteStride = pCreateResource->ByteStride;
     
     /* Allocate a host handle */
     pResource->HostHandle = PvgpuAllocateResourceHandle(pDevice);
@@ -725,7 +727,6 @@ void APIENTRY PvgpuCreateResource(
     cmd.height = pResource->Height;
     cmd.depth = pResource->Depth;
     cmd.mip_levels = pResource->MipLevels;
-    cmd.array_size = pResource->ArraySize;
     cmd.format = pResource->Format;
     cmd.bind_flags = pResource->BindFlags;
     
@@ -734,12 +735,32 @@ void APIENTRY PvgpuCreateResource(
     {
     case PVGPU_RESOURCE_TYPE_BUFFER:
         cmd.resource_type = PVGPU_RESOURCE_BUFFER;
+        if (pResource->MiscFlags & D3D11_DDI_RESOURCE_MISC_DRAWINDIRECT_ARGS)
+        {
+            cmd.misc_flags |= PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS;
+        }
+        if (pResource->MiscFlags & D3D11_DDI_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS)
+        {
+            cmd.misc_flags |= PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS;
+        }
+        if (pResource->MiscFlags & D3D11_DDI_RESOURCE_MISC_BUFFER_STRUCTURED)
+        {
+            /* Buffers have no height; it carries the element stride */
+            cmd.misc_flags |= PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED;
+            cmd.height = pResource->StructureByteStride;
+        }
         break;
     case PVGPU_RESOURCE_TYPE_TEXTURE1D:
         cmd.resource_type = PVGPU_RESOURCE_TEXTURE_1D;
         break;
     case PVGPU_RESOURCE_TYPE_TEXTURE2D:
         cmd.resource_type = PVGPU_RESOURCE_TEXTURE_2D;
+        /* 2D textures carry their array size in depth */
+        cmd.depth = pResource->ArraySize;
+        if (pResource->MiscFlags & D3D10_DDI_RESOURCE_MISC_TEXTURECUBE)
+        {
+            cmd.misc_flags |= PVGPU_RESOURCE_MISC_TEXTURECUBE;
+        }
         break;
     case PVGPU_RESOURCE_TYPE_TEXTURE3D:
         cmd.resource_type = PVGPU_RESOURCE_TEXTURE_3D;
@@ -1428,7 +1449,13 @@ void APIENTRY PvgpuSetRenderTargets(
     _In_reads_(NumViews) CONST D3D10DDI_HRENDERTARGETVIEW* phRenderTargetView,
     _In_ UINT NumViews,
     _In_ UINT ClearSlots,
-    _In_ D3D10DDI_HDEPTHSTENCILVIEW hDepthStencilView)
+    _In_ D3D10DDI_HDEPTHSTENCILVIEW hDepthStencilView,
+    _In_reads_(NumUAVs) CONST D3D11DDI_HUNORDEREDACCESSVIEW* phUnorderedAccessViews,
+    _In_reads_(NumUAVs) CONST UINT* pUAVInitialCounts,
+    _In_ UINT UAVStartSlot,
+    _In_ UINT NumUAVs,
+    _In_ UINT UAVFirstToSet,
+    _In_ UINT UAVNumberUpdated)
 {
     PVGPU_UMD_DEVICE* pDevice;
     PVGPU_UMD_RESOURCE* pDSV;
@@ -1436,6 +1463,9 @@ void APIENTRY PvgpuSetRenderTargets(
     UINT i;
     
     UNREFERENCED_PARAMETER(ClearSlots);
+    UNREFERENCED_PARAMETER(pUAVInitialCounts);
+    UNREFERENCED_PARAMETER(UAVFirstToSet);
+    UNREFERENCED_PARAMETER(UAVNumberUpdated);
     
     pDevice = (PVGPU_UMD_DEVICE*)hDevice.pDrvPrivate;
     pDSV = (PVGPU_UMD_RESOURCE*)hDepthStencilView.pDrvPrivate;
@@ -1462,6 +1492,21 @@ void APIENTRY PvgpuSetRenderTargets(
     pDevice->PipelineState.RenderTargetCount = NumViews;
     pDevice->PipelineState.DepthStencilView = cmd.dsv_id;
     
+    /* UAVs bound next to the render targets, created by
+     * PvgpuCreateUnorderedAccessView */
+    if (UAVStartSlot < PVGPU_MAX_OM_UAVS)
+    {
+        if (NumUAVs > PVGPU_MAX_OM_UAVS - UAVStartSlot) NumUAVs = PVGPU_MAX_OM_UAVS - UAVStartSlot;
+        cmd.uav_start_slot = UAVStartSlot;
+        cmd.num_uavs = NumUAVs;
+        for (i = 0; i < NumUAVs; i++)
+        {
+            PVGPU_UMD_SHADER_RESOURCE_VIEW* pUAV =
+                (PVGPU_UMD_SHADER_RESOURCE_VIEW*)phUnorderedAccessViews[i].pDrvPrivate;
+            cmd.uav_ids[i] = pUAV ? pUAV->HostHandle : 0;
+        }
+    }
+    
     PvgpuWriteCommand(pDevice, PVGPU_CMD_SET_RENDER_TARGET, &cmd, sizeof(cmd));
 }
 
@@ -1656,6 +1701,37 @@ void APIENTRY PvgpuResourceCopy(
     PvgpuWriteCommand(pDevice, PVGPU_CMD_COPY_RESOURCE, &cmd, sizeof(cmd));
 }
 
+void APIENTRY PvgpuCopyStructureCount(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ D3D10DDI_HRESOURCE hDstBuffer,
+    _In_ UINT DstAlignedByteOffset,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hSrcView)
+{
+    PVGPU_UMD_DEVICE* pDevice;
+    PVGPU_UMD_RESOURCE* pDst;
+    PVGPU_UMD_SHADER_RESOURCE_VIEW* pSrc;
+    PvgpuCmdCopyStructureCount cmd;
+    
+    pDevice = (PVGPU_UMD_DEVICE*)hDevice.pDrvPrivate;
+    pDst = (PVGPU_UMD_RESOURCE*)hDstBuffer.pDrvPrivate;
+    pSrc = (PVGPU_UMD_SHADER_RESOURCE_VIEW*)hSrcView.pDrvPrivate;
+    
+    if (pDst == NULL || pSrc == NULL)
+    {
+        return;
+    }
+    
+    /* Build command */
+    ZeroMemory(&cmd, sizeof(cmd));
+    cmd.header.command_type = PVGPU_CMD_COPY_STRUCTURE_COUNT;
+    cmd.header.command_size = sizeof(cmd);
+    cmd.dst_buffer_id = pDst->HostHandle;
+    cmd.dst_offset = DstAlignedByteOffset;
+    cmd.src_uav_id = pSrc->HostHandle;
+    
+    PvgpuWriteCommand(pDevice, PVGPU_CMD_COPY_STRUCTURE_COUNT, &cmd, sizeof(cmd));
+}
+
 void APIENTRY PvgpuResourceCopyRegion(
     _In_ D3D10DDI_HDEVICE hDevice,
     _In_ D3D10DDI_HRESOURCE hDstResource,
@@ -1918,6 +1994,7 @@ void APIENTRY PvgpuResourceMap(
     pResource->IsMapped = TRUE;
     pResource->MappedAddress = pDevice->pHeap + heapOffset;
     pResource->MappedSize = mapSize;
+    pResource->MappedType = MapType;
     
     /* Return mapped pointer */
     pMappedSubresource->pData = pDevice->pHeap + heapOffset;
@@ -1966,6 +2043,13 @@ void APIENTRY PvgpuResourceUnmap(
     cmd.header.resource_id = pResource->HostHandle;
     cmd.subresource = Subresource;
     cmd.heap_offset = heapOffset;
+    cmd.map_type = pResource->MappedType;
+    
+    /* Only write maps carry data back; read maps leave the resource alone */
+    if (pResource->MappedType != D3D10_DDI_MAP_READ)
+    {
+        cmd.data_size = heapSize;
+    }
     
     PvgpuWriteCommand(pDevice, PVGPU_CMD_UNMAP_RESOURCE, &cmd, sizeof(cmd));
     
@@ -2829,6 +2913,94 @@ void APIENTRY PvgpuDestroyShaderResourceView(
     PvgpuWriteCommand(pDevice, PVGPU_CMD_DESTROY_SHADER_RESOURCE_VIEW, &cmd, sizeof(cmd));
 }
 
+SIZE_T APIENTRY PvgpuCalcPrivateUnorderedAccessViewSize(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ CONST D3D11DDIARG_CREATEUNORDEREDACCESSVIEW* pCreateUnorderedAccessView)
+{
+    UNREFERENCED_PARAMETER(hDevice);
+    UNREFERENCED_PARAMETER(pCreateUnorderedAccessView);
+    /* UAVs are tracked like SRVs; CsSetUnorderedAccessViews reads them so */
+    return sizeof(PVGPU_UMD_SHADER_RESOURCE_VIEW);
+}
+
+void APIENTRY PvgpuCreateUnorderedAccessView(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ CONST D3D11DDIARG_CREATEUNORDEREDACCESSVIEW* pCreateUnorderedAccessView,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hUnorderedAccessView,
+    _In_ D3D11DDI_HRTUNORDEREDACCESSVIEW hRTUnorderedAccessView)
+{
+    PVGPU_UMD_DEVICE* pDevice;
+    PVGPU_UMD_SHADER_RESOURCE_VIEW* pView;
+    PVGPU_UMD_RESOURCE* pResource;
+    PvgpuCmdCreateUnorderedAccessView cmd;
+    
+    UNREFERENCED_PARAMETER(hRTUnorderedAccessView);
+    
+    pDevice = (PVGPU_UMD_DEVICE*)hDevice.pDrvPrivate;
+    pView = (PVGPU_UMD_SHADER_RESOURCE_VIEW*)hUnorderedAccessView.pDrvPrivate;
+    pResource = (PVGPU_UMD_RESOURCE*)pCreateUnorderedAccessView->hDrvResource.pDrvPrivate;
+    
+    if (pView == NULL) return;
+    
+    /* Initialize view tracking */
+    pView->HostHandle = PvgpuAllocateResourceHandle(pDevice);
+    pView->ResourceHandle = pResource ? pResource->HostHandle : 0;
+    pView->Format = pCreateUnorderedAccessView->Format;
+    
+    /* Build create command */
+    ZeroMemory(&cmd, sizeof(cmd));
+    cmd.header.command_type = PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW;
+    cmd.header.command_size = sizeof(cmd);
+    cmd.view_id = pView->HostHandle;
+    cmd.resource_id = pView->ResourceHandle;
+    cmd.format = pCreateUnorderedAccessView->Format;
+    
+    /* The DDI describes every 2D UAV as an array slice range; other
+     * dimensions are left at 0 for the host to reject */
+    switch (pCreateUnorderedAccessView->ResourceDimension)
+    {
+    case D3D10DDIRESOURCE_BUFFER:
+        cmd.view_dimension = PVGPU_UAV_DIMENSION_BUFFER;
+        cmd.u.buffer.first_element = pCreateUnorderedAccessView->Buffer.FirstElement;
+        cmd.u.buffer.num_elements = pCreateUnorderedAccessView->Buffer.NumElements;
+        cmd.u.buffer.flags = pCreateUnorderedAccessView->Buffer.Flags;
+        break;
+    case D3D10DDIRESOURCE_TEXTURE2D:
+        cmd.view_dimension = PVGPU_UAV_DIMENSION_TEXTURE2DARRAY;
+        cmd.u.texture2d_array.mip_slice = pCreateUnorderedAccessView->Tex2D.MipSlice;
+        cmd.u.texture2d_array.first_array_slice = pCreateUnorderedAccessView->Tex2D.FirstArraySlice;
+        cmd.u.texture2d_array.array_size = pCreateUnorderedAccessView->Tex2D.ArraySize;
+        break;
+    default:
+        break;
+    }
+    
+    PvgpuWriteCommand(pDevice, PVGPU_CMD_CREATE_UNORDERED_ACCESS_VIEW, &cmd, sizeof(cmd));
+    
+    PVGPU_TRACE("Created UAV %u for resource %u", pView->HostHandle, pView->ResourceHandle);
+}
+
+void APIENTRY PvgpuDestroyUnorderedAccessView(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hUnorderedAccessView)
+{
+    PVGPU_UMD_DEVICE* pDevice;
+    PVGPU_UMD_SHADER_RESOURCE_VIEW* pView;
+    PvgpuCommandHeader cmd;
+    
+    pDevice = (PVGPU_UMD_DEVICE*)hDevice.pDrvPrivate;
+    pView = (PVGPU_UMD_SHADER_RESOURCE_VIEW*)hUnorderedAccessView.pDrvPrivate;
+    
+    if (pView == NULL) return;
+    
+    ZeroMemory(&cmd, sizeof(cmd));
+    cmd.command_type = PVGPU_CMD_DESTROY_UNORDERED_ACCESS_VIEW;
+    cmd.command_size = sizeof(cmd);
+    cmd.resource_id = pView->HostHandle;
+    
+    PvgpuWriteCommand(pDevice, PVGPU_CMD_DESTROY_UNORDERED_ACCESS_VIEW, &cmd, sizeof(cmd));
+}
+
 /* ============================================================================
  * Constant Buffers and Shader Resources Binding
  * ============================================================================ */
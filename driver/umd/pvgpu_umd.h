@@ -94,6 +94,7 @@ typedef struct PVGPU_UMD_RESOURCE {
     BOOL                IsMapped;
     void*               MappedAddress;
     SIZE_T              MappedSize;
+    UINT32              MappedType;     /* D3D10_DDI_MAP of the open map */
     BOOL                IsShared;       /* Opened via OpenResource */
     D3D10DDI_HRTRESOURCE hRTResource;  /* Runtime handle */
 } PVGPU_UMD_RESOURCE;
@@ -474,7 +475,13 @@ void APIENTRY PvgpuSetRenderTargets(
     _In_reads_(NumViews) CONST D3D10DDI_HRENDERTARGETVIEW* phRenderTargetView,
     _In_ UINT NumViews,
     _In_ UINT ClearSlots,
-    _In_ D3D10DDI_HDEPTHSTENCILVIEW hDepthStencilView
+    _In_ D3D10DDI_HDEPTHSTENCILVIEW hDepthStencilView,
+    _In_reads_(NumUAVs) CONST D3D11DDI_HUNORDEREDACCESSVIEW* phUnorderedAccessViews,
+    _In_reads_(NumUAVs) CONST UINT* pUAVInitialCounts,
+    _In_ UINT UAVStartSlot,
+    _In_ UINT NumUAVs,
+    _In_ UINT UAVFirstToSet,
+    _In_ UINT UAVNumberUpdated
 );
 
 void APIENTRY PvgpuSetViewports(
@@ -575,6 +582,13 @@ void APIENTRY PvgpuResourceCopy(
     _In_ D3D10DDI_HRESOURCE hSrcResource
 );
 
+void APIENTRY PvgpuCopyStructureCount(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ D3D10DDI_HRESOURCE hDstBuffer,
+    _In_ UINT DstAlignedByteOffset,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hSrcView
+);
+
 void APIENTRY PvgpuResourceCopyRegion(
     _In_ D3D10DDI_HDEVICE hDevice,
     _In_ D3D10DDI_HRESOURCE hDstResource,
@@ -775,6 +789,23 @@ void APIENTRY PvgpuDestroyShaderResourceView(
     _In_ D3D10DDI_HSHADERRESOURCEVIEW hShaderResourceView
 );
 
+SIZE_T APIENTRY PvgpuCalcPrivateUnorderedAccessViewSize(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ CONST D3D11DDIARG_CREATEUNORDEREDACCESSVIEW* pCreateUnorderedAccessView
+);
+
+void APIENTRY PvgpuCreateUnorderedAccessView(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ CONST D3D11DDIARG_CREATEUNORDEREDACCESSVIEW* pCreateUnorderedAccessView,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hUnorderedAccessView,
+    _In_ D3D11DDI_HRTUNORDEREDACCESSVIEW hRTUnorderedAccessView
+);
+
+void APIENTRY PvgpuDestroyUnorderedAccessView(
+    _In_ D3D10DDI_HDEVICE hDevice,
+    _In_ D3D11DDI_HUNORDEREDACCESSVIEW hUnorderedAccessView
+);
+
 /* ============================================================================
  * Constant Buffers and Shader Resources Binding
  * ============================================================================ */
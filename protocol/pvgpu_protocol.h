@@ -26,7 +26,7 @@ extern "C" {
 
 #define PVGPU_MAGIC             0x50564750  /* "PVGP" in little-endian */
 #define PVGPU_VERSION_MAJOR     1
-#define PVGPU_VERSION_MINOR     0
+#define PVGPU_VERSION_MINOR     1
 #define PVGPU_VERSION           ((PVGPU_VERSION_MAJOR << 16) | PVGPU_VERSION_MINOR)
 
 /* Default sizes */
@@ -99,6 +99,63 @@ extern "C" {
  * =============================================================================
  */
 
+/* Number of records in the control region's error log */
+#define PVGPU_ERROR_LOG_ENTRIES     16
+
+/* Bytes of the control region's diagnostic message buffer */
+#define PVGPU_DIAGNOSTIC_SIZE       512
+
+/* One error log record (24 bytes), written by the host */
+typedef struct PvgpuErrorRecord {
+    volatile uint32_t code;             /* PVGPU_ERROR_* code */
+    volatile uint32_t data;             /* Same meaning as error_data */
+    volatile uint64_t command_offset;   /* Consumer pointer when the error was raised */
+    volatile uint64_t timestamp_us;     /* Host wall-clock time, us since the Unix epoch */
+} PvgpuErrorRecord;
+
+/* Capability block layout version; 0 means the host has not written one */
+#define PVGPU_CAPS_VERSION              2
+
+/* Largest 2D texture width or height D3D11 allows */
+#define PVGPU_MAX_TEXTURE_DIMENSION     16384
+
+/* Display formats a capability block can list */
+#define PVGPU_CAPS_MAX_DISPLAY_FORMATS  8
+
+/* PvgpuCapabilityBlock.output_modes bits */
+#define PVGPU_OUTPUT_WINDOW             (1 << 0)    /* Frames shown in a host window */
+#define PVGPU_OUTPUT_SHARED_TEXTURE     (1 << 1)    /* Frames copied to a shared texture */
+#define PVGPU_OUTPUT_HOST_BACKBUFFER    (1 << 2)    /* PVGPU_HOST_BACKBUFFER_ID is renderable */
+#define PVGPU_OUTPUT_PREVIOUS_FRAME     (1 << 3)    /* PVGPU_PREVIOUS_FRAME_ID holds the last frame */
+#define PVGPU_OUTPUT_TEARING            (1 << 4)    /* Tearing presents (variable refresh rate) */
+
+/* What the host device can do (352 bytes). Written before
+ * PVGPU_STATUS_READY is set, and rewritten only while PVGPU_STATUS_RECOVERY
+ * is set during CMD_SELECT_ADAPTER, so the guest can read it once READY is
+ * visible and RECOVERY is clear. A guest must ignore fields beyond `size` and
+ * treat version 0 as "no capabilities published". */
+typedef struct PvgpuCapabilityBlock {
+    /* 0x000 */ uint32_t version;               /* PVGPU_CAPS_VERSION of the host */
+    /* 0x004 */ uint32_t size;                  /* sizeof(PvgpuCapabilityBlock) of the host */
+    /* 0x008 */ uint32_t feature_level;         /* D3D_FEATURE_LEVEL of the device */
+    /* 0x00C */ uint32_t max_texture_dimension;
+    /* 0x010 */ uint32_t max_texture_array_size;
+    /* 0x014 */ uint32_t msaa_sample_mask;      /* Bit i: 1 << i samples work for R8G8B8A8_UNORM */
+    /* 0x018 */ uint32_t vendor_id;
+    /* 0x01C */ uint32_t device_id;
+    /* 0x020 */ uint64_t dedicated_video_memory; /* Dedicated VRAM in bytes */
+    /* 0x028 */ uint32_t output_modes;          /* PVGPU_OUTPUT_* bits */
+    /* 0x02C */ uint32_t display_format_count;
+    /* 0x030 */ uint32_t display_formats[PVGPU_CAPS_MAX_DISPLAY_FORMATS]; /* Swapchain DXGI_FORMATs */
+    /* 0x050 */ uint16_t adapter_description[128]; /* UTF-16, NUL-terminated */
+    /* 0x150 */ uint32_t adapter_index;         /* DXGI adapter index (version 2) */
+    /* 0x154 */ uint32_t reserved;
+    /* 0x158 */ uint64_t adapter_luid;          /* Adapter LUID, LowPart in bits 0-31 (version 2) */
+} PvgpuCapabilityBlock;
+
+_Static_assert(sizeof(PvgpuCapabilityBlock) == 0x160,
+               "Capability block must be 352 bytes");
+
 typedef struct PvgpuControlRegion {
     /* 0x000 */ uint32_t magic;                 /* Must be PVGPU_MAGIC */
     /* 0x004 */ uint32_t version;               /* Protocol version */
@@ -126,16 +183,55 @@ typedef struct PvgpuControlRegion {
     /* 0x120 */ volatile uint32_t status;       /* Device status flags */
     /* 0x124 */ volatile uint32_t error_code;   /* Last error code */
     /* 0x128 */ volatile uint32_t error_data;   /* Additional error info */
-    /* 0x12C */ uint32_t reserved1;
+    /* 0x12C */ volatile uint32_t host_resize_sequence; /* Bumped when the host resizes the display
+                                                      * (window resize); switch the guest mode to
+                                                      * display_width x display_height */
     
     /* Display configuration */
-    /* 0x130 */ uint32_t display_width;         /* Current display width */
-    /* 0x134 */ uint32_t display_height;        /* Current display height */
-    /* 0x138 */ uint32_t display_refresh;       /* Refresh rate in Hz */
-    /* 0x13C */ uint32_t display_format;        /* DXGI_FORMAT value */
+    /* 0x130 */ volatile uint32_t display_width;        /* Current display width */
+    /* 0x134 */ volatile uint32_t display_height;       /* Current display height */
+    /* 0x138 */ volatile uint32_t display_refresh;      /* Refresh rate in Hz */
+    /* 0x13C */ volatile uint32_t display_format;       /* DXGI_FORMAT in use (may be a fallback) */
+    
+    /* Host-owned presentation */
+    /* 0x140 */ volatile uint32_t host_backbuffer_id; /* Host backbuffer resource ID (0 = none) */
+    /* 0x144 */ volatile uint32_t previous_frame_id;  /* Previous frame copy resource ID (0 = none) */
+    
+    /* Error log: the host writes record (producer % PVGPU_ERROR_LOG_ENTRIES),
+     * then increments the producer. The guest reads records from consumer up
+     * to producer and advances consumer; if it falls more than
+     * PVGPU_ERROR_LOG_ENTRIES behind, the oldest records are lost. */
+    /* 0x148 */ volatile uint32_t error_log_producer; /* Written by host */
+    /* 0x14C */ volatile uint32_t error_log_consumer; /* Written by guest */
+    /* 0x150 */ PvgpuErrorRecord error_log[PVGPU_ERROR_LOG_ENTRIES];
+    
+    /* Capabilities, written before READY and on adapter switches */
+    /* 0x2D0 */ PvgpuCapabilityBlock capabilities;
+    
+    /* Diagnostic message: the driver's reason for the last
+     * PVGPU_ERROR_SHADER_COMPILE, as NUL-terminated UTF-8. The host writes
+     * the message, then increments diagnostic_sequence (0 = none yet). */
+    /* 0x430 */ volatile uint32_t diagnostic_sequence;
+    /* 0x434 */ volatile uint32_t diagnostic_resource_id; /* Resource the message is about */
+    /* 0x438 */ volatile uint32_t diagnostic_length;      /* Bytes, excluding the NUL */
+    /* 0x43C */ uint32_t reserved2;
+    /* 0x440 */ volatile char diagnostic[PVGPU_DIAGNOSTIC_SIZE];
+    
+    /* Video memory in bytes, refreshed periodically by the host */
+    /* 0x640 */ volatile uint64_t vram_budget;   /* DXGI local memory budget */
+    /* 0x648 */ volatile uint64_t vram_usage;    /* DXGI local memory in use */
+    /* 0x650 */ volatile uint64_t vram_tracked;  /* Estimated size of guest resources */
+    /* 0x658 */ uint64_t reserved3;
+    
+    /* Answer to the last CMD_CHECK_FORMAT_SUPPORT: the host writes the
+     * format and its support, then increments format_query_sequence */
+    /* 0x660 */ volatile uint32_t format_query_sequence;
+    /* 0x664 */ volatile uint32_t format_query_format; /* DXGI_FORMAT answered */
+    /* 0x668 */ volatile uint32_t format_support;      /* D3D11_FORMAT_SUPPORT bits (0 = unsupported) */
+    /* 0x66C */ uint32_t reserved4;
     
     /* Reserved for future use */
-    /* 0x140 */ uint8_t reserved[0xEC0];        /* Pad to 4KB total */
+    /* 0x670 */ uint8_t reserved[0x990];        /* Pad to 4KB total */
 } PvgpuControlRegion;
 
 _Static_assert(sizeof(PvgpuControlRegion) == PVGPU_CONTROL_REGION_SIZE, 
@@ -156,9 +252,16 @@ typedef struct PvgpuCommandHeader {
 
 #define PVGPU_CMD_HEADER_SIZE   sizeof(PvgpuCommandHeader)
 
+/* Reserved resource ID for the host-owned backbuffer */
+#define PVGPU_HOST_BACKBUFFER_ID    0xFFFFFFFF
+
+/* Reserved resource ID for the host's copy of the last presented frame.
+ * Shader-readable only; its contents are replaced at every present. */
+#define PVGPU_PREVIOUS_FRAME_ID     0xFFFFFFFE
+
 /* Command flags */
-#define PVGPU_CMD_FLAG_SYNC         (1 << 0)    /* Wait for completion */
-#define PVGPU_CMD_FLAG_NO_FENCE     (1 << 1)    /* Don't signal fence */
+#define PVGPU_CMD_FLAG_SYNC         (1 << 0)    /* Wait for GPU completion, then publish the fence and IRQ */
+#define PVGPU_CMD_FLAG_NO_FENCE     (1 << 1)    /* Don't publish the fence or raise an IRQ after it */
 
 /*
  * =============================================================================
@@ -174,6 +277,9 @@ typedef struct PvgpuCommandHeader {
 #define PVGPU_CMD_UPDATE_RESOURCE       0x0005
 #define PVGPU_CMD_COPY_RESOURCE         0x0006
 #define PVGPU_CMD_OPEN_RESOURCE         0x0007
+#define PVGPU_CMD_QUERY_MSAA_SUPPORT    0x0008
+#define PVGPU_CMD_CHECK_FORMAT_SUPPORT  0x0009
+#define PVGPU_CMD_COPY_STRUCTURE_COUNT  0x000A
 
 /* State object creation commands: 0x0010 - 0x002F */
 #define PVGPU_CMD_CREATE_BLEND_STATE        0x0010
@@ -200,6 +306,7 @@ typedef struct PvgpuCommandHeader {
 /* Shader creation commands: 0x0030 - 0x003F */
 #define PVGPU_CMD_CREATE_SHADER             0x0030
 #define PVGPU_CMD_DESTROY_SHADER            0x0031
+#define PVGPU_CMD_REPLACE_SHADER            0x0032
 
 /* State commands: 0x0100 - 0x01FF */
 #define PVGPU_CMD_SET_RENDER_TARGET     0x0101
@@ -216,6 +323,7 @@ typedef struct PvgpuCommandHeader {
 #define PVGPU_CMD_SET_INPUT_LAYOUT      0x010C
 #define PVGPU_CMD_SET_PRIMITIVE_TOPOLOGY 0x010D
 #define PVGPU_CMD_SET_SHADER_RESOURCE   0x010E
+#define PVGPU_CMD_SET_CONSTANT_BUFFERS  0x010F
 
 /* Draw commands: 0x0200 - 0x02FF */
 #define PVGPU_CMD_DRAW                  0x0201
@@ -225,6 +333,17 @@ typedef struct PvgpuCommandHeader {
 #define PVGPU_CMD_DISPATCH              0x0205
 #define PVGPU_CMD_CLEAR_RENDER_TARGET   0x0206
 #define PVGPU_CMD_CLEAR_DEPTH_STENCIL   0x0207
+#define PVGPU_CMD_DRAW_INSTANCED_INDIRECT          0x0208
+#define PVGPU_CMD_DRAW_INDEXED_INSTANCED_INDIRECT  0x0209
+#define PVGPU_CMD_CLEAR_RENDER_TARGET_UINT         0x020A
+
+/* CMD_DISPATCH thread_group_z marking an indirect dispatch:
+ * thread_group_x = args buffer ID, thread_group_y = byte offset */
+#define PVGPU_DISPATCH_INDIRECT         0xFFFFFFFF
+
+/* D3D11 limit on thread groups per dispatch dimension; larger dispatches
+ * fail with PVGPU_ERROR_INVALID_PARAMETER */
+#define PVGPU_MAX_DISPATCH_THREAD_GROUPS 65535
 
 /* Sync commands: 0x0300 - 0x03FF */
 #define PVGPU_CMD_FENCE                 0x0301
@@ -232,6 +351,45 @@ typedef struct PvgpuCommandHeader {
 #define PVGPU_CMD_FLUSH                 0x0303
 #define PVGPU_CMD_WAIT_FENCE            0x0304
 #define PVGPU_CMD_RESIZE_BUFFERS        0x0305
+#define PVGPU_CMD_QUERY_FENCE           0x0306
+#define PVGPU_CMD_RESET_DEVICE          0x0307
+#define PVGPU_CMD_SET_DISPLAY_CONFIG    0x0308
+#define PVGPU_CMD_BEGIN_FRAME           0x0309
+#define PVGPU_CMD_SET_FRAME_LATENCY     0x030A
+#define PVGPU_CMD_QUERY_DEVICE_STATUS   0x030B
+#define PVGPU_CMD_SELECT_ADAPTER        0x030C
+#define PVGPU_CMD_SHUTDOWN              0x030D
+
+/* PvgpuCmdSelectAdapter.flags */
+#define PVGPU_SELECT_ADAPTER_BY_LUID    (1 << 0)    /* Match adapter_luid, not adapter_index */
+
+/* Largest frame latency DXGI accepts */
+#define PVGPU_MAX_FRAME_LATENCY         16
+
+/* Maximum fence values in a single WAIT_FENCE batch */
+#define PVGPU_MAX_WAIT_FENCES           64
+
+/* Debug commands: 0x0400 - 0x04FF */
+#define PVGPU_CMD_BEGIN_EVENT           0x0401
+#define PVGPU_CMD_END_EVENT             0x0402
+#define PVGPU_CMD_SET_MARKER            0x0403
+#define PVGPU_CMD_DUMP_FRAMES           0x0404  /* Header only; dump host crash frame ring */
+#define PVGPU_CMD_SELFTEST              0x0405
+#define PVGPU_CMD_SET_LOG_LEVEL         0x0406
+#define PVGPU_CMD_SET_DEBUG_NAME        0x0407  /* Name header.resource_id in GPU captures */
+
+/* CMD_SET_LOG_LEVEL levels, most to least severe */
+#define PVGPU_LOG_LEVEL_ERROR           1
+#define PVGPU_LOG_LEVEL_WARN            2
+#define PVGPU_LOG_LEVEL_INFO            3
+#define PVGPU_LOG_LEVEL_DEBUG           4
+#define PVGPU_LOG_LEVEL_TRACE           5
+
+/* CMD_SELFTEST result codes (written to the heap as uint64_t) */
+#define PVGPU_SELFTEST_PASS             0
+#define PVGPU_SELFTEST_FAIL_CREATE      1   /* Test render target could not be created */
+#define PVGPU_SELFTEST_FAIL_READBACK    2   /* Staging copy or map failed */
+#define PVGPU_SELFTEST_FAIL_MISMATCH    3   /* Pixels differ from the clear color */
 
 /*
  * =============================================================================
@@ -261,14 +419,39 @@ typedef enum PvgpuResourceType {
     PVGPU_RESOURCE_UNORDERED_ACCESS_VIEW = 19,
 } PvgpuResourceType;
 
-/* Buffer bind flags (matching D3D11_BIND_FLAG) */
+/* Resource bind flags (matching D3D11_BIND_FLAG). The host rejects
+ * combinations D3D11 cannot create with PVGPU_ERROR_INVALID_PARAMETER and the
+ * offending flags as data: buffers cannot be render or depth targets, a
+ * constant buffer takes no other flags, textures take no buffer flags, and
+ * depth-stencil textures need a depth format (TYPELESS to also be sampled). */
 #define PVGPU_BIND_VERTEX_BUFFER        (1 << 0)
 #define PVGPU_BIND_INDEX_BUFFER         (1 << 1)
 #define PVGPU_BIND_CONSTANT_BUFFER      (1 << 2)
 #define PVGPU_BIND_SHADER_RESOURCE      (1 << 3)
-#define PVGPU_BIND_RENDER_TARGET        (1 << 4)
-#define PVGPU_BIND_DEPTH_STENCIL        (1 << 5)
-#define PVGPU_BIND_UNORDERED_ACCESS     (1 << 6)
+#define PVGPU_BIND_STREAM_OUTPUT        (1 << 4)
+#define PVGPU_BIND_RENDER_TARGET        (1 << 5)
+#define PVGPU_BIND_DEPTH_STENCIL        (1 << 6)
+#define PVGPU_BIND_UNORDERED_ACCESS     (1 << 7)
+
+/* Resource misc flags (PvgpuCmdCreateResource.misc_flags) */
+#define PVGPU_RESOURCE_MISC_SRGB_VIEW   (1u << 31)  /* _SRGB RTV/SRV over UNORM */
+#define PVGPU_RESOURCE_MISC_TEXTURECUBE (1u << 2)   /* Cube map; depth = 6 * cubes */
+#define PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS (1u << 4) /* Buffer of indirect args */
+#define PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS (1u << 5) /* Raw (byte address) UAVs */
+#define PVGPU_RESOURCE_MISC_BUFFER_STRUCTURED (1u << 6) /* Element stride in height */
+
+/* Structured buffers: height is the element stride, a multiple of 4 up to
+ * this, and width a multiple of it */
+#define PVGPU_MAX_STRUCTURE_STRIDE      2048
+
+/* PvgpuCmdOpenResource.misc_flags: shared_handle is an NT handle, valid in
+ * the backend process, to a texture created with
+ * D3D11_RESOURCE_MISC_SHARED_NTHANDLE (same value). Without it, shared_handle
+ * is the ID of a resource the backend already holds. */
+#define PVGPU_RESOURCE_MISC_SHARED_NTHANDLE (1u << 11)
+
+/* Texture2D arrays: depth is the slice count (0 or 1 = plain texture) */
+#define PVGPU_MAX_TEXTURE_ARRAY_SIZE    2048
 
 /* Shader stages */
 typedef enum PvgpuShaderStage {
@@ -293,18 +476,28 @@ typedef struct PvgpuCmdCreateResource {
     uint32_t resource_type;         /* PvgpuResourceType */
     uint32_t format;                /* DXGI_FORMAT */
     uint32_t width;                 /* Width (textures) or size (buffers) */
-    uint32_t height;                /* Height (textures) */
+    uint32_t height;                /* Height (textures) or stride (structured buffers) */
     uint32_t depth;                 /* Depth (3D textures) or array size */
     uint32_t mip_levels;            /* Mipmap levels */
     uint32_t sample_count;          /* MSAA sample count */
     uint32_t sample_quality;        /* MSAA quality */
     uint32_t bind_flags;            /* PVGPU_BIND_* flags */
-    uint32_t misc_flags;            /* Misc flags */
+    uint32_t misc_flags;            /* PVGPU_RESOURCE_MISC_*; unknown buffer bits fail */
     uint32_t heap_offset;           /* Offset in resource heap (for initial data) */
     uint32_t data_size;             /* Size of initial data */
+    uint32_t usage;                 /* PVGPU_USAGE_* (textures and buffers) */
+    uint32_t reserved;
     /* For shaders: bytecode follows in heap at heap_offset */
 } PvgpuCmdCreateResource;
 
+/* Resource usage (PvgpuCmdCreateResource.usage, matching D3D11_USAGE).
+ * IMMUTABLE resources need initial data, may only be bound as vertex, index
+ * or constant buffers and shader resources, and reject UPDATE_RESOURCE,
+ * write maps and COPY_RESOURCE into them. Commands from guests built before
+ * the usage field existed end at data_size and are created DEFAULT. */
+#define PVGPU_USAGE_DEFAULT             0
+#define PVGPU_USAGE_IMMUTABLE           1
+
 /* CMD_DESTROY_RESOURCE payload */
 typedef struct PvgpuCmdDestroyResource {
     PvgpuCommandHeader header;
@@ -314,26 +507,58 @@ typedef struct PvgpuCmdDestroyResource {
 /* CMD_OPEN_RESOURCE payload - opens a shared resource by global handle */
 typedef struct PvgpuCmdOpenResource {
     PvgpuCommandHeader header;
-    uint32_t shared_handle;         /* Global shared resource handle (NT or KMT) */
+    uint32_t shared_handle;         /* Resource ID, or NT handle with SHARED_NTHANDLE */
     uint32_t resource_type;         /* PvgpuResourceType */
     uint32_t format;                /* DXGI_FORMAT */
     uint32_t width;
     uint32_t height;
     uint32_t bind_flags;            /* PVGPU_BIND_* flags */
-    uint32_t misc_flags;
+    uint32_t misc_flags;            /* PVGPU_RESOURCE_MISC_SHARED_NTHANDLE */
 } PvgpuCmdOpenResource;
 
 /* CMD_MAP_RESOURCE payload */
 typedef struct PvgpuCmdMapResource {
     PvgpuCommandHeader header;
     uint32_t resource_id;           /* Resource to map (in header) */
-    uint32_t subresource;           /* Subresource index */
+    uint32_t subresource;           /* Below mips * array slices (0 for buffers) */
     uint32_t map_type;              /* Map type (read, write, etc.) */
     uint32_t map_flags;             /* Map flags */
-    uint32_t heap_offset;           /* Where in heap to map data */
-    uint32_t reserved[3];
+    uint32_t heap_offset;           /* Where in heap to map data; read maps copy it here, rows padded to row_pitch */
+    uint32_t pitch_offset;          /* Heap offset (8-byte aligned) of PvgpuMapPitch, 0 = none */
+    uint32_t reserved[2];
 } PvgpuCmdMapResource;
 
+/* Written by the host at PvgpuCmdMapResource.pitch_offset once the map is
+ * processed. Mapped data uses these strides, which may be padded. */
+typedef struct PvgpuMapPitch {
+    uint32_t row_pitch;
+    uint32_t depth_pitch;
+} PvgpuMapPitch;
+
+/* CMD_QUERY_MSAA_SUPPORT payload - host checks which of 1, 2, 4 and 8
+ * samples the adapter supports for format and writes a PvgpuMsaaSupport to
+ * the heap once the command has been consumed */
+typedef struct PvgpuCmdQueryMsaaSupport {
+    PvgpuCommandHeader header;
+    uint32_t format;                /* DXGI_FORMAT to check */
+    uint32_t heap_offset;           /* Heap offset (8-byte aligned) of PvgpuMsaaSupport */
+} PvgpuCmdQueryMsaaSupport;
+
+typedef struct PvgpuMsaaSupport {
+    uint32_t sample_count_mask;     /* Bit i set: 1 << i samples supported */
+    uint32_t reserved;
+    uint32_t quality_levels[4];     /* For 1, 2, 4, 8 samples (0 = unsupported) */
+} PvgpuMsaaSupport;
+
+/* CMD_CHECK_FORMAT_SUPPORT payload - host calls CheckFormatSupport for
+ * format on the real adapter and publishes the result in the control
+ * region (format_support) once the command has been consumed */
+typedef struct PvgpuCmdCheckFormatSupport {
+    PvgpuCommandHeader header;
+    uint32_t format;                /* DXGI_FORMAT to check */
+    uint32_t reserved[3];
+} PvgpuCmdCheckFormatSupport;
+
 /* Map types (matches D3D11_MAP) */
 #define PVGPU_MAP_READ              1
 #define PVGPU_MAP_WRITE             2
@@ -341,20 +566,28 @@ typedef struct PvgpuCmdMapResource {
 #define PVGPU_MAP_WRITE_DISCARD     4
 #define PVGPU_MAP_WRITE_NO_OVERWRITE 5
 
-/* CMD_UNMAP_RESOURCE payload */
+/* CMD_UNMAP_RESOURCE payload. Texture data written back with src_row_pitch
+ * set is copied row by row into the mapped row pitch, so the guest can send
+ * tightly packed rows. Commands from guests built before src_row_pitch
+ * existed end at data_size and are copied unchanged. Only maps whose
+ * map_type writes are copied back; data_size on a read map is ignored. A
+ * nonzero map_type that differs from the map's fails with
+ * PVGPU_ERROR_INVALID_PARAMETER and nothing is written back. */
 typedef struct PvgpuCmdUnmapResource {
     PvgpuCommandHeader header;
     uint32_t resource_id;           /* Resource to unmap (in header) */
     uint32_t subresource;           /* Subresource index */
     uint32_t heap_offset;           /* Where the data was mapped */
     uint32_t data_size;             /* Size of data to copy back (for write maps) */
+    uint32_t src_row_pitch;         /* Row pitch of texture data in the heap, 0 = mapped pitch */
+    uint32_t map_type;              /* map_type of the CMD_MAP_RESOURCE, 0 = not stated */
 } PvgpuCmdUnmapResource;
 
 /* CMD_UPDATE_RESOURCE payload */
 typedef struct PvgpuCmdUpdateResource {
     PvgpuCommandHeader header;
     uint32_t resource_id;           /* Resource to update (in header) */
-    uint32_t subresource;           /* Subresource index */
+    uint32_t subresource;           /* Below mips * array slices (0 for buffers) */
     uint32_t heap_offset;           /* Source data offset in heap */
     uint32_t data_size;             /* Size of data in heap */
     uint32_t dst_x, dst_y, dst_z;   /* Destination offset */
@@ -363,14 +596,35 @@ typedef struct PvgpuCmdUpdateResource {
     uint32_t depth_pitch;           /* Source depth pitch */
 } PvgpuCmdUpdateResource;
 
-/* CMD_SET_RENDER_TARGET payload */
+/* Render target and UAV slots shared by the output merger */
+#define PVGPU_MAX_OM_UAVS               8
+
+/* PvgpuCmdSetRenderTarget.dsv_flags: bind a read-only view of the depth
+ * and/or stencil plane so shaders can sample it while it is bound. Same
+ * values as D3D11_DSV_READ_ONLY_DEPTH / _STENCIL. */
+#define PVGPU_DSV_READ_ONLY_DEPTH       (1 << 0)
+#define PVGPU_DSV_READ_ONLY_STENCIL     (1 << 1)
+
+/* CMD_SET_RENDER_TARGET payload. UAVs occupy slots uav_start_slot ..
+ * uav_start_slot + num_uavs - 1, which must not overlap the render targets
+ * and end at or before PVGPU_MAX_OM_UAVS. Older guests send the command
+ * without the fields after rtv_ids; they read as zero. */
 typedef struct PvgpuCmdSetRenderTarget {
     PvgpuCommandHeader header;
     uint32_t num_rtvs;              /* Number of render targets */
     uint32_t dsv_id;                /* Depth stencil view ID (0 = none) */
     uint32_t rtv_ids[8];            /* Render target view IDs */
+    uint32_t dsv_flags;             /* PVGPU_DSV_READ_ONLY_* */
+    uint32_t uav_start_slot;        /* First UAV slot, >= num_rtvs */
+    uint32_t num_uavs;              /* 0 = render targets only */
+    uint32_t uav_ids[PVGPU_MAX_OM_UAVS]; /* UAV IDs (0 = unbind slot) */
 } PvgpuCmdSetRenderTarget;
 
+/* Maximum viewports / scissor rects per command. Viewports need a positive
+ * size and 0 <= min_depth <= max_depth <= 1; scissor rects must be non-empty
+ * and, with scissoring enabled, no more numerous than the bound viewports. */
+#define PVGPU_MAX_VIEWPORTS             16
+
 /* CMD_SET_VIEWPORT payload */
 typedef struct PvgpuCmdSetViewport {
     PvgpuCommandHeader header;
@@ -379,7 +633,7 @@ typedef struct PvgpuCmdSetViewport {
         float x, y;
         float width, height;
         float min_depth, max_depth;
-    } viewports[16];
+    } viewports[PVGPU_MAX_VIEWPORTS];
 } PvgpuCmdSetViewport;
 
 /* CMD_SET_SCISSOR payload */
@@ -388,7 +642,7 @@ typedef struct PvgpuCmdSetScissor {
     uint32_t num_rects;
     struct {
         int32_t left, top, right, bottom;
-    } rects[16];
+    } rects[PVGPU_MAX_VIEWPORTS];
 } PvgpuCmdSetScissor;
 
 /* CMD_SET_SHADER payload */
@@ -398,6 +652,9 @@ typedef struct PvgpuCmdSetShader {
     uint32_t shader_id;             /* Shader resource ID (0 = unbind) */
 } PvgpuCmdSetShader;
 
+/* CMD_CREATE_SHADER payload. Fails with PVGPU_ERROR_INVALID_COMMAND if
+ * shader_type is not a PvgpuShaderStage; CMD_CREATE_RESOURCE does the same
+ * for a resource_type outside PvgpuResourceType. */
 typedef struct PvgpuCmdCreateShader {
     PvgpuCommandHeader header;
     uint32_t shader_id;
@@ -406,6 +663,14 @@ typedef struct PvgpuCmdCreateShader {
     uint32_t bytecode_offset; /* Offset into heap where bytecode data resides */
 } PvgpuCmdCreateShader;
 
+/* CMD_REPLACE_SHADER payload - recreates an existing shader from new
+ * bytecode under the same ID, for live reload during development. Input
+ * layouts are left alone and the shader stays bound wherever it was. Fails
+ * with PVGPU_ERROR_RESOURCE_NOT_FOUND if shader_id is not a shader and with
+ * PVGPU_ERROR_INVALID_PARAMETER if shader_type or the bytecode's stage
+ * differs from the shader being replaced. */
+typedef PvgpuCmdCreateShader PvgpuCmdReplaceShader;
+
 typedef struct PvgpuCmdDestroyShader {
     PvgpuCommandHeader header;
     uint32_t shader_id;
@@ -422,6 +687,22 @@ typedef struct PvgpuCmdSetConstantBuffer {
     uint32_t size;                  /* Size in constants */
 } PvgpuCmdSetConstantBuffer;
 
+/* D3D11 constant buffer slots per shader stage */
+#define PVGPU_MAX_CONSTANT_BUFFERS 14
+
+/*
+ * CMD_SET_CONSTANT_BUFFERS payload. Binds num_buffers consecutive slots from
+ * start_slot with a single call; start_slot + num_buffers must not exceed
+ * PVGPU_MAX_CONSTANT_BUFFERS. Nothing is bound if any ID is unknown.
+ */
+typedef struct PvgpuCmdSetConstantBuffers {
+    PvgpuCommandHeader header;
+    uint32_t stage;                 /* PvgpuShaderStage */
+    uint32_t start_slot;
+    uint32_t num_buffers;
+    uint32_t buffer_ids[PVGPU_MAX_CONSTANT_BUFFERS]; /* Buffer IDs (0 = unbind) */
+} PvgpuCmdSetConstantBuffers;
+
 /* CMD_SET_VERTEX_BUFFER payload */
 typedef struct PvgpuCmdSetVertexBuffer {
     PvgpuCommandHeader header;
@@ -443,13 +724,19 @@ typedef struct PvgpuCmdSetIndexBuffer {
     uint32_t reserved;
 } PvgpuCmdSetIndexBuffer;
 
-/* CMD_SET_PRIMITIVE_TOPOLOGY payload */
+/* CMD_SET_PRIMITIVE_TOPOLOGY payload - values outside D3D11's range (0-5,
+ * the adjacency topologies 10-13, and patch lists 33-64) fail with
+ * INVALID_PARAMETER and leave the topology unchanged */
 typedef struct PvgpuCmdSetPrimitiveTopology {
     PvgpuCommandHeader header;
     uint32_t topology;              /* D3D11_PRIMITIVE_TOPOLOGY */
     uint32_t reserved[3];
 } PvgpuCmdSetPrimitiveTopology;
 
+/* Patch list with N control points: PVGPU_TOPOLOGY_PATCHLIST_1 + N - 1 */
+#define PVGPU_TOPOLOGY_PATCHLIST_1      33
+#define PVGPU_MAX_PATCH_CONTROL_POINTS  32
+
 /* CMD_DRAW payload */
 typedef struct PvgpuCmdDraw {
     PvgpuCommandHeader header;
@@ -487,6 +774,14 @@ typedef struct PvgpuCmdDrawIndexedInstanced {
     uint32_t reserved[3];
 } PvgpuCmdDrawIndexedInstanced;
 
+/* CMD_DRAW_INSTANCED_INDIRECT / CMD_DRAW_INDEXED_INSTANCED_INDIRECT payload */
+typedef struct PvgpuCmdDrawIndirect {
+    PvgpuCommandHeader header;
+    uint32_t args_buffer_id;        /* Buffer holding D3D11 indirect draw args */
+    uint32_t args_offset;           /* Byte offset of the args (4-byte aligned) */
+    uint32_t reserved[2];
+} PvgpuCmdDrawIndirect;
+
 /* CMD_DISPATCH payload (compute shader) */
 typedef struct PvgpuCmdDispatch {
     PvgpuCommandHeader header;
@@ -503,6 +798,16 @@ typedef struct PvgpuCmdClearRenderTarget {
     float color[4];
 } PvgpuCmdClearRenderTarget;
 
+/* CMD_CLEAR_RENDER_TARGET_UINT payload - exact clear of a UINT/SINT render
+ * target (SINT values as two's complement bits). Components must fit in 24
+ * bits of magnitude; other formats or values fail with
+ * PVGPU_ERROR_INVALID_PARAMETER. */
+typedef struct PvgpuCmdClearRenderTargetUint {
+    PvgpuCommandHeader header;
+    uint32_t rtv_id;
+    uint32_t color[4];
+} PvgpuCmdClearRenderTargetUint;
+
 /* CMD_CLEAR_DEPTH_STENCIL payload */
 typedef struct PvgpuCmdClearDepthStencil {
     PvgpuCommandHeader header;
@@ -519,15 +824,129 @@ typedef struct PvgpuCmdFence {
     uint64_t fence_value;           /* Fence value to signal */
 } PvgpuCmdFence;
 
+/* CMD_WAIT_FENCE payload - waits until every listed fence has completed */
+typedef struct PvgpuCmdWaitFence {
+    PvgpuCommandHeader header;
+    uint32_t fence_count;           /* Number of uint64_t fence values */
+    uint32_t heap_offset;           /* Heap offset of the fence value array */
+} PvgpuCmdWaitFence;
+
+/* CMD_QUERY_FENCE payload - host writes host_fence_completed to the heap */
+typedef struct PvgpuCmdQueryFence {
+    PvgpuCommandHeader header;
+    uint32_t heap_offset;           /* Heap offset (8-byte aligned) of uint64_t result */
+    uint32_t reserved;
+} PvgpuCmdQueryFence;
+
+/* CMD_QUERY_DEVICE_STATUS payload - host writes a PvgpuDeviceStatus to the
+ * heap once the command has been consumed. The fields are captured together,
+ * so unlike separate control region reads they cannot tear across an update. */
+typedef struct PvgpuCmdQueryDeviceStatus {
+    PvgpuCommandHeader header;
+    uint32_t heap_offset;           /* Heap offset (8-byte aligned) of PvgpuDeviceStatus */
+    uint32_t reserved;
+} PvgpuCmdQueryDeviceStatus;
+
+typedef struct PvgpuDeviceStatus {
+    uint32_t status;                /* PVGPU_STATUS_* flags */
+    uint32_t error_code;            /* Last error code */
+    uint32_t error_data;            /* Additional error info */
+    uint32_t reserved;
+    uint64_t host_fence_completed;  /* Including this command's fence */
+} PvgpuDeviceStatus;
+
+/* CMD_RESET_DEVICE payload - destroys every resource; all IDs become invalid */
+typedef struct PvgpuCmdResetDevice {
+    PvgpuCommandHeader header;
+    uint64_t fence_value;           /* Fence signalled once reset completes (0 = none) */
+} PvgpuCmdResetDevice;
+
+/* CMD_SHUTDOWN payload - graceful shutdown. Commands before it are
+ * processed and a pending frame presented; the backend then waits for the
+ * GPU, publishes fence_value, sets PVGPU_STATUS_SHUTDOWN and exits. Commands
+ * after it are never consumed. */
+typedef struct PvgpuCmdShutdown {
+    PvgpuCommandHeader header;
+    uint64_t fence_value;           /* Fence signalled once earlier work finishes (0 = none) */
+} PvgpuCmdShutdown;
+
+/* CMD_SELECT_ADAPTER payload - recreates the device on another adapter.
+ * Every resource is destroyed as with CMD_RESET_DEVICE; PVGPU_STATUS_RECOVERY
+ * is set until the new device is up and the capability block describes it.
+ * An adapter that does not exist fails with INVALID_PARAMETER and changes
+ * nothing. */
+typedef struct PvgpuCmdSelectAdapter {
+    PvgpuCommandHeader header;
+    uint32_t adapter_index;         /* DXGI adapter index, unless BY_LUID */
+    uint32_t flags;                 /* PVGPU_SELECT_ADAPTER_* */
+    uint64_t adapter_luid;          /* LUID with PVGPU_SELECT_ADAPTER_BY_LUID */
+    uint64_t fence_value;           /* Fence signalled once the switch completes (0 = none) */
+} PvgpuCmdSelectAdapter;
+
+/* CMD_SET_DISPLAY_CONFIG payload - applied as one transition while
+ * PVGPU_STATUS_RESIZING is set; the control region's display fields hold
+ * the new mode once the flag clears */
+typedef struct PvgpuCmdSetDisplayConfig {
+    PvgpuCommandHeader header;
+    uint32_t width;                 /* New display width in pixels */
+    uint32_t height;                /* New display height in pixels */
+    uint32_t refresh_rate;          /* Refresh rate in Hz (0 = keep current) */
+    uint32_t format;                /* DXGI_FORMAT (0 = keep current) */
+} PvgpuCmdSetDisplayConfig;
+
+/* CMD_BEGIN_FRAME payload - opens a frame that the next CMD_PRESENT closes.
+ * The host presents a frame only after consuming all of its commands and
+ * completes fence_value once the frame is presented or dropped. After the
+ * first BEGIN_FRAME, a PRESENT without an open frame is dropped, as is an
+ * open frame superseded by another BEGIN_FRAME. */
+typedef struct PvgpuCmdBeginFrame {
+    PvgpuCommandHeader header;
+    uint64_t frame_id;              /* Guest frame counter, for logging */
+    uint64_t fence_value;           /* Completed when presented or dropped */
+} PvgpuCmdBeginFrame;
+
+/* CMD_SET_FRAME_LATENCY payload - how many frames the driver may queue
+ * ahead of the GPU. Lower values cut input latency at some throughput
+ * cost. Values above PVGPU_MAX_FRAME_LATENCY fail with
+ * PVGPU_ERROR_INVALID_PARAMETER. */
+typedef struct PvgpuCmdSetFrameLatency {
+    PvgpuCommandHeader header;
+    uint32_t max_frame_latency;     /* 1-16 frames (0 = driver default of 3) */
+    uint32_t reserved;
+} PvgpuCmdSetFrameLatency;
+
 /* CMD_PRESENT payload */
 typedef struct PvgpuCmdPresent {
     PvgpuCommandHeader header;
     uint32_t backbuffer_id;         /* Render target to present */
     uint32_t sync_interval;         /* VSync interval (0 = no vsync) */
-    uint32_t flags;                 /* Present flags */
-    uint32_t reserved;
+    uint32_t flags;                 /* PVGPU_PRESENT_* flags */
+    uint32_t dirty_rects_offset;    /* Heap offset of PvgpuDirtyRects (0 = whole frame) */
 } PvgpuCmdPresent;
 
+/* CMD_PRESENT flags, equal to the DXGI_PRESENT flags they request. Other
+ * bits are ignored; DO_NOT_SEQUENCE is dropped when the host presents
+ * immediately (sync interval 0 or tearing). */
+#define PVGPU_PRESENT_DO_NOT_SEQUENCE   0x00000002
+#define PVGPU_PRESENT_RESTART           0x00000004
+#define PVGPU_PRESENT_DO_NOT_WAIT       0x00000008
+
+/* Dirty region list for CMD_PRESENT, stored in the resource heap. Only the
+ * listed rects may differ from the previous frame; the host passes them to
+ * the compositor, or presents the whole frame if there are more than
+ * PVGPU_MAX_DIRTY_RECTS. */
+#define PVGPU_MAX_DIRTY_RECTS           64
+
+typedef struct PvgpuRect {
+    int32_t left, top, right, bottom;
+} PvgpuRect;
+
+typedef struct PvgpuDirtyRects {
+    uint32_t count;                 /* Number of rects that follow */
+    uint32_t reserved;
+    PvgpuRect rects[];              /* count entries */
+} PvgpuDirtyRects;
+
 /* CMD_RESIZE_BUFFERS payload */
 typedef struct PvgpuCmdResizeBuffers {
     PvgpuCommandHeader header;
@@ -540,6 +959,33 @@ typedef struct PvgpuCmdResizeBuffers {
     uint32_t reserved[2];
 } PvgpuCmdResizeBuffers;
 
+/* CMD_BEGIN_EVENT / CMD_END_EVENT / CMD_SET_MARKER / CMD_SET_DEBUG_NAME
+ * payload. SET_DEBUG_NAME names the texture, buffer or shader in
+ * header.resource_id; other resource kinds fail with INVALID_PARAMETER. */
+typedef struct PvgpuCmdDebugEvent {
+    PvgpuCommandHeader header;
+    uint32_t label_offset;          /* Heap offset of UTF-8 label */
+    uint32_t label_size;            /* Label size in bytes (0 for END_EVENT) */
+    uint32_t reserved[2];
+} PvgpuCmdDebugEvent;
+
+/* CMD_SELFTEST payload - host clears an internal render target to a known
+ * color, reads it back and writes a PVGPU_SELFTEST_* code to the heap once
+ * the command has been consumed */
+typedef struct PvgpuCmdSelftest {
+    PvgpuCommandHeader header;
+    uint32_t heap_offset;           /* Heap offset (8-byte aligned) of uint64_t result */
+    uint32_t reserved;
+} PvgpuCmdSelftest;
+
+/* CMD_SET_LOG_LEVEL payload - host logs at `level` and more severe from the
+ * next command on; any other level fails with INVALID_PARAMETER */
+typedef struct PvgpuCmdSetLogLevel {
+    PvgpuCommandHeader header;
+    uint32_t level;                 /* PVGPU_LOG_LEVEL_* */
+    uint32_t reserved;
+} PvgpuCmdSetLogLevel;
+
 /* CMD_SET_BLEND_STATE payload */
 typedef struct PvgpuCmdSetBlendState {
     PvgpuCommandHeader header;
@@ -572,6 +1018,16 @@ typedef struct PvgpuCmdCopyResource {
     uint32_t reserved[2];
 } PvgpuCmdCopyResource;
 
+/* CMD_COPY_STRUCTURE_COUNT payload - copies the hidden counter of an append
+ * or counter UAV into a DRAWINDIRECT_ARGS buffer */
+typedef struct PvgpuCmdCopyStructureCount {
+    PvgpuCommandHeader header;
+    uint32_t dst_buffer_id;         /* Buffer with PVGPU_RESOURCE_MISC_DRAWINDIRECT_ARGS */
+    uint32_t dst_offset;            /* Byte offset, 4-byte aligned */
+    uint32_t src_uav_id;            /* Buffer UAV with APPEND or COUNTER */
+    uint32_t reserved;
+} PvgpuCmdCopyStructureCount;
+
 /* CMD_COPY_RESOURCE_REGION payload */
 typedef struct PvgpuCmdCopyResourceRegion {
     PvgpuCommandHeader header;
@@ -593,7 +1049,16 @@ typedef struct PvgpuCmdCopyResourceRegion {
  * =============================================================================
  */
 
-/* CMD_CREATE_BLEND_STATE payload */
+/* D3D11 limit on render targets, each of which can blend differently */
+#define PVGPU_MAX_BLEND_TARGETS 8
+
+/*
+ * CMD_CREATE_BLEND_STATE payload. With independent_blend, the first
+ * num_targets entries apply to their render targets and the rest keep the
+ * defaults (no blending, all channels written); otherwise render_targets[0]
+ * applies to every target. Invalid descriptions fail with
+ * PVGPU_ERROR_INVALID_PARAMETER.
+ */
 typedef struct PvgpuCmdCreateBlendState {
     PvgpuCommandHeader header;
     uint32_t state_id;              /* Assigned state object ID */
@@ -608,7 +1073,12 @@ typedef struct PvgpuCmdCreateBlendState {
         uint32_t dest_blend_alpha;  /* D3D11_BLEND */
         uint32_t blend_op_alpha;    /* D3D11_BLEND_OP */
         uint32_t render_target_write_mask; /* D3D11_COLOR_WRITE_ENABLE */
-    } render_targets[8];
+    } render_targets[PVGPU_MAX_BLEND_TARGETS];
+    uint32_t num_targets;           /* Targets described, 1-8 (0 = all 8) */
+    uint32_t logic_op_enable;       /* D3D11.1 logic op on every target instead
+                                       of blending; needs independent_blend = 0 */
+    uint32_t logic_op;              /* D3D11_LOGIC_OP */
+    uint32_t reserved;
 } PvgpuCmdCreateBlendState;
 
 /* CMD_CREATE_RASTERIZER_STATE payload */
@@ -739,13 +1209,45 @@ typedef struct PvgpuCmdCreateShaderResourceView {
     } u;
 } PvgpuCmdCreateShaderResourceView;
 
-/* CMD_SET_SHADER_RESOURCES payload */
+/* Unordered access view dimensions (same as D3D11_UAV_DIMENSION) */
+#define PVGPU_UAV_DIMENSION_BUFFER          1
+#define PVGPU_UAV_DIMENSION_TEXTURE2D       4
+#define PVGPU_UAV_DIMENSION_TEXTURE2DARRAY  5
+
+/* Buffer UAV flags (same as D3D11_BUFFER_UAV_FLAG). RAW needs a buffer with
+ * PVGPU_RESOURCE_MISC_BUFFER_ALLOW_RAW_VIEWS and R32_TYPELESS; APPEND and
+ * COUNTER need a structured buffer. */
+#define PVGPU_UAV_FLAG_RAW                  (1u << 0)
+#define PVGPU_UAV_FLAG_APPEND               (1u << 1)
+#define PVGPU_UAV_FLAG_COUNTER              (1u << 2)
+
+/* CMD_CREATE_UNORDERED_ACCESS_VIEW payload. The resource needs
+ * PVGPU_BIND_UNORDERED_ACCESS. CMD_DESTROY_UNORDERED_ACCESS_VIEW is a bare
+ * header with the view ID in resource_id. */
+typedef struct PvgpuCmdCreateUnorderedAccessView {
+    PvgpuCommandHeader header;
+    uint32_t view_id;               /* Assigned view ID */
+    uint32_t resource_id;           /* Buffer or 2D texture to view */
+    uint32_t format;                /* DXGI_FORMAT (UNKNOWN for structured buffers) */
+    uint32_t view_dimension;        /* PVGPU_UAV_DIMENSION_* */
+    union {
+        struct { uint32_t first_element; uint32_t num_elements; uint32_t flags; } buffer;
+        struct { uint32_t mip_slice; } texture2d;
+        struct { uint32_t mip_slice; uint32_t first_array_slice; uint32_t array_size; } texture2d_array;
+    } u;
+} PvgpuCmdCreateUnorderedAccessView;
+
+/* CMD_SET_SHADER_RESOURCES payload - binds num_views consecutive views in
+ * one call. Views past slot PVGPU_MAX_SHADER_RESOURCES - 1 are dropped; an
+ * unknown ID leaves only its own slot unchanged. */
+#define PVGPU_MAX_SHADER_RESOURCES 128
+
 typedef struct PvgpuCmdSetShaderResources {
     PvgpuCommandHeader header;
     uint32_t stage;                 /* PvgpuShaderStage */
     uint32_t start_slot;
     uint32_t num_views;
-    uint32_t view_ids[128];         /* SRV IDs (0 = unbind) */
+    uint32_t view_ids[PVGPU_MAX_SHADER_RESOURCES]; /* SRV IDs (0 = unbind) */
 } PvgpuCmdSetShaderResources;
 
 /* CMD_SET_SAMPLERS payload */
@@ -791,6 +1293,7 @@ typedef struct PvgpuCmdSetSamplers {
 #define PVGPU_STATUS_RESIZING           (1 << 4)    /* Swapchain resize in progress */
 #define PVGPU_STATUS_RECOVERY           (1 << 5)    /* Device recovery in progress */
 #define PVGPU_STATUS_SHUTDOWN           (1 << 6)    /* Backend is shutting down */
+#define PVGPU_STATUS_MEMORY_PRESSURE    (1 << 7)    /* VRAM use near budget, throttle allocations */
 
 /*
  * =============================================================================
@@ -921,6 +1424,12 @@ typedef struct PvgpuEscapeSetDisplayMode {
 /* Align size to 16-byte boundary */
 #define PVGPU_ALIGN16(x) (((x) + 15) & ~15)
 
+/* Required alignment of heap offsets for bulk data: initial data, shader
+ * bytecode, map/update data, fence arrays and dirty rect lists. The host
+ * rejects misaligned offsets with PVGPU_ERROR_INVALID_PARAMETER. Strings
+ * (debug labels, semantic names) are exempt. */
+#define PVGPU_HEAP_ALIGNMENT            16
+
 #ifdef __cplusplus
 }
 #endif
@@ -56,6 +56,10 @@ OBJECT_DECLARE_SIMPLE_TYPE(PvgpuState, PVGPU)
 #define IPC_MSG_DOORBELL        3
 #define IPC_MSG_IRQ             4
 #define IPC_MSG_SHUTDOWN        5
+#define IPC_MSG_HANDSHAKE_NACK  6
+
+/* Handshake flags, sent after the shmem name's terminator */
+#define IPC_HANDSHAKE_EXTENDED_REPLY (1u << 0)  /* ACK/NACK carry shmem sizes */
 
 /* IPC message header */
 typedef struct {
@@ -208,7 +212,8 @@ static bool pvgpu_backend_send(PvgpuState *s, uint32_t msg_type,
     return true;
 }
 
-/* Read a message from the backend */
+/* Read a message from the backend. *payload_size is the capacity of
+ * payload on entry and the received size on return. */
 static bool pvgpu_backend_recv(PvgpuState *s, uint32_t *msg_type,
                                void *payload, uint32_t *payload_size)
 {
@@ -225,6 +230,11 @@ static bool pvgpu_backend_recv(PvgpuState *s, uint32_t *msg_type,
         return false;
     }
     
+    if (header.payload_size > *payload_size) {
+        error_report("pvgpu: backend message %u payload of %u bytes exceeds %u",
+                     header.msg_type, header.payload_size, *payload_size);
+        return false;
+    }
     *msg_type = header.msg_type;
     *payload_size = header.payload_size;
     
@@ -315,6 +325,11 @@ static bool pvgpu_backend_recv(PvgpuState *s, uint32_t *msg_type,
         return false;
     }
     
+    if (header.payload_size > *payload_size) {
+        error_report("pvgpu: backend message %u payload of %u bytes exceeds %u",
+                     header.msg_type, header.payload_size, *payload_size);
+        return false;
+    }
     *msg_type = header.msg_type;
     *payload_size = header.payload_size;
     
@@ -342,35 +357,69 @@ static void pvgpu_backend_disconnect(PvgpuState *s)
 /* Perform handshake with backend */
 static bool pvgpu_backend_handshake(PvgpuState *s)
 {
-    /* Build handshake payload: shmem_size (u64) + shmem_name (string) */
-    uint8_t payload[256];
+    /* Build handshake payload: shmem_size (u64) + shmem_name (string) +
+     * flags (u32). Older backends stop reading the name at its terminator
+     * and never see the flags. */
+    uint8_t payload[256 + sizeof(uint32_t)];
     uint64_t size64 = s->shmem_size;
     memcpy(payload, &size64, sizeof(size64));
     
     size_t name_len = strlen(s->shmem_name);
     memcpy(payload + 8, s->shmem_name, name_len + 1);  /* Include null terminator */
     
+    uint32_t flags = IPC_HANDSHAKE_EXTENDED_REPLY;
+    memcpy(payload + 8 + name_len + 1, &flags, sizeof(flags));
+    
     /* Send handshake */
-    if (!pvgpu_backend_send(s, IPC_MSG_HANDSHAKE, payload, 8 + name_len + 1)) {
+    if (!pvgpu_backend_send(s, IPC_MSG_HANDSHAKE, payload,
+                            8 + name_len + 1 + sizeof(flags))) {
         error_report("pvgpu: failed to send handshake");
         return false;
     }
     
-    /* Wait for acknowledgement */
+    /* Wait for acknowledgement. ACK: features, preferred and maximum shmem
+     * size (u64 each). NACK: reason (u32), maximum shmem size (u64). Older
+     * backends send only the features / reason. */
     uint32_t msg_type;
-    uint64_t features;
-    uint32_t payload_size = sizeof(features);
+    uint8_t reply[24] = { 0 };
+    uint32_t payload_size = sizeof(reply);
     
-    if (!pvgpu_backend_recv(s, &msg_type, &features, &payload_size)) {
+    if (!pvgpu_backend_recv(s, &msg_type, reply, &payload_size)) {
         error_report("pvgpu: failed to receive handshake ack");
         return false;
     }
     
+    if (msg_type == IPC_MSG_HANDSHAKE_NACK) {
+        uint32_t reason;
+        uint64_t max_size;
+        memcpy(&reason, reply, sizeof(reason));
+        memcpy(&max_size, reply + 4, sizeof(max_size));
+        if (payload_size >= 12 && s->shmem_size > max_size) {
+            error_report("pvgpu: backend rejected handshake: shmem_size=%u exceeds "
+                         "its maximum of %"PRIu64, s->shmem_size, max_size);
+        } else {
+            error_report("pvgpu: backend rejected handshake (reason=0x%x, shmem_size=%u)",
+                         reason, s->shmem_size);
+        }
+        return false;
+    }
+    
     if (msg_type != IPC_MSG_HANDSHAKE_ACK) {
         error_report("pvgpu: unexpected message type %u (expected handshake ack)", msg_type);
         return false;
     }
     
+    uint64_t features;
+    memcpy(&features, reply, sizeof(features));
+    if (payload_size >= sizeof(reply)) {
+        uint64_t preferred;
+        memcpy(&preferred, reply + 8, sizeof(preferred));
+        if (preferred != 0 && preferred != s->shmem_size) {
+            info_report("pvgpu: backend prefers shmem_size=%"PRIu64" (using %u)",
+                        preferred, s->shmem_size);
+        }
+    }
+    
     /* Validate features - ensure backend supports at least D3D11 */
     if (payload_size >= sizeof(features) && features != 0) {
         uint64_t required = PVGPU_FEATURE_D3D11;